@@ -1,94 +1,474 @@
-#[macro_use]
 extern crate clap;
 extern crate modbus;
-use clap::App;
+use clap::{Args, Parser, Subcommand};
 use modbus::tcp;
-use modbus::{Client, Coil};
+use modbus::{Client, Coil, Error};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::process::exit;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Process exit codes, clap exits with `2` on invalid arguments.
+const EXIT_CONNECTION_FAILED: i32 = 3;
+const EXIT_TIMEOUT: i32 = 4;
+const EXIT_EXCEPTION: i32 = 5;
+const EXIT_ERROR: i32 = 6;
+
+#[derive(Parser)]
+#[command(
+    author = "Falco Hirschenberger <falco.hirschenberger@gmail.com>",
+    version,
+    about = "Modbus Tcp client"
+)]
+struct Cli {
+    #[command(flatten)]
+    connection: Connection,
+
+    /// Print results and errors as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Connection flags shared by all subcommands.
+#[derive(Args)]
+struct Connection {
+    /// The IP address or hostname of the server
+    server: String,
+
+    /// The TCP port of the server
+    #[arg(long, default_value_t = 502, global = true)]
+    port: u16,
+
+    /// The modbus unit identifier
+    #[arg(long, default_value_t = 1, global = true)]
+    uid: u8,
+
+    /// Read and write timeout in milliseconds (default: none)
+    #[arg(long, value_name = "MS", global = true)]
+    timeout: Option<u64>,
+
+    /// Connection timeout in milliseconds (default: OS default)
+    #[arg(long, value_name = "MS", global = true)]
+    connect_timeout: Option<u64>,
+
+    /// Tolerate the protocol deviations of the quirk profile NAME
+    #[arg(long, value_name = "NAME", global = true, value_parser = clap::builder::PossibleValuesParser::new(tcp::Quirks::PROFILES))]
+    quirks: Option<String>,
+}
+
+impl Connection {
+    fn config(&self) -> modbus::Result<tcp::Config> {
+        let quirks = self.quirks.as_deref().and_then(tcp::Quirks::profile);
+        let mut builder = tcp::Config::builder()
+            .port(self.port)
+            .uid(self.uid)
+            .quirks(quirks.unwrap_or_default());
+        if let Some(ms) = self.timeout {
+            builder = builder.timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.connect_timeout {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        builder.build()
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Read coils, discrete inputs or registers
+    #[command(subcommand)]
+    Read(Read),
+    /// Write coils or holding registers
+    #[command(subcommand)]
+    Write(Write),
+    /// Read the device identification objects (function 43)
+    DeviceInfo {
+        /// The object category to read: basic, regular or extended
+        #[arg(default_value = "basic")]
+        category: String,
+    },
+    /// Sweep unit ids and report which ones answer
+    Scan {
+        /// First unit id to probe
+        #[arg(long, default_value_t = 1)]
+        first: u8,
+        /// Last unit id to probe
+        #[arg(long, default_value_t = 247)]
+        last: u8,
+        /// Holding register used as probe
+        #[arg(long, default_value_t = 0)]
+        addr: u16,
+    },
+    /// Log the values of a read as timestamped CSV rows at a fixed interval
+    Watch {
+        #[command(subcommand)]
+        read: Read,
+        /// Milliseconds between two reads
+        #[arg(long, default_value_t = 1000)]
+        interval: u64,
+        /// Append the rows to FILE instead of printing them
+        #[arg(long, value_name = "FILE")]
+        output: Option<String>,
+        /// Rotate the output file when it exceeds BYTES
+        #[arg(long, value_name = "BYTES", requires = "output")]
+        max_size: Option<u64>,
+        /// Rotate the output file after SECS seconds
+        #[arg(long, value_name = "SECS", requires = "output")]
+        max_age: Option<u64>,
+        /// Stop after COUNT reads (default: run until interrupted)
+        #[arg(long, value_name = "COUNT")]
+        count: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Read {
+    /// Read QUANTITY coils from ADDR
+    Coils { addr: u16, quantity: u16 },
+    /// Read QUANTITY discrete inputs from ADDR
+    DiscreteInputs { addr: u16, quantity: u16 },
+    /// Read QUANTITY holding registers from ADDR
+    HoldingRegisters { addr: u16, quantity: u16 },
+    /// Read QUANTITY input registers from ADDR
+    InputRegisters { addr: u16, quantity: u16 },
+}
+
+#[derive(Subcommand)]
+enum Write {
+    /// Write a single coil value (On or Off) to ADDR
+    Coil { addr: u16, value: Coil },
+    /// Write multiple coil values (On or Off) starting at ADDR
+    Coils {
+        addr: u16,
+        #[arg(required = true)]
+        values: Vec<Coil>,
+    },
+    /// Write VALUE to holding register ADDR
+    Register { addr: u16, value: u16 },
+    /// Write multiple holding register values starting at ADDR
+    Registers {
+        addr: u16,
+        #[arg(required = true)]
+        values: Vec<u16>,
+    },
+}
+
+enum Output {
+    Coils(Vec<Coil>),
+    Registers(Vec<u16>),
+    Strings(Vec<(u8, String)>),
+    Done,
+}
+
+impl Output {
+    fn print(&self, json: bool) {
+        match (self, json) {
+            (Output::Coils(c), false) => println!("{:?}", c),
+            (Output::Registers(r), false) => println!("{:?}", r),
+            (Output::Strings(s), false) => {
+                for (id, value) in s {
+                    println!("{:#04x}: {}", id, value);
+                }
+            }
+            (Output::Done, false) => (),
+            (Output::Coils(c), true) => {
+                let values: Vec<&str> = c
+                    .iter()
+                    .map(|c| if *c == Coil::On { "true" } else { "false" })
+                    .collect();
+                println!("{{\"ok\":true,\"values\":[{}]}}", values.join(","))
+            }
+            (Output::Registers(r), true) => {
+                let values: Vec<String> = r.iter().map(u16::to_string).collect();
+                println!("{{\"ok\":true,\"values\":[{}]}}", values.join(","))
+            }
+            (Output::Strings(s), true) => {
+                let values: Vec<String> = s
+                    .iter()
+                    .map(|(id, value)| format!("\"{}\":\"{}\"", id, escape(value)))
+                    .collect();
+                println!("{{\"ok\":true,\"values\":{{{}}}}}", values.join(","))
+            }
+            (Output::Done, true) => println!("{{\"ok\":true}}"),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn exit_code(err: &Error) -> i32 {
+    match *err.root() {
+        Error::Exception(_) => EXIT_EXCEPTION,
+        Error::ConnectionClosed | Error::ConnectionLimit(_) => EXIT_CONNECTION_FAILED,
+        Error::Timeout => EXIT_TIMEOUT,
+        _ => EXIT_ERROR,
+    }
+}
+
+fn fail(json: bool, err: &Error, code: i32) -> ! {
+    if json {
+        let kind = match code {
+            EXIT_CONNECTION_FAILED => "connection",
+            EXIT_TIMEOUT => "timeout",
+            EXIT_EXCEPTION => "exception",
+            _ => "error",
+        };
+        let message = escape(&err.to_string());
+        match *err.root() {
+            Error::Exception(ref e) => println!(
+                "{{\"ok\":false,\"kind\":\"{}\",\"exception\":\"{:?}\",\"error\":\"{}\"}}",
+                kind, e, message
+            ),
+            _ => println!(
+                "{{\"ok\":false,\"kind\":\"{}\",\"error\":\"{}\"}}",
+                kind, message
+            ),
+        }
+    } else {
+        eprintln!("{}", err);
+    }
+    exit(code)
+}
+
+#[cfg(feature = "read-device-info")]
+fn device_info(client: &mut tcp::Transport, category: &str) -> modbus::Result<Output> {
+    use modbus::mei::DeviceInfoCategory;
+
+    let category = match category {
+        "basic" => DeviceInfoCategory::Basic,
+        "regular" => DeviceInfoCategory::Regular,
+        "extended" => DeviceInfoCategory::Extended,
+        _ => {
+            return Err(Error::InvalidData(modbus::Reason::Custom(format!(
+                "unknown device info category '{}'",
+                category
+            ))))
+        }
+    };
+    let objects = client.read_device_info(category)?;
+    Ok(Output::Strings(
+        objects.iter().map(|o| (o.id(), o.to_string())).collect(),
+    ))
+}
+
+#[cfg(not(feature = "read-device-info"))]
+fn device_info(_: &mut tcp::Transport, _: &str) -> modbus::Result<Output> {
+    Err(Error::InvalidData(modbus::Reason::Custom(
+        "built without the `read-device-info` feature".to_string(),
+    )))
+}
+
+fn scan(client: &mut tcp::Transport, first: u8, last: u8, addr: u16) -> Output {
+    let mut found = vec![];
+    for uid in first..=last {
+        client.set_uid(uid);
+        // Any well formed answer, including most exceptions, means a device is listening on this
+        // unit id. Gateways answer with the gateway exceptions for units they can't reach.
+        match client
+            .read_holding_registers(addr, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Ok(_) => found.push((uid, "ok".to_string())),
+            Err(Error::Exception(modbus::ExceptionCode::GatewayTarget))
+            | Err(Error::Exception(modbus::ExceptionCode::GatewayPath)) => (),
+            Err(Error::Exception(e)) => found.push((uid, format!("{:?}", e))),
+            Err(_) => (),
+        }
+    }
+    Output::Strings(found)
+}
+
+// Read the values selected by `read`, coils are given as `0` or `1`.
+fn read_values(client: &mut tcp::Transport, read: &Read) -> modbus::Result<(u16, Vec<u16>)> {
+    let bits = |coils: Vec<Coil>| coils.iter().map(|c| (*c == Coil::On) as u16).collect();
+    Ok(match *read {
+        Read::Coils { addr, quantity } => (addr, bits(client.read_coils(addr, quantity)?)),
+        Read::DiscreteInputs { addr, quantity } => {
+            (addr, bits(client.read_discrete_inputs(addr, quantity)?))
+        }
+        Read::HoldingRegisters { addr, quantity } => {
+            (addr, client.read_holding_registers(addr, quantity)?)
+        }
+        Read::InputRegisters { addr, quantity } => {
+            (addr, client.read_input_registers(addr, quantity)?)
+        }
+    })
+}
+
+/// Destination of the CSV rows of `watch`, a file is rotated by renaming it with the time of
+/// the rotation appended.
+struct Log {
+    path: Option<String>,
+    file: Option<File>,
+    opened: Instant,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    printed_header: bool,
+}
+
+impl Log {
+    fn write(&mut self, header: &str, row: &str) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => {
+                if !self.printed_header {
+                    println!("{}", header);
+                    self.printed_header = true;
+                }
+                println!("{}", row);
+                return Ok(());
+            }
+        };
+        if let Some(ref file) = self.file {
+            let full = self
+                .max_size
+                .is_some_and(|max| file.metadata().is_ok_and(|m| m.len() >= max));
+            let old = self.max_age.is_some_and(|max| self.opened.elapsed() >= max);
+            if full || old {
+                self.file = None;
+                fs::rename(&path, format!("{}.{}", path, timestamp()))?;
+            }
+        }
+        if self.file.is_none() {
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            if file.metadata()?.len() == 0 {
+                writeln!(file, "{}", header)?;
+            }
+            self.file = Some(file);
+            self.opened = Instant::now();
+        }
+        writeln!(self.file.as_mut().unwrap(), "{}", row)
+    }
+}
+
+// Seconds since the epoch with millisecond resolution.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:03}", now.as_secs(), now.subsec_millis())
+}
+
+fn watch(
+    client: &mut tcp::Transport,
+    read: &Read,
+    interval: Duration,
+    count: Option<u64>,
+    mut log: Log,
+) -> modbus::Result<Output> {
+    let mut header = None;
+    let mut next = Instant::now();
+    let mut reads = 0;
+    while count != Some(reads) {
+        reads += 1;
+        match read_values(client, read) {
+            Ok((addr, values)) => {
+                let header = header.get_or_insert_with(|| {
+                    let columns: Vec<String> =
+                        (addr..).take(values.len()).map(|a| a.to_string()).collect();
+                    format!("timestamp,{}", columns.join(","))
+                });
+                let values: Vec<String> = values.iter().map(u16::to_string).collect();
+                log.write(header, &format!("{},{}", timestamp(), values.join(",")))?;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", timestamp(), e);
+                if let Error::Io(_) | Error::ConnectionClosed | Error::ConnectionLimit(_) =
+                    *e.root()
+                {
+                    if let Err(e) = client.reconnect() {
+                        eprintln!("reconnect failed: {}", e);
+                    }
+                }
+            }
+        }
+        next += interval;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+    Ok(Output::Done)
+}
 
 fn main() {
-    let matches = App::new("client")
-        .author("Falco Hirschenberger <falco.hirschenberger@gmail.com>")
-        .version(&crate_version!()[..])
-        .about("Modbus Tcp client")
-        .args_from_usage(
-            "<SERVER> 'The IP address or hostname of the server'
-                        \
-                          --read-coils=[ADDR] [QUANTITY] 'Read QUANTITY coils from ADDR'
-                        \
-                          --read-discrete-inputs=[ADDR] [QUANTITY] 'Read QUANTITY inputs from \
-                          ADDR'
-                        --write-single-coil=[ADDR] [On,Off] \
-                          'Write the coil value (On or Off) to ADDR'
-                        \
-                          --write-multiple-coils=[ADDR] [On,Off..] 'Write multiple coil values \
-                          (On or Off) to ADDR (use \"..\" without spaces to group them e.g. \
-                          \"On, Off, On, Off\")'
-                        \
-                          --read-input-registers=[ADDR], [QUANTITY] 'Read QUANTITY input \
-                          registersfrom ADDR'
-                        \
-                          --read-holding-registers=[ADDR], [QUANTITY] 'Read QUANTITY holding \
-                          registers from ADDR'
-                        \
-                          --write-single-register=[ADDR] [VALUE] 'Write VALUE to register ADDR'
-                        \
-                          --write-multiple-registers=[ADDR] [V1,V2...] 'Write multiple register \
-                          values to ADDR (use \"..\" to group them e.g. \"23, 24, 25\")'",
-        )
-        .get_matches();
-
-    let mut client = tcp::Transport::new(matches.value_of("SERVER").unwrap()).unwrap();
-
-    if let Some(args) = matches.values_of("read-coils") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let qtty: u16 = args[1].parse().expect(matches.usage());
-        println!("{:?}", client.read_coils(addr, qtty).expect("IO Error"));
-    } else if let Some(args) = matches.values_of("read-discrete-inputs") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let qtty: u16 = args[1].parse().expect(matches.usage());
-        println!(
-            "{:?}",
-            client.read_discrete_inputs(addr, qtty).expect("IO Error")
-        );
-    } else if let Some(args) = matches.values_of("write-single-coil") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let value: Coil = args[1].parse().expect(matches.usage());
-        client.write_single_coil(addr, value).expect("IO Error");
-    } else if let Some(args) = matches.values_of("write-multiple-coils") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let values: Vec<Coil> = args[1]
-            .split(',')
-            .map(|s| s.trim().parse().expect(matches.usage()))
-            .collect();
-        client
+    let cli = Cli::parse();
+
+    let mut cfg = match cli.connection.config() {
+        Ok(cfg) => cfg,
+        Err(e) => fail(cli.json, &e, EXIT_ERROR),
+    };
+    if let Command::Scan { .. } = cli.command {
+        // Without a timeout the scan would block forever on the first silent unit id.
+        cfg.tcp_read_timeout = cfg.tcp_read_timeout.or(Some(Duration::from_millis(500)));
+    }
+    let mut client = match tcp::Transport::new_with_cfg(&cli.connection.server, cfg) {
+        Ok(client) => client,
+        Err(e) => fail(cli.json, &Error::Io(e), EXIT_CONNECTION_FAILED),
+    };
+
+    let result = match cli.command {
+        Command::Read(Read::Coils { addr, quantity }) => {
+            client.read_coils(addr, quantity).map(Output::Coils)
+        }
+        Command::Read(Read::DiscreteInputs { addr, quantity }) => client
+            .read_discrete_inputs(addr, quantity)
+            .map(Output::Coils),
+        Command::Read(Read::HoldingRegisters { addr, quantity }) => client
+            .read_holding_registers(addr, quantity)
+            .map(Output::Registers),
+        Command::Read(Read::InputRegisters { addr, quantity }) => client
+            .read_input_registers(addr, quantity)
+            .map(Output::Registers),
+        Command::Write(Write::Coil { addr, value }) => {
+            client.write_single_coil(addr, value).map(|_| Output::Done)
+        }
+        Command::Write(Write::Coils { addr, values }) => client
             .write_multiple_coils(addr, &values)
-            .expect("IO Error");
-    } else if let Some(args) = matches.values_of("read-holding-registers") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let qtty: u16 = args[1].parse().expect(matches.usage());
-        println!(
-            "{:?}",
-            client.read_holding_registers(addr, qtty).expect("IO Error")
-        );
-    } else if let Some(args) = matches.values_of("write-single-register") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let value: u16 = args[1].parse().expect(matches.usage());
-        client.write_single_register(addr, value).expect("IO Error");
-    } else if let Some(args) = matches.values_of("write-multiple-registers") {
-        let args: Vec<&str> = args.collect();
-        let addr: u16 = args[0].parse().expect(matches.usage());
-        let values: Vec<u16> = args[1]
-            .split(',')
-            .map(|s| s.trim().parse().expect(matches.usage()))
-            .collect();
-        client
+            .map(|_| Output::Done),
+        Command::Write(Write::Register { addr, value }) => client
+            .write_single_register(addr, value)
+            .map(|_| Output::Done),
+        Command::Write(Write::Registers { addr, values }) => client
             .write_multiple_registers(addr, &values)
-            .expect("IO Error");
+            .map(|_| Output::Done),
+        Command::DeviceInfo { ref category } => device_info(&mut client, category),
+        Command::Scan { first, last, addr } => Ok(scan(&mut client, first, last, addr)),
+        Command::Watch {
+            ref read,
+            interval,
+            ref output,
+            max_size,
+            max_age,
+            count,
+        } => {
+            let log = Log {
+                path: output.clone(),
+                file: None,
+                opened: Instant::now(),
+                max_size,
+                max_age: max_age.map(Duration::from_secs),
+                printed_header: false,
+            };
+            watch(
+                &mut client,
+                read,
+                Duration::from_millis(interval),
+                count,
+                log,
+            )
+        }
+    };
+
+    match result {
+        Ok(output) => output.print(cli.json),
+        Err(e) => {
+            let code = exit_code(&e);
+            fail(cli.json, &e, code)
+        }
     }
 }
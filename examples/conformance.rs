@@ -0,0 +1,139 @@
+extern crate clap;
+extern crate modbus;
+use clap::Parser;
+use modbus::tcp;
+use modbus::{Client, Coil, Error, ExceptionCode};
+
+#[derive(Parser)]
+#[command(
+    author = "Falco Hirschenberger <falco.hirschenberger@gmail.com>",
+    version,
+    about = "Modbus Tcp server conformance checker"
+)]
+struct Cli {
+    /// The IP address or hostname of the server
+    server: String,
+    /// The TCP port of the server
+    #[arg(long, default_value_t = 502)]
+    port: u16,
+    /// The unit identifier to address
+    #[arg(long, default_value_t = 1)]
+    uid: u8,
+}
+
+/// What a conforming server is expected to answer for a check.
+enum Expect {
+    Ok,
+    Exception(ExceptionCode),
+}
+
+struct Check {
+    name: &'static str,
+    expect: Expect,
+    run: fn(&mut tcp::Transport) -> modbus::Result<()>,
+}
+
+fn checks() -> Vec<Check> {
+    vec![
+        Check {
+            name: "read 1 coil at address 0",
+            expect: Expect::Ok,
+            run: |t| t.read_coils(0, 1).map(|_| ()),
+        },
+        Check {
+            name: "read 1 holding register at address 0",
+            expect: Expect::Ok,
+            run: |t| t.read_holding_registers(0, 1).map(|_| ()),
+        },
+        Check {
+            name: "read 125 holding registers (maximum quantity)",
+            expect: Expect::Ok,
+            run: |t| t.read_holding_registers(0, 125).map(|_| ()),
+        },
+        Check {
+            name: "read 126 holding registers (above maximum)",
+            expect: Expect::Exception(ExceptionCode::IllegalDataValue),
+            run: |t| t.read_holding_registers(0, 126).map(|_| ()),
+        },
+        Check {
+            name: "read 125 input registers (maximum quantity)",
+            expect: Expect::Ok,
+            run: |t| t.read_input_registers(0, 125).map(|_| ()),
+        },
+        Check {
+            name: "read 126 input registers (above maximum)",
+            expect: Expect::Exception(ExceptionCode::IllegalDataValue),
+            run: |t| t.read_input_registers(0, 126).map(|_| ()),
+        },
+        Check {
+            name: "read 2 holding registers at 0xffff (address overflow)",
+            expect: Expect::Exception(ExceptionCode::IllegalDataAddress),
+            run: |t| t.read_holding_registers(0xffff, 2).map(|_| ()),
+        },
+        Check {
+            name: "read 2 coils at 0xffff (address overflow)",
+            expect: Expect::Exception(ExceptionCode::IllegalDataAddress),
+            run: |t| t.read_coils(0xffff, 2).map(|_| ()),
+        },
+        Check {
+            name: "write 123 holding registers (maximum quantity)",
+            expect: Expect::Ok,
+            run: |t| t.write_multiple_registers(0, &[0; 123]),
+        },
+        Check {
+            name: "write 0 holding registers (zero quantity)",
+            expect: Expect::Exception(ExceptionCode::IllegalDataValue),
+            run: |t| t.write_multiple_registers(0, &[]),
+        },
+        Check {
+            name: "write single coil at address 0",
+            expect: Expect::Ok,
+            run: |t| t.write_single_coil(0, Coil::Off),
+        },
+        Check {
+            name: "write/read multiple registers (0x17)",
+            expect: Expect::Ok,
+            run: |t| {
+                t.write_read_multiple_registers(0, 1, &[0], 0, 1)
+                    .map(|_| ())
+            },
+        },
+    ]
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let cfg = tcp::Config::builder()
+        .port(cli.port)
+        .uid(cli.uid)
+        .build()
+        .expect("invalid configuration");
+
+    let mut failed = 0;
+    for check in checks() {
+        // Use a fresh connection for every check, a misbehaving server must not be able to
+        // influence the outcome of the following checks.
+        let result = tcp::Transport::new_with_cfg(&cli.server, cfg)
+            .map_err(Error::Io)
+            .and_then(|mut t| (check.run)(&mut t));
+        let passed = match (&check.expect, result.as_ref().map_err(Error::root)) {
+            (Expect::Ok, Ok(())) => true,
+            (Expect::Exception(expected), Err(Error::Exception(code))) => expected == code,
+            _ => false,
+        };
+        let outcome = match result {
+            Ok(()) => "ok".to_string(),
+            Err(e) => e.to_string(),
+        };
+        if !passed {
+            failed += 1;
+        }
+        println!(
+            "[{}] {}: {}",
+            if passed { "PASS" } else { "FAIL" },
+            check.name,
+            outcome
+        );
+    }
+    println!("{} checks, {} failed", checks().len(), failed);
+}
@@ -0,0 +1,150 @@
+extern crate clap;
+extern crate modbus;
+use clap::Parser;
+use modbus::tcp;
+use modbus::Client;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Maximum number of registers the protocol allows to read in one request.
+const MAX_READ_QUANTITY: u16 = 125;
+
+#[derive(Parser)]
+#[command(
+    author = "Falco Hirschenberger <falco.hirschenberger@gmail.com>",
+    version,
+    about = "Samples holding registers and prints a draft register map"
+)]
+struct Cli {
+    /// The IP address or hostname of the server
+    server: String,
+    /// The TCP port of the server
+    #[arg(long, default_value_t = 502)]
+    port: u16,
+    /// The unit identifier to address
+    #[arg(long, default_value_t = 1)]
+    uid: u8,
+    /// First register address to sample
+    #[arg(long, default_value_t = 0)]
+    start: u16,
+    /// Number of registers to sample
+    #[arg(long, default_value_t = 100)]
+    count: u16,
+    /// Number of sampling rounds
+    #[arg(long, default_value_t = 10)]
+    samples: usize,
+    /// Milliseconds between sampling rounds
+    #[arg(long, default_value_t = 1000)]
+    interval: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum Class {
+    /// The register never changed while sampling.
+    Static,
+    /// The register only increased (allowing for a single 16bit wrap around).
+    Counter,
+    /// The register changed in any other way.
+    Dynamic,
+}
+
+fn classify(samples: &[u16]) -> Class {
+    if samples.windows(2).all(|w| w[0] == w[1]) {
+        return Class::Static;
+    }
+    let decreasing: Vec<u16> = samples
+        .windows(2)
+        .filter(|w| w[1] < w[0])
+        .map(|w| w[0] - w[1])
+        .collect();
+    if decreasing.len() <= 1 && decreasing.iter().all(|d| *d > 0x8000) {
+        Class::Counter
+    } else {
+        Class::Dynamic
+    }
+}
+
+/// Two adjacent registers are a float candidate if all their samples decode to a finite,
+/// plausibly scaled IEEE-754 value and at least one sample is not zero.
+fn is_float_pair(hi: &[u16], lo: &[u16]) -> bool {
+    let values: Vec<f32> = hi
+        .iter()
+        .zip(lo)
+        .map(|(h, l)| f32::from_bits((u32::from(*h) << 16) | u32::from(*l)))
+        .collect();
+    values.iter().any(|v| *v != 0.0)
+        && values
+            .iter()
+            .all(|v| *v == 0.0 || (v.is_normal() && v.abs() > 1e-6 && v.abs() < 1e9))
+}
+
+fn sample(client: &mut tcp::Transport, start: u16, count: u16) -> modbus::Result<Vec<Option<u16>>> {
+    let mut values = Vec::with_capacity(count as usize);
+    let mut addr = start;
+    let end = start as u32 + count as u32;
+    while (addr as u32) < end {
+        let quantity = std::cmp::min(MAX_READ_QUANTITY as u32, end - addr as u32) as u16;
+        match client.read_holding_registers(addr, quantity) {
+            Ok(regs) => values.extend(regs.into_iter().map(Some)),
+            // Unreadable blocks are probed register by register, so a single hole in the map
+            // does not hide the readable neighbours.
+            Err(ref e) if matches!(e.root(), modbus::Error::Exception(_)) => {
+                for a in addr..addr + quantity {
+                    values.push(client.read_holding_registers(a, 1).ok().map(|r| r[0]));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        addr = addr.wrapping_add(quantity);
+    }
+    Ok(values)
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let cfg = tcp::Config::builder()
+        .port(cli.port)
+        .uid(cli.uid)
+        .build()
+        .expect("invalid configuration");
+    let (start, count, rounds, interval) = (cli.start, cli.count, cli.samples, cli.interval);
+
+    let mut client = tcp::Transport::new_with_cfg(&cli.server, cfg).expect("IO Error");
+
+    let mut history: Vec<Vec<Option<u16>>> = Vec::with_capacity(rounds);
+    for round in 0..rounds {
+        if round > 0 {
+            sleep(Duration::from_millis(interval));
+        }
+        history.push(sample(&mut client, start, count).expect("IO Error"));
+    }
+
+    // Transpose the sampling rounds into a per register series, `None` if it was unreadable.
+    let series: Vec<Option<Vec<u16>>> = (0..count as usize)
+        .map(|i| history.iter().map(|round| round[i]).collect())
+        .collect();
+
+    println!(
+        "# draft register map, {} samples every {}ms",
+        rounds, interval
+    );
+    println!("address,class,last_value,float_pair");
+    for (i, s) in series.iter().enumerate() {
+        let addr = start as usize + i;
+        let values = match s {
+            Some(v) => v,
+            None => continue,
+        };
+        let float_pair = match series.get(i + 1) {
+            Some(Some(next)) => is_float_pair(values, next),
+            _ => false,
+        };
+        println!(
+            "{},{:?},{},{}",
+            addr,
+            classify(values),
+            values.last().unwrap(),
+            if float_pair { "candidate" } else { "" }
+        );
+    }
+}
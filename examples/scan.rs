@@ -0,0 +1,214 @@
+extern crate clap;
+extern crate modbus;
+use clap::Parser;
+use modbus::server::{Bank, Server};
+use modbus::tagmap::TagMap;
+use modbus::{tcp, Client, Error, ExceptionCode, Table};
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+const TABLES: [(Table, &str); 4] = [
+    (Table::Coils, "coil"),
+    (Table::DiscreteInputs, "discrete_input"),
+    (Table::HoldingRegisters, "holding_register"),
+    (Table::InputRegisters, "input_register"),
+];
+
+#[derive(Parser)]
+#[command(
+    author = "Falco Hirschenberger <falco.hirschenberger@gmail.com>",
+    version,
+    about = "Sweeps unit ids and address ranges and prints the readable ranges of every responsive unit"
+)]
+struct Cli {
+    /// The IP address or hostname of the server, an in-process simulator is used if omitted
+    server: Option<String>,
+    /// The TCP port of the server
+    #[arg(long, default_value_t = 502)]
+    port: u16,
+    /// First unit id to probe
+    #[arg(long, default_value_t = 1)]
+    first_uid: u8,
+    /// Last unit id to probe
+    #[arg(long, default_value_t = 247)]
+    last_uid: u8,
+    /// First address to probe in every table
+    #[arg(long, default_value_t = 0)]
+    start: u16,
+    /// Number of addresses to probe in every table
+    #[arg(long, default_value_t = 1000)]
+    count: u16,
+    /// Read timeout in milliseconds, silent unit ids cost this much each
+    #[arg(long, default_value_t = 300)]
+    timeout: u64,
+    /// Write a starter tag map `unit<UID>.csv` of the readable addresses of every unit to DIR
+    #[arg(long, value_name = "DIR")]
+    tags: Option<PathBuf>,
+}
+
+/// The readable ranges of a unit, as table, first address and count.
+type Ranges = Vec<(Table, u16, u16)>;
+
+/// Whether a unit id answers at all. Any well formed answer, including most exceptions, means a
+/// device is listening. Gateways answer with the gateway exceptions for units they can't reach.
+fn responds(client: &mut tcp::Transport, addr: u16) -> modbus::Result<bool> {
+    let result = client.read_holding_registers(addr, 1);
+    match result.as_ref().map_err(Error::root) {
+        Ok(_) => Ok(true),
+        Err(Error::Exception(ExceptionCode::GatewayTarget))
+        | Err(Error::Exception(ExceptionCode::GatewayPath)) => Ok(false),
+        Err(Error::Exception(_)) => Ok(true),
+        // the read timeout elapsed
+        Err(Error::Timeout) => Ok(false),
+        Err(_) => result.map(|_| true),
+    }
+}
+
+fn read(client: &mut tcp::Transport, table: Table, addr: u16, count: u16) -> modbus::Result<()> {
+    match table {
+        Table::Coils => client.read_coils(addr, count).map(drop),
+        Table::DiscreteInputs => client.read_discrete_inputs(addr, count).map(drop),
+        Table::HoldingRegisters => client.read_holding_registers(addr, count).map(drop),
+        Table::InputRegisters => client.read_input_registers(addr, count).map(drop),
+    }
+}
+
+/// Find the readable addresses of `table` in the `count` addresses from `addr`. Blocks which
+/// fail with an exception are halved until the readable parts are found, so a few holes don't
+/// need a request per address.
+fn probe(
+    client: &mut tcp::Transport,
+    table: Table,
+    addr: u16,
+    count: u16,
+    found: &mut Vec<(u16, u16)>,
+) -> modbus::Result<()> {
+    let result = read(client, table, addr, count);
+    match result.as_ref().map_err(Error::root) {
+        Ok(()) => {
+            match found.last_mut() {
+                Some((first, n)) if *first as u32 + *n as u32 == addr as u32 => *n += count,
+                _ => found.push((addr, count)),
+            }
+            Ok(())
+        }
+        Err(Error::Exception(_)) if count > 1 => {
+            let half = count / 2;
+            probe(client, table, addr, half, found)?;
+            probe(client, table, addr + half, count - half, found)
+        }
+        Err(Error::Exception(_)) => Ok(()),
+        Err(_) => result,
+    }
+}
+
+fn scan_unit(client: &mut tcp::Transport, start: u16, count: u16) -> modbus::Result<Ranges> {
+    let mut ranges = vec![];
+    for &(table, _) in &TABLES {
+        let max = match table {
+            Table::Coils | Table::DiscreteInputs => 2000,
+            Table::HoldingRegisters | Table::InputRegisters => 125,
+        };
+        let end = start as u32 + count as u32;
+        let mut found = vec![];
+        let mut addr = start as u32;
+        while addr < end.min(0x10000) {
+            let n = (end.min(0x10000) - addr).min(max) as u16;
+            probe(client, table, addr as u16, n, &mut found)?;
+            addr += n as u32;
+        }
+        ranges.extend(found.into_iter().map(|(addr, n)| (table, addr, n)));
+    }
+    Ok(ranges)
+}
+
+fn table_name(table: Table) -> &'static str {
+    TABLES.iter().find(|(t, _)| *t == table).unwrap().1
+}
+
+/// A tag map with one `bool` or `u16` tag per readable address, to be edited into the real map.
+fn starter_tags(uid: u8, ranges: &Ranges) -> String {
+    let mut csv = format!(
+        "# starter tag map of unit {}, generated by the scan example\n\
+         # name,table,address,type,scale,offset,order,unit,description,labels\n",
+        uid
+    );
+    for &(table, first, count) in ranges {
+        let name = table_name(table);
+        let data_type = match table {
+            Table::Coils | Table::DiscreteInputs => "bool",
+            Table::HoldingRegisters | Table::InputRegisters => "u16",
+        };
+        for addr in first..=(first as u32 + count as u32 - 1) as u16 {
+            csv.push_str(&format!("{0}_{1},{0},{1},{2}\n", name, addr, data_type));
+        }
+    }
+    csv
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let (host, port) = match cli.server {
+        Some(ref server) => (server.clone(), cli.port),
+        None => {
+            let mut bank = Bank::new(300);
+            bank.coils.truncate(16);
+            bank.discrete_inputs.clear();
+            bank.input_registers.truncate(20);
+            let server = Server::bind("127.0.0.1:0", bank).unwrap();
+            let port = server.local_addr().unwrap().port();
+            println!("simulator listening on port {}", port);
+            thread::spawn(move || server.serve());
+            ("127.0.0.1".to_string(), port)
+        }
+    };
+    // Late answers of units which responded after the timeout are discarded.
+    let cfg = tcp::Config::builder()
+        .port(port)
+        .timeout(Duration::from_millis(cli.timeout))
+        .max_stale_responses(u8::MAX)
+        .build()
+        .expect("invalid configuration");
+    let mut client = tcp::Transport::new_with_cfg(&host, cfg).expect("connection failed");
+
+    for uid in cli.first_uid..=cli.last_uid {
+        client.set_uid(uid);
+        let ranges = match responds(&mut client, cli.start) {
+            Ok(true) => scan_unit(&mut client, cli.start, cli.count),
+            Ok(false) => continue,
+            Err(e) => Err(e),
+        };
+        let ranges = match ranges {
+            Ok(ranges) => ranges,
+            Err(e) => {
+                eprintln!("unit {}: scan failed: {}", uid, e);
+                continue;
+            }
+        };
+        println!("unit {}:", uid);
+        if ranges.is_empty() {
+            println!("  no readable addresses");
+        }
+        for &(table, addr, count) in &ranges {
+            println!(
+                "  {:<16} {:>5}..{:<5} ({} addresses)",
+                table_name(table),
+                addr,
+                addr as u32 + count as u32 - 1,
+                count
+            );
+        }
+        if let Some(ref dir) = cli.tags {
+            let csv = starter_tags(uid, &ranges);
+            // The generated map has to load, otherwise it's no starting point.
+            TagMap::read_csv(BufReader::new(csv.as_bytes())).expect("invalid tag map");
+            let path = dir.join(format!("unit{}.csv", uid));
+            fs::write(&path, csv).expect("writing the tag map failed");
+            println!("  tag map written to {}", path.display());
+        }
+    }
+}
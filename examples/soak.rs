@@ -0,0 +1,166 @@
+extern crate clap;
+extern crate modbus;
+use clap::Parser;
+use modbus::server::{Bank, Server};
+use modbus::tcp;
+use modbus::{Client, Coil};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Parser)]
+#[command(
+    author = "Falco Hirschenberger <falco.hirschenberger@gmail.com>",
+    version,
+    about = "Modbus Tcp soak test, hammers a server with mixed operations and reports statistics"
+)]
+struct Cli {
+    /// The IP address or hostname of the server, an in-process simulator is used if omitted
+    server: Option<String>,
+    /// The TCP port of the server
+    #[arg(long, default_value_t = 502)]
+    port: u16,
+    /// The unit identifier to address
+    #[arg(long, default_value_t = 1)]
+    uid: u8,
+    /// Total run time in seconds
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+    /// Seconds between two statistics reports
+    #[arg(long, default_value_t = 10)]
+    report: u64,
+    /// Read and write timeout in milliseconds
+    #[arg(long, default_value_t = 1000)]
+    timeout: u64,
+    /// First address of the exercised coils and holding registers
+    #[arg(long, default_value_t = 0)]
+    addr: u16,
+}
+
+/// An operation with the base address and the iteration counter.
+type Operation = fn(&mut tcp::Transport, u16, u16) -> modbus::Result<()>;
+
+/// The mix of operations, run round robin.
+const OPERATIONS: [(&str, Operation); 6] = [
+    ("read coils", |t, a, _| t.read_coils(a, 64).map(|_| ())),
+    ("read holding", |t, a, _| {
+        t.read_holding_registers(a, 125).map(|_| ())
+    }),
+    ("read input", |t, a, _| {
+        t.read_input_registers(a, 16).map(|_| ())
+    }),
+    ("write coil", |t, a, i| {
+        t.write_single_coil(a + i % 64, Coil::from(i % 2 == 0))
+    }),
+    ("write register", |t, a, i| t.write_single_register(a, i)),
+    ("write registers", |t, a, i| {
+        t.write_multiple_registers(a, &[i; 16])
+    }),
+];
+
+#[derive(Clone, Default)]
+struct Stats {
+    calls: u64,
+    errors: u64,
+    reconnects: u64,
+    // latencies since the last report
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    fn print(&mut self, name: &str) {
+        self.latencies.sort();
+        let percentile = |p: usize| {
+            self.latencies
+                .get((self.latencies.len() * p / 100).min(self.latencies.len().saturating_sub(1)))
+                .map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+        };
+        println!(
+            "  {:<16} calls {:>8}  errors {:>5}  reconnects {:>3}  p50 {:>7.2}ms  p99 {:>7.2}ms  max {:>7.2}ms",
+            name,
+            self.calls,
+            self.errors,
+            self.reconnects,
+            percentile(50),
+            percentile(99),
+            percentile(100)
+        );
+        self.latencies.clear();
+    }
+}
+
+/// Resident set size of this process, only available on Linux.
+fn rss_kib() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4)
+}
+
+// Print the totals and the latencies since the last report.
+fn report(stats: &mut [Stats], elapsed: Duration) {
+    println!(
+        "after {}s, rss {}:",
+        elapsed.as_secs(),
+        rss_kib().map_or("n/a".to_string(), |kib| format!("{} KiB", kib))
+    );
+    for ((name, _), s) in OPERATIONS.iter().zip(stats.iter_mut()) {
+        s.print(name);
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let (host, port) = match cli.server {
+        Some(ref server) => (server.clone(), cli.port),
+        None => {
+            let server = Server::bind("127.0.0.1:0", Bank::new(1000)).unwrap();
+            let port = server.local_addr().unwrap().port();
+            println!("simulator listening on port {}", port);
+            thread::spawn(move || server.serve());
+            ("127.0.0.1".to_string(), port)
+        }
+    };
+    let cfg = tcp::Config::builder()
+        .port(port)
+        .uid(cli.uid)
+        .timeout(Duration::from_millis(cli.timeout))
+        .build()
+        .expect("invalid configuration");
+
+    let mut client = tcp::Transport::new_with_cfg(&host, cfg).expect("connection failed");
+    let mut stats = vec![Stats::default(); OPERATIONS.len()];
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut i: u16 = 0;
+    while start.elapsed() < Duration::from_secs(cli.duration) {
+        let n = i as usize % OPERATIONS.len();
+        let (_, op) = OPERATIONS[n];
+        let t = Instant::now();
+        let res = op(&mut client, cli.addr, i);
+        stats[n].latencies.push(t.elapsed());
+        stats[n].calls += 1;
+        if let Err(e) = res {
+            stats[n].errors += 1;
+            eprintln!("{}: {}", OPERATIONS[n].0, e);
+            if let modbus::Error::Io(_)
+            | modbus::Error::ConnectionClosed
+            | modbus::Error::ConnectionLimit(_) = *e.root()
+            {
+                stats[n].reconnects += 1;
+                if let Err(e) = client.reconnect() {
+                    eprintln!("reconnect failed: {}", e);
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+        i = i.wrapping_add(1);
+        if last_report.elapsed() >= Duration::from_secs(cli.report) {
+            report(&mut stats, start.elapsed());
+            last_report = Instant::now();
+        }
+    }
+    report(&mut stats, start.elapsed());
+    if stats.iter().any(|s| s.errors > 0) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,295 @@
+//! Addresses typed by the table they refer to
+//!
+//! Holding and input registers, and coils and discrete inputs, share the same address range and
+//! are only told apart by the function reading them, so reading input registers with the holding
+//! register function goes unnoticed until the values look wrong. The addresses of this module
+//! carry their table, `Client::read_at` and `Client::write_at` pick the matching function and
+//! addresses of the read-only tables can't be written. The functions taking a raw `u16` address
+//! remain available.
+//!
+//! Device manuals often list the traditional 1-based reference numbers instead, e.g. `40001` for
+//! the holding register at address `0`. `DataAddress::from_reference` translates them, as mixing
+//! up the two conventions is a common cause of values read off by one.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[cfg(feature = "mock")] {
+//! use modbus::address::{CoilAddress, HoldingAddress, InputAddress};
+//! use modbus::mock::MockClient;
+//! use modbus::server::Bank;
+//! use modbus::{Client, Coil};
+//!
+//! let mut client = MockClient::new(Bank::new(10));
+//! client.bank.input_registers[2] = 42;
+//! client.write_at(HoldingAddress(2), &[7]).unwrap();
+//! assert_eq!(client.read_at(InputAddress(2), 1).unwrap(), vec![42]);
+//! assert_eq!(client.read_at(HoldingAddress(2), 1).unwrap(), vec![7]);
+//! client.write_at(CoilAddress(0), &[Coil::On]).unwrap();
+//! # }
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Client, Coil, Error, Reason, Result, Table};
+
+/// An address of one of the four tables.
+pub trait Address: Copy + From<u16> + Into<u16> {
+    /// The type of the items of the table
+    type Value;
+    /// The table the address refers to
+    const TABLE: Table;
+
+    /// Read `quantity` items starting at this address in a single request.
+    fn read<C: Client + ?Sized>(self, client: &mut C, quantity: u16) -> Result<Vec<Self::Value>>;
+}
+
+/// An address of one of the writable tables, coils and holding registers.
+pub trait WritableAddress: Address {
+    /// Write `values` starting at this address in a single request.
+    fn write<C: Client + ?Sized>(self, client: &mut C, values: &[Self::Value]) -> Result<()>;
+}
+
+macro_rules! address {
+    ($(#[$doc:meta])* $name:ident, $table:ident, $value:ty, $read:ident) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub u16);
+
+        impl From<u16> for $name {
+            fn from(addr: u16) -> $name {
+                $name(addr)
+            }
+        }
+
+        impl From<$name> for u16 {
+            fn from(addr: $name) -> u16 {
+                addr.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl Address for $name {
+            type Value = $value;
+            const TABLE: Table = Table::$table;
+
+            fn read<C>(self, client: &mut C, quantity: u16) -> Result<Vec<$value>>
+            where
+                C: Client + ?Sized,
+            {
+                client.$read(self.0, quantity)
+            }
+        }
+    };
+}
+
+address!(
+    /// The address of a coil
+    CoilAddress,
+    Coils,
+    Coil,
+    read_coils
+);
+address!(
+    /// The address of a discrete input
+    DiscreteAddress,
+    DiscreteInputs,
+    Coil,
+    read_discrete_inputs
+);
+address!(
+    /// The address of a holding register
+    HoldingAddress,
+    HoldingRegisters,
+    u16,
+    read_holding_registers
+);
+address!(
+    /// The address of an input register
+    InputAddress,
+    InputRegisters,
+    u16,
+    read_input_registers
+);
+
+impl WritableAddress for CoilAddress {
+    fn write<C: Client + ?Sized>(self, client: &mut C, values: &[Coil]) -> Result<()> {
+        client.write_multiple_coils(self.0, values)
+    }
+}
+
+impl WritableAddress for HoldingAddress {
+    fn write<C: Client + ?Sized>(self, client: &mut C, values: &[u16]) -> Result<()> {
+        client.write_multiple_registers(self.0, values)
+    }
+}
+
+/// An address of any of the tables, e.g. parsed from a reference number.
+///
+/// ```
+/// use modbus::address::{DataAddress, HoldingAddress};
+///
+/// let addr = DataAddress::from_reference("40001").unwrap();
+/// assert_eq!(addr, DataAddress::Holding(HoldingAddress(0)));
+/// assert_eq!(addr.to_reference(), "40001");
+/// // six digits for addresses above 9998
+/// assert_eq!(
+///     "300010".parse::<DataAddress>().unwrap().address(),
+///     DataAddress::from_reference("30010").unwrap().address()
+/// );
+/// assert!(DataAddress::from_reference("40000").is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DataAddress {
+    Coil(CoilAddress),
+    Discrete(DiscreteAddress),
+    Holding(HoldingAddress),
+    Input(InputAddress),
+}
+
+impl DataAddress {
+    /// Parse a 1-based reference number: the table digit (`0` coils, `1` discrete inputs, `3`
+    /// input registers, `4` holding registers) followed by four digits from `0001` to `9999`,
+    /// or five digits from `00001` to `65536` for the extended range.
+    pub fn from_reference(reference: &str) -> Result<DataAddress> {
+        let err = || {
+            Error::InvalidData(Reason::Custom(format!(
+                "invalid reference number '{}'",
+                reference
+            )))
+        };
+        if !(5..=6).contains(&reference.len()) || !reference.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(err());
+        }
+        let number: u32 = reference[1..].parse().map_err(|_| err())?;
+        if !(1..=0x10000).contains(&number) {
+            return Err(err());
+        }
+        let addr = (number - 1) as u16;
+        Ok(match &reference[..1] {
+            "0" => DataAddress::Coil(CoilAddress(addr)),
+            "1" => DataAddress::Discrete(DiscreteAddress(addr)),
+            "3" => DataAddress::Input(InputAddress(addr)),
+            "4" => DataAddress::Holding(HoldingAddress(addr)),
+            _ => return Err(err()),
+        })
+    }
+
+    /// The reference number, with six digits only for addresses above `9998`.
+    pub fn to_reference(&self) -> String {
+        let digit = match self.table() {
+            Table::Coils => 0,
+            Table::DiscreteInputs => 1,
+            Table::InputRegisters => 3,
+            Table::HoldingRegisters => 4,
+        };
+        let number = self.address() as u32 + 1;
+        if number > 9999 {
+            format!("{}{:05}", digit, number)
+        } else {
+            format!("{}{:04}", digit, number)
+        }
+    }
+
+    /// The table of the address.
+    pub fn table(&self) -> Table {
+        match *self {
+            DataAddress::Coil(_) => Table::Coils,
+            DataAddress::Discrete(_) => Table::DiscreteInputs,
+            DataAddress::Holding(_) => Table::HoldingRegisters,
+            DataAddress::Input(_) => Table::InputRegisters,
+        }
+    }
+
+    /// The 0-based protocol address.
+    pub fn address(&self) -> u16 {
+        match *self {
+            DataAddress::Coil(a) => a.0,
+            DataAddress::Discrete(a) => a.0,
+            DataAddress::Holding(a) => a.0,
+            DataAddress::Input(a) => a.0,
+        }
+    }
+}
+
+impl FromStr for DataAddress {
+    type Err = Error;
+
+    /// Parse a reference number, see `DataAddress::from_reference`.
+    fn from_str(s: &str) -> Result<DataAddress> {
+        DataAddress::from_reference(s)
+    }
+}
+
+impl fmt::Display for DataAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_reference())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references() {
+        let cases = [
+            ("00001", DataAddress::Coil(CoilAddress(0))),
+            ("10010", DataAddress::Discrete(DiscreteAddress(9))),
+            ("39999", DataAddress::Input(InputAddress(9998))),
+            ("410000", DataAddress::Holding(HoldingAddress(9999))),
+            ("465536", DataAddress::Holding(HoldingAddress(65535))),
+        ];
+        for (reference, addr) in cases {
+            assert_eq!(DataAddress::from_reference(reference).unwrap(), addr);
+            assert_eq!(addr.to_string(), reference);
+        }
+        assert_eq!(
+            DataAddress::from_reference("400001")
+                .unwrap()
+                .to_reference(),
+            "40001"
+        );
+        for invalid in [
+            "40000", "465537", "20001", "4001", "4000001", "4x001", "+4001",
+        ] {
+            match DataAddress::from_reference(invalid) {
+                Err(Error::InvalidData(Reason::Custom(_))) => (),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn typed_requests() {
+        use crate::mock::MockClient;
+        use crate::server::Bank;
+
+        let mut client = MockClient::new(Bank::new(10));
+        client.bank.discrete_inputs[1] = Coil::On;
+        client.write_at(CoilAddress(3), &[Coil::On]).unwrap();
+        client.write_at(HoldingAddress(4), &[5, 6]).unwrap();
+        assert_eq!(
+            client.read_at(DiscreteAddress(1), 1).unwrap(),
+            vec![Coil::On]
+        );
+        assert_eq!(client.read_at(CoilAddress(3), 1).unwrap(), vec![Coil::On]);
+        assert_eq!(client.read_at(HoldingAddress(4), 2).unwrap(), vec![5, 6]);
+        assert_eq!(client.read_at(InputAddress(4), 2).unwrap(), vec![0, 0]);
+        let functions: Vec<u8> = client.requests().iter().map(|r| r.pdu[0]).collect();
+        assert_eq!(functions, [0x0f, 0x10, 0x02, 0x01, 0x03, 0x04]);
+
+        // a trait object reads through the address
+        let dynamic: &mut dyn Client = &mut client;
+        assert_eq!(HoldingAddress::from(5).read(dynamic, 1).unwrap(), vec![6]);
+        assert_eq!(u16::from(InputAddress(7)), 7);
+        assert_eq!(HoldingAddress::TABLE, Table::HoldingRegisters);
+    }
+}
@@ -0,0 +1,464 @@
+//! Modbus ASCII transport
+//!
+//! Frames are sent as `:`-delimited hexadecimal text with an LRC checksum and a `CR LF`
+//! terminator. The transport works on top of any byte stream implementing `Read` and `Write`,
+//! usually a serial port handle. Response timeouts have to be configured on that stream.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use modbus::{ascii, Client};
+//! # use std::net::TcpStream;
+//!
+//! # let port = TcpStream::connect("127.0.0.1:5020").unwrap();
+//! // `port` can be any `Read + Write` stream, e.g. an opened serial port.
+//! let mut client = ascii::Transport::new_with_uid(port, 17);
+//! let values = client.read_holding_registers(0x6b, 3).unwrap();
+//! ```
+
+use std::io::{Read, Write};
+
+use crate::pdu::{self, Function, MAX_PDU_SIZE};
+use crate::{
+    binary, Client, Coil, DiagnosticsSubFunction, Error, ErrorContext, FileRecord, Reason, Result,
+    SlaveId,
+};
+
+#[cfg(feature = "read-device-info")]
+use crate::mei;
+
+const FRAME_START: u8 = b':';
+const FRAME_END: &[u8] = b"\r\n";
+/// Maximum number of characters in an ASCII frame, including start and end delimiters.
+const MAX_FRAME_SIZE: usize = 513;
+/// Unit id used to address all devices on the bus, which never answer.
+const BROADCAST_UID: u8 = 0;
+
+/// Context object which holds state for all modbus operations.
+pub struct Transport<S: Read + Write> {
+    uid: u8,
+    stream: S,
+    lenient_write_echo: bool,
+    // the request of the last failed transaction
+    context: Option<ErrorContext>,
+}
+
+impl<S: Read + Write> Transport<S> {
+    /// Create a new transport on `stream`, addressing unit id `1`.
+    pub fn new(stream: S) -> Transport<S> {
+        Self::new_with_uid(stream, 1)
+    }
+
+    /// Create a new transport on `stream`, addressing unit id `uid`.
+    pub fn new_with_uid(stream: S, uid: u8) -> Transport<S> {
+        Transport {
+            uid,
+            stream,
+            lenient_write_echo: false,
+            context: None,
+        }
+    }
+
+    /// The last failed request, or `None` if the last request succeeded.
+    pub fn last_error_context(&self) -> Option<ErrorContext> {
+        self.context
+    }
+
+    /// Accept responses to multiple writes which echo a wrong quantity, for devices which apply
+    /// the write nevertheless.
+    pub fn set_lenient_write_echo(&mut self, lenient: bool) {
+        self.lenient_write_echo = lenient;
+    }
+
+    /// Mutable access to the underlying stream, e.g. to change its timeouts.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Consume the transport, returning the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    fn send(&mut self, pdu: &[u8]) -> Result<()> {
+        if pdu.is_empty() {
+            return Err(Error::InvalidData(Reason::SendBufferEmpty));
+        }
+        if pdu.len() > MAX_PDU_SIZE {
+            return Err(Error::InvalidData(Reason::SendBufferTooBig));
+        }
+        let mut frame = vec![self.uid];
+        frame.extend_from_slice(pdu);
+        frame.push(binary::lrc(&frame));
+
+        let mut buff = Vec::with_capacity(frame.len() * 2 + 3);
+        buff.push(FRAME_START);
+        for b in &frame {
+            buff.extend_from_slice(format!("{:02X}", b).as_bytes());
+        }
+        buff.extend_from_slice(FRAME_END);
+        self.stream.write_all(&buff)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    // Receive the next frame and return its PDU after checking checksum and unit id. Characters
+    // before the frame start are discarded.
+    fn receive(&mut self) -> Result<Vec<u8>> {
+        let mut byte = [0u8];
+        loop {
+            crate::read_exact(&mut self.stream, &mut byte)?;
+            if byte[0] == FRAME_START {
+                break;
+            }
+        }
+
+        let mut text = Vec::new();
+        loop {
+            crate::read_exact(&mut self.stream, &mut byte)?;
+            match byte[0] {
+                // a new frame start aborts the current frame
+                FRAME_START => text.clear(),
+                b'\n' => break,
+                b => text.push(b),
+            }
+            if text.len() > MAX_FRAME_SIZE {
+                return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+            }
+        }
+        if text.pop() != Some(b'\r') || text.len() % 2 != 0 || text.len() < 6 {
+            return Err(Error::InvalidData(Reason::DecodingError));
+        }
+
+        let mut frame = text
+            .chunks(2)
+            .map(|c| {
+                std::str::from_utf8(c)
+                    .ok()
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+                    .ok_or(Error::InvalidData(Reason::DecodingError))
+            })
+            .collect::<Result<Vec<u8>>>()?;
+        let lrc = frame.pop().unwrap();
+        if binary::lrc(&frame) != lrc {
+            return Err(Error::InvalidData(Reason::InvalidChecksum));
+        }
+        if frame[0] != self.uid {
+            return Err(Error::InvalidResponse);
+        }
+        Ok(frame.split_off(1))
+    }
+
+    // Send the request `req` and return the response PDU after checking its function code.
+    // Broadcast requests are not answered, an empty response is returned for them. The request
+    // is kept as the context of an error.
+    fn transact(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        let result = self.exchange(req);
+        self.context = match result {
+            Ok(_) => None,
+            Err(_) => Some(ErrorContext::new(req, self.uid, None)),
+        };
+        result
+    }
+
+    fn exchange(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        self.send(req)?;
+        if self.uid == BROADCAST_UID {
+            return Ok(vec![]);
+        }
+        let resp = self.receive()?;
+        pdu::check_response(req, &resp)?;
+        Ok(resp)
+    }
+
+    // Send a request which is only valid with a response, so it can't be broadcast.
+    fn query(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        if self.uid == BROADCAST_UID {
+            return Err(Error::InvalidFunction);
+        }
+        self.transact(req)
+    }
+
+    // Send the write request `req` and check the echo in the response, if any.
+    fn write(&mut self, req: &[u8]) -> Result<()> {
+        let resp = self.transact(req)?;
+        if resp.is_empty() {
+            return Ok(());
+        }
+        pdu::check_write_echo(req, &resp, self.lenient_write_echo)
+    }
+}
+
+impl<S: Read + Write> Client for Transport<S> {
+    /// Read `count` bits starting at address `addr`.
+    fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+        let req = Function::ReadCoils(addr, count).request()?;
+        pdu::parse_bits(&self.query(&req)?, count)
+    }
+
+    /// Read `count` input bits starting at address `addr`.
+    fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+        let req = Function::ReadDiscreteInputs(addr, count).request()?;
+        pdu::parse_bits(&self.query(&req)?, count)
+    }
+
+    /// Read `count` 16bit registers starting at address `addr`.
+    fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadHoldingRegisters(addr, count).request()?;
+        pdu::parse_registers(&self.query(&req)?, count)
+    }
+
+    /// Read `count` 16bit input registers starting at address `addr`.
+    fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadInputRegisters(addr, count).request()?;
+        pdu::parse_registers(&self.query(&req)?, count)
+    }
+
+    /// Write a single coil (bit) to address `addr`.
+    fn write_single_coil(&mut self, addr: u16, value: Coil) -> Result<()> {
+        self.write(&Function::WriteSingleCoil(addr, value.code()).request()?)
+    }
+
+    /// Write a single 16bit register to address `addr`.
+    fn write_single_register(&mut self, addr: u16, value: u16) -> Result<()> {
+        self.write(&Function::WriteSingleRegister(addr, value).request()?)
+    }
+
+    /// Write a multiple coils (bits) starting at address `addr`.
+    fn write_multiple_coils(&mut self, addr: u16, values: &[Coil]) -> Result<()> {
+        let bytes = binary::pack_bits(values);
+        self.write(&Function::WriteMultipleCoils(addr, values.len() as u16, &bytes).request()?)
+    }
+
+    /// Write a multiple 16bit registers starting at address `addr`.
+    fn write_multiple_registers(&mut self, addr: u16, values: &[u16]) -> Result<()> {
+        let bytes = binary::unpack_bytes(values);
+        self.write(&Function::WriteMultipleRegisters(addr, values.len() as u16, &bytes).request()?)
+    }
+
+    /// Read the eight exception status outputs.
+    fn read_exception_status(&mut self) -> Result<u8> {
+        pdu::parse_exception_status(&self.query(&Function::ReadExceptionStatus.request()?)?)
+    }
+
+    /// Run the diagnostics sub-function `sub` with `data`.
+    fn diagnostics(&mut self, sub: DiagnosticsSubFunction, data: &[u16]) -> Result<Vec<u16>> {
+        if self.uid == BROADCAST_UID {
+            return Err(Error::InvalidFunction);
+        }
+        let req = Function::Diagnostics(sub, data).request()?;
+        if sub == DiagnosticsSubFunction::ForceListenOnlyMode {
+            self.send(&req)?;
+            return Ok(vec![]);
+        }
+        pdu::parse_diagnostics(sub, &self.transact(&req)?)
+    }
+
+    /// Read the identification of the device.
+    fn report_slave_id(&mut self) -> Result<SlaveId> {
+        pdu::parse_slave_id(&self.query(&Function::ReportSlaveId.request()?)?)
+    }
+
+    /// Modify the holding register at `addr` with `and_mask` and `or_mask`.
+    fn mask_write_register(&mut self, addr: u16, and_mask: u16, or_mask: u16) -> Result<()> {
+        self.write(&Function::MaskWriteRegister(addr, and_mask, or_mask).request()?)
+    }
+
+    /// Write a multiple 16bit registers starting at address `write_addr` and read starting at address `read_addr`.
+    fn write_read_multiple_registers(
+        &mut self,
+        write_address: u16,
+        write_quantity: u16,
+        write_values: &[u16],
+        read_address: u16,
+        read_quantity: u16,
+    ) -> Result<Vec<u16>> {
+        let write_bytes = binary::unpack_bytes(write_values);
+        let req = Function::WriteReadMultipleRegisters(
+            write_address,
+            write_quantity,
+            &write_bytes,
+            read_address,
+            read_quantity,
+        )
+        .request()?;
+        pdu::parse_registers(&self.query(&req)?, read_quantity)
+    }
+
+    /// Read the values of the FIFO queue at `addr`.
+    fn read_fifo_queue(&mut self, addr: u16) -> Result<Vec<u16>> {
+        pdu::parse_fifo_queue(&self.query(&Function::ReadFifoQueue(addr).request()?)?)
+    }
+
+    /// Read the file records given as `(file_number, record_number, record_length)`.
+    fn read_file_records(&mut self, records: &[(u16, u16, u16)]) -> Result<Vec<FileRecord>> {
+        let req = Function::ReadFileRecord(records).request()?;
+        pdu::parse_file_records(records, &self.query(&req)?)
+    }
+
+    /// Write the file records `records`.
+    fn write_file_records(&mut self, records: &[FileRecord]) -> Result<()> {
+        self.write(&Function::WriteFileRecord(records).request()?)
+    }
+
+    /// Set the unit identifier, `0` broadcasts write requests to all devices.
+    fn set_uid(&mut self, uid: u8) {
+        self.uid = uid;
+    }
+
+    #[cfg(feature = "read-device-info")]
+    /// Read the device identification objects of `obj_category` (function 43 / MEI type 14).
+    fn read_device_info(
+        &mut self,
+        obj_category: mei::DeviceInfoCategory,
+    ) -> Result<Vec<mei::DeviceInfoObject>> {
+        let req = Function::ReadDeviceInfo(obj_category).request()?;
+        pdu::parse_device_info(&self.query(&req)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExceptionCode;
+    use std::io::{self, Cursor};
+
+    /// In-memory stream replaying `input` and recording everything written.
+    struct Mock {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn transport(uid: u8, input: &str) -> Transport<Mock> {
+        Transport::new_with_uid(
+            Mock {
+                input: Cursor::new(input.as_bytes().to_vec()),
+                output: vec![],
+            },
+            uid,
+        )
+    }
+
+    fn output(t: Transport<Mock>) -> String {
+        String::from_utf8(t.into_inner().output).unwrap()
+    }
+
+    #[test]
+    fn read_holding_registers() {
+        let mut t = transport(0x11, ":110306022B0000006455\r\n");
+        assert_eq!(
+            t.read_holding_registers(0x6b, 3).unwrap(),
+            vec![0x022b, 0x0000, 0x0064]
+        );
+        assert_eq!(output(t), ":1103006B00037E\r\n");
+    }
+
+    #[test]
+    fn write_single_coil() {
+        let mut t = transport(0x11, "noise:110500ACFF003F\r\n");
+        assert!(t.write_single_coil(0xac, Coil::On).is_ok());
+        assert_eq!(output(t), ":110500ACFF003F\r\n");
+    }
+
+    #[test]
+    fn write_multiple_registers() {
+        let mut t = transport(0x11, ":111000010002DC\r\n");
+        assert!(t.write_multiple_registers(1, &[0x000a, 0x0102]).is_ok());
+        assert_eq!(output(t), ":11100001000204000A0102CB\r\n");
+    }
+
+    #[test]
+    fn write_echo_with_wrong_quantity() {
+        let response = ":111000010003DB\r\n";
+        match transport(0x11, response).write_multiple_registers(1, &[0x000a, 0x0102]) {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut t = transport(0x11, response);
+        t.set_lenient_write_echo(true);
+        assert!(t.write_multiple_registers(1, &[0x000a, 0x0102]).is_ok());
+    }
+
+    #[test]
+    fn broadcast_is_not_answered() {
+        let mut t = transport(0, "");
+        assert!(t.write_single_register(1, 3).is_ok());
+        assert!(t.read_holding_registers(1, 1).is_err());
+        assert_eq!(output(t), ":000600010003F6\r\n");
+    }
+
+    #[test]
+    fn invalid_responses() {
+        match transport(0x11, ":1183026A\r\n")
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport(0x11, ":1183026B\r\n")
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::InvalidChecksum)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport(0x11, ":1183026G\r\n")
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::DecodingError)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport(0x12, ":1183026A\r\n")
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport(0x11, ":1183026A")
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::ConnectionClosed) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut t = transport(0x11, ":1183026A\r\n:110302002AC0\r\n");
+        match t.read_holding_registers(7, 1) {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            t.last_error_context(),
+            Some(ErrorContext {
+                function: 0x03,
+                address: Some(7),
+                uid: 0x11,
+                transaction_id: None,
+            })
+        );
+        assert_eq!(t.read_holding_registers(7, 1).unwrap(), vec![42]);
+        assert_eq!(t.last_error_context(), None);
+    }
+}
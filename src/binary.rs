@@ -1,6 +1,7 @@
+use crate::{Coil, Error, Reason, Result};
 use byteorder::{BigEndian, ReadBytesExt};
 use std::io::Cursor;
-use crate::{Coil, Error, Reason, Result};
+use std::str::FromStr;
 
 pub fn unpack_bits(bytes: &[u8], count: u16) -> Vec<Coil> {
     let mut res = Vec::with_capacity(count as usize);
@@ -16,7 +17,7 @@ pub fn unpack_bits(bytes: &[u8], count: u16) -> Vec<Coil> {
 
 pub fn pack_bits(bits: &[Coil]) -> Vec<u8> {
     let bitcount = bits.len();
-    let packed_size = bitcount / 8 + if bitcount % 8 > 0 { 1 } else { 0 };
+    let packed_size = bitcount.div_ceil(8);
     let mut res = vec![0; packed_size];
     for (i, b) in bits.iter().enumerate() {
         let v = match *b {
@@ -38,6 +39,8 @@ pub fn unpack_bytes(data: &[u16]) -> Vec<u8> {
     res
 }
 
+// `usize::is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
 pub fn pack_bytes(bytes: &[u8]) -> Result<Vec<u16>> {
     let size = bytes.len();
     // check if we can create u16s from bytes by packing two u8s together without rest
@@ -53,6 +56,440 @@ pub fn pack_bytes(bytes: &[u8]) -> Result<Vec<u16>> {
     Ok(res)
 }
 
+/// Longitudinal redundancy check of Modbus ASCII frames: the two's complement of the sum of all
+/// bytes of the frame, without the start and end characters.
+///
+/// ```
+/// use modbus::binary::lrc;
+///
+/// assert_eq!(lrc(&[0x11, 0x03, 0x00, 0x6b, 0x00, 0x03]), 0x7e);
+/// ```
+pub fn lrc(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |acc, b| acc.wrapping_add(*b))
+        .wrapping_neg()
+}
+
+// The CRC of every byte value, for the reflected polynomial 0xa001.
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Cyclic redundancy check of Modbus RTU frames over the unit id and the PDU, which is
+/// appended to the frame with its low byte first.
+///
+/// ```
+/// use modbus::binary::crc16;
+///
+/// let crc = crc16(&[0x11, 0x03, 0x00, 0x6b, 0x00, 0x03]);
+/// assert_eq!(crc.to_le_bytes(), [0x76, 0x87]);
+/// ```
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, &b| {
+        (crc >> 8) ^ CRC16_TABLE[((crc ^ b as u16) & 0xff) as usize]
+    })
+}
+
+/// Order of the bytes within a register or of the registers within a value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endian {
+    /// The most significant byte or register comes first
+    Big,
+    /// The least significant byte or register comes first
+    Little,
+}
+
+// Convert between big endian bytes and the register bytes of a value in the given byte and word
+// order. Swapping the bytes of each register and reversing the registers commute and are their
+// own inverse, so the same conversion is used in both directions.
+pub(crate) fn reorder(bytes: &mut [u8], byte_order: Endian, word_order: Endian) {
+    if bytes.len() < 2 {
+        return;
+    }
+    if byte_order == Endian::Little {
+        bytes.chunks_exact_mut(2).for_each(|w| w.swap(0, 1));
+    }
+    if word_order == Endian::Little {
+        let words = bytes.len() / 2;
+        for i in 0..words / 2 {
+            let j = words - 1 - i;
+            bytes.swap(2 * i, 2 * j);
+            bytes.swap(2 * i + 1, 2 * j + 1);
+        }
+    }
+}
+
+/// Decode typed values from consecutive registers, like the registers returned by
+/// `read_holding_registers`.
+///
+/// Every value is read from the registers following the previous one. The byte order applies to
+/// the two bytes of each register, the word order to the registers of values wider than 16 bits.
+///
+/// # Examples
+///
+/// ```
+/// use modbus::binary::{Decoder, Endian};
+///
+/// let registers = [0x5678, 0x1234, 0x0fdb, 0x4049];
+/// let mut decoder = Decoder::new(&registers, Endian::Big, Endian::Little);
+/// assert_eq!(decoder.read_u32().unwrap(), 0x1234_5678);
+/// assert_eq!(decoder.read_f32().unwrap(), 3.1415927);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    bytes: Vec<u8>,
+    pos: usize,
+    byte_order: Endian,
+    word_order: Endian,
+}
+
+impl Decoder {
+    pub fn new(registers: &[u16], byte_order: Endian, word_order: Endian) -> Decoder {
+        Decoder {
+            bytes: unpack_bytes(registers),
+            pos: 0,
+            byte_order,
+            word_order,
+        }
+    }
+
+    /// The number of bytes which are not decoded yet.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    /// Skip `count` bytes, e.g. unused registers between two values.
+    pub fn skip(&mut self, count: usize) -> Result<()> {
+        self.take_slice(count).map(|_| ())
+    }
+
+    fn take_slice(&mut self, count: usize) -> Result<&[u8]> {
+        if count > self.remaining() {
+            return Err(Error::InvalidData(Reason::DecodingError));
+        }
+        self.pos += count;
+        Ok(&self.bytes[self.pos - count..self.pos])
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let (byte_order, word_order) = (self.byte_order, self.word_order);
+        let mut bytes: [u8; N] = self.take_slice(N)?.try_into().unwrap();
+        reorder(&mut bytes, byte_order, word_order);
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        self.take().map(u8::from_be_bytes)
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8> {
+        self.take().map(i8::from_be_bytes)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16> {
+        self.take().map(i16::from_be_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        self.take().map(u32::from_be_bytes)
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32> {
+        self.take().map(i32::from_be_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        self.take().map(u64::from_be_bytes)
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64> {
+        self.take().map(i64::from_be_bytes)
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32> {
+        self.take().map(f32::from_be_bytes)
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64> {
+        self.take().map(f64::from_be_bytes)
+    }
+
+    /// Read a register as an enum, e.g. the state of a device, via its `TryFrom<u16>`
+    /// implementation. Values without a variant fail with `DecodingError`.
+    pub fn read_enum<T: TryFrom<u16>>(&mut self) -> Result<T> {
+        let value = self.read_u16()?;
+        T::try_from(value).map_err(|_| Error::InvalidData(Reason::DecodingError))
+    }
+
+    /// Read a register as an enum like `read_enum`, values without a variant are passed to
+    /// `unknown`, e.g. to map them to an `Unknown(u16)` variant.
+    pub fn read_enum_or_else<T, F>(&mut self, unknown: F) -> Result<T>
+    where
+        T: TryFrom<u16>,
+        F: FnOnce(u16) -> T,
+    {
+        let value = self.read_u16()?;
+        Ok(T::try_from(value).unwrap_or_else(|_| unknown(value)))
+    }
+
+    /// Read an UTF-8 string of `len` bytes, trailing NUL bytes used as padding are removed. The
+    /// bytes of each register are swapped with the little endian byte order.
+    pub fn read_string(&mut self, len: usize) -> Result<String> {
+        let byte_order = self.byte_order;
+        let mut bytes = self.take_slice(len)?.to_vec();
+        reorder(&mut bytes, byte_order, Endian::Big);
+        while bytes.last() == Some(&0) {
+            bytes.pop();
+        }
+        String::from_utf8(bytes).map_err(|_| Error::InvalidData(Reason::DecodingError))
+    }
+}
+
+/// Encode typed values into consecutive registers, e.g. for `write_multiple_registers`.
+///
+/// The inverse of `Decoder`, the byte and word order have the same meaning.
+///
+/// # Examples
+///
+/// ```
+/// use modbus::binary::{Encoder, Endian};
+///
+/// let mut encoder = Encoder::new(Endian::Big, Endian::Little);
+/// encoder.add_u32(0x1234_5678).add_i16(-2);
+/// assert_eq!(encoder.into_registers(), vec![0x5678, 0x1234, 0xfffe]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+    byte_order: Endian,
+    word_order: Endian,
+}
+
+impl Encoder {
+    pub fn new(byte_order: Endian, word_order: Endian) -> Encoder {
+        Encoder {
+            bytes: vec![],
+            byte_order,
+            word_order,
+        }
+    }
+
+    fn put<const N: usize>(&mut self, mut bytes: [u8; N]) -> &mut Encoder {
+        reorder(&mut bytes, self.byte_order, self.word_order);
+        self.bytes.extend_from_slice(&bytes);
+        self
+    }
+
+    pub fn add_u8(&mut self, value: u8) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_i8(&mut self, value: i8) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_u16(&mut self, value: u16) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_i16(&mut self, value: i16) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_u32(&mut self, value: u32) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_i32(&mut self, value: i32) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_u64(&mut self, value: u64) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_i64(&mut self, value: i64) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_f32(&mut self, value: f32) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    pub fn add_f64(&mut self, value: f64) -> &mut Encoder {
+        self.put(value.to_be_bytes())
+    }
+
+    /// Add an enum as a single register, the inverse of `Decoder::read_enum`.
+    pub fn add_enum<T: Into<u16>>(&mut self, value: T) -> &mut Encoder {
+        self.add_u16(value.into())
+    }
+
+    /// Add the UTF-8 bytes of `value`, swapped per register with the little endian byte order.
+    pub fn add_string(&mut self, value: &str) -> &mut Encoder {
+        let mut bytes = value.as_bytes().to_vec();
+        if self.byte_order == Endian::Little && bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        reorder(&mut bytes, self.byte_order, Endian::Big);
+        self.bytes.extend_from_slice(&bytes);
+        self
+    }
+
+    /// The registers of the encoded values, an odd number of bytes is padded with a zero byte.
+    pub fn into_registers(mut self) -> Vec<u16> {
+        if self.bytes.len() % 2 == 1 {
+            self.bytes.push(0);
+        }
+        pack_bytes(&self.bytes).unwrap()
+    }
+}
+
+/// Order of the four registers of a 64 bit value, named by the registers in the order they are
+/// transmitted, where `a` is the most significant register.
+///
+/// Together with the byte order within the registers this covers the eight layouts used by
+/// devices. `Abcd` is big endian, `Dcba` is little endian word order and `Badc` and `Cdab` swap
+/// the registers within or between the two 32 bit halves.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegisterOrder {
+    Abcd,
+    Badc,
+    Cdab,
+    Dcba,
+}
+
+impl RegisterOrder {
+    pub const ALL: [RegisterOrder; 4] = [
+        RegisterOrder::Abcd,
+        RegisterOrder::Badc,
+        RegisterOrder::Cdab,
+        RegisterOrder::Dcba,
+    ];
+
+    // The significance of the register at each position, 0 being the most significant.
+    fn positions(self) -> [usize; 4] {
+        match self {
+            RegisterOrder::Abcd => [0, 1, 2, 3],
+            RegisterOrder::Badc => [1, 0, 3, 2],
+            RegisterOrder::Cdab => [2, 3, 0, 1],
+            RegisterOrder::Dcba => [3, 2, 1, 0],
+        }
+    }
+}
+
+impl FromStr for RegisterOrder {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<RegisterOrder> {
+        match s.to_ascii_lowercase().as_str() {
+            "abcd" => Ok(RegisterOrder::Abcd),
+            "badc" => Ok(RegisterOrder::Badc),
+            "cdab" => Ok(RegisterOrder::Cdab),
+            "dcba" => Ok(RegisterOrder::Dcba),
+            _ => Err(Error::InvalidData(Reason::Custom(format!(
+                "invalid register order '{}'",
+                s
+            )))),
+        }
+    }
+}
+
+/// Decode a 64 bit value from four registers in the given register and byte order.
+pub fn decode_u64(registers: [u16; 4], order: RegisterOrder, byte_order: Endian) -> u64 {
+    order
+        .positions()
+        .iter()
+        .zip(registers)
+        .fold(0, |value, (&significance, reg)| {
+            let reg = match byte_order {
+                Endian::Big => reg,
+                Endian::Little => reg.swap_bytes(),
+            };
+            value | (reg as u64) << (16 * (3 - significance))
+        })
+}
+
+/// Encode a 64 bit value into four registers in the given register and byte order.
+pub fn encode_u64(value: u64, order: RegisterOrder, byte_order: Endian) -> [u16; 4] {
+    order.positions().map(|significance| {
+        let reg = (value >> (16 * (3 - significance))) as u16;
+        match byte_order {
+            Endian::Big => reg,
+            Endian::Little => reg.swap_bytes(),
+        }
+    })
+}
+
+/// Find the layout of a 64 bit counter from `samples` read one after the other while the counter
+/// was increasing.
+///
+/// The layout which decodes the samples to a non-decreasing sequence with the smallest
+/// increase is returned. `None` is returned if no layout or more than one layout fits, e.g.
+/// because the counter did not change between the samples.
+pub fn calibrate_u64(samples: &[[u16; 4]]) -> Option<(RegisterOrder, Endian)> {
+    let mut candidates: Vec<(u64, RegisterOrder, Endian)> = RegisterOrder::ALL
+        .iter()
+        .flat_map(|&order| [(order, Endian::Big), (order, Endian::Little)])
+        .filter_map(|(order, byte_order)| {
+            let values: Vec<u64> = samples
+                .iter()
+                .map(|s| decode_u64(*s, order, byte_order))
+                .collect();
+            if values.windows(2).all(|w| w[0] <= w[1]) {
+                Some((values.last()? - values.first()?, order, byte_order))
+            } else {
+                None
+            }
+        })
+        .collect();
+    candidates.sort_by_key(|c| c.0);
+    match candidates[..] {
+        [(best, order, byte_order), ..] if candidates.len() == 1 || candidates[1].0 > best => {
+            Some((order, byte_order))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(crc16(&[]), 0xffff);
+    // the check value of CRC-16/MODBUS
+    assert_eq!(crc16(b"123456789"), 0x4b37);
+    let frame = [0x11, 0x03, 0x06, 0xae, 0x41, 0x56, 0x52, 0x43, 0x40];
+    assert_eq!(crc16(&frame), 0xad49);
+    assert_eq!(lrc(&[]), 0);
+    assert_eq!(lrc(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a]), 0xf2);
+    // the sum of the bytes and their LRC is zero
+    assert_eq!(
+        frame
+            .iter()
+            .fold(lrc(&frame), |acc, b| acc.wrapping_add(*b)),
+        0
+    );
+}
+
 #[test]
 fn test_unpack_bits() {
     // assert_eq!(unpack_bits(, 0), &[]);
@@ -100,3 +537,160 @@ fn test_pack_bytes() {
     assert!(pack_bytes(&[1]).is_err());
     assert!(pack_bytes(&[1, 2, 3]).is_err());
 }
+
+#[test]
+fn test_decoder() {
+    let registers = [
+        0x1234, 0x5678, 0x3412, 0x7856, 0x5678, 0x1234, 0x7856, 0x3412,
+    ];
+    let mut decoder = Decoder::new(&registers, Endian::Big, Endian::Big);
+    assert_eq!(decoder.read_u32().unwrap(), 0x1234_5678);
+    let mut decoder = Decoder::new(&registers[2..], Endian::Little, Endian::Big);
+    assert_eq!(decoder.read_u32().unwrap(), 0x1234_5678);
+    let mut decoder = Decoder::new(&registers[4..], Endian::Big, Endian::Little);
+    assert_eq!(decoder.read_u32().unwrap(), 0x1234_5678);
+    let mut decoder = Decoder::new(&registers[6..], Endian::Little, Endian::Little);
+    assert_eq!(decoder.read_u32().unwrap(), 0x1234_5678);
+
+    let registers = [0xfffe, 0x0102, 0x0304, 0x0506, 0x0708, 0x4142, 0x4300];
+    let mut decoder = Decoder::new(&registers, Endian::Big, Endian::Little);
+    assert_eq!(decoder.read_i16().unwrap(), -2);
+    assert_eq!(decoder.read_u64().unwrap(), 0x0708_0506_0304_0102);
+    assert_eq!(decoder.read_u8().unwrap(), 0x41);
+    assert_eq!(decoder.read_i8().unwrap(), 0x42);
+    assert_eq!(decoder.remaining(), 2);
+    assert!(decoder.read_u32().is_err());
+    assert_eq!(decoder.read_string(2).unwrap(), "C");
+    assert!(decoder.read_u8().is_err());
+
+    let registers = [0x4241, 0x0043];
+    let mut decoder = Decoder::new(&registers, Endian::Little, Endian::Big);
+    assert_eq!(decoder.read_string(4).unwrap(), "ABC");
+}
+
+#[test]
+fn test_enum() {
+    #[derive(Debug, PartialEq)]
+    enum State {
+        Stop,
+        Run,
+        Unknown(u16),
+    }
+
+    impl TryFrom<u16> for State {
+        type Error = ();
+        fn try_from(value: u16) -> std::result::Result<State, ()> {
+            match value {
+                0 => Ok(State::Stop),
+                1 => Ok(State::Run),
+                _ => Err(()),
+            }
+        }
+    }
+
+    impl From<State> for u16 {
+        fn from(state: State) -> u16 {
+            match state {
+                State::Stop => 0,
+                State::Run => 1,
+                State::Unknown(value) => value,
+            }
+        }
+    }
+
+    let mut encoder = Encoder::new(Endian::Little, Endian::Big);
+    encoder
+        .add_enum(State::Run)
+        .add_enum(State::Unknown(7))
+        .add_enum(State::Unknown(7));
+    let registers = encoder.into_registers();
+    assert_eq!(registers, vec![0x0100, 0x0700, 0x0700]);
+
+    let mut decoder = Decoder::new(&registers, Endian::Little, Endian::Big);
+    assert_eq!(decoder.read_enum::<State>().unwrap(), State::Run);
+    assert_eq!(
+        decoder.read_enum_or_else(State::Unknown).unwrap(),
+        State::Unknown(7)
+    );
+    match decoder.read_enum::<State>() {
+        Err(Error::InvalidData(Reason::DecodingError)) => (),
+        r => panic!("unexpected result {:?}", r),
+    }
+    assert!(decoder.read_enum::<State>().is_err());
+}
+
+#[test]
+fn test_encoder() {
+    for &(byte_order, word_order) in &[
+        (Endian::Big, Endian::Big),
+        (Endian::Big, Endian::Little),
+        (Endian::Little, Endian::Big),
+        (Endian::Little, Endian::Little),
+    ] {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder
+            .add_u16(0xbeef)
+            .add_i32(-123_456)
+            .add_f64(-1.5)
+            .add_string("AB")
+            .add_u64(u64::MAX - 1)
+            .add_f32(0.25)
+            .add_i64(i64::MIN)
+            .add_u32(7)
+            .add_i16(-7)
+            .add_u8(1)
+            .add_i8(-1);
+        let registers = encoder.into_registers();
+        assert_eq!(registers.len(), 22);
+
+        let mut decoder = Decoder::new(&registers, byte_order, word_order);
+        assert_eq!(decoder.read_u16().unwrap(), 0xbeef);
+        assert_eq!(decoder.read_i32().unwrap(), -123_456);
+        assert_eq!(decoder.read_f64().unwrap(), -1.5);
+        assert_eq!(decoder.read_string(2).unwrap(), "AB");
+        assert_eq!(decoder.read_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(decoder.read_f32().unwrap(), 0.25);
+        assert_eq!(decoder.read_i64().unwrap(), i64::MIN);
+        assert_eq!(decoder.read_u32().unwrap(), 7);
+        assert_eq!(decoder.read_i16().unwrap(), -7);
+        assert_eq!(decoder.read_u8().unwrap(), 1);
+        assert_eq!(decoder.read_i8().unwrap(), -1);
+    }
+
+    let mut encoder = Encoder::new(Endian::Little, Endian::Big);
+    encoder.add_string("ABC");
+    assert_eq!(encoder.into_registers(), vec![0x4241, 0x0043]);
+}
+
+#[test]
+fn test_u64_register_orders() {
+    let value = 0x0102_0304_0506_0708;
+    for (name, registers) in [
+        ("abcd", [0x0102, 0x0304, 0x0506, 0x0708]),
+        ("BADC", [0x0304, 0x0102, 0x0708, 0x0506]),
+        ("cdab", [0x0506, 0x0708, 0x0102, 0x0304]),
+        ("dcba", [0x0708, 0x0506, 0x0304, 0x0102]),
+    ] {
+        let order: RegisterOrder = name.parse().unwrap();
+        assert_eq!(decode_u64(registers, order, Endian::Big), value);
+        assert_eq!(encode_u64(value, order, Endian::Big), registers);
+        let swapped = registers.map(u16::swap_bytes);
+        assert_eq!(decode_u64(swapped, order, Endian::Little), value);
+        assert_eq!(encode_u64(value, order, Endian::Little), swapped);
+    }
+    assert!("abdc".parse::<RegisterOrder>().is_err());
+}
+
+#[test]
+fn test_calibrate_u64() {
+    for order in RegisterOrder::ALL {
+        for byte_order in [Endian::Big, Endian::Little] {
+            let samples: Vec<[u16; 4]> = [0x0001_fffe_fff0, 0x0001_ffff_0010, 0x0002_0000_0400]
+                .iter()
+                .map(|v| encode_u64(*v, order, byte_order))
+                .collect();
+            assert_eq!(calibrate_u64(&samples), Some((order, byte_order)));
+        }
+    }
+    assert_eq!(calibrate_u64(&[[0, 0, 0, 1], [0, 0, 0, 1]]), None);
+}
@@ -0,0 +1,387 @@
+//! Record the frames exchanged by a TCP transport into files for offline analysis
+//!
+//! A `Capture` is attached to a `tcp::Transport` as its frame observer and appends every sent
+//! and received frame to a file, optionally rotated when it grows too large. The frames are
+//! written raw, as hexdump lines or as pcapng file which Wireshark opens with its Modbus/TCP
+//! dissector.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use modbus::capture::{Capture, Format};
+//! use modbus::{Client, Transport};
+//!
+//! let capture = Capture::create("modbus.pcapng", Format::Pcapng)
+//!     .unwrap()
+//!     .rotate(10 << 20, 5);
+//! let mut client = Transport::new("192.168.0.10").unwrap();
+//! capture.attach(&mut client);
+//! client.read_holding_registers(0, 10).unwrap();
+//! ```
+
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::tcp::{Direction, FrameEvent, Transport};
+
+/// File format of a `Capture`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// The frames as they are, one after the other
+    Raw,
+    /// One line per frame with the time in seconds since the epoch, the direction (`>` for sent
+    /// and `<` for received frames) and the bytes in hex
+    Hexdump,
+    /// A pcapng file with the frames as TCP segments between `10.0.0.1` and port 502 of
+    /// `10.0.0.2`, the addresses of the actual connection are not recorded
+    Pcapng,
+}
+
+// The addresses and ports of the client and server in pcapng files.
+const CLIENT: ([u8; 4], u16) = ([10, 0, 0, 1], 49152);
+const SERVER: ([u8; 4], u16) = ([10, 0, 0, 2], 502);
+// Link type of raw IPv4 packets.
+const LINKTYPE_RAW: u16 = 101;
+
+/// A capture file which frames are recorded to, clones record to the same file.
+#[derive(Clone)]
+pub struct Capture {
+    writer: Arc<Mutex<Writer>>,
+}
+
+struct Writer {
+    path: PathBuf,
+    format: Format,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    max_files: usize,
+    // the next TCP sequence numbers of the client and the server
+    seq: [u32; 2],
+    error: Option<io::Error>,
+}
+
+impl Capture {
+    /// Capture to the file at `path` in `format`, an existing file is replaced.
+    pub fn create<P: AsRef<Path>>(path: P, format: Format) -> io::Result<Capture> {
+        let path = path.as_ref().to_path_buf();
+        let mut writer = Writer {
+            file: File::create(&path)?,
+            path,
+            format,
+            size: 0,
+            max_size: None,
+            max_files: 0,
+            seq: [1, 1],
+            error: None,
+        };
+        writer.write_header()?;
+        Ok(Capture {
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+
+    /// Start a new file when the current one exceeds `max_size` bytes. The previous files are
+    /// kept as `<path>.1` (the newest) to `<path>.<max_files>`, older ones are removed.
+    pub fn rotate(self, max_size: u64, max_files: usize) -> Capture {
+        {
+            let mut writer = self.lock();
+            writer.max_size = Some(max_size);
+            writer.max_files = max_files;
+        }
+        self
+    }
+
+    /// Record all frames sent or received by `transport`, replacing its frame observer.
+    pub fn attach(&self, transport: &mut Transport) {
+        let capture = self.clone();
+        transport.set_frame_observer(move |event| capture.record(event));
+    }
+
+    /// Record the frame of `event`. Errors are kept until they are taken with `take_error`,
+    /// the following frames are recorded nevertheless.
+    pub fn record(&self, event: &FrameEvent) {
+        let mut writer = self.lock();
+        if let Err(err) = writer.record(event) {
+            writer.error.get_or_insert(err);
+        }
+    }
+
+    /// The first error which occurred while recording since the last call.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.lock().error.take()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Writer> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Writer {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.format != Format::Pcapng {
+            return Ok(());
+        }
+        // section header block, version 1.0 with an unspecified section length
+        let mut shb = vec![];
+        shb.extend(0x1a2b_3c4d_u32.to_le_bytes());
+        shb.extend(1u16.to_le_bytes());
+        shb.extend(0u16.to_le_bytes());
+        shb.extend((-1i64).to_le_bytes());
+        self.write(&block(0x0a0d_0d0a, &shb))?;
+        // interface description block without snapshot length, timestamps in microseconds
+        let mut idb = vec![];
+        idb.extend(LINKTYPE_RAW.to_le_bytes());
+        idb.extend(0u16.to_le_bytes());
+        idb.extend(0u32.to_le_bytes());
+        self.write(&block(0x0000_0001, &idb))
+    }
+
+    fn record(&mut self, event: &FrameEvent) -> io::Result<()> {
+        if self.max_size.is_some_and(|max| self.size >= max) {
+            self.rotate()?;
+        }
+        let time = event.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        match self.format {
+            Format::Raw => self.write(event.frame),
+            Format::Hexdump => {
+                let mut line = format!(
+                    "{}.{:06} {}",
+                    time.as_secs(),
+                    time.subsec_micros(),
+                    match event.direction {
+                        Direction::Sent => '>',
+                        Direction::Received => '<',
+                    }
+                );
+                for b in event.frame {
+                    let _ = write!(line, " {:02x}", b);
+                }
+                line.push('\n');
+                self.write(line.as_bytes())
+            }
+            Format::Pcapng => {
+                let packet = self.packet(event.direction, event.frame);
+                self.write(&enhanced_packet_block(time, &packet))
+            }
+        }
+    }
+
+    // Move the current file to `<path>.1` after shifting the older files and start a new one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                match fs::rename(numbered(n), numbered(n + 1)) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                    r => r?,
+                }
+            }
+            fs::rename(&self.path, numbered(1))?;
+        }
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        self.write_header()
+    }
+
+    // Wrap `frame` into the IPv4 and TCP headers of a segment in `direction`.
+    fn packet(&mut self, direction: Direction, frame: &[u8]) -> Vec<u8> {
+        let (i, (src, src_port), (dst, dst_port)) = match direction {
+            Direction::Sent => (0, CLIENT, SERVER),
+            Direction::Received => (1, SERVER, CLIENT),
+        };
+        let seq = self.seq[i];
+        let ack = self.seq[1 - i];
+        self.seq[i] = seq.wrapping_add(frame.len() as u32);
+
+        let mut tcp = vec![];
+        tcp.extend(src_port.to_be_bytes());
+        tcp.extend(dst_port.to_be_bytes());
+        tcp.extend(seq.to_be_bytes());
+        tcp.extend(ack.to_be_bytes());
+        // header length of 5 words, PSH and ACK flags, window, checksum and urgent pointer
+        tcp.extend([0x50, 0x18, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(frame);
+        let mut pseudo = vec![];
+        pseudo.extend(src);
+        pseudo.extend(dst);
+        pseudo.extend([0, 6]);
+        pseudo.extend((tcp.len() as u16).to_be_bytes());
+        let sum = checksum(&[&pseudo, &tcp]);
+        tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+
+        let mut ip = vec![0x45, 0];
+        ip.extend((20 + tcp.len() as u16).to_be_bytes());
+        // identification, don't fragment, TTL, protocol TCP and the checksum
+        ip.extend([0, 0, 0x40, 0, 64, 6, 0, 0]);
+        ip.extend(src);
+        ip.extend(dst);
+        let sum = checksum(&[&ip]);
+        ip[10..12].copy_from_slice(&sum.to_be_bytes());
+        ip.extend(tcp);
+        ip
+    }
+}
+
+// A pcapng block of `block_type` with `body` padded to 32 bits.
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padding = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + padding) as u32;
+    let mut block = vec![];
+    block.extend(block_type.to_le_bytes());
+    block.extend(len.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(block.len() + padding, 0);
+    block.extend(len.to_le_bytes());
+    block
+}
+
+fn enhanced_packet_block(time: Duration, packet: &[u8]) -> Vec<u8> {
+    let micros = time.as_micros() as u64;
+    let mut body = vec![];
+    body.extend(0u32.to_le_bytes());
+    body.extend(((micros >> 32) as u32).to_le_bytes());
+    body.extend((micros as u32).to_le_bytes());
+    body.extend((packet.len() as u32).to_le_bytes());
+    body.extend((packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(packet);
+    block(0x0000_0006, &body)
+}
+
+// The internet checksum of the concatenated `parts`, each of an even length except the last.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for word in part.chunks(2) {
+            sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+    use std::time::SystemTime;
+
+    // A path in the temporary directory unique to this process and `name`.
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("modbus-capture-{}-{}", process::id(), name))
+    }
+
+    fn event(direction: Direction, frame: &[u8]) -> FrameEvent<'_> {
+        FrameEvent {
+            direction,
+            frame,
+            time: UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456),
+        }
+    }
+
+    const REQUEST: [u8; 12] = [0, 1, 0, 0, 0, 6, 1, 3, 0, 0x10, 0, 1];
+    const RESPONSE: [u8; 11] = [0, 1, 0, 0, 0, 5, 1, 3, 2, 0xbe, 0xef];
+
+    #[test]
+    fn hexdump_and_raw() {
+        let path = temp_path("hexdump");
+        let capture = Capture::create(&path, Format::Hexdump).unwrap();
+        capture.record(&event(Direction::Sent, &REQUEST));
+        capture.record(&event(Direction::Received, &RESPONSE));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1700000000.123456 > 00 01 00 00 00 06 01 03 00 10 00 01\n\
+             1700000000.123456 < 00 01 00 00 00 05 01 03 02 be ef\n"
+        );
+
+        let capture = Capture::create(&path, Format::Raw).unwrap();
+        capture.record(&event(Direction::Sent, &REQUEST));
+        capture.record(&FrameEvent {
+            time: SystemTime::now(),
+            ..event(Direction::Received, &RESPONSE)
+        });
+        assert_eq!(fs::read(&path).unwrap(), [&REQUEST[..], &RESPONSE].concat());
+        assert!(capture.take_error().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pcapng() {
+        let path = temp_path("pcapng");
+        let capture = Capture::create(&path, Format::Pcapng).unwrap();
+        capture.record(&event(Direction::Sent, &REQUEST));
+        capture.record(&event(Direction::Received, &RESPONSE));
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // walk the blocks, checking that both length fields agree
+        let mut blocks = vec![];
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let kind = u32::from_le_bytes(rest[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            assert_eq!(rest[len - 4..len], rest[4..8]);
+            blocks.push((kind, rest[8..len - 4].to_vec()));
+            rest = &rest[len..];
+        }
+        let kinds: Vec<u32> = blocks.iter().map(|b| b.0).collect();
+        assert_eq!(kinds, [0x0a0d0d0a, 1, 6, 6]);
+        assert_eq!(blocks[1].1[..2], LINKTYPE_RAW.to_le_bytes());
+
+        let epb = &blocks[2].1;
+        let micros = (u32::from_le_bytes(epb[4..8].try_into().unwrap()) as u64) << 32
+            | u32::from_le_bytes(epb[8..12].try_into().unwrap()) as u64;
+        assert_eq!(micros, 1_700_000_000_123_456);
+        let packet = &epb[20..20 + 52];
+        assert_eq!(epb[12..16], 52u32.to_le_bytes());
+        // IPv4 from 10.0.0.1 to 10.0.0.2 with a valid header checksum
+        assert_eq!(packet[..4], [0x45, 0, 0, 52]);
+        assert_eq!(packet[12..20], [10, 0, 0, 1, 10, 0, 0, 2]);
+        assert_eq!(checksum(&[&packet[..20]]), 0);
+        // TCP to port 502 with the frame as payload
+        assert_eq!(packet[22..24], 502u16.to_be_bytes());
+        assert_eq!(packet[40..], REQUEST);
+
+        // the response acknowledges the request
+        let packet = &blocks[3].1[20..];
+        assert_eq!(packet[20..22], 502u16.to_be_bytes());
+        assert_eq!(packet[24..28], 1u32.to_be_bytes());
+        assert_eq!(packet[28..32], 13u32.to_be_bytes());
+    }
+
+    #[test]
+    fn rotation() {
+        let path = temp_path("rotation");
+        let numbered = |n| PathBuf::from(format!("{}.{}", path.display(), n));
+        let capture = Capture::create(&path, Format::Raw).unwrap().rotate(20, 2);
+        for _ in 0..4 {
+            capture.record(&event(Direction::Sent, &REQUEST));
+            capture.record(&event(Direction::Received, &RESPONSE));
+        }
+        // every file holds a request and a response
+        for p in [&path, &numbered(1), &numbered(2)] {
+            assert_eq!(fs::read(p).unwrap().len(), 23);
+            fs::remove_file(p).unwrap();
+        }
+        assert!(!numbered(3).exists());
+    }
+}
@@ -1,22 +1,432 @@
-use crate::{Coil, Result};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+use crate::address::{Address, WritableAddress};
+use crate::binary::{self, Decoder, Encoder, Endian};
+use crate::pdu::{MAX_READ_BITS, MAX_READ_REGISTERS, MAX_WRITE_BITS, MAX_WRITE_REGISTERS};
+use crate::{
+    Coil, DiagnosticsSubFunction, Error, ExceptionCode, FileRecord, Reason, Result, SlaveId, Table,
+};
+
+#[cfg(feature = "read-device-info")]
+use crate::mei;
+
+// Split `count` items starting at `address` into `(address, count)` pairs of at most `max` items.
+fn chunks(address: u16, count: usize, max: u16) -> Result<Vec<(u16, u16)>> {
+    if address as usize + count > 0x10000 {
+        return Err(Error::InvalidData(Reason::Custom(format!(
+            "{} items starting at address {} exceed the address range",
+            count, address
+        ))));
+    }
+    let end = address as usize + count;
+    Ok((address as usize..end)
+        .step_by(max as usize)
+        .map(|a| (a as u16, (end - a).min(max as usize) as u16))
+        .collect())
+}
+
+/// A read request planned by `plan_reads`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadRequest {
+    pub table: Table,
+    pub address: u16,
+    pub quantity: u16,
+}
+
+/// Limits of the requests planned by `plan_reads`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadLimits {
+    /// Ranges up to `max_gap` unused items apart are read with the same request (Default: `0`)
+    pub max_gap: u16,
+    /// Maximum number of coils or discrete inputs per request (Default: `2000`)
+    pub max_bits: u16,
+    /// Maximum number of registers per request (Default: `125`)
+    pub max_registers: u16,
+}
+
+impl Default for ReadLimits {
+    fn default() -> ReadLimits {
+        ReadLimits {
+            max_gap: 0,
+            max_bits: MAX_READ_BITS,
+            max_registers: MAX_READ_REGISTERS,
+        }
+    }
+}
+
+/// Plan the requests reading the `ranges`, given as `(table, address, quantity)`, e.g. the
+/// items of a poll list, with as few requests as possible.
+///
+/// Overlapping and adjacent ranges of a table are merged, ranges up to `limits.max_gap` items
+/// apart too, reading the items in between. Ranges exceeding the maximum quantity of a request
+/// are split. The requests are returned ordered by table and address.
+///
+/// # Examples
+///
+/// ```
+/// use modbus::{plan_reads, ReadLimits, ReadRequest, Table};
+///
+/// let limits = ReadLimits {
+///     max_gap: 4,
+///     ..Default::default()
+/// };
+/// let ranges = [
+///     (Table::HoldingRegisters, 0, 2),
+///     (Table::HoldingRegisters, 5, 2),
+///     (Table::HoldingRegisters, 100, 1),
+/// ];
+/// assert_eq!(
+///     plan_reads(&ranges, limits),
+///     vec![
+///         ReadRequest {
+///             table: Table::HoldingRegisters,
+///             address: 0,
+///             quantity: 7,
+///         },
+///         ReadRequest {
+///             table: Table::HoldingRegisters,
+///             address: 100,
+///             quantity: 1,
+///         },
+///     ]
+/// );
+/// ```
+pub fn plan_reads(ranges: &[(Table, u16, u16)], limits: ReadLimits) -> Vec<ReadRequest> {
+    let mut requests = vec![];
+    for table in [
+        Table::Coils,
+        Table::DiscreteInputs,
+        Table::HoldingRegisters,
+        Table::InputRegisters,
+    ] {
+        let max = match table {
+            Table::Coils | Table::DiscreteInputs => limits.max_bits,
+            Table::HoldingRegisters | Table::InputRegisters => limits.max_registers,
+        };
+        let mut intervals: Vec<(u32, u32)> = ranges
+            .iter()
+            .filter(|&&(t, _, quantity)| t == table && quantity > 0)
+            .map(|&(_, address, quantity)| {
+                let end = (address as u32 + quantity as u32).min(0x10000);
+                (address as u32, end)
+            })
+            .collect();
+        intervals.sort_unstable();
+        let push = |requests: &mut Vec<ReadRequest>, (start, end): (u32, u32)| {
+            requests.push(ReadRequest {
+                table,
+                address: start as u16,
+                quantity: (end - start) as u16,
+            })
+        };
+        let (max, max_gap) = (max.max(1) as u32, limits.max_gap as u32);
+        let mut current: Option<(u32, u32)> = None;
+        for (mut start, end) in intervals {
+            while start < end {
+                match current {
+                    Some((first, last)) if start <= last + max_gap => {
+                        if end.max(last) - first <= max {
+                            current = Some((first, end.max(last)));
+                            start = end;
+                        } else if start < first + max {
+                            // fill the request up and continue with the rest of the range
+                            push(&mut requests, (first, first + max));
+                            start = first + max;
+                            current = None;
+                        } else {
+                            push(&mut requests, (first, last));
+                            current = None;
+                        }
+                    }
+                    _ => {
+                        if let Some(request) = current {
+                            push(&mut requests, request);
+                        }
+                        current = Some((start, end.min(start + max)));
+                        start = end.min(start + max);
+                        if start < end {
+                            push(&mut requests, current.take().unwrap());
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(request) = current {
+            push(&mut requests, request);
+        }
+    }
+    requests
+}
 
 pub trait Client {
     fn read_discrete_inputs(&mut self, address: u16, quantity: u16) -> Result<Vec<Coil>>;
 
     fn read_coils(&mut self, address: u16, quantity: u16) -> Result<Vec<Coil>>;
 
+    /// Read the coils in `range` and return them packed into bytes, the first coil of the range
+    /// in the least significant bit of the first byte.
+    ///
+    /// Ranges exceeding the limit of a single request are read with several requests. These are
+    /// split on multiples of 8 coils, so the bytes of the responses line up with the result.
+    fn read_coil_range(&mut self, range: Range<u16>) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(range.len().div_ceil(8));
+        for (addr, count) in chunks(range.start, range.len(), MAX_READ_BITS)? {
+            bytes.extend(binary::pack_bits(&self.read_coils(addr, count)?));
+        }
+        Ok(bytes)
+    }
+
     fn write_single_coil(&mut self, address: u16, value: Coil) -> Result<()>;
 
+    /// Write `coils` in a single request, more than 1968 coils are rejected.
     fn write_multiple_coils(&mut self, address: u16, coils: &[Coil]) -> Result<()>;
 
+    /// Write `coils`, split into several requests if they exceed the limit of a single request.
+    /// The write is not atomic, a failing request leaves the coils of the requests before it
+    /// written.
+    fn write_multiple_coils_chunked(&mut self, address: u16, coils: &[Coil]) -> Result<()> {
+        for (addr, count) in chunks(address, coils.len(), MAX_WRITE_BITS)? {
+            let offset = (addr - address) as usize;
+            self.write_multiple_coils(addr, &coils[offset..offset + count as usize])?;
+        }
+        Ok(())
+    }
+
     fn read_input_registers(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>>;
 
+    /// Read `quantity` holding registers in a single request, so multi-register values are read
+    /// atomically if the device updates its registers between requests only. Quantities above
+    /// the protocol limit of 125 registers are rejected.
     fn read_holding_registers(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>>;
 
+    /// Read `quantity` input registers, split into several requests if `quantity` exceeds the
+    /// limit of a single request. Values spanning two requests can be torn, see `read_stable`.
+    fn read_input_registers_chunked(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>> {
+        let mut values = Vec::with_capacity(quantity as usize);
+        for (addr, count) in chunks(address, quantity as usize, MAX_READ_REGISTERS)? {
+            values.extend(self.read_input_registers(addr, count)?);
+        }
+        Ok(values)
+    }
+
+    /// Read `quantity` holding registers, split into several requests if `quantity` exceeds the
+    /// limit of a single request. Values spanning two requests can be torn, see `read_stable`.
+    fn read_holding_registers_chunked(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>> {
+        let mut values = Vec::with_capacity(quantity as usize);
+        for (addr, count) in chunks(address, quantity as usize, MAX_READ_REGISTERS)? {
+            values.extend(self.read_holding_registers(addr, count)?);
+        }
+        Ok(values)
+    }
+
+    /// Read the holding registers at `addresses`, e.g. of a poll list, with as few requests as
+    /// possible and return their values by address.
+    ///
+    /// Adjacent addresses are read with one request of at most 125 registers. Addresses which
+    /// are up to `max_gap` registers apart are merged too, the registers in between are read but
+    /// not returned. Some devices answer reads of unmapped registers with an exception, use a
+    /// `max_gap` of `0` for them.
+    fn read_holding_registers_batch(
+        &mut self,
+        addresses: &[u16],
+        max_gap: u16,
+    ) -> Result<BTreeMap<u16, u16>> {
+        let mut values = BTreeMap::new();
+        let ranges: Vec<_> = addresses
+            .iter()
+            .map(|&addr| (Table::HoldingRegisters, addr, 1))
+            .collect();
+        let limits = ReadLimits {
+            max_gap,
+            ..Default::default()
+        };
+        for req in plan_reads(&ranges, limits) {
+            let registers = self.read_holding_registers(req.address, req.quantity)?;
+            values.extend((req.address..=u16::MAX).zip(registers));
+        }
+        values.retain(|addr, _| addresses.contains(addr));
+        Ok(values)
+    }
+
     fn write_single_register(&mut self, address: u16, value: u16) -> Result<()>;
 
     fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> Result<()>;
 
+    /// Write `values`, split into several requests if they exceed the limit of a single request.
+    /// The write is not atomic, a failing request leaves the registers of the requests before
+    /// it written.
+    fn write_multiple_registers_chunked(&mut self, address: u16, values: &[u16]) -> Result<()> {
+        for (addr, count) in chunks(address, values.len(), MAX_WRITE_REGISTERS)? {
+            let offset = (addr - address) as usize;
+            self.write_multiple_registers(addr, &values[offset..offset + count as usize])?;
+        }
+        Ok(())
+    }
+
+    /// Read `quantity` items of the table of `address` in a single request, see `address`.
+    fn read_at<A: Address>(&mut self, address: A, quantity: u16) -> Result<Vec<A::Value>>
+    where
+        Self: Sized,
+    {
+        address.read(self, quantity)
+    }
+
+    /// Write `values` to the table of `address` in a single request, see `address`.
+    fn write_at<A: WritableAddress>(&mut self, address: A, values: &[A::Value]) -> Result<()>
+    where
+        Self: Sized,
+    {
+        address.write(self, values)
+    }
+
+    /// Read a `u32` from the 2 holding registers starting at `address` in a single request.
+    /// `byte_order` is the order of the bytes within a register, `word_order` the order of the
+    /// registers, see `binary::Decoder`.
+    fn read_u32(&mut self, address: u16, byte_order: Endian, word_order: Endian) -> Result<u32> {
+        let registers = self.read_holding_registers(address, 2)?;
+        Decoder::new(&registers, byte_order, word_order).read_u32()
+    }
+
+    /// Read a `i32` from 2 holding registers, see `read_u32`.
+    fn read_i32(&mut self, address: u16, byte_order: Endian, word_order: Endian) -> Result<i32> {
+        let registers = self.read_holding_registers(address, 2)?;
+        Decoder::new(&registers, byte_order, word_order).read_i32()
+    }
+
+    /// Read a `u64` from 4 holding registers, see `read_u32`.
+    fn read_u64(&mut self, address: u16, byte_order: Endian, word_order: Endian) -> Result<u64> {
+        let registers = self.read_holding_registers(address, 4)?;
+        Decoder::new(&registers, byte_order, word_order).read_u64()
+    }
+
+    /// Read a `i64` from 4 holding registers, see `read_u32`.
+    fn read_i64(&mut self, address: u16, byte_order: Endian, word_order: Endian) -> Result<i64> {
+        let registers = self.read_holding_registers(address, 4)?;
+        Decoder::new(&registers, byte_order, word_order).read_i64()
+    }
+
+    /// Read a `f32` from 2 holding registers, see `read_u32`.
+    fn read_f32(&mut self, address: u16, byte_order: Endian, word_order: Endian) -> Result<f32> {
+        let registers = self.read_holding_registers(address, 2)?;
+        Decoder::new(&registers, byte_order, word_order).read_f32()
+    }
+
+    /// Read a `f64` from 4 holding registers, see `read_u32`.
+    fn read_f64(&mut self, address: u16, byte_order: Endian, word_order: Endian) -> Result<f64> {
+        let registers = self.read_holding_registers(address, 4)?;
+        Decoder::new(&registers, byte_order, word_order).read_f64()
+    }
+
+    /// Write `value` to the 2 holding registers starting at `address` in a single request, in
+    /// the same order as `read_u32`.
+    fn write_u32(
+        &mut self,
+        address: u16,
+        value: u32,
+        byte_order: Endian,
+        word_order: Endian,
+    ) -> Result<()> {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder.add_u32(value);
+        self.write_multiple_registers(address, &encoder.into_registers())
+    }
+
+    /// Write `value` to 2 holding registers, see `write_u32`.
+    fn write_i32(
+        &mut self,
+        address: u16,
+        value: i32,
+        byte_order: Endian,
+        word_order: Endian,
+    ) -> Result<()> {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder.add_i32(value);
+        self.write_multiple_registers(address, &encoder.into_registers())
+    }
+
+    /// Write `value` to 4 holding registers, see `write_u32`.
+    fn write_u64(
+        &mut self,
+        address: u16,
+        value: u64,
+        byte_order: Endian,
+        word_order: Endian,
+    ) -> Result<()> {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder.add_u64(value);
+        self.write_multiple_registers(address, &encoder.into_registers())
+    }
+
+    /// Write `value` to 4 holding registers, see `write_u32`.
+    fn write_i64(
+        &mut self,
+        address: u16,
+        value: i64,
+        byte_order: Endian,
+        word_order: Endian,
+    ) -> Result<()> {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder.add_i64(value);
+        self.write_multiple_registers(address, &encoder.into_registers())
+    }
+
+    /// Write `value` to 2 holding registers, see `write_u32`.
+    fn write_f32(
+        &mut self,
+        address: u16,
+        value: f32,
+        byte_order: Endian,
+        word_order: Endian,
+    ) -> Result<()> {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder.add_f32(value);
+        self.write_multiple_registers(address, &encoder.into_registers())
+    }
+
+    /// Write `value` to 4 holding registers, see `write_u32`.
+    fn write_f64(
+        &mut self,
+        address: u16,
+        value: f64,
+        byte_order: Endian,
+        word_order: Endian,
+    ) -> Result<()> {
+        let mut encoder = Encoder::new(byte_order, word_order);
+        encoder.add_f64(value);
+        self.write_multiple_registers(address, &encoder.into_registers())
+    }
+
+    /// Read the eight exception status outputs of the device (function 7), the first output is
+    /// the least significant bit. Fails with an `IllegalFunction` exception unless implemented.
+    fn read_exception_status(&mut self) -> Result<u8> {
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
+    /// Run the diagnostics sub-function `sub` with the data field `data` (function 8) and return
+    /// the data field of the response. `ForceListenOnlyMode` is not answered by the device and
+    /// returns no data. Fails with an `IllegalFunction` exception unless implemented.
+    fn diagnostics(&mut self, sub: DiagnosticsSubFunction, data: &[u16]) -> Result<Vec<u16>> {
+        let _ = (sub, data);
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
+    /// Read the identification, run status and device specific data of the device (function
+    /// 17). Fails with an `IllegalFunction` exception unless implemented.
+    fn report_slave_id(&mut self) -> Result<SlaveId> {
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
+    /// Modify the holding register at `address` to `(value & and_mask) | (or_mask & !and_mask)`
+    /// in a single request, e.g. to set or clear individual bits. Fails with an
+    /// `IllegalFunction` exception unless implemented.
+    fn mask_write_register(&mut self, address: u16, and_mask: u16, or_mask: u16) -> Result<()> {
+        let _ = (address, and_mask, or_mask);
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
     fn write_read_multiple_registers(
         &mut self,
         write_address: u16,
@@ -26,5 +436,320 @@ pub trait Client {
         read_quantity: u16,
     ) -> Result<Vec<u16>>;
 
+    /// Read the values of the FIFO queue at `address` (function 24), at most 31 values. Fails
+    /// with an `IllegalFunction` exception unless implemented.
+    fn read_fifo_queue(&mut self, address: u16) -> Result<Vec<u16>> {
+        let _ = address;
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
+    /// Read the file records given as `(file_number, record_number, record_length)` in a single
+    /// request (function 20). Fails with an `IllegalFunction` exception unless implemented.
+    fn read_file_records(&mut self, records: &[(u16, u16, u16)]) -> Result<Vec<FileRecord>> {
+        let _ = records;
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
+    /// Write `records` in a single request (function 21). Fails with an `IllegalFunction`
+    /// exception unless implemented.
+    fn write_file_records(&mut self, records: &[FileRecord]) -> Result<()> {
+        let _ = records;
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+
     fn set_uid(&mut self, uid: u8);
+
+    #[cfg(feature = "read-device-info")]
+    /**
+    Some devices support modbus function 43 (Modbus Encasulated Interface) to read device information as strings.
+    This will return an `IllegalFunction (0x01)` exception code if this request is not supported by the device.
+    The default implementation returns this exception without a request, so clients implemented
+    outside of this crate keep compiling when the feature is enabled.
+    */
+    fn read_device_info(
+        &mut self,
+        obj_category: mei::DeviceInfoCategory,
+    ) -> Result<Vec<mei::DeviceInfoObject>> {
+        let _ = obj_category;
+        Err(Error::Exception(ExceptionCode::IllegalFunction))
+    }
+}
+
+/// Run `read` on all `clients` in parallel, one thread per client, and return the results in the
+/// order of the clients, e.g. to poll the status of many devices at once.
+///
+/// # Examples
+///
+/// ```no_run
+/// use modbus::{read_all, Client, Transport};
+///
+/// let mut clients = vec![
+///     Transport::new("192.168.0.10").unwrap(),
+///     Transport::new("192.168.0.11").unwrap(),
+/// ];
+/// for result in read_all(&mut clients, |c| c.read_input_registers(0, 4)) {
+///     println!("{:?}", result);
+/// }
+/// ```
+pub fn read_all<C, T, F>(clients: &mut [C], read: F) -> Vec<Result<T>>
+where
+    C: Client + Send,
+    T: Send,
+    F: Fn(&mut C) -> Result<T> + Sync,
+{
+    let read = &read;
+    thread::scope(|s| {
+        let handles: Vec<_> = clients
+            .iter_mut()
+            .map(|client| s.spawn(move || read(client)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Repeat `read` until `valid` accepts its result, at most `attempts` times.
+///
+/// A multi-register value is torn if the device updates it while it is read, e.g. between the
+/// requests of a chunked read. Such values are usually implausible, like a counter going
+/// backwards, and are read again.
+///
+/// # Examples
+///
+/// ```no_run
+/// use modbus::{read_validated, Client, Transport};
+///
+/// let mut client = Transport::new("192.168.0.10").unwrap();
+/// // the second register holds a checksum of the first one
+/// let regs = read_validated(
+///     || client.read_holding_registers(0, 2),
+///     3,
+///     |regs| regs[1] == !regs[0],
+/// );
+/// ```
+pub fn read_validated<T, R, V>(mut read: R, attempts: u32, mut valid: V) -> Result<T>
+where
+    R: FnMut() -> Result<T>,
+    V: FnMut(&T) -> bool,
+{
+    for _ in 0..attempts {
+        let value = read()?;
+        if valid(&value) {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidData(Reason::Custom(format!(
+        "no valid value in {} reads",
+        attempts
+    ))))
+}
+
+/// Repeat `read` until two consecutive reads return the same value, at most `attempts` times,
+/// to detect values torn by an update between the requests of a chunked read.
+pub fn read_stable<T, R>(mut read: R, attempts: u32) -> Result<T>
+where
+    T: PartialEq,
+    R: FnMut() -> Result<T>,
+{
+    let mut previous = read()?;
+    for _ in 1..attempts {
+        let value = read()?;
+        if value == previous {
+            return Ok(value);
+        }
+        previous = value;
+    }
+    Err(Error::InvalidData(Reason::Custom(format!(
+        "no stable value in {} reads",
+        attempts
+    ))))
+}
+
+/// Run `f` between writing the `unlock` and the `lock` sequence, given as `(address, value)`
+/// pairs of holding registers, for devices which accept configuration writes only after an
+/// unlock code was written.
+///
+/// The `lock` sequence is written even if unlocking or `f` fail or `f` panics, so the device
+/// isn't left unlocked. The first error is returned, an error of the `lock` sequence only if
+/// everything else succeeded.
+///
+/// # Examples
+///
+/// ```no_run
+/// use modbus::{with_unlock, Client, Transport};
+///
+/// let mut client = Transport::new("192.168.0.10").unwrap();
+/// // write the code 0x5aa5 to register 1000 to unlock and 0 to lock again
+/// with_unlock(&mut client, &[(1000, 0x5aa5)], &[(1000, 0)], |c| {
+///     c.write_single_register(200, 9600)?;
+///     c.write_single_register(201, 2)
+/// })
+/// .unwrap();
+/// ```
+pub fn with_unlock<C, T, F>(
+    client: &mut C,
+    unlock: &[(u16, u16)],
+    lock: &[(u16, u16)],
+    f: F,
+) -> Result<T>
+where
+    C: Client + ?Sized,
+    F: FnOnce(&mut C) -> Result<T>,
+{
+    let write = |client: &mut C, sequence: &[(u16, u16)]| {
+        sequence
+            .iter()
+            .try_for_each(|&(addr, value)| client.write_single_register(addr, value))
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        write(client, unlock).and_then(|()| f(client))
+    }));
+    let locked = write(client, lock);
+    match result {
+        Ok(Ok(value)) => locked.map(|()| value),
+        Ok(Err(e)) => Err(e),
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn planned_reads() {
+        // plan the reads of single holding registers as `(address, quantity)`
+        let plan = |addresses: &[u16], max_gap| {
+            let ranges: Vec<_> = addresses
+                .iter()
+                .map(|&a| (Table::HoldingRegisters, a, 1))
+                .collect();
+            let limits = ReadLimits {
+                max_gap,
+                ..Default::default()
+            };
+            plan_reads(&ranges, limits)
+                .into_iter()
+                .map(|r| (r.address, r.quantity))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(plan(&[], 2), vec![]);
+        assert_eq!(
+            plan(&[7, 3, 4, 5, 4, 10, 20], 0),
+            vec![(3, 3), (7, 1), (10, 1), (20, 1)]
+        );
+        assert_eq!(plan(&[7, 3, 4, 5, 10, 20], 2), vec![(3, 8), (20, 1)]);
+        assert_eq!(
+            plan(&[0, 124, 125, 126, 0xffff], 200),
+            vec![(0, 125), (125, 2), (0xffff, 1)]
+        );
+
+        let limits = ReadLimits {
+            max_gap: 10,
+            max_bits: 16,
+            max_registers: 100,
+        };
+        let ranges = [
+            (Table::InputRegisters, 0xfff0, 0x20),
+            (Table::Coils, 0, 40),
+            (Table::HoldingRegisters, 50, 100),
+            (Table::Coils, 45, 1),
+            (Table::HoldingRegisters, 0, 60),
+            (Table::HoldingRegisters, 161, 1),
+            (Table::HoldingRegisters, 300, 0),
+        ];
+        let requests: Vec<_> = plan_reads(&ranges, limits)
+            .into_iter()
+            .map(|r| (r.table, r.address, r.quantity))
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                (Table::Coils, 0, 16),
+                (Table::Coils, 16, 16),
+                (Table::Coils, 32, 14),
+                (Table::HoldingRegisters, 0, 100),
+                (Table::HoldingRegisters, 100, 50),
+                (Table::HoldingRegisters, 161, 1),
+                (Table::InputRegisters, 0xfff0, 16),
+            ]
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn batch_reads() {
+        use crate::mock::MockClient;
+        use crate::server::Bank;
+
+        let mut client = MockClient::new(Bank::new(300));
+        for (addr, value) in client.bank.holding_registers.iter_mut().enumerate() {
+            *value = addr as u16 * 2;
+        }
+        let values = client
+            .read_holding_registers_batch(&[260, 1, 3, 200, 4, 299], 1)
+            .unwrap();
+        assert_eq!(
+            values.into_iter().collect::<Vec<_>>(),
+            vec![(1, 2), (3, 6), (4, 8), (200, 400), (260, 520), (299, 598)]
+        );
+        assert_eq!(client.requests().len(), 4);
+
+        // up to the last address
+        let mut client = MockClient::new(Bank::new(0x10000));
+        client.bank.holding_registers[0xffff] = 5;
+        let values = client
+            .read_holding_registers_batch(&[0xfffe, 0xffff], 0)
+            .unwrap();
+        assert_eq!(
+            values.into_iter().collect::<Vec<_>>(),
+            vec![(0xfffe, 0), (0xffff, 5)]
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn unlocked_writes() {
+        use crate::mock::MockClient;
+        use crate::server::Bank;
+
+        // the addresses of the requests
+        let writes = |client: &MockClient| {
+            let addr = |r: &crate::mock::Request| u16::from_be_bytes([r.pdu[1], r.pdu[2]]);
+            client.requests().iter().map(addr).collect::<Vec<_>>()
+        };
+        let mut client = MockClient::new(Bank::new(10));
+        let value = with_unlock(&mut client, &[(0, 0x5a), (0, 0xa5)], &[(0, 0)], |c| {
+            c.write_single_register(5, 7)?;
+            c.read_holding_registers(5, 1)
+        });
+        assert_eq!(value.unwrap(), vec![7]);
+        assert_eq!(writes(&client), vec![0, 0, 5, 5, 0]);
+
+        // locked again after a failed write, with the error of the write
+        client.clear_requests();
+        let res = with_unlock(&mut client, &[(0, 0x5a)], &[(0, 0)], |c| {
+            c.write_single_register(10, 1)
+        });
+        assert!(matches!(res, Err(Error::Exception(_))));
+        assert_eq!(writes(&client), vec![0, 10, 0]);
+
+        // and after a panic
+        client.clear_requests();
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            with_unlock(&mut client, &[(0, 0x5a)], &[(0, 0)], |_| -> Result<()> {
+                panic!("closure panicked")
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(writes(&client), vec![0, 0]);
+        assert_eq!(client.read_holding_registers(0, 1).unwrap(), vec![0]);
+
+        // an error while locking is returned if everything else succeeded
+        client.clear_requests();
+        client.push_error(Error::InvalidResponse);
+        assert!(with_unlock(&mut client, &[], &[(0, 0)], |_| Ok(())).is_err());
+    }
 }
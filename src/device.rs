@@ -0,0 +1,1183 @@
+//! A handle to a polled device, the glue of dashboard-like applications
+//!
+//! `Device::spawn` moves a client into a background thread which polls a list of address ranges
+//! at a fixed interval. The last polled values are kept in a mirror, which can be read from any
+//! thread without waiting for the device, and every change is reported as an `Event` on a
+//! channel, e.g. to update a UI. Writes are queued to the polling thread, so the client is never
+//! shared between threads. Dropping the `Device` stops the thread.
+//!
+//! Further options are set with `Device::builder`, e.g. a bounded history of the last polled
+//! values of every address for lightweight trend displays, without an external database, or
+//! `Alarm`s raised and cleared by threshold rules on the polled values, or values derived from
+//! the polled values by an `Expression`. The statistics and the last polled values can be saved
+//! as snapshot and restored at startup, so they are available before the first poll completes.
+//! `Device::shutdown` stops a device in a controlled way, executing the queued writes until a
+//! deadline and reporting the dropped ones, `Device::reload` replaces the polls at runtime.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use modbus::device::{Device, Event, Poll};
+//! use modbus::{tcp, Table};
+//!
+//! let client = tcp::Transport::new("192.168.0.10").unwrap();
+//! let (device, events) = Device::spawn(
+//!     client,
+//!     vec![Poll::new(Table::HoldingRegisters, 0, 10)],
+//!     Duration::from_millis(500),
+//! );
+//! device.write_register(3, 42).unwrap();
+//! for event in events {
+//!     match event {
+//!         Event::Changed(Table::HoldingRegisters, addr, value) => println!("{}: {}", addr, value),
+//!         Event::Error(e) => eprintln!("polling failed: {}", e),
+//!         _ => (),
+//!     }
+//! }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::ops::RangeBounds;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{Client, Coil, Error, Reason, Result, Table};
+
+/// An address range polled by a `Device`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Poll {
+    pub table: Table,
+    pub addr: u16,
+    pub count: u16,
+}
+
+impl Poll {
+    pub fn new(table: Table, addr: u16, count: u16) -> Poll {
+        Poll { table, addr, count }
+    }
+}
+
+/// Notification sent by the polling thread of a `Device`.
+#[derive(Debug)]
+pub enum Event {
+    /// The value at an address was read for the first time or changed, coils and discrete
+    /// inputs are `0` or `1`
+    Changed(Table, u16, u16),
+    /// A poll failed, the mirror keeps the last values
+    Error(Error),
+    /// The alarm with the name raised (`true`) or cleared (`false`) at the value, see
+    /// `DeviceBuilder::alarm`
+    Alarm(String, bool, u16),
+    /// The derived value with the name was computed for the first time or changed, see
+    /// `DeviceBuilder::derived`
+    Derived(String, f64),
+}
+
+/// The computation of a derived value from polled values.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    /// The sum of the values at the addresses
+    Sum(Vec<(Table, u16)>),
+    /// The product of the values at the addresses
+    Product(Vec<(Table, u16)>),
+    /// The values at the addresses multiplied with their factors, summed up and the offset added,
+    /// e.g. to scale them into engineering units
+    Linear(Vec<(f64, Table, u16)>, f64),
+}
+
+impl Expression {
+    // The value of the expression, `None` if an input wasn't polled (yet).
+    fn eval(&self, values: &HashMap<(Table, u16), u16>) -> Option<f64> {
+        let value = |table, addr| values.get(&(table, addr)).map(|&v| f64::from(v));
+        match self {
+            Expression::Sum(inputs) => inputs.iter().map(|&(t, a)| value(t, a)).sum(),
+            Expression::Product(inputs) => inputs.iter().map(|&(t, a)| value(t, a)).product(),
+            Expression::Linear(terms, offset) => terms
+                .iter()
+                .map(|&(factor, t, a)| value(t, a).map(|v| factor * v))
+                .sum::<Option<f64>>()
+                .map(|sum| sum + offset),
+        }
+    }
+}
+
+/// Statistics of the polls of a `Device`, see `Device::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// The number of polled address ranges read, successfully or not
+    pub polls: u64,
+    /// The number of failed reads
+    pub errors: u64,
+    /// The time of the last successful read
+    pub last_success: Option<SystemTime>,
+}
+
+/// A polled value with the time it was read, see `DeviceBuilder::history`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub value: u16,
+}
+
+/// A threshold rule on a polled address, raising an `Event::Alarm` when the value exceeds a
+/// limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alarm {
+    name: String,
+    table: Table,
+    addr: u16,
+    high: Option<u16>,
+    low: Option<u16>,
+    hysteresis: u16,
+    delay: Duration,
+}
+
+impl Alarm {
+    /// An alarm named `name` on the address `addr` of `table`, without limits.
+    pub fn new(name: &str, table: Table, addr: u16) -> Alarm {
+        Alarm {
+            name: name.to_string(),
+            table,
+            addr,
+            high: None,
+            low: None,
+            hysteresis: 0,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Raise the alarm when the value is above `limit`.
+    pub fn high(mut self, limit: u16) -> Alarm {
+        self.high = Some(limit);
+        self
+    }
+
+    /// Raise the alarm when the value is below `limit`.
+    pub fn low(mut self, limit: u16) -> Alarm {
+        self.low = Some(limit);
+        self
+    }
+
+    /// Clear the alarm only when the value is back within the limits by more than `hysteresis`,
+    /// so a value jittering around a limit doesn't toggle it (Default: `0`)
+    pub fn hysteresis(mut self, hysteresis: u16) -> Alarm {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// Raise the alarm only when the limit is exceeded by every poll for at least `delay`
+    /// (Default: `0`, raised by the first poll)
+    pub fn delay(mut self, delay: Duration) -> Alarm {
+        self.delay = delay;
+        self
+    }
+
+    fn exceeded(&self, value: u16) -> bool {
+        self.high.is_some_and(|high| value > high) || self.low.is_some_and(|low| value < low)
+    }
+
+    fn cleared(&self, value: u16) -> bool {
+        let h = self.hysteresis;
+        let above = self.high.is_some_and(|high| value > high.saturating_sub(h));
+        let below = self.low.is_some_and(|low| value < low.saturating_add(h));
+        !(above || below)
+    }
+}
+
+// The evaluation state of an `Alarm`.
+struct AlarmState {
+    alarm: Alarm,
+    active: bool,
+    // the time since the limit is exceeded, while the alarm isn't active (yet)
+    exceeded: Option<Instant>,
+}
+
+/// The report of `Device::shutdown`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Shutdown {
+    /// The number of queued writes executed after the shutdown started
+    pub written: usize,
+    /// The queued writes dropped at the deadline as table, address and value, coils are `0` or
+    /// `1`
+    pub dropped: Vec<(Table, u16, u16)>,
+}
+
+/// The report of `Device::reload`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reload {
+    /// The polls which weren't configured before
+    pub added: Vec<Poll>,
+    /// The polls which aren't configured anymore
+    pub removed: Vec<Poll>,
+}
+
+enum Command {
+    // a coil (`0` or `1`) or holding register
+    Write(Table, u16, u16),
+    Reload(Vec<Poll>, Duration, Sender<Reload>),
+    // ends the polling thread after the writes queued before
+    Shutdown,
+}
+
+type Queue = Receiver<(Command, Sender<Result<()>>)>;
+
+// The state shared by the polling thread and the `Device` handle.
+#[derive(Default)]
+struct State {
+    values: HashMap<(Table, u16), u16>,
+    // the last samples of every address, the newest at the back
+    history: HashMap<(Table, u16), VecDeque<Sample>>,
+    derived: HashMap<String, f64>,
+    stats: Stats,
+    // the deadline of the queued writes and the report, once the shutdown started
+    shutdown: Option<(Instant, Shutdown)>,
+}
+
+/// Options of a `Device`, created with `Device::builder`.
+pub struct DeviceBuilder {
+    polls: Vec<Poll>,
+    interval: Duration,
+    history: usize,
+    alarms: Vec<Alarm>,
+    derived: Vec<(String, Expression)>,
+    restored: State,
+}
+
+impl DeviceBuilder {
+    /// Keep the last `len` polled values of every address with the time they were read, e.g.
+    /// for trend displays, see `Device::history`. Every poll adds a sample, whether the value
+    /// changed or not (Default: `0`, no history)
+    pub fn history(mut self, len: usize) -> DeviceBuilder {
+        self.history = len;
+        self
+    }
+
+    /// Evaluate `alarm` after every poll, the address must be polled.
+    pub fn alarm(mut self, alarm: Alarm) -> DeviceBuilder {
+        self.alarms.push(alarm);
+        self
+    }
+
+    /// Compute the value named `name` with `expression` after every poll, e.g. the total power
+    /// as the sum of the powers of three phases, see `Device::derived`.
+    pub fn derived(mut self, name: &str, expression: Expression) -> DeviceBuilder {
+        self.derived.push((name.to_string(), expression));
+        self
+    }
+
+    /// Restore the values and statistics of a snapshot written by `Device::save_snapshot` from
+    /// `reader`, so they are available before the first poll completes. The first poll sends
+    /// events only for values which differ from the snapshot.
+    ///
+    /// ```no_run
+    /// # use modbus::device::{Device, Poll};
+    /// # use modbus::{tcp, Table};
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// # use std::time::Duration;
+    /// let polls = vec![Poll::new(Table::HoldingRegisters, 0, 10)];
+    /// let mut builder = Device::builder(polls, Duration::from_secs(1));
+    /// if let Ok(file) = File::open("device.snapshot") {
+    ///     builder = builder.restore(BufReader::new(file)).unwrap();
+    /// }
+    /// let (device, _) = builder.spawn(tcp::Transport::new("127.0.0.1").unwrap());
+    /// // ...
+    /// device.save_snapshot(File::create("device.snapshot").unwrap()).unwrap();
+    /// ```
+    pub fn restore<R: BufRead>(mut self, reader: R) -> Result<DeviceBuilder> {
+        let mut state = State::default();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |reason: &str| {
+                Error::InvalidData(Reason::Custom(format!(
+                    "snapshot line {}: {}",
+                    n + 1,
+                    reason
+                )))
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            match fields[..] {
+                [name, count] => {
+                    let count = count.parse().map_err(|_| err("invalid number"))?;
+                    match name {
+                        "polls" => state.stats.polls = count,
+                        "errors" => state.stats.errors = count,
+                        "last_success" => {
+                            state.stats.last_success =
+                                Some(UNIX_EPOCH + Duration::from_millis(count))
+                        }
+                        _ => return Err(err("unknown statistic")),
+                    }
+                }
+                [table, addr, value] => {
+                    let table = match table {
+                        "coil" => Table::Coils,
+                        "discrete_input" => Table::DiscreteInputs,
+                        "holding_register" => Table::HoldingRegisters,
+                        "input_register" => Table::InputRegisters,
+                        _ => return Err(err("unknown table")),
+                    };
+                    let addr = addr.parse().map_err(|_| err("invalid address"))?;
+                    let value = value.parse().map_err(|_| err("invalid value"))?;
+                    state.values.insert((table, addr), value);
+                }
+                _ => return Err(err("expected statistic,value or table,address,value")),
+            }
+        }
+        self.restored = state;
+        Ok(self)
+    }
+
+    /// Start polling with `client`, the first poll is done immediately. Returns the handle and
+    /// the receiver of the events.
+    pub fn spawn<C: Client + Send + 'static>(self, client: C) -> (Device, Receiver<Event>) {
+        let state = Arc::new(Mutex::new(self.restored));
+        let (commands, queue) = mpsc::channel();
+        let (events, receiver) = mpsc::channel();
+        let poller = Poller {
+            client,
+            polls: self.polls,
+            interval: self.interval,
+            history: self.history,
+            alarms: self
+                .alarms
+                .into_iter()
+                .map(|alarm| AlarmState {
+                    alarm,
+                    active: false,
+                    exceeded: None,
+                })
+                .collect(),
+            derived: self.derived,
+            state: state.clone(),
+            events,
+        };
+        let thread = thread::spawn(move || poller.run(queue));
+        (
+            Device {
+                state,
+                commands: Some(commands),
+                thread: Some(thread),
+            },
+            receiver,
+        )
+    }
+}
+
+/// Handle to a device polled in a background thread.
+pub struct Device {
+    state: Arc<Mutex<State>>,
+    commands: Option<Sender<(Command, Sender<Result<()>>)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Device {
+    /// Start polling `polls` with `client` every `interval`, the first poll is done immediately.
+    /// Returns the handle and the receiver of the events.
+    pub fn spawn<C: Client + Send + 'static>(
+        client: C,
+        polls: Vec<Poll>,
+        interval: Duration,
+    ) -> (Device, Receiver<Event>) {
+        Device::builder(polls, interval).spawn(client)
+    }
+
+    /// Configure a device polling `polls` every `interval` with further options.
+    pub fn builder(polls: Vec<Poll>, interval: Duration) -> DeviceBuilder {
+        DeviceBuilder {
+            polls,
+            interval,
+            history: 0,
+            alarms: vec![],
+            derived: vec![],
+            restored: State::default(),
+        }
+    }
+
+    /// The last polled value at `addr` of `table`, `None` if it wasn't polled (yet).
+    pub fn value(&self, table: Table, addr: u16) -> Option<u16> {
+        self.state
+            .lock()
+            .unwrap()
+            .values
+            .get(&(table, addr))
+            .copied()
+    }
+
+    /// The last value computed of the derived value `name`, `None` if its inputs weren't polled
+    /// (yet).
+    pub fn derived(&self, name: &str) -> Option<f64> {
+        self.state.lock().unwrap().derived.get(name).copied()
+    }
+
+    /// The statistics of the polls.
+    pub fn stats(&self) -> Stats {
+        self.state.lock().unwrap().stats
+    }
+
+    /// Write the statistics and the last polled values as snapshot to `writer`, e.g. to restore
+    /// them with `DeviceBuilder::restore` after a restart.
+    ///
+    /// Every line has the form `statistic,value` or `table,address,value` like the images of
+    /// `server::Bank::read_csv`.
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        writeln!(writer, "# statistic,value")?;
+        writeln!(writer, "polls,{}", state.stats.polls)?;
+        writeln!(writer, "errors,{}", state.stats.errors)?;
+        if let Some(time) = state.stats.last_success {
+            let millis = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            writeln!(writer, "last_success,{}", millis)?;
+        }
+        writeln!(writer, "# table,address,value")?;
+        let mut values: Vec<_> = state.values.iter().collect();
+        values.sort_by_key(|&(&(table, addr), _)| (table as u8, addr));
+        for (&(table, addr), value) in values {
+            let table = match table {
+                Table::Coils => "coil",
+                Table::DiscreteInputs => "discrete_input",
+                Table::HoldingRegisters => "holding_register",
+                Table::InputRegisters => "input_register",
+            };
+            writeln!(writer, "{},{},{}", table, addr, value)?;
+        }
+        Ok(())
+    }
+
+    /// The samples kept of the address `addr` of `table`, the oldest first.
+    pub fn history(&self, table: Table, addr: u16) -> Vec<Sample> {
+        self.history_range(table, addr, ..)
+    }
+
+    /// The last `n` samples kept of the address `addr` of `table`, the oldest first.
+    pub fn last(&self, table: Table, addr: u16, n: usize) -> Vec<Sample> {
+        let state = self.state.lock().unwrap();
+        match state.history.get(&(table, addr)) {
+            Some(samples) => samples
+                .iter()
+                .skip(samples.len().saturating_sub(n))
+                .copied()
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// The samples kept of the address `addr` of `table` which were read within `range`, the
+    /// oldest first.
+    pub fn history_range<R: RangeBounds<SystemTime>>(
+        &self,
+        table: Table,
+        addr: u16,
+        range: R,
+    ) -> Vec<Sample> {
+        let state = self.state.lock().unwrap();
+        state
+            .history
+            .get(&(table, addr))
+            .map(|samples| {
+                samples
+                    .iter()
+                    .filter(|s| range.contains(&s.time))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Write a single coil, waiting for the polling thread to execute the request.
+    pub fn write_coil(&self, addr: u16, value: Coil) -> Result<()> {
+        self.execute(Command::Write(
+            Table::Coils,
+            addr,
+            (value == Coil::On) as u16,
+        ))
+    }
+
+    /// Write a single holding register, waiting for the polling thread to execute the request.
+    pub fn write_register(&self, addr: u16, value: u16) -> Result<()> {
+        self.execute(Command::Write(Table::HoldingRegisters, addr, value))
+    }
+
+    /// Replace the polls and the interval, e.g. after a configuration change, without dropping
+    /// the connection. The new configuration is applied between two transactions and polled
+    /// immediately, the last values and history of addresses which aren't polled anymore are
+    /// removed. Returns the polls added and removed.
+    pub fn reload(&self, polls: Vec<Poll>, interval: Duration) -> Result<Reload> {
+        let (report, reload) = mpsc::channel();
+        self.execute(Command::Reload(polls, interval, report))?;
+        reload.recv().map_err(|_| Error::ConnectionClosed)
+    }
+
+    /// Shut the device down in a controlled way: No more writes are accepted, polling stops and
+    /// the writes queued so far are executed until `timeout` elapsed. The writes still queued
+    /// then are dropped and fail with `Error::Timeout`. Returns the report of the written and
+    /// dropped writes, calling it again returns the same report.
+    ///
+    /// Afterwards, the writes fail with `Error::ConnectionClosed` and the last values, history
+    /// and derived values remain available.
+    pub fn shutdown(&self, timeout: Duration) -> Shutdown {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.shutdown.is_none() {
+                state.shutdown = Some((Instant::now() + timeout, Shutdown::default()));
+            }
+        }
+        let _ = self.send(Command::Shutdown).map(|result| result.recv());
+        let state = self.state.lock().unwrap();
+        state
+            .shutdown
+            .as_ref()
+            .map(|(_, report)| report.clone())
+            .unwrap_or_default()
+    }
+
+    fn execute(&self, command: Command) -> Result<()> {
+        if self.state.lock().unwrap().shutdown.is_some() {
+            return Err(Error::ConnectionClosed);
+        }
+        self.send(command)?
+            .recv()
+            .map_err(|_| Error::ConnectionClosed)?
+    }
+
+    // Queue `command`, the receiver gets its result.
+    fn send(&self, command: Command) -> Result<Receiver<Result<()>>> {
+        let (reply, result) = mpsc::channel();
+        self.commands
+            .as_ref()
+            .and_then(|c| c.send((command, reply)).ok())
+            .ok_or(Error::ConnectionClosed)?;
+        Ok(result)
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        // closing the command channel ends the polling thread
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// The polling thread of a `Device`, owning the client.
+struct Poller<C> {
+    client: C,
+    polls: Vec<Poll>,
+    interval: Duration,
+    history: usize,
+    alarms: Vec<AlarmState>,
+    derived: Vec<(String, Expression)>,
+    state: Arc<Mutex<State>>,
+    events: Sender<Event>,
+}
+
+impl<C: Client> Poller<C> {
+    // Poll and execute the queued commands until the `Device` is dropped.
+    fn run(mut self, queue: Queue) {
+        let mut next = Instant::now();
+        loop {
+            match queue.recv_timeout(next.saturating_duration_since(Instant::now())) {
+                Ok((Command::Shutdown, reply)) => {
+                    let _ = reply.send(Ok(()));
+                    break;
+                }
+                Ok((Command::Reload(polls, interval, report), reply)) => {
+                    let _ = report.send(self.reload(polls, interval));
+                    let _ = reply.send(Ok(()));
+                    next = Instant::now();
+                }
+                Ok((Command::Write(table, addr, value), reply)) => {
+                    let _ = reply.send(self.write(table, addr, value));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.state.lock().unwrap().shutdown.is_none() {
+                        self.poll();
+                    }
+                    next = (next + self.interval).max(Instant::now());
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    }
+
+    // Write `value` at `addr` of `table`, unless the deadline of a shutdown passed.
+    fn write(&mut self, table: Table, addr: u16, value: u16) -> Result<()> {
+        let deadline = self.state.lock().unwrap().shutdown.as_ref().map(|s| s.0);
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            if let Some((_, report)) = self.state.lock().unwrap().shutdown.as_mut() {
+                report.dropped.push((table, addr, value));
+            }
+            return Err(Error::Timeout);
+        }
+        let res = match table {
+            Table::Coils => {
+                let value = if value == 0 { Coil::Off } else { Coil::On };
+                self.client.write_single_coil(addr, value)
+            }
+            _ => self.client.write_single_register(addr, value),
+        };
+        if deadline.is_some() {
+            if let Some((_, report)) = self.state.lock().unwrap().shutdown.as_mut() {
+                report.written += 1;
+            }
+        }
+        res
+    }
+
+    // Replace the polls and the interval, forgetting the addresses which aren't polled anymore.
+    fn reload(&mut self, polls: Vec<Poll>, interval: Duration) -> Reload {
+        let report = Reload {
+            added: polls
+                .iter()
+                .filter(|p| !self.polls.contains(p))
+                .copied()
+                .collect(),
+            removed: self
+                .polls
+                .iter()
+                .filter(|p| !polls.contains(p))
+                .copied()
+                .collect(),
+        };
+        let polled = |&(table, addr): &(Table, u16)| {
+            polls.iter().any(|p| {
+                p.table == table
+                    && addr >= p.addr
+                    && u32::from(addr) < u32::from(p.addr) + u32::from(p.count)
+            })
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            state.values.retain(|key, _| polled(key));
+            state.history.retain(|key, _| polled(key));
+        }
+        self.polls = polls;
+        self.interval = interval;
+        report
+    }
+
+    // Read all polls, nobody listening to the events is fine, the state is updated
+    // nevertheless.
+    fn poll(&mut self) {
+        for i in 0..self.polls.len() {
+            let poll = self.polls[i];
+            let res = read(&mut self.client, &poll);
+            {
+                let mut state = self.state.lock().unwrap();
+                state.stats.polls += 1;
+                match res {
+                    Ok(_) => state.stats.last_success = Some(SystemTime::now()),
+                    Err(_) => state.stats.errors += 1,
+                }
+            }
+            match res {
+                Ok(values) => self.update(&poll, &values),
+                Err(e) => {
+                    let _ = self.events.send(Event::Error(e));
+                }
+            }
+        }
+        self.derive();
+        self.evaluate();
+    }
+
+    // Compute the derived values with the current values.
+    fn derive(&self) {
+        let mut state = self.state.lock().unwrap();
+        for (name, expression) in &self.derived {
+            let Some(value) = expression.eval(&state.values) else {
+                continue;
+            };
+            if state.derived.insert(name.clone(), value) != Some(value) {
+                let _ = self.events.send(Event::Derived(name.clone(), value));
+            }
+        }
+    }
+
+    // Raise or clear the alarms with the current values.
+    fn evaluate(&mut self) {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        for s in &mut self.alarms {
+            let Some(&value) = state.values.get(&(s.alarm.table, s.alarm.addr)) else {
+                continue;
+            };
+            let active = if s.active {
+                !s.alarm.cleared(value)
+            } else if s.alarm.exceeded(value) {
+                let since = *s.exceeded.get_or_insert(now);
+                now.duration_since(since) >= s.alarm.delay
+            } else {
+                s.exceeded = None;
+                false
+            };
+            if active != s.active {
+                s.active = active;
+                s.exceeded = None;
+                let _ = self
+                    .events
+                    .send(Event::Alarm(s.alarm.name.clone(), active, value));
+            }
+        }
+    }
+
+    // Store the polled `values` and send an event for every changed value.
+    fn update(&self, poll: &Poll, values: &[u16]) {
+        let time = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        for (addr, &value) in (poll.addr..=u16::MAX).zip(values) {
+            let key = (poll.table, addr);
+            if state.values.insert(key, value) != Some(value) {
+                let _ = self.events.send(Event::Changed(poll.table, addr, value));
+            }
+            if self.history > 0 {
+                let samples = state.history.entry(key).or_default();
+                if samples.len() == self.history {
+                    samples.pop_front();
+                }
+                samples.push_back(Sample { time, value });
+            }
+        }
+    }
+}
+
+fn read<C: Client>(client: &mut C, poll: &Poll) -> Result<Vec<u16>> {
+    let bits = |coils: Vec<Coil>| coils.into_iter().map(|c| (c == Coil::On) as u16).collect();
+    match poll.table {
+        Table::Coils => client.read_coils(poll.addr, poll.count).map(bits),
+        Table::DiscreteInputs => client.read_discrete_inputs(poll.addr, poll.count).map(bits),
+        Table::HoldingRegisters => client.read_holding_registers(poll.addr, poll.count),
+        Table::InputRegisters => client.read_input_registers(poll.addr, poll.count),
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::server::{Bank, Server};
+    use crate::tcp;
+
+    #[test]
+    fn poll_and_write() {
+        let mut bank = Bank::new(10);
+        bank.input_registers[2] = 7;
+        let server = Server::bind("127.0.0.1:0", bank).unwrap();
+        let cfg = tcp::Config {
+            tcp_port: server.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let bank = server.bank();
+        thread::spawn(move || server.serve());
+
+        let client = tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        let (device, events) = Device::spawn(
+            client,
+            vec![
+                Poll::new(Table::InputRegisters, 2, 1),
+                Poll::new(Table::Coils, 0, 2),
+            ],
+            Duration::from_millis(20),
+        );
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::InputRegisters, 2, 7)
+        ));
+        assert!(matches!(next(), Event::Changed(Table::Coils, 0, 0)));
+        assert!(matches!(next(), Event::Changed(Table::Coils, 1, 0)));
+        assert_eq!(device.value(Table::InputRegisters, 2), Some(7));
+        assert_eq!(device.value(Table::InputRegisters, 3), None);
+
+        device.write_coil(1, Coil::On).unwrap();
+        assert_eq!(bank.lock().unwrap().coils[1], Coil::On);
+        assert!(matches!(next(), Event::Changed(Table::Coils, 1, 1)));
+        bank.lock().unwrap().input_registers[2] = 8;
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::InputRegisters, 2, 8)
+        ));
+
+        assert!(device.write_register(100, 1).is_err());
+        drop(device);
+        // the polling thread dropped its event sender
+        while events.recv_timeout(Duration::from_secs(5)).is_ok() {}
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn last_address() {
+        use crate::mock::MockClient;
+
+        let mut client = MockClient::new(Bank::new(0x10000));
+        client.bank.holding_registers[0xffff] = 5;
+        let (device, events) = Device::spawn(
+            client,
+            vec![Poll::new(Table::HoldingRegisters, 0xffff, 1)],
+            Duration::from_millis(20),
+        );
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0xffff, 5)
+        ));
+        // the polling thread is still running
+        device.write_register(0xffff, 6).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0xffff, 6)
+        ));
+        assert_eq!(device.value(Table::HoldingRegisters, 0xffff), Some(6));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn history() {
+        use crate::mock::MockClient;
+
+        let start = SystemTime::now();
+        let (device, events) = Device::builder(
+            vec![Poll::new(Table::HoldingRegisters, 0, 1)],
+            Duration::from_millis(20),
+        )
+        .history(2)
+        .spawn(MockClient::new(Bank::new(1)));
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0, 0)
+        ));
+        for value in 1..=3 {
+            device.write_register(0, value).unwrap();
+            assert!(matches!(
+                next(),
+                Event::Changed(Table::HoldingRegisters, 0, v) if v == value
+            ));
+        }
+        let history = device.history(Table::HoldingRegisters, 0);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].value, 3);
+        assert!(history[0].time <= history[1].time && history[0].time >= start);
+        assert_eq!(device.last(Table::HoldingRegisters, 0, 1), &history[1..]);
+        assert_eq!(
+            device.history_range(Table::HoldingRegisters, 0, history[1].time..),
+            &history[1..]
+        );
+        assert!(device.history(Table::HoldingRegisters, 1).is_empty());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn alarms() {
+        use crate::mock::MockClient;
+
+        let (device, events) = Device::builder(
+            vec![Poll::new(Table::HoldingRegisters, 0, 1)],
+            Duration::from_millis(20),
+        )
+        .alarm(
+            Alarm::new("high", Table::HoldingRegisters, 0)
+                .high(10)
+                .hysteresis(2),
+        )
+        .alarm(
+            Alarm::new("delayed", Table::HoldingRegisters, 0)
+                .high(10)
+                .delay(Duration::from_secs(3600)),
+        )
+        .alarm(Alarm::new("low", Table::HoldingRegisters, 0).low(1))
+        .spawn(MockClient::new(Bank::new(1)));
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0, 0)
+        ));
+        assert!(matches!(next(), Event::Alarm(name, true, 0) if name == "low"));
+
+        device.write_register(0, 11).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0, 11)
+        ));
+        assert!(matches!(next(), Event::Alarm(name, true, 11) if name == "high"));
+        assert!(matches!(next(), Event::Alarm(name, false, 11) if name == "low"));
+        // within the hysteresis
+        device.write_register(0, 9).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0, 9)
+        ));
+        device.write_register(0, 8).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0, 8)
+        ));
+        assert!(matches!(next(), Event::Alarm(name, false, 8) if name == "high"));
+        // the delayed alarm was never raised
+        assert!(events.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn derived() {
+        use crate::mock::MockClient;
+
+        let phases = vec![
+            (Table::InputRegisters, 0),
+            (Table::InputRegisters, 1),
+            (Table::InputRegisters, 2),
+        ];
+        let mut client = MockClient::new(Bank::new(4));
+        client.bank.input_registers[..3].copy_from_slice(&[100, 200, 300]);
+        let (device, events) = Device::builder(
+            vec![Poll::new(Table::InputRegisters, 0, 3)],
+            Duration::from_millis(20),
+        )
+        .derived("total", Expression::Sum(phases.clone()))
+        .derived("product", Expression::Product(phases[..2].to_vec()))
+        .derived(
+            "scaled",
+            Expression::Linear(vec![(0.5, Table::InputRegisters, 0)], -10.0),
+        )
+        .derived(
+            "unpolled",
+            Expression::Sum(vec![(Table::HoldingRegisters, 0)]),
+        )
+        .spawn(client);
+        let mut derived = vec![];
+        while derived.len() < 3 {
+            match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+                Event::Derived(name, value) => derived.push((name, value)),
+                Event::Changed(..) => {}
+                e => panic!("unexpected event {:?}", e),
+            }
+        }
+        assert_eq!(
+            derived,
+            vec![
+                ("total".to_string(), 600.0),
+                ("product".to_string(), 20000.0),
+                ("scaled".to_string(), 40.0)
+            ]
+        );
+        assert_eq!(device.derived("total"), Some(600.0));
+        assert_eq!(device.derived("unpolled"), None);
+        // unchanged values send no events
+        assert!(events.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn snapshot() {
+        use crate::mock::MockClient;
+
+        let polls = vec![Poll::new(Table::HoldingRegisters, 0, 2)];
+        let mut client = MockClient::new(Bank::new(2));
+        client.bank.holding_registers[1] = 7;
+        let (device, events) = Device::spawn(client, polls.clone(), Duration::from_millis(20));
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 0, 0)
+        ));
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::HoldingRegisters, 1, 7)
+        ));
+        let stats = device.stats();
+        assert!(stats.polls >= 1);
+        assert_eq!(stats.errors, 0);
+        assert!(stats.last_success.is_some());
+        let mut snapshot = vec![];
+        device.save_snapshot(&mut snapshot).unwrap();
+        drop(device);
+
+        // the restored values are available before the first poll
+        let (device, events) = Device::builder(polls.clone(), Duration::from_secs(3600))
+            .restore(&snapshot[..])
+            .unwrap()
+            .spawn(MockClient::new(Bank::new(0)));
+        assert_eq!(device.value(Table::HoldingRegisters, 1), Some(7));
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Event::Error(_)
+        ));
+        // the first device kept polling after `stats`, the time is saved in milliseconds
+        let restored = device.stats();
+        assert!(restored.polls > stats.polls);
+        assert_eq!(restored.errors, 1);
+        assert!(
+            restored.last_success.unwrap() + Duration::from_millis(1) > stats.last_success.unwrap()
+        );
+
+        let invalid = Device::builder(polls, Duration::from_secs(1))
+            .restore(&b"coil,0,1\nregister,0,1\n"[..]);
+        assert!(
+            matches!(invalid, Err(Error::InvalidData(Reason::Custom(ref s))) if s == "snapshot line 2: unknown table")
+        );
+    }
+
+    // A client taking its time for writes.
+    #[cfg(feature = "mock")]
+    struct SlowClient(crate::mock::MockClient, Duration);
+
+    #[cfg(feature = "mock")]
+    impl Client for SlowClient {
+        fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+            self.0.read_coils(addr, count)
+        }
+
+        fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+            self.0.read_discrete_inputs(addr, count)
+        }
+
+        fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+            self.0.read_holding_registers(addr, count)
+        }
+
+        fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+            self.0.read_input_registers(addr, count)
+        }
+
+        fn write_single_coil(&mut self, addr: u16, value: Coil) -> Result<()> {
+            thread::sleep(self.1);
+            self.0.write_single_coil(addr, value)
+        }
+
+        fn write_single_register(&mut self, addr: u16, value: u16) -> Result<()> {
+            thread::sleep(self.1);
+            self.0.write_single_register(addr, value)
+        }
+
+        fn write_multiple_coils(&mut self, addr: u16, values: &[Coil]) -> Result<()> {
+            self.0.write_multiple_coils(addr, values)
+        }
+
+        fn write_multiple_registers(&mut self, addr: u16, values: &[u16]) -> Result<()> {
+            self.0.write_multiple_registers(addr, values)
+        }
+
+        fn write_read_multiple_registers(
+            &mut self,
+            write_addr: u16,
+            write_count: u16,
+            write_values: &[u16],
+            read_addr: u16,
+            read_count: u16,
+        ) -> Result<Vec<u16>> {
+            self.0.write_read_multiple_registers(
+                write_addr,
+                write_count,
+                write_values,
+                read_addr,
+                read_count,
+            )
+        }
+
+        fn set_uid(&mut self, uid: u8) {
+            self.0.set_uid(uid)
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn shutdown() {
+        use crate::mock::MockClient;
+
+        let client = SlowClient(MockClient::new(Bank::new(10)), Duration::from_millis(200));
+        let (device, _events) = Device::spawn(client, vec![], Duration::from_secs(3600));
+        let device = Arc::new(device);
+        let writers: Vec<_> = (0..3)
+            .map(|addr| {
+                let device = device.clone();
+                let writer = thread::spawn(move || device.write_register(addr, 1));
+                thread::sleep(Duration::from_millis(20));
+                writer
+            })
+            .collect();
+
+        // the first write is executing, the second one starts before the deadline
+        let report = device.shutdown(Duration::from_millis(250));
+        assert_eq!(
+            report,
+            Shutdown {
+                written: 1,
+                dropped: vec![(Table::HoldingRegisters, 2, 1)],
+            }
+        );
+        let results: Vec<_> = writers.into_iter().map(|w| w.join().unwrap()).collect();
+        assert!(results[0].is_ok() && results[1].is_ok());
+        assert!(matches!(results[2], Err(Error::Timeout)));
+        assert!(matches!(
+            device.write_coil(0, Coil::On),
+            Err(Error::ConnectionClosed)
+        ));
+        assert_eq!(device.shutdown(Duration::ZERO), report);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn reload() {
+        use crate::mock::MockClient;
+
+        let mut client = MockClient::new(Bank::new(10));
+        client.bank.input_registers[..4].copy_from_slice(&[1, 2, 3, 4]);
+        let (device, events) = Device::builder(
+            vec![
+                Poll::new(Table::InputRegisters, 0, 2),
+                Poll::new(Table::InputRegisters, 2, 1),
+            ],
+            Duration::from_secs(3600),
+        )
+        .history(10)
+        .spawn(client);
+        let next = || events.recv_timeout(Duration::from_secs(5)).unwrap();
+        for addr in 0..3 {
+            assert!(matches!(next(), Event::Changed(Table::InputRegisters, a, _) if a == addr));
+        }
+
+        let report = device
+            .reload(
+                vec![
+                    Poll::new(Table::InputRegisters, 2, 1),
+                    Poll::new(Table::InputRegisters, 3, 1),
+                ],
+                Duration::from_secs(3600),
+            )
+            .unwrap();
+        assert_eq!(
+            report,
+            Reload {
+                added: vec![Poll::new(Table::InputRegisters, 3, 1)],
+                removed: vec![Poll::new(Table::InputRegisters, 0, 2)],
+            }
+        );
+        // the new configuration is polled immediately, without waiting for the old interval
+        assert!(matches!(
+            next(),
+            Event::Changed(Table::InputRegisters, 3, 4)
+        ));
+        assert_eq!(device.value(Table::InputRegisters, 0), None);
+        assert!(device.history(Table::InputRegisters, 1).is_empty());
+        assert_eq!(device.value(Table::InputRegisters, 2), Some(3));
+        assert_eq!(device.history(Table::InputRegisters, 2).len(), 2);
+
+        device.shutdown(Duration::ZERO);
+        assert!(matches!(
+            device.reload(vec![], Duration::from_secs(1)),
+            Err(Error::ConnectionClosed)
+        ));
+    }
+}
@@ -0,0 +1,206 @@
+//! Health reports of the hosts of a `Manager`
+//!
+//! A service polling devices usually exposes its own health, e.g. on an HTTP endpoint. A
+//! `Report` condenses the statistics collected by the `Manager` into a health state per host
+//! and overall, and renders them as JSON for such an endpoint.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use modbus::health::Report;
+//! use modbus::manager::{Host, Manager};
+//! use modbus::tcp::Config;
+//!
+//! let manager = Manager::new(vec![Host::new("boiler", "192.168.0.10", Config::default())]);
+//! // hosts without a successful call during the last minute are unhealthy
+//! let report = Report::new(&manager, Duration::from_secs(60));
+//! println!("{}", report.to_json());
+//! ```
+
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::manager::{Manager, Status};
+
+/// Health state of a host or of all hosts together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// All hosts answered recently
+    Healthy,
+    /// Some, but not all hosts answered recently
+    Degraded,
+    /// No host answered recently
+    Unhealthy,
+}
+
+impl State {
+    fn name(self) -> &'static str {
+        match self {
+            State::Healthy => "healthy",
+            State::Degraded => "degraded",
+            State::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+/// Health of a single host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostHealth {
+    pub name: String,
+    /// `Healthy` if the last successful call is at most `max_age` old, otherwise `Unhealthy`
+    pub state: State,
+    pub status: Status,
+    /// Fraction of the calls which failed, `0.0` without any calls
+    pub error_rate: f64,
+}
+
+/// Health of all hosts of a `Manager`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// Time the report was created
+    pub time: SystemTime,
+    pub state: State,
+    pub hosts: Vec<HostHealth>,
+}
+
+impl Report {
+    /// Create a report of the hosts of `manager`, considering hosts healthy which had a
+    /// successful call within `max_age`.
+    pub fn new(manager: &Manager, max_age: Duration) -> Report {
+        Report::at(manager, max_age, SystemTime::now())
+    }
+
+    fn at(manager: &Manager, max_age: Duration, time: SystemTime) -> Report {
+        let hosts: Vec<HostHealth> = manager
+            .status()
+            .into_iter()
+            .map(|(name, status)| {
+                let recent = status
+                    .last_success
+                    .is_some_and(|t| time.duration_since(t).unwrap_or_default() <= max_age);
+                HostHealth {
+                    name: name.to_string(),
+                    state: if recent {
+                        State::Healthy
+                    } else {
+                        State::Unhealthy
+                    },
+                    status,
+                    error_rate: if status.calls == 0 {
+                        0.0
+                    } else {
+                        status.errors as f64 / status.calls as f64
+                    },
+                }
+            })
+            .collect();
+        let healthy = hosts.iter().filter(|h| h.state == State::Healthy).count();
+        let state = if healthy == hosts.len() {
+            State::Healthy
+        } else if healthy > 0 {
+            State::Degraded
+        } else {
+            State::Unhealthy
+        };
+        Report { time, state, hosts }
+    }
+
+    /// Render the report as a JSON object, times are milliseconds since the Unix epoch.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"state\":\"{}\",\"time\":{},\"hosts\":[",
+            self.state.name(),
+            unix_millis(self.time)
+        );
+        for (i, host) in self.hosts.iter().enumerate() {
+            let last_success = match host.status.last_success {
+                Some(t) => unix_millis(t).to_string(),
+                None => "null".to_string(),
+            };
+            let _ = write!(
+                json,
+                "{}{{\"name\":\"{}\",\"state\":\"{}\",\"connected\":{},\"calls\":{},\
+                 \"errors\":{},\"connects\":{},\"error_rate\":{},\"last_success\":{}}}",
+                if i == 0 { "" } else { "," },
+                escape(&host.name),
+                host.state.name(),
+                host.status.connected,
+                host.status.calls,
+                host.status.errors,
+                host.status.connects,
+                host.error_rate,
+                last_success
+            );
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manager::Host;
+    use crate::tcp::Config;
+    use crate::{Error, Result};
+    use std::net::TcpListener;
+
+    #[test]
+    fn report() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let mut manager = Manager::new(vec![
+            Host::new("up", "127.0.0.1", cfg),
+            Host::new("\"down\"", "127.0.0.1", cfg),
+        ]);
+        let report = Report::new(&manager, Duration::from_secs(60));
+        assert_eq!(report.state, State::Unhealthy);
+        assert_eq!(report.hosts[0].error_rate, 0.0);
+
+        assert!(manager.call("up", |_| Ok(())).is_ok());
+        let res: Result<()> = manager.call("\"down\"", |_| Err(Error::InvalidResponse));
+        assert!(res.is_err());
+        let report = Report::new(&manager, Duration::from_secs(60));
+        assert_eq!(report.state, State::Degraded);
+        assert_eq!(report.hosts[0].state, State::Healthy);
+        assert_eq!(report.hosts[1].state, State::Unhealthy);
+        assert_eq!(report.hosts[1].error_rate, 1.0);
+
+        let later = SystemTime::now() + Duration::from_secs(120);
+        let report = Report::at(&manager, Duration::from_secs(60), later);
+        assert_eq!(report.state, State::Unhealthy);
+
+        let json = report.to_json();
+        assert!(json.starts_with("{\"state\":\"unhealthy\",\"time\":"));
+        assert!(json.contains(
+            "{\"name\":\"\\\"down\\\"\",\"state\":\"unhealthy\",\"connected\":true,\"calls\":1,\
+             \"errors\":1,\"connects\":1,\"error_rate\":1,\"last_success\":null}"
+        ));
+        assert!(json.ends_with("}]}"));
+    }
+}
@@ -0,0 +1,294 @@
+//! Journal the value changes of polled devices into a SQLite database
+//!
+//! A `Journal` writes the `Event`s of a `device::Device` into the table `journal` of a SQLite
+//! database, a lightweight historian for edge deployments which doesn't need further services.
+//! The rows are collected and inserted in one transaction per batch, which is written when it
+//! is full or its oldest row is older than the maximum delay.
+//!
+//! The table is created if it doesn't exist yet:
+//!
+//! ```sql
+//! CREATE TABLE journal (
+//!     device TEXT NOT NULL,
+//!     tag TEXT,
+//!     timestamp INTEGER NOT NULL, -- milliseconds since the epoch
+//!     value INTEGER,
+//!     quality TEXT NOT NULL       -- 'good' or 'bad'
+//! )
+//! ```
+//!
+//! Changed values are recorded with the quality `good`. A failed poll is recorded as a row with
+//! the quality `bad` and without tag and value, the values of the device are unknown until the
+//! next change.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::thread;
+//! use std::time::Duration;
+//! use modbus::device::{Device, Poll};
+//! use modbus::journal::Journal;
+//! use modbus::{tcp, Table};
+//!
+//! let client = tcp::Transport::new("192.168.0.10").unwrap();
+//! let (device, events) = Device::spawn(
+//!     client,
+//!     vec![Poll::new(Table::HoldingRegisters, 0, 10)],
+//!     Duration::from_millis(500),
+//! );
+//! let mut journal = Journal::open("history.db", "boiler")
+//!     .unwrap()
+//!     .batch(100, Duration::from_secs(5));
+//! thread::spawn(move || journal.run(&events));
+//! ```
+
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+
+use crate::device::Event;
+use crate::{Error, Result, Table};
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS journal (
+    device TEXT NOT NULL,
+    tag TEXT,
+    timestamp INTEGER NOT NULL,
+    value INTEGER,
+    quality TEXT NOT NULL
+)";
+
+type TagNames = Box<dyn Fn(Table, u16) -> String + Send>;
+
+/// A journal of the value changes of one device.
+pub struct Journal {
+    conn: Connection,
+    device: String,
+    tag_names: TagNames,
+    batch_size: usize,
+    max_delay: Duration,
+    // the rows of the current batch and the time its first row was added
+    pending: Vec<(Option<String>, i64, Option<u16>, &'static str)>,
+    since: Instant,
+}
+
+impl Journal {
+    /// Journal the events of `device` into the database at `path`, which is created if it
+    /// doesn't exist. Every event is written immediately unless `batch` is used.
+    pub fn open<P: AsRef<Path>>(path: P, device: &str) -> Result<Journal> {
+        let conn = Connection::open(path).map_err(db_err)?;
+        conn.execute(SCHEMA, []).map_err(db_err)?;
+        Ok(Journal {
+            conn,
+            device: device.to_string(),
+            tag_names: Box::new(tag_name),
+            batch_size: 1,
+            max_delay: Duration::ZERO,
+            pending: Vec::new(),
+            since: Instant::now(),
+        })
+    }
+
+    /// Write the rows in batches of up to `size` rows, delaying them at most by `max_delay`.
+    pub fn batch(mut self, size: usize, max_delay: Duration) -> Journal {
+        self.batch_size = size.max(1);
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Name the tags with `names` instead of the table and address like `holding_register:3`,
+    /// e.g. with the names of a tag map.
+    pub fn tag_names(mut self, names: impl Fn(Table, u16) -> String + Send + 'static) -> Journal {
+        self.tag_names = Box::new(names);
+        self
+    }
+
+    /// Add `event` to the current batch, writing it if it's due.
+    pub fn record(&mut self, event: &Event) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let row = match *event {
+            Event::Changed(table, addr, value) => (
+                Some((self.tag_names)(table, addr)),
+                timestamp,
+                Some(value),
+                "good",
+            ),
+            Event::Error(_) => (None, timestamp, None, "bad"),
+            // alarms and derived values aren't samples of a tag
+            Event::Alarm(..) | Event::Derived(..) => return Ok(()),
+        };
+        if self.pending.is_empty() {
+            self.since = Instant::now();
+        }
+        self.pending.push(row);
+        if self.pending.len() >= self.batch_size || self.since.elapsed() >= self.max_delay {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the current batch.
+    pub fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction().map_err(db_err)?;
+        {
+            let mut insert = tx
+                .prepare_cached(
+                    "INSERT INTO journal (device, tag, timestamp, value, quality)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .map_err(db_err)?;
+            for (tag, timestamp, value, quality) in &self.pending {
+                insert
+                    .execute(params![self.device, tag, timestamp, value, quality])
+                    .map_err(db_err)?;
+            }
+        }
+        tx.commit().map_err(db_err)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Record the `events` until their sender is dropped, writing late batches in time even if
+    /// no further events arrive. Returns early if a batch can't be written.
+    pub fn run(&mut self, events: &Receiver<Event>) -> Result<()> {
+        loop {
+            let event = if self.pending.is_empty() {
+                events.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                events.recv_timeout(self.max_delay.saturating_sub(self.since.elapsed()))
+            };
+            match event {
+                Ok(event) => self.record(&event)?,
+                Err(RecvTimeoutError::Timeout) => self.flush()?,
+                Err(RecvTimeoutError::Disconnected) => return self.flush(),
+            }
+        }
+    }
+
+    /// The connection to the database, e.g. to query the journal.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl Drop for Journal {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+fn tag_name(table: Table, addr: u16) -> String {
+    let table = match table {
+        Table::Coils => "coil",
+        Table::DiscreteInputs => "discrete_input",
+        Table::HoldingRegisters => "holding_register",
+        Table::InputRegisters => "input_register",
+    };
+    format!("{}:{}", table, addr)
+}
+
+fn db_err(e: rusqlite::Error) -> Error {
+    Error::Io(io::Error::other(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::{env, fs, process, thread};
+
+    fn rows(journal: &Journal) -> Vec<(String, Option<String>, Option<u16>, String)> {
+        let mut query = journal
+            .connection()
+            .prepare("SELECT device, tag, value, quality FROM journal ORDER BY rowid")
+            .unwrap();
+        let rows = query
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+            .unwrap();
+        rows.map(|r| r.unwrap()).collect()
+    }
+
+    #[test]
+    fn batches() {
+        let mut journal = Journal::open(":memory:", "boiler")
+            .unwrap()
+            .batch(3, Duration::from_secs(60));
+        journal
+            .record(&Event::Changed(Table::HoldingRegisters, 3, 42))
+            .unwrap();
+        journal
+            .record(&Event::Error(Error::InvalidResponse))
+            .unwrap();
+        assert!(rows(&journal).is_empty());
+        journal.record(&Event::Changed(Table::Coils, 0, 1)).unwrap();
+        let expected = vec![
+            (
+                "boiler".to_string(),
+                Some("holding_register:3".to_string()),
+                Some(42),
+                "good".to_string(),
+            ),
+            ("boiler".to_string(), None, None, "bad".to_string()),
+            (
+                "boiler".to_string(),
+                Some("coil:0".to_string()),
+                Some(1),
+                "good".to_string(),
+            ),
+        ];
+        assert_eq!(rows(&journal), expected);
+
+        journal = journal.tag_names(|_, addr| format!("temp{}", addr));
+        journal
+            .record(&Event::Changed(Table::InputRegisters, 7, 1))
+            .unwrap();
+        assert_eq!(rows(&journal).len(), 3);
+        journal.flush().unwrap();
+        assert_eq!(rows(&journal)[3].1.as_deref(), Some("temp7"));
+    }
+
+    #[test]
+    fn run_writes_late_batches() {
+        let path = env::temp_dir().join(format!("modbus-journal-{}.db", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut journal = Journal::open(&path, "pump")
+            .unwrap()
+            .batch(100, Duration::from_millis(50));
+        let (events, receiver) = mpsc::channel();
+        let worker = thread::spawn(move || journal.run(&receiver));
+        for addr in 0..5 {
+            events
+                .send(Event::Changed(Table::InputRegisters, addr, addr))
+                .unwrap();
+        }
+
+        // the batch is written after the delay, while the sender is still alive
+        let reader = Connection::open(&path).unwrap();
+        let count = || -> u32 {
+            reader
+                .query_row("SELECT COUNT(*) FROM journal", [], |r| r.get(0))
+                .unwrap()
+        };
+        let start = Instant::now();
+        while count() < 5 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        events
+            .send(Event::Changed(Table::InputRegisters, 0, 9))
+            .unwrap();
+        drop(events);
+        worker.join().unwrap().unwrap();
+        assert_eq!(count(), 6);
+        drop(reader);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,13 +4,13 @@
 //!
 //! ```
 //! # extern crate modbus;
-//! # extern crate test_server;
-//! # use test_server::start_dummy_server;
 //! # fn main() {
 //! use modbus::{Client, Coil};
 //! use modbus::tcp;
-//! # if cfg!(feature = "modbus-server-tests") {
-//! # let (_s, port) = start_dummy_server(Some(22221));
+//! # #[cfg(feature = "server")] {
+//! # let server = modbus::server::Server::bind("127.0.0.1:0", modbus::server::Bank::new(500)).unwrap();
+//! # let port = server.local_addr().unwrap().port();
+//! # std::thread::spawn(move || server.serve());
 //!
 //! let mut cfg = tcp::Config::default();
 //! # cfg.tcp_port = port;
@@ -28,55 +28,59 @@ use std::fmt;
 use std::io;
 use std::str::FromStr;
 
+pub mod address;
+#[cfg(feature = "ascii")]
+pub mod ascii;
 pub mod binary;
+#[cfg(feature = "capture")]
+pub mod capture;
 mod client;
+#[cfg(feature = "device")]
+pub mod device;
 
+#[cfg(feature = "health")]
+pub mod health;
+#[cfg(feature = "journal")]
+pub mod journal;
+#[cfg(feature = "manager")]
+pub mod manager;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod pdu;
+pub mod pymodbus;
+#[cfg(feature = "routing")]
+pub mod routing;
 pub mod scoped;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod shared;
+#[cfg(feature = "tagmap")]
+pub mod tagmap;
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
 
 /// The Modbus TCP backend implements a Modbus variant used for communication over TCP/IPv4 networks.
 pub mod tcp;
-pub use crate::client::Client;
+pub use crate::client::{
+    plan_reads, read_all, read_stable, read_validated, with_unlock, Client, ReadLimits, ReadRequest,
+};
 pub use crate::tcp::Config;
 pub use crate::tcp::Transport;
+/// The TLS library used by `Transport::new_tls_with_cfg`, to build its client configuration.
+#[cfg(feature = "tls")]
+pub use rustls;
 
-type Address = u16;
-type Quantity = u16;
-type Value = u16;
-
-enum Function<'a> {
-    ReadCoils(Address, Quantity),
-    ReadDiscreteInputs(Address, Quantity),
-    ReadHoldingRegisters(Address, Quantity),
-    ReadInputRegisters(Address, Quantity),
-    WriteSingleCoil(Address, Value),
-    WriteSingleRegister(Address, Value),
-    WriteMultipleCoils(Address, Quantity, &'a [u8]),
-    WriteMultipleRegisters(Address, Quantity, &'a [u8]),
-    WriteReadMultipleRegisters(Address, Quantity, &'a [u8], Address, Quantity),
-}
-
-impl<'a> Function<'a> {
-    fn code(&self) -> u8 {
-        match *self {
-            Function::ReadCoils(_, _) => 0x01,
-            Function::ReadDiscreteInputs(_, _) => 0x02,
-            Function::ReadHoldingRegisters(_, _) => 0x03,
-            Function::ReadInputRegisters(_, _) => 0x04,
-            Function::WriteSingleCoil(_, _) => 0x05,
-            Function::WriteSingleRegister(_, _) => 0x06,
-            Function::WriteMultipleCoils(_, _, _) => 0x0f,
-            Function::WriteMultipleRegisters(_, _, _) => 0x10,
-            Function::WriteReadMultipleRegisters(_, _, _, _, _) => 0x17,
-        }
-        // ReadExceptionStatus     = 0x07,
-        // ReportSlaveId           = 0x11,
-        // MaskWriteRegister       = 0x16,
-        // WriteAndReadRegisters   = 0x17
-    }
+/// The four data tables of the Modbus data model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Table {
+    Coils,
+    DiscreteInputs,
+    HoldingRegisters,
+    InputRegisters,
 }
 
 enum_from_primitive! {
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 /// Modbus exception codes returned from the server.
 pub enum ExceptionCode {
     IllegalFunction         = 0x01,
@@ -93,6 +97,28 @@ pub enum ExceptionCode {
 }
 }
 
+enum_from_primitive! {
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Sub-functions of the Diagnostics function (0x08), mostly used on serial lines.
+pub enum DiagnosticsSubFunction {
+    ReturnQueryData                    = 0x00,
+    RestartCommunicationsOption        = 0x01,
+    ReturnDiagnosticRegister           = 0x02,
+    ChangeAsciiInputDelimiter          = 0x03,
+    ForceListenOnlyMode                = 0x04,
+    ClearCountersAndDiagnosticRegister = 0x0a,
+    ReturnBusMessageCount              = 0x0b,
+    ReturnBusCommunicationErrorCount   = 0x0c,
+    ReturnBusExceptionErrorCount       = 0x0d,
+    ReturnSlaveMessageCount            = 0x0e,
+    ReturnSlaveNoResponseCount         = 0x0f,
+    ReturnSlaveNakCount                = 0x10,
+    ReturnSlaveBusyCount               = 0x11,
+    ReturnBusCharacterOverrunCount     = 0x12,
+    ClearOverrunCounterAndFlag         = 0x14
+}
+}
+
 /// `InvalidData` reasons
 #[derive(Debug)]
 pub enum Reason {
@@ -104,6 +130,7 @@ pub enum Reason {
     DecodingError,
     EncodingError,
     InvalidByteorder,
+    InvalidChecksum,
     Custom(String),
 }
 
@@ -117,6 +144,43 @@ pub enum Error {
     InvalidFunction,
     ParseCoilError,
     ParseInfoError,
+    ParseUrlError(String),
+    ConnectionClosed,
+    /// The device closed the connection before it ever answered a request, as devices which
+    /// accept only one or a few connections do while they are taken, with the error of the
+    /// closed connection. Only returned with `tcp::Config::connection_limit_retry`.
+    ConnectionLimit(Box<Error>),
+    /// No response arrived within the read timeout, or a request didn't complete before its
+    /// deadline, e.g. of a `watchdog::Watchdog`.
+    Timeout,
+    /// The value read back after a write differs from the written one, see
+    /// `tcp::Config::verify_writes`. Coils are compared as `0` or `1`.
+    VerificationFailed {
+        addr: u16,
+        expected: u16,
+        actual: u16,
+    },
+    /// An error of a TCP transaction with dumps of the request and response frames, only
+    /// returned with the `debug-frames` feature.
+    #[cfg(feature = "debug-frames")]
+    Frames(Box<Error>, String, String),
+}
+
+impl Error {
+    /// The error without the frame dumps added by the `debug-frames` feature, which is the one
+    /// to match on.
+    pub fn root(&self) -> &Error {
+        match *self {
+            #[cfg(feature = "debug-frames")]
+            Error::Frames(ref err, _, _) => err.root(),
+            _ => self,
+        }
+    }
+
+    /// The numeric code of the error, see `ErrorCode`.
+    pub fn code(&self) -> ErrorCode {
+        self.into()
+    }
 }
 
 impl fmt::Display for Error {
@@ -131,6 +195,28 @@ impl fmt::Display for Error {
             InvalidFunction => write!(f, "invalid modbus function"),
             ParseCoilError => write!(f, "parse coil could not be parsed"),
             ParseInfoError => write!(f, "failed parsing device info as utf8"),
+            ParseUrlError(ref reason) => write!(f, "failed parsing endpoint url: {}", reason),
+            ConnectionClosed => write!(f, "connection closed by peer"),
+            ConnectionLimit(ref err) => write!(
+                f,
+                "no response before the connection broke, \
+                 the device may be limited to fewer connections: {}",
+                err
+            ),
+            Timeout => write!(f, "timed out waiting for the response"),
+            VerificationFailed {
+                addr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "write verification failed at address {}: expected {}, read back {}",
+                addr, expected, actual
+            ),
+            #[cfg(feature = "debug-frames")]
+            Frames(ref err, ref req, ref resp) => {
+                write!(f, "{} (request: {}, response: {})", err, req, resp)
+            }
         }
     }
 }
@@ -147,12 +233,22 @@ impl std::error::Error for Error {
             InvalidFunction => "invalid modbus function",
             ParseCoilError => "parse coil could not be parsed",
             ParseInfoError => "failed parsing device info as utf8",
+            ParseUrlError(_) => "failed parsing endpoint url",
+            ConnectionClosed => "connection closed by peer",
+            ConnectionLimit(_) => "connection limit of the device reached",
+            Timeout => "timed out",
+            VerificationFailed { .. } => "write verification failed",
+            #[cfg(feature = "debug-frames")]
+            Frames(_, _, _) => "error with frame dumps",
         }
     }
 
     fn cause(&self) -> Option<&dyn std::error::Error> {
         match *self {
             Error::Io(ref err) => Some(err),
+            Error::ConnectionLimit(ref err) => Some(err.as_ref()),
+            #[cfg(feature = "debug-frames")]
+            Error::Frames(ref err, _, _) => Some(err.as_ref()),
             _ => None,
         }
     }
@@ -164,15 +260,217 @@ impl From<ExceptionCode> for Error {
     }
 }
 
+/// Reads and writes which time out are reported as `Error::Timeout`, all other I/O errors as
+/// `Error::Io`.
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
-        Error::Io(err)
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+/// The request an error occurred in, see `tcp::Transport::last_error_context` and
+/// `ascii::Transport::last_error_context`.
+///
+/// ```
+/// use modbus::ErrorContext;
+///
+/// let context = ErrorContext::new(&[0x03, 0x00, 0x6b, 0x00, 0x02], 1, Some(7));
+/// assert_eq!(context.address, Some(0x6b));
+/// assert_eq!(context.to_string(), "function 0x03, address 107, unit 1, transaction 7");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// The function code of the request
+    pub function: u8,
+    /// The (first) address of the request, if the function has one, see `pdu::address`
+    pub address: Option<u16>,
+    /// The unit identifier of the request
+    pub uid: u8,
+    /// The transaction identifier of the request, only known for Modbus TCP
+    pub transaction_id: Option<u16>,
+}
+
+impl ErrorContext {
+    /// The context of the request PDU `req` to the unit `uid`.
+    pub fn new(req: &[u8], uid: u8, transaction_id: Option<u16>) -> ErrorContext {
+        ErrorContext {
+            function: req.first().copied().unwrap_or(0),
+            address: pdu::address(req),
+            uid,
+            transaction_id,
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "function 0x{:02x}", self.function)?;
+        if let Some(address) = self.address {
+            write!(f, ", address {}", address)?;
+        }
+        write!(f, ", unit {}", self.uid)?;
+        if let Some(tid) = self.transaction_id {
+            write!(f, ", transaction {}", tid)?;
+        }
+        Ok(())
+    }
+}
+
+/// A numeric code identifying the kind of an `Error`, which is cheap to copy and compare and
+/// never allocates, e.g. for hot loops or allocation free contexts.
+///
+/// Exceptions keep their exception code (`0x01` to `0x0b`), invalid data is identified by its
+/// `Reason` (codes from `0x200`) and all other errors use codes from `0x100`. The details of an
+/// error, e.g. the I/O error or the values of a failed verification, are dropped.
+///
+/// ```
+/// use modbus::{Error, ErrorCode, ExceptionCode, Reason};
+///
+/// let err = Error::Exception(ExceptionCode::IllegalDataAddress);
+/// assert_eq!(ErrorCode::from(&err).code(), 0x02);
+/// let err = Error::InvalidData(Reason::UnexpectedReplySize);
+/// assert_eq!(ErrorCode::from(err), ErrorCode::UNEXPECTED_REPLY_SIZE);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ErrorCode(u16);
+
+impl ErrorCode {
+    pub const IO: ErrorCode = ErrorCode(0x100);
+    pub const INVALID_RESPONSE: ErrorCode = ErrorCode(0x101);
+    pub const INVALID_FUNCTION: ErrorCode = ErrorCode(0x102);
+    pub const PARSE_COIL: ErrorCode = ErrorCode(0x103);
+    pub const PARSE_INFO: ErrorCode = ErrorCode(0x104);
+    pub const PARSE_URL: ErrorCode = ErrorCode(0x105);
+    pub const CONNECTION_CLOSED: ErrorCode = ErrorCode(0x106);
+    pub const VERIFICATION_FAILED: ErrorCode = ErrorCode(0x107);
+    pub const CONNECTION_LIMIT: ErrorCode = ErrorCode(0x108);
+    pub const TIMEOUT: ErrorCode = ErrorCode(0x109);
+    pub const UNEXPECTED_REPLY_SIZE: ErrorCode = ErrorCode(0x200);
+    pub const BYTECOUNT_NOT_EVEN: ErrorCode = ErrorCode(0x201);
+    pub const SEND_BUFFER_EMPTY: ErrorCode = ErrorCode(0x202);
+    pub const RECV_BUFFER_EMPTY: ErrorCode = ErrorCode(0x203);
+    pub const SEND_BUFFER_TOO_BIG: ErrorCode = ErrorCode(0x204);
+    pub const DECODING_ERROR: ErrorCode = ErrorCode(0x205);
+    pub const ENCODING_ERROR: ErrorCode = ErrorCode(0x206);
+    pub const INVALID_BYTEORDER: ErrorCode = ErrorCode(0x207);
+    pub const INVALID_CHECKSUM: ErrorCode = ErrorCode(0x208);
+    /// Invalid data with a `Reason::Custom`
+    pub const INVALID_DATA: ErrorCode = ErrorCode(0x2ff);
+
+    /// The numeric value of the code.
+    pub fn code(self) -> u16 {
+        self.0
+    }
+
+    /// The exception code if the error is a modbus exception.
+    pub fn exception(self) -> Option<ExceptionCode> {
+        u8::try_from(self.0)
+            .ok()
+            .and_then(enum_primitive::FromPrimitive::from_u8)
+    }
+
+    /// A short static description of the error.
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorCode::IO => "I/O error",
+            ErrorCode::INVALID_RESPONSE => "invalid response",
+            ErrorCode::INVALID_FUNCTION => "invalid modbus function",
+            ErrorCode::PARSE_COIL => "parse coil could not be parsed",
+            ErrorCode::PARSE_INFO => "failed parsing device info as utf8",
+            ErrorCode::PARSE_URL => "failed parsing endpoint url",
+            ErrorCode::CONNECTION_CLOSED => "connection closed by peer",
+            ErrorCode::VERIFICATION_FAILED => "write verification failed",
+            ErrorCode::CONNECTION_LIMIT => "connection limit of the device reached",
+            ErrorCode::TIMEOUT => "timed out",
+            ErrorCode::UNEXPECTED_REPLY_SIZE => "invalid data: unexpected reply size",
+            ErrorCode::BYTECOUNT_NOT_EVEN => "invalid data: byte count not even",
+            ErrorCode::SEND_BUFFER_EMPTY => "invalid data: send buffer empty",
+            ErrorCode::RECV_BUFFER_EMPTY => "invalid data: receive buffer empty",
+            ErrorCode::SEND_BUFFER_TOO_BIG => "invalid data: send buffer too big",
+            ErrorCode::DECODING_ERROR => "invalid data: decoding error",
+            ErrorCode::ENCODING_ERROR => "invalid data: encoding error",
+            ErrorCode::INVALID_BYTEORDER => "invalid data: invalid byte order",
+            ErrorCode::INVALID_CHECKSUM => "invalid data: invalid checksum",
+            ErrorCode::INVALID_DATA => "invalid data",
+            _ if self.exception().is_some() => "modbus exception",
+            _ => "unknown error",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.exception() {
+            Some(code) => write!(f, "modbus exception: {:?}", code),
+            None => f.write_str(self.description()),
+        }
+    }
+}
+
+impl From<ExceptionCode> for ErrorCode {
+    fn from(code: ExceptionCode) -> ErrorCode {
+        ErrorCode(code as u16)
+    }
+}
+
+impl<'a> From<&'a Error> for ErrorCode {
+    fn from(err: &'a Error) -> ErrorCode {
+        match *err.root() {
+            Error::Exception(code) => code.into(),
+            Error::Io(_) => ErrorCode::IO,
+            Error::InvalidResponse => ErrorCode::INVALID_RESPONSE,
+            Error::InvalidData(ref reason) => match *reason {
+                Reason::UnexpectedReplySize => ErrorCode::UNEXPECTED_REPLY_SIZE,
+                Reason::BytecountNotEven => ErrorCode::BYTECOUNT_NOT_EVEN,
+                Reason::SendBufferEmpty => ErrorCode::SEND_BUFFER_EMPTY,
+                Reason::RecvBufferEmpty => ErrorCode::RECV_BUFFER_EMPTY,
+                Reason::SendBufferTooBig => ErrorCode::SEND_BUFFER_TOO_BIG,
+                Reason::DecodingError => ErrorCode::DECODING_ERROR,
+                Reason::EncodingError => ErrorCode::ENCODING_ERROR,
+                Reason::InvalidByteorder => ErrorCode::INVALID_BYTEORDER,
+                Reason::InvalidChecksum => ErrorCode::INVALID_CHECKSUM,
+                Reason::Custom(_) => ErrorCode::INVALID_DATA,
+            },
+            Error::InvalidFunction => ErrorCode::INVALID_FUNCTION,
+            Error::ParseCoilError => ErrorCode::PARSE_COIL,
+            Error::ParseInfoError => ErrorCode::PARSE_INFO,
+            Error::ParseUrlError(_) => ErrorCode::PARSE_URL,
+            Error::ConnectionClosed => ErrorCode::CONNECTION_CLOSED,
+            Error::ConnectionLimit(_) => ErrorCode::CONNECTION_LIMIT,
+            Error::Timeout => ErrorCode::TIMEOUT,
+            Error::VerificationFailed { .. } => ErrorCode::VERIFICATION_FAILED,
+            #[cfg(feature = "debug-frames")]
+            Error::Frames(..) => unreachable!("root() strips the frame dumps"),
+        }
+    }
+}
+
+impl From<Error> for ErrorCode {
+    fn from(err: Error) -> ErrorCode {
+        ErrorCode::from(&err)
     }
 }
 
 /// Result type used to nofify success or failure in communication
 pub type Result<T> = std::result::Result<T, Error>;
 
+// Fill `buf` from `stream`, retrying interrupted reads. A read returning no data means that the
+// peer closed the connection, which is reported as `Error::ConnectionClosed`.
+fn read_exact<R: io::Read>(stream: &mut R, mut buf: &mut [u8]) -> Result<()> {
+    while !buf.is_empty() {
+        match stream.read(buf) {
+            Ok(0) => return Err(Error::ConnectionClosed),
+            Ok(n) => buf = &mut buf[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
 /// Single bit status values, used in read or write coil functions
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Coil {
@@ -223,6 +521,43 @@ impl std::ops::Not for Coil {
     }
 }
 
+/// A record of registers in a file, used by the file record functions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileRecord {
+    /// The file the record is in
+    pub file_number: u16,
+    /// The register offset of the record in the file (`0..=9999`)
+    pub record_number: u16,
+    /// The register values of the record
+    pub record_data: Vec<u16>,
+}
+
+/// Identification of a device as returned by the Report Slave ID function
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SlaveId {
+    /// Device specific identifier
+    pub slave_id: u8,
+    /// Whether the device is running
+    pub run_indicator: bool,
+    /// Device specific additional data
+    pub additional_data: Vec<u8>,
+}
+
+impl SlaveId {
+    // Encode as Report Slave ID response PDU.
+    #[cfg(feature = "server")]
+    fn to_response(&self) -> Vec<u8> {
+        let mut pdu = vec![
+            crate::pdu::Function::ReportSlaveId.code(),
+            (self.additional_data.len() + 2) as u8,
+            self.slave_id,
+            if self.run_indicator { 0xff } else { 0x00 },
+        ];
+        pdu.extend_from_slice(&self.additional_data);
+        pdu
+    }
+}
+
 #[cfg(feature = "read-device-info")]
 /// Types specific to the special ReadDeviceInfo function
 pub mod mei {
@@ -263,13 +598,15 @@ pub mod mei {
         pub fn new(obj_id: u8, value: String) -> Self {
             Self { id: obj_id, value }
         }
-        pub fn to_string(&self) -> String {
-            self.value.clone()
-        }
         pub fn id(&self) -> u8 {
             self.id
         }
     }
+    impl std::fmt::Display for DeviceInfoObject {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str(&self.value)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -284,4 +621,52 @@ mod tests {
         let b: Coil = false.into();
         assert_eq!(a, !b);
     }
+
+    #[test]
+    fn test_error_code() {
+        let codes = [
+            Error::Exception(ExceptionCode::GatewayTarget).into(),
+            Error::Io(io::ErrorKind::Other.into()).into(),
+            Error::InvalidData(Reason::InvalidChecksum).into(),
+            Error::InvalidData(Reason::Custom("x".into())).into(),
+            Error::ConnectionClosed.code(),
+            Error::from(io::Error::from(io::ErrorKind::WouldBlock)).code(),
+        ];
+        assert_eq!(
+            codes.map(ErrorCode::code),
+            [0x0b, 0x100, 0x208, 0x2ff, 0x106, 0x109]
+        );
+        assert_eq!(codes[0].exception(), Some(ExceptionCode::GatewayTarget));
+        assert_eq!(codes[1].exception(), None);
+        assert_eq!(codes[0].to_string(), "modbus exception: GatewayTarget");
+        assert_eq!(codes[2].to_string(), "invalid data: invalid checksum");
+    }
+
+    #[test]
+    fn test_read_exact() {
+        // yields one byte per read and is interrupted before every read
+        struct Flaky(Vec<u8>, bool);
+        impl io::Read for Flaky {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1 = !self.1;
+                if self.1 {
+                    return Err(io::ErrorKind::Interrupted.into());
+                }
+                if self.0.is_empty() {
+                    return Ok(0);
+                }
+                buf[0] = self.0.remove(0);
+                Ok(1)
+            }
+        }
+
+        let mut stream = Flaky(vec![1, 2, 3], false);
+        let mut buf = [0; 2];
+        assert!(read_exact(&mut stream, &mut buf).is_ok());
+        assert_eq!(buf, [1, 2]);
+        match read_exact(&mut stream, &mut buf) {
+            Err(Error::ConnectionClosed) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
 }
@@ -0,0 +1,321 @@
+//! A manager owning the transports to many named hosts
+//!
+//! Connections are established lazily on first use and dropped again when an I/O error or a
+//! timeout occurs or the host closes the connection, so the next call to the same host transparently reconnects.
+//! Per host statistics are collected and can be queried with `Manager::status`.
+//!
+//! A host can have fallback endpoints, e.g. a backup cellular link or the IPv6 address of a
+//! dual-stack device, which are tried in order when connecting to the current endpoint fails.
+//! The manager sticks to an endpoint as long as it can connect to it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use modbus::Client;
+//! use modbus::manager::{Host, Manager};
+//! use modbus::tcp::Config;
+//!
+//! let mut manager = Manager::new(vec![
+//!     Host::new("boiler", "192.168.0.10", Config::default()),
+//!     Host::new("pump", "192.168.0.11", Config::default()),
+//! ]);
+//!
+//! let temperature = manager.call("boiler", |t| t.read_input_registers(0, 1));
+//! for (name, status) in manager.status() {
+//!     println!("{}: {:?}", name, status);
+//! }
+//! ```
+
+use std::time::SystemTime;
+
+use crate::tcp::Config;
+use crate::{Error, Reason, Result, Transport};
+
+/// Connection parameters of a single named host.
+#[derive(Clone)]
+pub struct Host {
+    /// The name the host is addressed with in the `Manager`
+    pub name: String,
+    /// IP address or hostname of the host
+    pub addr: String,
+    /// Transport configuration used when (re)connecting
+    pub cfg: Config,
+    /// Further endpoints of the same device as address and configuration, tried in this order
+    /// after `addr`
+    pub fallbacks: Vec<(String, Config)>,
+}
+
+impl Host {
+    pub fn new(name: &str, addr: &str, cfg: Config) -> Host {
+        Host {
+            name: name.to_string(),
+            addr: addr.to_string(),
+            cfg,
+            fallbacks: vec![],
+        }
+    }
+
+    /// Add the fallback endpoint `addr` with `cfg`.
+    pub fn with_fallback(mut self, addr: &str, cfg: Config) -> Host {
+        self.fallbacks.push((addr.to_string(), cfg));
+        self
+    }
+
+    // The endpoint with index `i`, `0` is the primary one.
+    fn endpoint(&self, i: usize) -> (&str, Config) {
+        match i {
+            0 => (&self.addr, self.cfg),
+            _ => (&self.fallbacks[i - 1].0, self.fallbacks[i - 1].1),
+        }
+    }
+
+    /// Create a host from an endpoint url, see `Config::from_url` for the syntax.
+    pub fn from_url(name: &str, url: &str) -> Result<Host> {
+        let (addr, cfg) = Config::from_url(url)?;
+        Ok(Host {
+            name: name.to_string(),
+            addr,
+            cfg,
+            fallbacks: vec![],
+        })
+    }
+}
+
+/// Health and usage statistics of a managed host.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Status {
+    /// Whether a connection is currently established
+    pub connected: bool,
+    /// Number of calls issued to the host
+    pub calls: u64,
+    /// Number of calls which failed, including failed connection attempts
+    pub errors: u64,
+    /// Number of connections established
+    pub connects: u64,
+    /// Time of the last successful call
+    pub last_success: Option<SystemTime>,
+    /// Index of the endpoint connected to last, `0` is the primary one and `1` the first
+    /// fallback
+    pub endpoint: usize,
+}
+
+struct Entry {
+    host: Host,
+    transport: Option<Transport>,
+    status: Status,
+}
+
+/// Owns the transports to a list of hosts and hands them out by name.
+pub struct Manager {
+    entries: Vec<Entry>,
+}
+
+impl Manager {
+    /// Create a new manager for `hosts`, no connection is established before first use.
+    pub fn new<I: IntoIterator<Item = Host>>(hosts: I) -> Manager {
+        Manager {
+            entries: hosts
+                .into_iter()
+                .map(|host| Entry {
+                    host,
+                    transport: None,
+                    status: Status::default(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Add another host, replacing (and disconnecting) a host with the same name.
+    pub fn add(&mut self, host: Host) {
+        self.remove(&host.name);
+        self.entries.push(Entry {
+            host,
+            transport: None,
+            status: Status::default(),
+        });
+    }
+
+    /// Remove the host `name`, returns `false` if there was no such host.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.host.name != name);
+        len != self.entries.len()
+    }
+
+    /// Names of all managed hosts in the order they were added.
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.iter().map(|e| e.host.name.as_str()).collect()
+    }
+
+    /// Run `fun` with the transport of host `name`, connecting first if necessary.
+    ///
+    /// If `fun` fails with an I/O error, a timeout or because the host closed the connection, the
+    /// connection is dropped and re-established on the next call.
+    pub fn call<T, F>(&mut self, name: &str, fun: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transport) -> Result<T>,
+    {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.host.name == name)
+            .ok_or_else(|| Error::InvalidData(Reason::Custom(format!("unknown host {}", name))))?;
+        entry.status.calls += 1;
+
+        if entry.transport.is_none() {
+            match entry.connect() {
+                Ok(t) => {
+                    entry.status.connects += 1;
+                    entry.transport = Some(t);
+                }
+                Err(e) => {
+                    entry.status.errors += 1;
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let res = fun(entry.transport.as_mut().unwrap());
+        match res {
+            Ok(_) => entry.status.last_success = Some(SystemTime::now()),
+            Err(ref e) => {
+                entry.status.errors += 1;
+                if let Error::Io(_)
+                | Error::Timeout
+                | Error::ConnectionClosed
+                | Error::ConnectionLimit(_) = *e.root()
+                {
+                    entry.transport = None;
+                }
+            }
+        }
+        res
+    }
+
+    /// Close the connection to host `name`, it will be reconnected on the next call.
+    pub fn disconnect(&mut self, name: &str) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.host.name == name) {
+            if let Some(mut t) = entry.transport.take() {
+                let _ = t.close();
+            }
+        }
+    }
+
+    /// The status of a single host.
+    pub fn host_status(&self, name: &str) -> Option<Status> {
+        self.entries
+            .iter()
+            .find(|e| e.host.name == name)
+            .map(Entry::status)
+    }
+
+    /// The status of all hosts in the order they were added.
+    pub fn status(&self) -> Vec<(&str, Status)> {
+        self.entries
+            .iter()
+            .map(|e| (e.host.name.as_str(), e.status()))
+            .collect()
+    }
+}
+
+impl Entry {
+    // Connect to the endpoint used last, then to the others in order.
+    fn connect(&mut self) -> std::io::Result<Transport> {
+        let current = self.status.endpoint;
+        let mut last_err = None;
+        for i in std::iter::once(current)
+            .chain((0..=self.host.fallbacks.len()).filter(|&i| i != current))
+        {
+            let (addr, cfg) = self.host.endpoint(i);
+            match Transport::new_with_cfg(addr, cfg) {
+                Ok(t) => {
+                    self.status.endpoint = i;
+                    return Ok(t);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap())
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            connected: self.transport.is_some(),
+            ..self.status
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn unknown_host() {
+        let mut manager = Manager::new(vec![]);
+        assert!(manager.call("nope", |_| Ok(())).is_err());
+        assert_eq!(manager.host_status("nope"), None);
+    }
+
+    #[test]
+    fn connect_and_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let mut manager = Manager::new(vec![Host::new("dev", "127.0.0.1", cfg)]);
+        assert_eq!(manager.names(), vec!["dev"]);
+
+        assert!(manager.call("dev", |_| Ok(())).is_ok());
+        let status = manager.host_status("dev").unwrap();
+        assert!(status.connected);
+        assert_eq!((status.calls, status.connects, status.errors), (1, 1, 0));
+        assert!(status.last_success.is_some());
+
+        let res: Result<()> = manager.call("dev", |_| {
+            Err(Error::Io(std::io::ErrorKind::BrokenPipe.into()))
+        });
+        assert!(res.is_err());
+        let status = manager.host_status("dev").unwrap();
+        assert!(!status.connected);
+        assert_eq!((status.calls, status.errors), (2, 1));
+
+        assert!(manager.call("dev", |_| Ok(())).is_ok());
+        assert_eq!(manager.host_status("dev").unwrap().connects, 2);
+
+        assert!(manager.remove("dev"));
+        assert!(manager.status().is_empty());
+    }
+
+    #[test]
+    fn fallback_endpoints() {
+        let cfg = |listener: &TcpListener| Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let primary = TcpListener::bind("127.0.0.1:0").unwrap();
+        let backup = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = Host::new("dev", "127.0.0.1", cfg(&primary))
+            .with_fallback("127.0.0.1", cfg(&backup))
+            .with_fallback("::1", cfg(&backup));
+        let primary_cfg = cfg(&primary);
+        drop(primary);
+        let mut manager = Manager::new(vec![host]);
+
+        assert!(manager.call("dev", |_| Ok(())).is_ok());
+        assert_eq!(manager.host_status("dev").unwrap().endpoint, 1);
+
+        // the primary is back, but the manager sticks to the backup
+        let _primary = TcpListener::bind(("127.0.0.1", primary_cfg.tcp_port)).unwrap();
+        manager.disconnect("dev");
+        assert!(manager.call("dev", |_| Ok(())).is_ok());
+        assert_eq!(manager.host_status("dev").unwrap().endpoint, 1);
+
+        drop(backup);
+        manager.disconnect("dev");
+        assert!(manager.call("dev", |_| Ok(())).is_ok());
+        assert_eq!(manager.host_status("dev").unwrap().endpoint, 0);
+    }
+}
@@ -0,0 +1,258 @@
+//! An in-memory client for unit testing code using the `Client` trait
+//!
+//! A `MockClient` executes requests against the tables of a `Bank`, exactly like a `Server`
+//! would, without any network connection or test server. Every request is recorded, so tests
+//! can check what was sent, and errors or exceptions can be scripted for the next requests.
+//!
+//! # Examples
+//!
+//! ```
+//! use modbus::mock::MockClient;
+//! use modbus::server::Bank;
+//! use modbus::{Client, Error, ExceptionCode};
+//!
+//! let mut client = MockClient::new(Bank::new(10));
+//! client.bank.input_registers[2] = 42;
+//! assert_eq!(client.read_input_registers(2, 1).unwrap(), vec![42]);
+//!
+//! client.push_error(Error::Exception(ExceptionCode::SlaveOrServerBusy));
+//! assert!(client.write_single_register(0, 1).is_err());
+//! assert!(client.write_single_register(0, 1).is_ok());
+//! assert_eq!(client.requests().len(), 3);
+//! ```
+
+use std::collections::VecDeque;
+
+use crate::pdu::{self, Function};
+use crate::server::Bank;
+use crate::{binary, Client, Coil, DiagnosticsSubFunction, Error, FileRecord, Result, SlaveId};
+
+#[cfg(feature = "read-device-info")]
+use crate::mei;
+
+/// A request issued to a `MockClient`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    /// The unit id set when the request was issued
+    pub uid: u8,
+    /// The request PDU, starting with the function code
+    pub pdu: Vec<u8>,
+}
+
+/// A `Client` answering requests from an in-memory `Bank`.
+pub struct MockClient {
+    /// The tables the requests are executed on
+    pub bank: Bank,
+    uid: u8,
+    requests: Vec<Request>,
+    errors: VecDeque<Error>,
+}
+
+impl MockClient {
+    /// Create a client executing requests on `bank`, addressing unit id `1`.
+    pub fn new(bank: Bank) -> MockClient {
+        MockClient {
+            bank,
+            uid: 1,
+            requests: vec![],
+            errors: VecDeque::new(),
+        }
+    }
+
+    /// The requests issued so far, the oldest first.
+    pub fn requests(&self) -> &[Request] {
+        &self.requests
+    }
+
+    /// Forget the requests issued so far.
+    pub fn clear_requests(&mut self) {
+        self.requests.clear();
+    }
+
+    /// Fail the next request which isn't failed by an earlier pushed error with `err`, without
+    /// executing it. The request is recorded nevertheless.
+    pub fn push_error(&mut self, err: Error) {
+        self.errors.push_back(err);
+    }
+
+    // Record and execute the request `req` and return the response PDU after checking it.
+    fn transact(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        self.requests.push(Request {
+            uid: self.uid,
+            pdu: req.to_vec(),
+        });
+        if let Some(err) = self.errors.pop_front() {
+            return Err(err);
+        }
+        let resp = self.bank.process(req);
+        pdu::check_response(req, &resp)?;
+        Ok(resp)
+    }
+
+    fn write(&mut self, req: &[u8]) -> Result<()> {
+        let resp = self.transact(req)?;
+        pdu::check_write_echo(req, &resp, false)
+    }
+}
+
+impl Client for MockClient {
+    fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+        let req = Function::ReadCoils(addr, count).request()?;
+        pdu::parse_bits(&self.transact(&req)?, count)
+    }
+
+    fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+        let req = Function::ReadDiscreteInputs(addr, count).request()?;
+        pdu::parse_bits(&self.transact(&req)?, count)
+    }
+
+    fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadHoldingRegisters(addr, count).request()?;
+        pdu::parse_registers(&self.transact(&req)?, count)
+    }
+
+    fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadInputRegisters(addr, count).request()?;
+        pdu::parse_registers(&self.transact(&req)?, count)
+    }
+
+    fn write_single_coil(&mut self, addr: u16, value: Coil) -> Result<()> {
+        self.write(&Function::WriteSingleCoil(addr, value.code()).request()?)
+    }
+
+    fn write_single_register(&mut self, addr: u16, value: u16) -> Result<()> {
+        self.write(&Function::WriteSingleRegister(addr, value).request()?)
+    }
+
+    fn write_multiple_coils(&mut self, addr: u16, values: &[Coil]) -> Result<()> {
+        let bytes = binary::pack_bits(values);
+        self.write(&Function::WriteMultipleCoils(addr, values.len() as u16, &bytes).request()?)
+    }
+
+    fn write_multiple_registers(&mut self, addr: u16, values: &[u16]) -> Result<()> {
+        let bytes = binary::unpack_bytes(values);
+        self.write(&Function::WriteMultipleRegisters(addr, values.len() as u16, &bytes).request()?)
+    }
+
+    fn read_exception_status(&mut self) -> Result<u8> {
+        pdu::parse_exception_status(&self.transact(&Function::ReadExceptionStatus.request()?)?)
+    }
+
+    fn diagnostics(&mut self, sub: DiagnosticsSubFunction, data: &[u16]) -> Result<Vec<u16>> {
+        let req = Function::Diagnostics(sub, data).request()?;
+        pdu::parse_diagnostics(sub, &self.transact(&req)?)
+    }
+
+    fn report_slave_id(&mut self) -> Result<SlaveId> {
+        pdu::parse_slave_id(&self.transact(&Function::ReportSlaveId.request()?)?)
+    }
+
+    fn mask_write_register(&mut self, addr: u16, and_mask: u16, or_mask: u16) -> Result<()> {
+        self.write(&Function::MaskWriteRegister(addr, and_mask, or_mask).request()?)
+    }
+
+    fn write_read_multiple_registers(
+        &mut self,
+        write_address: u16,
+        write_quantity: u16,
+        write_values: &[u16],
+        read_address: u16,
+        read_quantity: u16,
+    ) -> Result<Vec<u16>> {
+        let write_bytes = binary::unpack_bytes(write_values);
+        let req = Function::WriteReadMultipleRegisters(
+            write_address,
+            write_quantity,
+            &write_bytes,
+            read_address,
+            read_quantity,
+        )
+        .request()?;
+        pdu::parse_registers(&self.transact(&req)?, read_quantity)
+    }
+
+    fn read_fifo_queue(&mut self, addr: u16) -> Result<Vec<u16>> {
+        pdu::parse_fifo_queue(&self.transact(&Function::ReadFifoQueue(addr).request()?)?)
+    }
+
+    fn read_file_records(&mut self, records: &[(u16, u16, u16)]) -> Result<Vec<FileRecord>> {
+        let req = Function::ReadFileRecord(records).request()?;
+        pdu::parse_file_records(records, &self.transact(&req)?)
+    }
+
+    fn write_file_records(&mut self, records: &[FileRecord]) -> Result<()> {
+        self.write(&Function::WriteFileRecord(records).request()?)
+    }
+
+    /// Set the unit identifier recorded with the following requests, the bank answers all of
+    /// them.
+    fn set_uid(&mut self, uid: u8) {
+        self.uid = uid;
+    }
+
+    #[cfg(feature = "read-device-info")]
+    fn read_device_info(
+        &mut self,
+        obj_category: mei::DeviceInfoCategory,
+    ) -> Result<Vec<mei::DeviceInfoObject>> {
+        let req = Function::ReadDeviceInfo(obj_category).request()?;
+        pdu::parse_device_info(&self.transact(&req)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExceptionCode;
+
+    #[test]
+    fn requests_and_errors() {
+        let mut client = MockClient::new(Bank::new(4));
+        client.bank.discrete_inputs[1] = Coil::On;
+        assert_eq!(
+            client.read_discrete_inputs(0, 2).unwrap(),
+            vec![Coil::Off, Coil::On]
+        );
+        client.set_uid(3);
+        client
+            .write_multiple_coils_chunked(2, &[Coil::On, Coil::On])
+            .unwrap();
+        assert_eq!(
+            client.bank.coils,
+            vec![Coil::Off, Coil::Off, Coil::On, Coil::On]
+        );
+        assert_eq!(
+            client.requests(),
+            &[
+                Request {
+                    uid: 1,
+                    pdu: vec![0x02, 0x00, 0x00, 0x00, 0x02],
+                },
+                Request {
+                    uid: 3,
+                    pdu: vec![0x0f, 0x00, 0x02, 0x00, 0x02, 0x01, 0x03],
+                },
+            ]
+        );
+
+        client.clear_requests();
+        client.push_error(Error::ConnectionClosed);
+        client.push_error(Error::Exception(ExceptionCode::SlaveOrServerBusy));
+        match client.write_single_register(0, 1) {
+            Err(Error::ConnectionClosed) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match client.write_single_register(0, 1) {
+            Err(Error::Exception(ExceptionCode::SlaveOrServerBusy)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(client.bank.holding_registers[0], 0);
+        match client.read_holding_registers(3, 2) {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(client.write_single_register(0, 1).is_ok());
+        assert_eq!(client.bank.holding_registers[0], 1);
+        assert_eq!(client.requests().len(), 4);
+    }
+}
@@ -0,0 +1,722 @@
+//! Transport independent encoding and decoding of protocol data units
+//!
+//! A PDU is the function code followed by the function specific data, the part of a Modbus frame
+//! which is the same on every link. The transports of this crate only add their framing (MBAP
+//! header, unit id, checksum, ...) around the request PDUs encoded here and hand the PDU of the
+//! response back to the parsers of this module. Transports for other links, e.g. Modbus over CAN
+//! or websockets, can be built the same way.
+//!
+//! # Examples
+//!
+//! ```
+//! use modbus::pdu::{self, Function};
+//!
+//! let req = Function::ReadHoldingRegisters(0x6b, 2).request().unwrap();
+//! assert_eq!(req, [0x03, 0x00, 0x6b, 0x00, 0x02]);
+//!
+//! // send `req` over the link and receive the response PDU
+//! let resp = [0x03, 0x04, 0x02, 0x2b, 0x00, 0x64];
+//! pdu::check_response(&req, &resp).unwrap();
+//! assert_eq!(pdu::parse_registers(&resp, 2).unwrap(), vec![0x022b, 0x0064]);
+//! ```
+
+use enum_primitive::FromPrimitive;
+
+use crate::{
+    binary, Coil, DiagnosticsSubFunction, Error, ExceptionCode, FileRecord, Reason, Result, SlaveId,
+};
+
+#[cfg(feature = "read-device-info")]
+use crate::mei::{DeviceInfoCategory, DeviceInfoObject};
+
+/// Maximum size of a PDU, limited by the 256 byte frame of the serial line.
+pub const MAX_PDU_SIZE: usize = 253;
+/// Maximum number of coils or discrete inputs read by a single request.
+pub const MAX_READ_BITS: u16 = 2000;
+/// Maximum number of registers read by a single request.
+pub const MAX_READ_REGISTERS: u16 = 125;
+/// Maximum number of coils written by a single request.
+pub const MAX_WRITE_BITS: u16 = 1968;
+/// Maximum number of registers written by a single request.
+pub const MAX_WRITE_REGISTERS: u16 = 123;
+/// Maximum number of registers written by a combined write and read request.
+pub const MAX_WRITE_READ_REGISTERS: u16 = 121;
+
+/// Reference type of every sub-request of the file record functions.
+const FILE_REFERENCE_TYPE: u8 = 0x06;
+/// Maximum size of the request data of the file record functions.
+const MAX_FILE_REQUEST_DATA: usize = 0xf5;
+const MAX_RECORD_NUMBER: u16 = 9999;
+
+type Address = u16;
+type Quantity = u16;
+type Value = u16;
+
+/// A request of a Modbus function with its parameters.
+///
+/// Values of the multiple write functions are passed encoded, as packed bits
+/// (`binary::pack_bits`) or big endian registers (`binary::unpack_bytes`).
+pub enum Function<'a> {
+    ReadCoils(Address, Quantity),
+    ReadDiscreteInputs(Address, Quantity),
+    ReadHoldingRegisters(Address, Quantity),
+    ReadInputRegisters(Address, Quantity),
+    ReadExceptionStatus,
+    ReportSlaveId,
+    Diagnostics(DiagnosticsSubFunction, &'a [u16]),
+    /// The value is `0xff00` for `Coil::On` and `0x0000` for `Coil::Off`
+    WriteSingleCoil(Address, Value),
+    WriteSingleRegister(Address, Value),
+    WriteMultipleCoils(Address, Quantity, &'a [u8]),
+    WriteMultipleRegisters(Address, Quantity, &'a [u8]),
+    /// Address, AND mask and OR mask
+    MaskWriteRegister(Address, Value, Value),
+    /// Write address, quantity and values, read address and quantity
+    WriteReadMultipleRegisters(Address, Quantity, &'a [u8], Address, Quantity),
+    ReadFifoQueue(Address),
+    /// Sub-requests as `(file_number, record_number, record_length)`
+    ReadFileRecord(&'a [(u16, u16, u16)]),
+    WriteFileRecord(&'a [FileRecord]),
+    #[cfg(feature = "read-device-info")]
+    ReadDeviceInfo(DeviceInfoCategory),
+    /// Any other, e.g. vendor specific, function code (`1..=127`) with its request data
+    Custom(u8, &'a [u8]),
+}
+
+impl<'a> Function<'a> {
+    /// The function code of the request.
+    pub fn code(&self) -> u8 {
+        match *self {
+            Function::ReadCoils(_, _) => 0x01,
+            Function::ReadDiscreteInputs(_, _) => 0x02,
+            Function::ReadHoldingRegisters(_, _) => 0x03,
+            Function::ReadInputRegisters(_, _) => 0x04,
+            Function::ReadExceptionStatus => 0x07,
+            Function::Diagnostics(_, _) => 0x08,
+            Function::ReportSlaveId => 0x11,
+            Function::WriteSingleCoil(_, _) => 0x05,
+            Function::WriteSingleRegister(_, _) => 0x06,
+            Function::WriteMultipleCoils(_, _, _) => 0x0f,
+            Function::WriteMultipleRegisters(_, _, _) => 0x10,
+            Function::MaskWriteRegister(_, _, _) => 0x16,
+            Function::WriteReadMultipleRegisters(_, _, _, _, _) => 0x17,
+            Function::ReadFifoQueue(_) => 0x18,
+            Function::ReadFileRecord(_) => 0x14,
+            Function::WriteFileRecord(_) => 0x15,
+            #[cfg(feature = "read-device-info")]
+            Function::ReadDeviceInfo(_) => 0x2b,
+            Function::Custom(code, _) => code,
+        }
+    }
+
+    /// Encode the request PDU, checking the parameters against the limits of the specification.
+    pub fn request(&self) -> Result<Vec<u8>> {
+        let mut pdu = vec![self.code()];
+        match *self {
+            Function::ReadCoils(addr, count) | Function::ReadDiscreteInputs(addr, count) => {
+                check_read_quantity(count, MAX_READ_BITS)?;
+                push_u16s(&mut pdu, &[addr, count]);
+            }
+            Function::ReadHoldingRegisters(addr, count)
+            | Function::ReadInputRegisters(addr, count) => {
+                check_read_quantity(count, MAX_READ_REGISTERS)?;
+                push_u16s(&mut pdu, &[addr, count]);
+            }
+            Function::ReadExceptionStatus | Function::ReportSlaveId => (),
+            Function::Diagnostics(sub, data) => {
+                // function code and sub-function leave 250 bytes of the PDU for data
+                if data.len() > 125 {
+                    return Err(Error::InvalidData(Reason::SendBufferTooBig));
+                }
+                push_u16s(&mut pdu, &[sub as u16]);
+                push_u16s(&mut pdu, data);
+            }
+            Function::WriteSingleCoil(addr, value) | Function::WriteSingleRegister(addr, value) => {
+                push_u16s(&mut pdu, &[addr, value]);
+            }
+            Function::WriteMultipleCoils(addr, quantity, values)
+            | Function::WriteMultipleRegisters(addr, quantity, values) => {
+                let (max, items) = match *self {
+                    Function::WriteMultipleCoils(..) => (MAX_WRITE_BITS, "coils"),
+                    _ => (MAX_WRITE_REGISTERS, "registers"),
+                };
+                if quantity > max {
+                    return Err(Error::InvalidData(Reason::Custom(format!(
+                        "{} {} exceed the limit of {} per write request",
+                        quantity, items, max
+                    ))));
+                }
+                if pdu.len() + 5 + values.len() > MAX_PDU_SIZE {
+                    return Err(Error::InvalidData(Reason::SendBufferTooBig));
+                }
+                push_u16s(&mut pdu, &[addr, quantity]);
+                pdu.push(values.len() as u8);
+                pdu.extend_from_slice(values);
+            }
+            Function::MaskWriteRegister(addr, and_mask, or_mask) => {
+                push_u16s(&mut pdu, &[addr, and_mask, or_mask]);
+            }
+            Function::WriteReadMultipleRegisters(
+                write_addr,
+                write_quantity,
+                write_values,
+                read_addr,
+                read_quantity,
+            ) => {
+                self.check_write_read_quantities()?;
+                push_u16s(
+                    &mut pdu,
+                    &[read_addr, read_quantity, write_addr, write_quantity],
+                );
+                pdu.push(write_values.len() as u8);
+                pdu.extend_from_slice(write_values);
+            }
+            Function::ReadFifoQueue(addr) => push_u16s(&mut pdu, &[addr]),
+            Function::ReadFileRecord(records) => return file_read_request(records),
+            Function::WriteFileRecord(records) => return file_write_request(records),
+            #[cfg(feature = "read-device-info")]
+            Function::ReadDeviceInfo(category) => {
+                pdu.extend_from_slice(&[
+                    0x0E, // MEI Type 14 (Read Device Identification)
+                    match category {
+                        DeviceInfoCategory::Basic => 0x01,
+                        DeviceInfoCategory::Regular => 0x02,
+                        DeviceInfoCategory::Extended => 0x03,
+                    },
+                    0x00, // Object ID
+                ]);
+            }
+            Function::Custom(code, data) => {
+                // codes with the high bit set are exception responses
+                if code == 0 || code & 0x80 != 0 {
+                    return Err(Error::InvalidFunction);
+                }
+                if pdu.len() + data.len() > MAX_PDU_SIZE {
+                    return Err(Error::InvalidData(Reason::SendBufferTooBig));
+                }
+                pdu.extend_from_slice(data);
+            }
+        }
+        Ok(pdu)
+    }
+
+    // Check the quantities of a combined write/read request against the limits of the
+    // specification and the number of values to write.
+    fn check_write_read_quantities(&self) -> Result<()> {
+        if let Function::WriteReadMultipleRegisters(
+            _,
+            write_quantity,
+            write_values,
+            _,
+            read_quantity,
+        ) = *self
+        {
+            if write_quantity < 1 || write_values.is_empty() {
+                return Err(Error::InvalidData(Reason::SendBufferEmpty));
+            }
+            if read_quantity < 1 {
+                return Err(Error::InvalidData(Reason::RecvBufferEmpty));
+            }
+            if write_quantity > MAX_WRITE_READ_REGISTERS || read_quantity > MAX_READ_REGISTERS {
+                return Err(Error::InvalidData(Reason::SendBufferTooBig));
+            }
+            if write_values.len() != 2 * write_quantity as usize {
+                return Err(Error::InvalidData(Reason::Custom(format!(
+                    "{} values for a write quantity of {}",
+                    write_values.len() / 2,
+                    write_quantity
+                ))));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_read_quantity(count: u16, max_count: u16) -> Result<()> {
+    if count < 1 {
+        return Err(Error::InvalidData(Reason::RecvBufferEmpty));
+    }
+    if count > max_count {
+        return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+    }
+    Ok(())
+}
+
+fn push_u16s(pdu: &mut Vec<u8>, values: &[u16]) {
+    pdu.extend_from_slice(&binary::unpack_bytes(values));
+}
+
+/// The (first) address of the request `req`, for the functions which address a table. The
+/// write/read function returns its read address.
+pub fn address(req: &[u8]) -> Option<u16> {
+    match req.first() {
+        Some(0x01..=0x06 | 0x0f | 0x10 | 0x16..=0x18) if req.len() >= 3 => {
+            Some(u16::from_be_bytes([req[1], req[2]]))
+        }
+        _ => None,
+    }
+}
+
+/// The length of the response PDU to the request `req`, from the first bytes `head` of the
+/// response, for links without a length field like Modbus RTU. Up to three bytes are needed,
+/// e.g. the function code and the byte count.
+///
+/// Returns `None` if `head` is too short or the length of the responses of the function is
+/// unknown, e.g. for vendor specific functions.
+///
+/// ```
+/// use modbus::pdu;
+///
+/// let req = [0x03, 0x00, 0x6b, 0x00, 0x02];
+/// assert_eq!(pdu::response_len(&req, &[0x03, 0x04]), Some(6));
+/// assert_eq!(pdu::response_len(&req, &[0x83, 0x02]), Some(2));
+/// ```
+pub fn response_len(req: &[u8], head: &[u8]) -> Option<usize> {
+    let (&function, rest) = head.split_first()?;
+    if function & 0x80 != 0 {
+        return Some(2);
+    }
+    Some(match function {
+        0x01..=0x04 | 0x0c | 0x11 | 0x14 | 0x15 | 0x17 => 2 + *rest.first()? as usize,
+        0x05 | 0x06 | 0x0b | 0x0f | 0x10 => 5,
+        0x07 => 2,
+        // the sub-functions in use echo the length of the request
+        0x08 => req.len(),
+        0x16 => 7,
+        0x18 => 3 + u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize,
+        _ => return None,
+    })
+}
+
+/// Check the function code of the response `resp` to the request `req`.
+///
+/// The function byte is checked first: an exception response is a complete, valid answer of its
+/// own and is returned as `Error::Exception` before any payload is expected.
+pub fn check_response(req: &[u8], resp: &[u8]) -> Result<()> {
+    let (function, code) = match (req.first(), resp.first()) {
+        (Some(f), Some(c)) => (*f, *c),
+        _ => return Err(Error::InvalidData(Reason::UnexpectedReplySize)),
+    };
+    if code == function | 0x80 {
+        if resp.len() != 2 {
+            return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+        }
+        match ExceptionCode::from_u8(resp[1]) {
+            Some(code) => Err(Error::Exception(code)),
+            None => Err(Error::InvalidResponse),
+        }
+    } else if code == function {
+        Ok(())
+    } else {
+        Err(Error::InvalidResponse)
+    }
+}
+
+// Return the data of a read response after checking its byte count.
+fn reply_data(resp: &[u8], expected_bytes: usize) -> Result<&[u8]> {
+    if resp.len() != expected_bytes + 2 || resp[1] as usize != expected_bytes {
+        Err(Error::InvalidData(Reason::UnexpectedReplySize))
+    } else {
+        Ok(&resp[2..])
+    }
+}
+
+/// Parse the response to reading `count` coils or discrete inputs.
+pub fn parse_bits(resp: &[u8], count: u16) -> Result<Vec<Coil>> {
+    let bytes = reply_data(resp, count.div_ceil(8) as usize)?;
+    Ok(binary::unpack_bits(bytes, count))
+}
+
+/// Parse the response to reading `count` registers, also used for Write/Read Multiple Registers.
+pub fn parse_registers(resp: &[u8], count: u16) -> Result<Vec<u16>> {
+    binary::pack_bytes(reply_data(resp, 2 * count as usize)?)
+}
+
+/// Check the echo `resp` of a write request `req`.
+///
+/// The responses to the single and multiple writes echo the address and the value or quantity
+/// of the request, with `lenient` a wrong quantity of a multiple write is accepted. All other
+/// writes are echoed completely.
+pub fn check_write_echo(req: &[u8], resp: &[u8], lenient: bool) -> Result<()> {
+    let echoed = match req.first() {
+        // write single coil (5) or register (6)
+        Some(0x05) | Some(0x06) => 5,
+        // write multiple coils (15) or registers (16)
+        Some(0x0f) | Some(0x10) if lenient => 3,
+        Some(0x0f) | Some(0x10) => 5,
+        _ if resp == req => return Ok(()),
+        _ => return Err(Error::InvalidResponse),
+    };
+    if resp.len() != 5 {
+        return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+    }
+    if resp[..echoed] != req[..echoed] {
+        return Err(Error::InvalidResponse);
+    }
+    Ok(())
+}
+
+/// Parse the response to Read Exception Status.
+pub fn parse_exception_status(resp: &[u8]) -> Result<u8> {
+    match *resp {
+        [_, status] => Ok(status),
+        _ => Err(Error::InvalidData(Reason::UnexpectedReplySize)),
+    }
+}
+
+/// Check that the diagnostics response is for the sub-function `sub` and return its data.
+pub fn parse_diagnostics(sub: DiagnosticsSubFunction, resp: &[u8]) -> Result<Vec<u16>> {
+    if resp.len() < 3 || resp[1..3] != (sub as u16).to_be_bytes() {
+        return Err(Error::InvalidResponse);
+    }
+    binary::pack_bytes(&resp[3..])
+}
+
+/// Parse the response to Report Slave ID.
+pub fn parse_slave_id(resp: &[u8]) -> Result<SlaveId> {
+    match resp {
+        [_, count, slave_id, run_indicator, additional_data @ ..]
+            if *count as usize == resp.len() - 2 =>
+        {
+            Ok(SlaveId {
+                slave_id: *slave_id,
+                run_indicator: *run_indicator != 0x00,
+                additional_data: additional_data.to_vec(),
+            })
+        }
+        _ => Err(Error::InvalidData(Reason::UnexpectedReplySize)),
+    }
+}
+
+/// Parse the response to Read FIFO Queue: byte count, FIFO count and at most 31 values.
+pub fn parse_fifo_queue(resp: &[u8]) -> Result<Vec<u16>> {
+    if resp.len() < 5 {
+        return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+    }
+    let byte_count = u16::from_be_bytes([resp[1], resp[2]]) as usize;
+    let fifo_count = u16::from_be_bytes([resp[3], resp[4]]) as usize;
+    if fifo_count > 31 || byte_count != 2 + 2 * fifo_count || resp.len() != 3 + byte_count {
+        return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+    }
+    binary::pack_bytes(&resp[5..])
+}
+
+// Encode a Read File Record request for the `(file_number, record_number, record_length)`
+// sub-requests.
+fn file_read_request(records: &[(u16, u16, u16)]) -> Result<Vec<u8>> {
+    if records.is_empty() {
+        return Err(Error::InvalidData(Reason::SendBufferEmpty));
+    }
+    let data_len = 7 * records.len();
+    // the response repeats reference type and length for every record
+    let resp_len: usize = records.iter().map(|r| 2 + 2 * r.2 as usize).sum();
+    if data_len > MAX_FILE_REQUEST_DATA || resp_len > MAX_FILE_REQUEST_DATA {
+        return Err(Error::InvalidData(Reason::SendBufferTooBig));
+    }
+    let mut pdu = vec![Function::ReadFileRecord(records).code(), data_len as u8];
+    for &(file_number, record_number, record_length) in records {
+        if record_number > MAX_RECORD_NUMBER || record_length < 1 {
+            return Err(Error::InvalidData(Reason::EncodingError));
+        }
+        pdu.push(FILE_REFERENCE_TYPE);
+        push_u16s(&mut pdu, &[file_number, record_number, record_length]);
+    }
+    Ok(pdu)
+}
+
+/// Parse the response to the Read File Record sub-requests `records`.
+pub fn parse_file_records(records: &[(u16, u16, u16)], resp: &[u8]) -> Result<Vec<FileRecord>> {
+    let size_err = Error::InvalidData(Reason::UnexpectedReplySize);
+    if resp.len() < 2 || resp[1] as usize != resp.len() - 2 {
+        return Err(size_err);
+    }
+    let mut data = &resp[2..];
+    let mut res = Vec::with_capacity(records.len());
+    for &(file_number, record_number, record_length) in records {
+        let len = 1 + 2 * record_length as usize;
+        match data {
+            [l, FILE_REFERENCE_TYPE, rest @ ..] if *l as usize == len && rest.len() >= len - 1 => {
+                res.push(FileRecord {
+                    file_number,
+                    record_number,
+                    record_data: binary::pack_bytes(&rest[..len - 1])?,
+                });
+                data = &rest[len - 1..];
+            }
+            [_, FILE_REFERENCE_TYPE, ..] => return Err(size_err),
+            _ => return Err(Error::InvalidResponse),
+        }
+    }
+    if !data.is_empty() {
+        return Err(size_err);
+    }
+    Ok(res)
+}
+
+// Encode a Write File Record request, the response is an echo of it.
+fn file_write_request(records: &[FileRecord]) -> Result<Vec<u8>> {
+    if records.is_empty() || records.iter().any(|r| r.record_data.is_empty()) {
+        return Err(Error::InvalidData(Reason::SendBufferEmpty));
+    }
+    let data_len: usize = records.iter().map(|r| 7 + 2 * r.record_data.len()).sum();
+    if data_len > MAX_FILE_REQUEST_DATA {
+        return Err(Error::InvalidData(Reason::SendBufferTooBig));
+    }
+    let mut pdu = vec![Function::WriteFileRecord(records).code(), data_len as u8];
+    for record in records {
+        if record.record_number > MAX_RECORD_NUMBER {
+            return Err(Error::InvalidData(Reason::EncodingError));
+        }
+        pdu.push(FILE_REFERENCE_TYPE);
+        push_u16s(
+            &mut pdu,
+            &[
+                record.file_number,
+                record.record_number,
+                record.record_data.len() as u16,
+            ],
+        );
+        push_u16s(&mut pdu, &record.record_data);
+    }
+    Ok(pdu)
+}
+
+/// Parse the objects of a Read Device Identification response.
+#[cfg(feature = "read-device-info")]
+pub fn parse_device_info(resp: &[u8]) -> Result<Vec<DeviceInfoObject>> {
+    let mut info: Vec<DeviceInfoObject> = vec![];
+    let byte = |i: usize| {
+        resp.get(i)
+            .copied()
+            .ok_or(Error::InvalidData(Reason::UnexpectedReplySize))
+    };
+    let obj_count = byte(6)? as usize;
+    let mut cursor: usize = 6;
+    for _ in 0..obj_count {
+        cursor += 1;
+        let id = byte(cursor)?;
+
+        cursor += 1;
+        let len = byte(cursor)? as usize;
+
+        let val_buf = resp
+            .get(cursor + 1..cursor + 1 + len)
+            .ok_or(Error::InvalidData(Reason::UnexpectedReplySize))?;
+        cursor += len;
+
+        let object = DeviceInfoObject::new(
+            id,
+            match String::from_utf8(val_buf.to_vec()) {
+                Ok(val) => val,
+                Err(_) => return Err(Error::ParseInfoError),
+            },
+        );
+        info.push(object)
+    }
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        assert_eq!(
+            Function::ReadCoils(0x13, 19).request().unwrap(),
+            [0x01, 0x00, 0x13, 0x00, 0x13]
+        );
+        assert_eq!(
+            Function::WriteSingleCoil(0xac, Coil::On.code())
+                .request()
+                .unwrap(),
+            [0x05, 0x00, 0xac, 0xff, 0x00]
+        );
+        assert_eq!(
+            Function::WriteMultipleCoils(0x13, 10, &[0xcd, 0x01])
+                .request()
+                .unwrap(),
+            [0x0f, 0x00, 0x13, 0x00, 0x0a, 0x02, 0xcd, 0x01]
+        );
+        assert_eq!(
+            Function::WriteReadMultipleRegisters(0x0e, 1, &[0x00, 0xff], 0x03, 6)
+                .request()
+                .unwrap(),
+            [0x17, 0x00, 0x03, 0x00, 0x06, 0x00, 0x0e, 0x00, 0x01, 0x02, 0x00, 0xff]
+        );
+        assert_eq!(
+            Function::Diagnostics(DiagnosticsSubFunction::ReturnQueryData, &[0xa537])
+                .request()
+                .unwrap(),
+            [0x08, 0x00, 0x00, 0xa5, 0x37]
+        );
+        assert_eq!(Function::ReadExceptionStatus.request().unwrap(), [0x07]);
+        assert_eq!(
+            Function::ReadFifoQueue(0x04de).request().unwrap(),
+            [0x18, 0x04, 0xde]
+        );
+        let req = Function::WriteReadMultipleRegisters(0x0e, 1, &[0x00, 0xff], 0x03, 6)
+            .request()
+            .unwrap();
+        assert_eq!(address(&req), Some(0x03));
+        assert_eq!(address(&[0x18, 0x04, 0xde]), Some(0x04de));
+        assert_eq!(address(&[0x07]), None);
+        assert_eq!(address(&[0x08, 0x00, 0x00, 0xa5, 0x37]), None);
+
+        match Function::ReadHoldingRegisters(0, 0).request() {
+            Err(Error::InvalidData(Reason::RecvBufferEmpty)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(Function::ReadCoils(0, MAX_READ_BITS + 1).request().is_err());
+        match Function::WriteMultipleCoils(0, 1969, &[0; 247]).request() {
+            Err(Error::InvalidData(Reason::Custom(ref msg))) => {
+                assert_eq!(msg, "1969 coils exceed the limit of 1968 per write request")
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(Function::WriteMultipleCoils(0, 1968, &[0; 246])
+            .request()
+            .is_ok());
+        assert!(Function::WriteMultipleRegisters(0, 124, &[0; 248])
+            .request()
+            .is_err());
+        assert_eq!(
+            Function::Custom(0x41, &[0x01, 0x02]).request().unwrap(),
+            [0x41, 0x01, 0x02]
+        );
+        assert!(Function::Custom(0x81, &[]).request().is_err());
+        assert!(Function::Custom(0x41, &[0; 253]).request().is_err());
+    }
+
+    #[test]
+    fn responses() {
+        let req = Function::ReadCoils(0x13, 10).request().unwrap();
+        assert!(check_response(&req, &[0x01, 0x02, 0xcd, 0x01]).is_ok());
+        match check_response(&req, &[0x81, 0x02]) {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(check_response(&req, &[0x03, 0x02, 0xcd, 0x01]).is_err());
+        assert!(check_response(&req, &[]).is_err());
+
+        let bits = parse_bits(&[0x01, 0x02, 0xcd, 0x01], 10).unwrap();
+        assert_eq!(bits.len(), 10);
+        assert_eq!(bits[..2], [Coil::On, Coil::Off]);
+        assert!(parse_bits(&[0x01, 0x01, 0xcd], 10).is_err());
+        assert_eq!(
+            parse_registers(&[0x03, 0x04, 0x02, 0x2b, 0x00, 0x64], 2).unwrap(),
+            vec![0x022b, 0x0064]
+        );
+        assert!(parse_registers(&[0x03, 0x04, 0x02, 0x2b], 2).is_err());
+
+        let req = [0x10, 0x00, 0x01, 0x00, 0x02, 0x04, 0x00, 0x0a, 0x01, 0x02];
+        assert!(check_write_echo(&req, &req[..5], false).is_ok());
+        assert!(check_write_echo(&req, &[0x10, 0x00, 0x01, 0x00, 0x01], false).is_err());
+        assert!(check_write_echo(&req, &[0x10, 0x00, 0x01, 0x00, 0x01], true).is_ok());
+        assert!(check_write_echo(&req, &req[..3], true).is_err());
+        let req = [0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x25];
+        assert!(check_write_echo(&req, &req, false).is_ok());
+        assert!(check_write_echo(&req, &req[..5], false).is_err());
+
+        assert_eq!(parse_exception_status(&[0x07, 0x6d]).unwrap(), 0x6d);
+        assert_eq!(
+            parse_diagnostics(
+                DiagnosticsSubFunction::ReturnQueryData,
+                &[0x08, 0x00, 0x00, 0xa5, 0x37]
+            )
+            .unwrap(),
+            vec![0xa537]
+        );
+        assert!(parse_diagnostics(
+            DiagnosticsSubFunction::ReturnBusMessageCount,
+            &[0x08, 0x00, 0x00, 0xa5, 0x37]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn file_records() {
+        // example from the specification
+        let records = [(4, 1, 2), (3, 9, 2)];
+        let req = Function::ReadFileRecord(&records).request().unwrap();
+        assert_eq!(
+            req,
+            [
+                0x14, 0x0e, 0x06, 0x00, 0x04, 0x00, 0x01, 0x00, 0x02, 0x06, 0x00, 0x03, 0x00, 0x09,
+                0x00, 0x02
+            ]
+        );
+        let resp = [
+            0x14, 0x0c, 0x05, 0x06, 0x0d, 0xfe, 0x00, 0x20, 0x05, 0x06, 0x33, 0xcd, 0x00, 0x40,
+        ];
+        assert_eq!(
+            parse_file_records(&records, &resp).unwrap(),
+            vec![
+                FileRecord {
+                    file_number: 4,
+                    record_number: 1,
+                    record_data: vec![0x0dfe, 0x0020],
+                },
+                FileRecord {
+                    file_number: 3,
+                    record_number: 9,
+                    record_data: vec![0x33cd, 0x0040],
+                },
+            ]
+        );
+        assert!(parse_file_records(&records, &resp[..10]).is_err());
+        assert!(parse_file_records(&records[..1], &resp).is_err());
+        assert!(Function::ReadFileRecord(&[]).request().is_err());
+        assert!(Function::ReadFileRecord(&[(1, 10000, 1)])
+            .request()
+            .is_err());
+        assert!(Function::ReadFileRecord(&[(1, 0, 125)]).request().is_err());
+
+        let req = Function::WriteFileRecord(&[FileRecord {
+            file_number: 4,
+            record_number: 7,
+            record_data: vec![0x06af, 0x04be, 0x100d],
+        }])
+        .request()
+        .unwrap();
+        assert_eq!(
+            req,
+            [
+                0x15, 0x0d, 0x06, 0x00, 0x04, 0x00, 0x07, 0x00, 0x03, 0x06, 0xaf, 0x04, 0xbe, 0x10,
+                0x0d
+            ]
+        );
+        assert!(Function::WriteFileRecord(&[FileRecord::default()])
+            .request()
+            .is_err());
+    }
+
+    #[test]
+    fn fifo_queue() {
+        assert_eq!(
+            parse_fifo_queue(&[0x18, 0x00, 0x06, 0x00, 0x02, 0x01, 0xb8, 0x12, 0x84]).unwrap(),
+            vec![0x01b8, 0x1284]
+        );
+        assert_eq!(
+            parse_fifo_queue(&[0x18, 0x00, 0x02, 0x00, 0x00]).unwrap(),
+            vec![]
+        );
+        assert!(parse_fifo_queue(&[0x18, 0x00, 0x06, 0x00, 0x02, 0x01, 0xb8]).is_err());
+        assert!(parse_fifo_queue(&[0x18, 0x00, 0x04, 0x00, 0x02, 0x01, 0xb8]).is_err());
+    }
+
+    #[test]
+    fn slave_id() {
+        let id = SlaveId {
+            slave_id: 0x42,
+            run_indicator: true,
+            additional_data: b"PLC".to_vec(),
+        };
+        let pdu = [0x11, 0x05, 0x42, 0xff, b'P', b'L', b'C'];
+        assert_eq!(parse_slave_id(&pdu).unwrap(), id);
+        #[cfg(feature = "server")]
+        assert_eq!(id.to_response(), pdu);
+        assert!(parse_slave_id(&pdu[..6]).is_err());
+        assert!(parse_slave_id(&[0x11, 0x01, 0x42]).is_err());
+    }
+}
@@ -0,0 +1,439 @@
+//! Payload builder and decoder with the semantics of pymodbus
+//!
+//! `PayloadBuilder` and `PayloadDecoder` mirror `BinaryPayloadBuilder` and
+//! `BinaryPayloadDecoder` of pymodbus, including their method names and the meaning of
+//! `byteorder` and `wordorder`, so code ported from Python produces and reads the same registers.
+//!
+//! Like in pymodbus, all values are appended to one byte stream which is split into registers
+//! at the end, so 8 bit values and strings of odd length shift the following values by one
+//! byte. The byte order applies to values of 16 bits and more, the word order only to values of
+//! 32 and 64 bits. Strings are copied as they are. Note that pymodbus defaults to the little
+//! endian byte order and the big endian word order.
+//!
+//! # Examples
+//!
+//! ```
+//! use modbus::binary::Endian;
+//! use modbus::pymodbus::{PayloadBuilder, PayloadDecoder};
+//!
+//! let mut builder = PayloadBuilder::new(Endian::Big, Endian::Little);
+//! builder.add_32bit_float(22.34).add_16bit_int(-2);
+//! let registers = builder.to_registers();
+//! assert_eq!(registers, vec![0xb852, 0x41b2, 0xfffe]);
+//!
+//! let mut decoder = PayloadDecoder::from_registers(&registers, Endian::Big, Endian::Little);
+//! assert_eq!(decoder.decode_32bit_float().unwrap(), 22.34);
+//! assert_eq!(decoder.decode_16bit_int().unwrap(), -2);
+//! ```
+
+use crate::binary::{self, Endian};
+use crate::{Coil, Error, Reason, Result};
+
+/// Build registers from typed values like pymodbus' `BinaryPayloadBuilder`.
+#[derive(Clone, Debug)]
+pub struct PayloadBuilder {
+    payload: Vec<u8>,
+    byteorder: Endian,
+    wordorder: Endian,
+}
+
+impl PayloadBuilder {
+    pub fn new(byteorder: Endian, wordorder: Endian) -> PayloadBuilder {
+        PayloadBuilder {
+            payload: vec![],
+            byteorder,
+            wordorder,
+        }
+    }
+
+    // Append the big endian `bytes` of a value, values of up to 16 bits ignore the word order.
+    fn add<const N: usize>(&mut self, mut bytes: [u8; N]) -> &mut PayloadBuilder {
+        let wordorder = if N > 2 { self.wordorder } else { Endian::Big };
+        binary::reorder(&mut bytes, self.byteorder, wordorder);
+        self.payload.extend_from_slice(&bytes);
+        self
+    }
+
+    pub fn add_8bit_uint(&mut self, value: u8) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_8bit_int(&mut self, value: i8) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_16bit_uint(&mut self, value: u16) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_16bit_int(&mut self, value: i16) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    /// Add `value` as IEEE 754 half precision float, rounded to the nearest representable value.
+    pub fn add_16bit_float(&mut self, value: f32) -> &mut PayloadBuilder {
+        self.add(f16_from_f32(value).to_be_bytes())
+    }
+
+    pub fn add_32bit_uint(&mut self, value: u32) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_32bit_int(&mut self, value: i32) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_32bit_float(&mut self, value: f32) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_64bit_uint(&mut self, value: u64) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_64bit_int(&mut self, value: i64) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    pub fn add_64bit_float(&mut self, value: f64) -> &mut PayloadBuilder {
+        self.add(value.to_be_bytes())
+    }
+
+    /// Add the UTF-8 bytes of `value` unchanged, regardless of the byte order.
+    pub fn add_string(&mut self, value: &str) -> &mut PayloadBuilder {
+        self.payload.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    /// Add `bits` packed into bytes, the first bit is the least significant bit of the first
+    /// byte.
+    pub fn add_bits(&mut self, bits: &[Coil]) -> &mut PayloadBuilder {
+        self.payload.extend(binary::pack_bits(bits));
+        self
+    }
+
+    /// Remove all added values.
+    pub fn reset(&mut self) {
+        self.payload.clear();
+    }
+
+    /// The payload split into two byte chunks, an odd number of bytes is padded with a zero
+    /// byte.
+    pub fn build(&self) -> Vec<[u8; 2]> {
+        self.payload
+            .chunks(2)
+            .map(|c| [c[0], c.get(1).copied().unwrap_or(0)])
+            .collect()
+    }
+
+    /// The registers of the payload, as written with `write_multiple_registers`.
+    pub fn to_registers(&self) -> Vec<u16> {
+        self.build().into_iter().map(u16::from_be_bytes).collect()
+    }
+
+    /// The bits of the registers of the payload, the most significant bit of each register
+    /// first.
+    pub fn to_coils(&self) -> Vec<Coil> {
+        self.to_registers()
+            .into_iter()
+            .flat_map(|r| (0..16).rev().map(move |i| (r >> i & 1 == 1).into()))
+            .collect()
+    }
+}
+
+/// Decode typed values from registers like pymodbus' `BinaryPayloadDecoder`.
+#[derive(Clone, Debug)]
+pub struct PayloadDecoder {
+    payload: Vec<u8>,
+    pointer: usize,
+    byteorder: Endian,
+    wordorder: Endian,
+}
+
+impl PayloadDecoder {
+    /// Decode the bytes of `registers`, e.g. read with `read_holding_registers`.
+    pub fn from_registers(registers: &[u16], byteorder: Endian, wordorder: Endian) -> Self {
+        PayloadDecoder {
+            payload: binary::unpack_bytes(registers),
+            pointer: 0,
+            byteorder,
+            wordorder,
+        }
+    }
+
+    fn next(&mut self, count: usize) -> Result<&[u8]> {
+        if self.pointer + count > self.payload.len() {
+            return Err(Error::InvalidData(Reason::DecodingError));
+        }
+        self.pointer += count;
+        Ok(&self.payload[self.pointer - count..self.pointer])
+    }
+
+    // Take the bytes of a value and return them in big endian order.
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let wordorder = if N > 2 { self.wordorder } else { Endian::Big };
+        let byteorder = self.byteorder;
+        let mut bytes: [u8; N] = self.next(N)?.try_into().unwrap();
+        binary::reorder(&mut bytes, byteorder, wordorder);
+        Ok(bytes)
+    }
+
+    pub fn decode_8bit_uint(&mut self) -> Result<u8> {
+        self.take().map(u8::from_be_bytes)
+    }
+
+    pub fn decode_8bit_int(&mut self) -> Result<i8> {
+        self.take().map(i8::from_be_bytes)
+    }
+
+    pub fn decode_16bit_uint(&mut self) -> Result<u16> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    pub fn decode_16bit_int(&mut self) -> Result<i16> {
+        self.take().map(i16::from_be_bytes)
+    }
+
+    /// Decode an IEEE 754 half precision float.
+    pub fn decode_16bit_float(&mut self) -> Result<f32> {
+        self.take().map(|b| f32_from_f16(u16::from_be_bytes(b)))
+    }
+
+    pub fn decode_32bit_uint(&mut self) -> Result<u32> {
+        self.take().map(u32::from_be_bytes)
+    }
+
+    pub fn decode_32bit_int(&mut self) -> Result<i32> {
+        self.take().map(i32::from_be_bytes)
+    }
+
+    pub fn decode_32bit_float(&mut self) -> Result<f32> {
+        self.take().map(f32::from_be_bytes)
+    }
+
+    pub fn decode_64bit_uint(&mut self) -> Result<u64> {
+        self.take().map(u64::from_be_bytes)
+    }
+
+    pub fn decode_64bit_int(&mut self) -> Result<i64> {
+        self.take().map(i64::from_be_bytes)
+    }
+
+    pub fn decode_64bit_float(&mut self) -> Result<f64> {
+        self.take().map(f64::from_be_bytes)
+    }
+
+    /// Decode the next `size` bytes unchanged, regardless of the byte order.
+    pub fn decode_string(&mut self, size: usize) -> Result<Vec<u8>> {
+        self.next(size).map(<[u8]>::to_vec)
+    }
+
+    /// Decode the `8 * package_len` bits of the next `package_len` bytes, the least significant
+    /// bit of each byte first.
+    pub fn decode_bits(&mut self, package_len: usize) -> Result<Vec<Coil>> {
+        let bytes = self.next(package_len)?;
+        Ok(binary::unpack_bits(bytes, 8 * package_len as u16))
+    }
+
+    pub fn skip_bytes(&mut self, nbytes: usize) -> Result<()> {
+        self.next(nbytes).map(|_| ())
+    }
+
+    /// Decode the payload from the start again.
+    pub fn reset(&mut self) {
+        self.pointer = 0;
+    }
+}
+
+// Convert to the bits of the nearest half precision float, ties to even.
+fn f16_from_f32(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16 & 0x8000) as u16;
+    let exp = (bits >> 23 & 0xff) as i32;
+    let man = bits & 0x7f_ffff;
+    if exp == 0xff {
+        // infinity or NaN
+        return sign | 0x7c00 | if man != 0 { 0x200 } else { 0 };
+    }
+    let exp = exp - 127 + 15;
+    if exp >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // the mantissa bits to keep and the number of bits to drop, subnormals drop more
+    let (half, shift) = if exp <= 0 {
+        if exp < -10 {
+            return sign;
+        }
+        ((man | 0x80_0000) >> (14 - exp), (14 - exp) as u32)
+    } else {
+        ((exp as u32) << 10 | man >> 13, 13)
+    };
+    let full = if exp <= 0 { man | 0x80_0000 } else { man };
+    let rem = full & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    // a carry out of the mantissa correctly increments the exponent
+    let rounded = if rem > halfway || rem == halfway && half & 1 == 1 {
+        half + 1
+    } else {
+        half
+    };
+    sign | rounded as u16
+}
+
+fn f32_from_f16(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exp = (half >> 10 & 0x1f) as u32;
+    let man = (half & 0x3ff) as u32;
+    match exp {
+        0 => {
+            let value = man as f32 * 2f32.powi(-24);
+            if sign != 0 {
+                -value
+            } else {
+                value
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | man << 13),
+        _ => f32::from_bits(sign | (exp + 112) << 23 | man << 13),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The values of the payload example of the pymodbus documentation.
+    fn example(byteorder: Endian, wordorder: Endian) -> Vec<u16> {
+        let mut builder = PayloadBuilder::new(byteorder, wordorder);
+        builder
+            .add_string("abcdefgh")
+            .add_bits(&[0, 1, 0, 1, 1, 0, 1, 0].map(|b| (b == 1).into()))
+            .add_8bit_int(-0x12)
+            .add_8bit_uint(0x12)
+            .add_16bit_int(-0x5678)
+            .add_16bit_uint(0x1234)
+            .add_32bit_int(-0x1234)
+            .add_32bit_uint(0x12345678)
+            .add_16bit_float(12.34)
+            .add_16bit_float(-12.34)
+            .add_32bit_float(22.34)
+            .add_32bit_float(-22.34)
+            .add_64bit_int(-0xDEADBEEF)
+            .add_64bit_uint(0x12345678DEADBEEF)
+            .add_64bit_uint(0xDEADBEEFDEADBEED)
+            .add_64bit_float(123.45)
+            .add_64bit_float(-123.45);
+        builder.to_registers()
+    }
+
+    #[test]
+    fn builder() {
+        // produced by pymodbus' BinaryPayloadBuilder with the same values
+        assert_eq!(
+            example(Endian::Big, Endian::Little),
+            [
+                0x6162, 0x6364, 0x6566, 0x6768, 0x5aee, 0x12a9, 0x8812, 0x34ed, 0xccff, 0xff56,
+                0x7812, 0x344a, 0x2cca, 0x2cb8, 0x5241, 0xb2b8, 0x52c1, 0xb241, 0x1121, 0x52ff,
+                0xffff, 0xffbe, 0xefde, 0xad56, 0x7812, 0x34be, 0xedde, 0xadbe, 0xefde, 0xadcc,
+                0xcdcc, 0xccdc, 0xcc40, 0x5ecc, 0xcdcc, 0xccdc, 0xccc0, 0x5e00,
+            ]
+        );
+        assert_eq!(
+            example(Endian::Little, Endian::Big),
+            [
+                0x6162, 0x6364, 0x6566, 0x6768, 0x5aee, 0x1288, 0xa934, 0x12ff, 0xffcc, 0xed34,
+                0x1278, 0x562c, 0x4a2c, 0xcab2, 0x4152, 0xb8b2, 0xc152, 0xb8ff, 0xffff, 0xff52,
+                0x2111, 0x4134, 0x1278, 0x56ad, 0xdeef, 0xbead, 0xdeef, 0xbead, 0xdeed, 0xbe5e,
+                0x40cc, 0xdccc, 0xcccd, 0xcc5e, 0xc0cc, 0xdccc, 0xcccd, 0xcc00,
+            ]
+        );
+        assert_eq!(
+            example(Endian::Big, Endian::Big),
+            [
+                0x6162, 0x6364, 0x6566, 0x6768, 0x5aee, 0x12a9, 0x8812, 0x34ff, 0xffed, 0xcc12,
+                0x3456, 0x784a, 0x2cca, 0x2c41, 0xb2b8, 0x52c1, 0xb2b8, 0x52ff, 0xffff, 0xff21,
+                0x5241, 0x1112, 0x3456, 0x78de, 0xadbe, 0xefde, 0xadbe, 0xefde, 0xadbe, 0xed40,
+                0x5edc, 0xcccc, 0xcccc, 0xcdc0, 0x5edc, 0xcccc, 0xcccc, 0xcd00,
+            ]
+        );
+        assert_eq!(
+            example(Endian::Little, Endian::Little),
+            [
+                0x6162, 0x6364, 0x6566, 0x6768, 0x5aee, 0x1288, 0xa934, 0x12cc, 0xedff, 0xff78,
+                0x5634, 0x122c, 0x4a2c, 0xca52, 0xb8b2, 0x4152, 0xb8b2, 0xc111, 0x4152, 0x21ff,
+                0xffff, 0xffef, 0xbead, 0xde78, 0x5634, 0x12ed, 0xbead, 0xdeef, 0xbead, 0xdecd,
+                0xcccc, 0xcccc, 0xdc5e, 0x40cd, 0xcccc, 0xcccc, 0xdc5e, 0xc000,
+            ]
+        );
+
+        let mut builder = PayloadBuilder::new(Endian::Little, Endian::Big);
+        builder.add_8bit_uint(0x81).add_16bit_uint(0x0201);
+        assert_eq!(builder.build(), vec![[0x81, 0x01], [0x02, 0x00]]);
+        let coils = builder.to_coils();
+        assert_eq!(coils.len(), 32);
+        let first = coils[..16]
+            .iter()
+            .fold(0, |acc, &c| acc << 1 | (c == Coil::On) as u16);
+        assert_eq!(first, 0x8101);
+        builder.reset();
+        assert_eq!(builder.to_registers(), vec![]);
+    }
+
+    #[test]
+    fn decoder() {
+        for (byteorder, wordorder) in [
+            (Endian::Big, Endian::Little),
+            (Endian::Little, Endian::Little),
+        ] {
+            let registers = example(byteorder, wordorder);
+            let mut decoder = PayloadDecoder::from_registers(&registers, byteorder, wordorder);
+            assert_eq!(decoder.decode_string(8).unwrap(), b"abcdefgh");
+            assert_eq!(
+                decoder.decode_bits(1).unwrap(),
+                [0, 1, 0, 1, 1, 0, 1, 0].map(|b| Coil::from(b == 1))
+            );
+            assert_eq!(decoder.decode_8bit_int().unwrap(), -0x12);
+            assert_eq!(decoder.decode_8bit_uint().unwrap(), 0x12);
+            assert_eq!(decoder.decode_16bit_int().unwrap(), -0x5678);
+            assert_eq!(decoder.decode_16bit_uint().unwrap(), 0x1234);
+            assert_eq!(decoder.decode_32bit_int().unwrap(), -0x1234);
+            assert_eq!(decoder.decode_32bit_uint().unwrap(), 0x12345678);
+            assert_eq!(decoder.decode_16bit_float().unwrap(), 12.34375);
+            assert_eq!(decoder.decode_16bit_float().unwrap(), -12.34375);
+            assert_eq!(decoder.decode_32bit_float().unwrap(), 22.34);
+            assert_eq!(decoder.decode_32bit_float().unwrap(), -22.34);
+            assert_eq!(decoder.decode_64bit_int().unwrap(), -0xDEADBEEF);
+            assert_eq!(decoder.decode_64bit_uint().unwrap(), 0x12345678DEADBEEF);
+            decoder.skip_bytes(8).unwrap();
+            assert_eq!(decoder.decode_64bit_float().unwrap(), 123.45);
+            assert_eq!(decoder.decode_64bit_float().unwrap(), -123.45);
+            match decoder.decode_16bit_uint() {
+                Err(Error::InvalidData(Reason::DecodingError)) => (),
+                r => panic!("unexpected result {:?}", r),
+            }
+            decoder.reset();
+            assert_eq!(decoder.decode_string(2).unwrap(), b"ab");
+        }
+    }
+
+    #[test]
+    fn half_floats() {
+        // the bits packed by Python's `struct.pack("<e", value)`
+        for (value, half) in [
+            (12.34, 0x4a2c),
+            (-12.34, 0xca2c),
+            (65504.0, 0x7bff),
+            (1e-7, 0x0002),
+            (6e-5, 0x03ef),
+            (0.1, 0x2e66),
+            (70000.0, 0x7c00),
+            (f32::INFINITY, 0x7c00),
+        ] {
+            assert_eq!(f16_from_f32(value), half, "{}", value);
+        }
+        assert_eq!(f32_from_f16(0x7bff), 65504.0);
+        assert_eq!(f32_from_f16(0x0001), 2f32.powi(-24));
+        assert_eq!(f32_from_f16(0xc000), -2.0);
+        assert!(f32_from_f16(f16_from_f32(f32::NAN)).is_nan());
+    }
+}
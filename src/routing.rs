@@ -0,0 +1,211 @@
+//! Unit id routing through gateways
+//!
+//! A Modbus TCP to serial gateway forwards requests to the device on its serial line addressed
+//! by the unit id of the request, either unchanged or translated by a mapping table configured
+//! in the gateway. With several gateways in a row, the unit id sent to the first gateway
+//! selects the device at the end of the chain. This module lets an application describe the
+//! gateways and compute that unit id instead of doing the arithmetic by hand.
+//!
+//! # Examples
+//!
+//! ```
+//! use modbus::routing::{Gateway, Route, RoutingTable};
+//!
+//! // The first gateway forwards unit ids 101-110 to the addresses 1-10 of the next segment,
+//! // which is bridged by a second gateway forwarding addresses unchanged.
+//! let plant = Gateway::new().map(101..=110, 1).unwrap();
+//! let route = Route::direct().via(plant).via(Gateway::new());
+//! assert_eq!(route.uid(4).unwrap(), 104);
+//!
+//! let mut table = RoutingTable::new();
+//! table.add("boiler", "192.168.0.10", route, 4).unwrap();
+//! assert_eq!(table.resolve("boiler").unwrap(), ("192.168.0.10", 104));
+//! ```
+
+use std::ops::RangeInclusive;
+
+use crate::{Error, Reason, Result};
+
+/// Unit ids which can be assigned to devices on a serial line, `0` is the broadcast address.
+const SERIAL_UIDS: RangeInclusive<u8> = 1..=247;
+
+fn route_error(reason: String) -> Error {
+    Error::InvalidData(Reason::Custom(reason))
+}
+
+/// The unit id mapping table of a gateway.
+///
+/// Without any mapping, unit ids `1..=247` are forwarded unchanged.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Gateway {
+    // incoming unit ids and the device address of the first of them
+    mappings: Vec<(RangeInclusive<u8>, u8)>,
+}
+
+impl Gateway {
+    /// A transparent gateway forwarding unit ids unchanged.
+    pub fn new() -> Gateway {
+        Gateway::default()
+    }
+
+    /// Forward the unit ids `uids` to the consecutive devices starting at `first_device`.
+    ///
+    /// Fails if the range is empty, overlaps a range mapped before or maps to devices outside
+    /// of `1..=247`.
+    pub fn map(mut self, uids: RangeInclusive<u8>, first_device: u8) -> Result<Gateway> {
+        if uids.is_empty() {
+            return Err(route_error(format!("empty unit id range {:?}", uids)));
+        }
+        let last_device = first_device as u16 + (uids.end() - uids.start()) as u16;
+        if !SERIAL_UIDS.contains(&first_device) || last_device > *SERIAL_UIDS.end() as u16 {
+            return Err(route_error(format!(
+                "unit ids {:?} map to invalid device addresses {}-{}",
+                uids, first_device, last_device
+            )));
+        }
+        if let Some((other, _)) = self
+            .mappings
+            .iter()
+            .find(|(r, _)| r.start() <= uids.end() && uids.start() <= r.end())
+        {
+            return Err(route_error(format!(
+                "unit ids {:?} overlap the mapped unit ids {:?}",
+                uids, other
+            )));
+        }
+        self.mappings.push((uids, first_device));
+        Ok(self)
+    }
+
+    /// The device address a request with unit id `uid` is forwarded to.
+    pub fn forward(&self, uid: u8) -> Option<u8> {
+        if self.mappings.is_empty() {
+            return Some(uid).filter(|uid| SERIAL_UIDS.contains(uid));
+        }
+        self.mappings
+            .iter()
+            .find(|(uids, _)| uids.contains(&uid))
+            .map(|(uids, first)| first + (uid - uids.start()))
+    }
+
+    /// The unit id which is forwarded to the device with address `device`.
+    pub fn uid(&self, device: u8) -> Option<u8> {
+        if self.mappings.is_empty() {
+            return Some(device).filter(|uid| SERIAL_UIDS.contains(uid));
+        }
+        self.mappings.iter().find_map(|(uids, first)| {
+            let offset = device.checked_sub(*first)?;
+            uids.start()
+                .checked_add(offset)
+                .filter(|uid| uids.contains(uid))
+        })
+    }
+}
+
+/// The chain of gateways between the client and a device.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Route {
+    hops: Vec<Gateway>,
+}
+
+impl Route {
+    /// A route to a device which is connected directly, the unit id is sent unchanged.
+    pub fn direct() -> Route {
+        Route::default()
+    }
+
+    /// Extend the route by `gateway`, which is reached through the gateways added before.
+    pub fn via(mut self, gateway: Gateway) -> Route {
+        self.hops.push(gateway);
+        self
+    }
+
+    /// The unit id to send so that the request reaches the device with address `device` behind
+    /// the last gateway of the route.
+    pub fn uid(&self, device: u8) -> Result<u8> {
+        self.hops
+            .iter()
+            .enumerate()
+            .rev()
+            .try_fold(device, |addr, (hop, gateway)| {
+                gateway.uid(addr).ok_or_else(|| {
+                    route_error(format!("gateway {} can't reach address {}", hop, addr))
+                })
+            })
+    }
+}
+
+/// Named devices with the host and unit id to address them with.
+#[derive(Clone, Debug, Default)]
+pub struct RoutingTable {
+    devices: Vec<(String, String, u8)>,
+}
+
+impl RoutingTable {
+    pub fn new() -> RoutingTable {
+        RoutingTable::default()
+    }
+
+    /// Add the device `name` at address `device` reached through `route` from `host`, replacing
+    /// a device with the same name. Fails if the device is not reachable by the route.
+    pub fn add(&mut self, name: &str, host: &str, route: Route, device: u8) -> Result<()> {
+        let uid = route.uid(device)?;
+        self.devices.retain(|(n, _, _)| n != name);
+        self.devices.push((name.to_string(), host.to_string(), uid));
+        Ok(())
+    }
+
+    /// The host and unit id of device `name`.
+    pub fn resolve(&self, name: &str) -> Result<(&str, u8)> {
+        self.devices
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, host, uid)| (host.as_str(), *uid))
+            .ok_or_else(|| route_error(format!("unknown device {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_mappings() {
+        let gateway = Gateway::new()
+            .map(10..=19, 1)
+            .unwrap()
+            .map(20..=20, 247)
+            .unwrap();
+        assert_eq!(gateway.forward(10), Some(1));
+        assert_eq!(gateway.forward(19), Some(10));
+        assert_eq!(gateway.forward(20), Some(247));
+        assert_eq!(gateway.forward(21), None);
+        assert_eq!(gateway.uid(5), Some(14));
+        assert_eq!(gateway.uid(11), None);
+        assert_eq!(Gateway::new().forward(248), None);
+        assert_eq!(Gateway::new().uid(7), Some(7));
+
+        assert!(gateway.clone().map(15..=25, 100).is_err());
+        assert!(gateway.clone().map(30..=39, 240).is_err());
+        assert!(gateway.clone().map(30..=39, 0).is_err());
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 39..=30;
+        assert!(gateway.map(empty, 1).is_err());
+    }
+
+    #[test]
+    fn routes() {
+        let first = Gateway::new().map(100..=109, 1).unwrap();
+        let second = Gateway::new().map(1..=5, 20).unwrap();
+        let route = Route::direct().via(first).via(second);
+        assert_eq!(route.uid(22).unwrap(), 102);
+        assert!(route.uid(30).is_err());
+        assert_eq!(Route::direct().uid(255).unwrap(), 255);
+
+        let mut table = RoutingTable::new();
+        table.add("pump", "10.0.0.1", route.clone(), 20).unwrap();
+        assert!(table.add("valve", "10.0.0.1", route, 1).is_err());
+        assert_eq!(table.resolve("pump").unwrap(), ("10.0.0.1", 100));
+        assert!(table.resolve("valve").is_err());
+    }
+}
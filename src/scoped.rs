@@ -6,14 +6,14 @@
 //!
 //! ```
 //! # extern crate modbus;
-//! # extern crate test_server;
-//! # use test_server::start_dummy_server;
 //! # fn main() {
 //! use modbus::{Client, Coil};
 //! use modbus::tcp;
 //! use modbus::scoped::{ScopedCoil, CoilDropFunction};
-//! # if cfg!(feature = "modbus-server-tests") {
-//! # let (_s, port) = start_dummy_server(Some(22222));
+//! # #[cfg(feature = "server")] {
+//! # let server = modbus::server::Server::bind("127.0.0.1:0", modbus::server::Bank::new(500)).unwrap();
+//! # let port = server.local_addr().unwrap().port();
+//! # std::thread::spawn(move || server.serve());
 //!
 //! let mut cfg = tcp::Config::default();
 //! # cfg.tcp_port = port;
@@ -32,14 +32,14 @@
 //!
 //! ```
 //! # extern crate modbus;
-//! # extern crate test_server;
-//! # use test_server::start_dummy_server;
 //! # fn main() {
 //! use modbus::{Client, Coil};
 //! use modbus::tcp;
 //! use modbus::scoped::{ScopedRegister, RegisterDropFunction};
-//! # if cfg!(feature = "modbus-server-tests") {
-//! # let (_s, port) = start_dummy_server(Some(22223));
+//! # #[cfg(feature = "server")] {
+//! # let server = modbus::server::Server::bind("127.0.0.1:0", modbus::server::Bank::new(500)).unwrap();
+//! # let port = server.local_addr().unwrap().port();
+//! # std::thread::spawn(move || server.serve());
 //!
 //! let mut cfg = tcp::Config::default();
 //! # cfg.tcp_port = port;
@@ -81,6 +81,31 @@ pub enum RegisterDropFunction<'a> {
     Fun(&'a dyn Fn(u16) -> u16),
 }
 
+impl CoilDropFunction {
+    fn apply(&self, value: Coil) -> Coil {
+        match *self {
+            CoilDropFunction::On => Coil::On,
+            CoilDropFunction::Off => Coil::Off,
+            CoilDropFunction::Toggle => match value {
+                Coil::On => Coil::Off,
+                Coil::Off => Coil::On,
+            },
+        }
+    }
+}
+
+impl<'a> RegisterDropFunction<'a> {
+    fn apply(&self, value: u16) -> u16 {
+        match *self {
+            RegisterDropFunction::Zero => 0u16,
+            RegisterDropFunction::Increment => value + 1,
+            RegisterDropFunction::Decrement => value - 1,
+            RegisterDropFunction::Value(v) => v,
+            RegisterDropFunction::Fun(f) => f(value),
+        }
+    }
+}
+
 /// Auto object which modifies it's coil value depending on a given modification function if it
 /// goes out of scope.
 pub struct ScopedCoil<'a> {
@@ -93,14 +118,7 @@ impl<'a> Drop for ScopedCoil<'a> {
     fn drop(&mut self) {
         let _ = self.transport.read_coils(self.address, 1).map(|value| {
             if value.len() == 1 {
-                let drop_value = match self.fun {
-                    CoilDropFunction::On => Coil::On,
-                    CoilDropFunction::Off => Coil::Off,
-                    CoilDropFunction::Toggle => match value[0] {
-                        Coil::On => Coil::Off,
-                        Coil::Off => Coil::On,
-                    },
-                };
+                let drop_value = self.fun.apply(value[0]);
                 let _ = self.transport.write_single_coil(self.address, drop_value);
             }
         });
@@ -114,7 +132,7 @@ impl<'a> ScopedCoil<'a> {
         transport: &mut Transport,
         address: u16,
         fun: CoilDropFunction,
-    ) -> Result<ScopedCoil> {
+    ) -> Result<ScopedCoil<'_>> {
         Ok(ScopedCoil {
             address,
             fun,
@@ -142,13 +160,7 @@ impl<'a> Drop for ScopedRegister<'a> {
             .read_holding_registers(self.address, 1)
             .map(|value| {
                 if value.len() == 1 {
-                    let drop_value = match self.fun {
-                        RegisterDropFunction::Zero => 0u16,
-                        RegisterDropFunction::Increment => value[0] + 1,
-                        RegisterDropFunction::Decrement => value[0] - 1,
-                        RegisterDropFunction::Value(v) => v,
-                        RegisterDropFunction::Fun(f) => f(value[0]),
-                    };
+                    let drop_value = self.fun.apply(value[0]);
                     let _ = self
                         .transport
                         .write_single_register(self.address, drop_value);
@@ -176,3 +188,131 @@ impl<'a> ScopedRegister<'a> {
         self.transport
     }
 }
+
+/// Auto object which restores a block of coils when it goes out of scope, or modifies each of
+/// them with a drop function.
+pub struct ScopedCoils<'a> {
+    address: u16,
+    original: Vec<Coil>,
+    fun: Option<CoilDropFunction>,
+    transport: &'a mut Transport,
+}
+
+impl<'a> Drop for ScopedCoils<'a> {
+    fn drop(&mut self) {
+        let count = self.original.len() as u16;
+        let values = match self.fun {
+            None => Ok(self.original.clone()),
+            Some(ref fun) => self
+                .transport
+                .read_coils(self.address, count)
+                .map(|values| values.into_iter().map(|v| fun.apply(v)).collect()),
+        };
+        if let Ok(values) = values {
+            if values.len() == self.original.len() {
+                let _ = self.transport.write_multiple_coils(self.address, &values);
+            }
+        }
+    }
+}
+
+impl<'a> ScopedCoils<'a> {
+    /// Create a new `ScopedCoils` object which reads the `count` coils starting at `address` and
+    /// writes them back when the object goes out of scope.
+    pub fn new(transport: &mut Transport, address: u16, count: u16) -> Result<ScopedCoils<'_>> {
+        let original = transport.read_coils(address, count)?;
+        Ok(ScopedCoils {
+            address,
+            original,
+            fun: None,
+            transport,
+        })
+    }
+
+    /// Create a new `ScopedCoils` object which applies the drop function to each of the `count`
+    /// coils starting at `address` when the object goes out of scope.
+    pub fn with_drop_function(
+        transport: &mut Transport,
+        address: u16,
+        count: u16,
+        fun: CoilDropFunction,
+    ) -> Result<ScopedCoils<'_>> {
+        let mut scoped = ScopedCoils::new(transport, address, count)?;
+        scoped.fun = Some(fun);
+        Ok(scoped)
+    }
+
+    /// The values of the coils when the object was created.
+    pub fn original(&self) -> &[Coil] {
+        &self.original
+    }
+
+    pub fn mut_transport(&mut self) -> &mut Transport {
+        self.transport
+    }
+}
+
+/// Auto object which restores a block of registers when it goes out of scope, or modifies each
+/// of them with a drop function.
+pub struct ScopedRegisters<'a> {
+    address: u16,
+    original: Vec<u16>,
+    fun: Option<RegisterDropFunction<'a>>,
+    transport: &'a mut Transport,
+}
+
+impl<'a> Drop for ScopedRegisters<'a> {
+    fn drop(&mut self) {
+        let count = self.original.len() as u16;
+        let values = match self.fun {
+            None => Ok(self.original.clone()),
+            Some(ref fun) => self
+                .transport
+                .read_holding_registers(self.address, count)
+                .map(|values| values.into_iter().map(|v| fun.apply(v)).collect()),
+        };
+        if let Ok(values) = values {
+            if values.len() == self.original.len() {
+                let _ = self
+                    .transport
+                    .write_multiple_registers(self.address, &values);
+            }
+        }
+    }
+}
+
+impl<'a> ScopedRegisters<'a> {
+    /// Create a new `ScopedRegisters` object which reads the `count` registers starting at
+    /// `address` and writes them back when the object goes out of scope.
+    pub fn new(transport: &mut Transport, address: u16, count: u16) -> Result<ScopedRegisters<'_>> {
+        let original = transport.read_holding_registers(address, count)?;
+        Ok(ScopedRegisters {
+            address,
+            original,
+            fun: None,
+            transport,
+        })
+    }
+
+    /// Create a new `ScopedRegisters` object which applies the drop function to each of the
+    /// `count` registers starting at `address` when the object goes out of scope.
+    pub fn with_drop_function<'b>(
+        transport: &'b mut Transport,
+        address: u16,
+        count: u16,
+        fun: RegisterDropFunction<'b>,
+    ) -> Result<ScopedRegisters<'b>> {
+        let mut scoped = ScopedRegisters::new(transport, address, count)?;
+        scoped.fun = Some(fun);
+        Ok(scoped)
+    }
+
+    /// The values of the registers when the object was created.
+    pub fn original(&self) -> &[u16] {
+        &self.original
+    }
+
+    pub fn mut_transport(&mut self) -> &mut Transport {
+        self.transport
+    }
+}
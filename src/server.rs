@@ -0,0 +1,1446 @@
+//! Modbus TCP server
+//!
+//! A `Server` listens on a TCP socket and answers requests from the four data tables of a
+//! shared `Bank`: coils, discrete inputs, holding registers and input registers. Supported are
+//! the function codes 1–7, 15–17, 20–24 and the Return Query Data diagnostics (8/0), every
+//! other function is answered with an `IllegalFunction` exception. Requests to any unit id are
+//! answered.
+//!
+//! Address ranges of a table can be restricted with `Bank::set_access` to emulate devices with
+//! protected zones in their register map. The table contents can be loaded from and saved to a
+//! CSV image with `Bank::read_csv` and `Bank::write_csv`. To mimic slow field devices, the
+//! server can delay its responses by a configurable `Latency`. Retransmitted requests can be
+//! answered from a per-connection cache instead of executing them again, see
+//! `Server::set_duplicate_cache`.
+//!
+//! Devices which only accept writes after a login sequence, or otherwise behave differently per
+//! connection, can be emulated with a handler, see `Server::set_handler`. It sees every request
+//! together with the `Session` of its connection, in which it can keep its own state.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use modbus::server::{Bank, Server};
+//! use modbus::Coil;
+//! use std::thread;
+//!
+//! let server = Server::bind("0.0.0.0:5020", Bank::new(1000)).unwrap();
+//! let bank = server.bank();
+//! thread::spawn(move || server.serve());
+//!
+//! // The tables can be changed while the server is running
+//! bank.lock().unwrap().input_registers[0] = 42;
+//! bank.lock().unwrap().discrete_inputs[3] = Coil::On;
+//! ```
+
+use std::any::{Any, TypeId};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use crate::pdu::{
+    MAX_READ_BITS, MAX_READ_REGISTERS, MAX_WRITE_BITS, MAX_WRITE_READ_REGISTERS,
+    MAX_WRITE_REGISTERS,
+};
+use crate::{binary, Coil, Error, ExceptionCode, Reason, Result, SlaveId};
+
+const MODBUS_PROTOCOL_TCP: u16 = 0x0000;
+const MODBUS_HEADER_SIZE: usize = 7;
+/// Largest value of the MBAP `len` field: unit id and a PDU of at most 253 bytes.
+const MODBUS_MAX_LEN: usize = 254;
+
+/// The data tables of a `Bank`.
+pub use crate::Table;
+
+/// Access permitted to an address range of a `Table`.
+///
+/// Reading a write-only or writing a read-only address is answered with an `IllegalFunction`
+/// exception, any access to a `NoAccess` address with an `IllegalDataAddress` exception.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    ReadWrite,
+    ReadOnly,
+    WriteOnly,
+    NoAccess,
+}
+
+impl Access {
+    fn check(self, write: bool) -> std::result::Result<(), ExceptionCode> {
+        match (self, write) {
+            (Access::NoAccess, _) => Err(ExceptionCode::IllegalDataAddress),
+            (Access::ReadOnly, true) | (Access::WriteOnly, false) => {
+                Err(ExceptionCode::IllegalFunction)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The data tables served by a `Server`.
+///
+/// Table entries are addressed from `0`, requests reaching beyond the end of a table are
+/// answered with an `IllegalDataAddress` exception.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bank {
+    /// Read-write single bits (function codes 1, 5 and 15)
+    pub coils: Vec<Coil>,
+    /// Read-only single bits (function code 2)
+    pub discrete_inputs: Vec<Coil>,
+    /// Read-write 16bit registers (function codes 3, 6, 16, 22 and 23)
+    pub holding_registers: Vec<u16>,
+    /// Read-only 16bit registers (function code 4)
+    pub input_registers: Vec<u16>,
+    /// The eight exception status outputs (function code 7)
+    pub exception_status: u8,
+    /// The identification returned by the Report Slave ID function (function code 17)
+    pub slave_id: SlaveId,
+    /// FIFO queues by their address (function code 24)
+    pub fifo_queues: HashMap<u16, Vec<u16>>,
+    /// Register files by their file number, records are addressed by their register offset
+    /// (function codes 20 and 21)
+    pub files: HashMap<u16, Vec<u16>>,
+    // Access rules as (table, first address, end address), later rules take precedence.
+    access: Vec<(Table, u32, u32, Access)>,
+}
+
+type Response = std::result::Result<Vec<u8>, ExceptionCode>;
+type FileRequest<'a> = (u16, usize, usize, &'a [u8]);
+
+impl Bank {
+    /// Create a bank with `size` entries in each table, all set to `Off` or `0`.
+    pub fn new(size: usize) -> Bank {
+        Bank {
+            coils: vec![Coil::Off; size],
+            discrete_inputs: vec![Coil::Off; size],
+            holding_registers: vec![0; size],
+            input_registers: vec![0; size],
+            exception_status: 0,
+            slave_id: SlaveId::default(),
+            fifo_queues: HashMap::new(),
+            files: HashMap::new(),
+            access: vec![],
+        }
+    }
+
+    /// Restrict the access to the addresses `range` of `table`, overriding the access set for
+    /// these addresses before. All addresses are `ReadWrite` by default.
+    pub fn set_access<R: RangeBounds<u16>>(&mut self, table: Table, range: R, access: Access) {
+        let start = match range.start_bound() {
+            Bound::Included(a) => *a as u32,
+            Bound::Excluded(a) => *a as u32 + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(a) => *a as u32 + 1,
+            Bound::Excluded(a) => *a as u32,
+            Bound::Unbounded => 0x10000,
+        };
+        self.access.push((table, start, end, access));
+    }
+
+    /// The access permitted to address `addr` of `table`.
+    pub fn access(&self, table: Table, addr: u16) -> Access {
+        self.access
+            .iter()
+            .rev()
+            .find(|(t, start, end, _)| *t == table && (*start..*end).contains(&(addr as u32)))
+            .map_or(Access::ReadWrite, |rule| rule.3)
+    }
+
+    /// Replace the coils starting at `addr` with `values` and return their previous values.
+    ///
+    /// A request is served with the bank locked, so called on the handle returned by
+    /// `Server::bank` clients see either all previous or all new values:
+    ///
+    /// ```no_run
+    /// # use modbus::server::{Bank, Server};
+    /// # use modbus::Coil;
+    /// # let server = Server::bind("0.0.0.0:5020", Bank::new(1000)).unwrap();
+    /// let bank = server.bank();
+    /// let old = bank.lock().unwrap().swap_coils(8, &[Coil::On; 4]).unwrap();
+    /// ```
+    pub fn swap_coils(&mut self, addr: u16, values: &[Coil]) -> Result<Vec<Coil>> {
+        swap(&mut self.coils, addr, values)
+    }
+
+    /// Replace the holding registers starting at `addr` with `values` and return their previous
+    /// values, see `swap_coils`.
+    pub fn swap_holding_registers(&mut self, addr: u16, values: &[u16]) -> Result<Vec<u16>> {
+        swap(&mut self.holding_registers, addr, values)
+    }
+
+    /// Replace the coils starting at `addr` with `new` if they are equal to `current`, returns
+    /// whether they were replaced.
+    pub fn compare_and_set_coils(
+        &mut self,
+        addr: u16,
+        current: &[Coil],
+        new: &[Coil],
+    ) -> Result<bool> {
+        compare_and_set(&mut self.coils, addr, current, new)
+    }
+
+    /// Replace the holding registers starting at `addr` with `new` if they are equal to
+    /// `current`, returns whether they were replaced.
+    pub fn compare_and_set_holding_registers(
+        &mut self,
+        addr: u16,
+        current: &[u16],
+        new: &[u16],
+    ) -> Result<bool> {
+        compare_and_set(&mut self.holding_registers, addr, current, new)
+    }
+
+    /// Set table entries from a CSV image read from `reader`.
+    ///
+    /// Every line has the form `table,address,value` where `table` is one of `coil`,
+    /// `discrete_input`, `holding_register` or `input_register`. Addresses and values are decimal
+    /// or `0x` prefixed hexadecimal numbers, bit values are `0` or `1`. Empty lines and lines
+    /// starting with `#` are ignored. Entries not listed in the image keep their value.
+    ///
+    /// ```no_run
+    /// # use modbus::server::Bank;
+    /// # use std::fs::File;
+    /// # use std::io::BufReader;
+    /// let mut bank = Bank::new(1000);
+    /// bank.read_csv(BufReader::new(File::open("device.csv").unwrap())).unwrap();
+    /// ```
+    pub fn read_csv<R: BufRead>(&mut self, reader: R) -> Result<()> {
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |reason: &str| {
+                Error::InvalidData(Reason::Custom(format!("image line {}: {}", n + 1, reason)))
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (table, addr, value) = match fields[..] {
+                [table, addr, value] => (
+                    table,
+                    parse_number(addr).ok_or_else(|| err("invalid address"))? as usize,
+                    parse_number(value).ok_or_else(|| err("invalid value"))?,
+                ),
+                _ => return Err(err("expected table,address,value")),
+            };
+            let bit = || match value {
+                0 => Ok(Coil::Off),
+                1 => Ok(Coil::On),
+                _ => Err(err("invalid bit value")),
+            };
+            let entry_err = || err("address outside of table");
+            match table {
+                "coil" => *self.coils.get_mut(addr).ok_or_else(entry_err)? = bit()?,
+                "discrete_input" => {
+                    *self.discrete_inputs.get_mut(addr).ok_or_else(entry_err)? = bit()?
+                }
+                "holding_register" => {
+                    *self.holding_registers.get_mut(addr).ok_or_else(entry_err)? = value
+                }
+                "input_register" => {
+                    *self.input_registers.get_mut(addr).ok_or_else(entry_err)? = value
+                }
+                _ => return Err(err("unknown table")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write all entries which are not `Off` or `0` as CSV image to `writer`, see `read_csv` for
+    /// the format.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> Result<()> {
+        writeln!(writer, "# table,address,value")?;
+        let bits = [
+            ("coil", &self.coils),
+            ("discrete_input", &self.discrete_inputs),
+        ];
+        for (name, table) in bits {
+            for (addr, _) in table.iter().enumerate().filter(|(_, c)| **c == Coil::On) {
+                writeln!(writer, "{},{},1", name, addr)?;
+            }
+        }
+        let registers = [
+            ("holding_register", &self.holding_registers),
+            ("input_register", &self.input_registers),
+        ];
+        for (name, table) in registers {
+            for (addr, value) in table.iter().enumerate().filter(|(_, v)| **v != 0) {
+                writeln!(writer, "{},{},{}", name, addr, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Check `limit` and the access rules for all addresses touched by the request. Malformed
+    // requests pass, they are rejected by the function handlers.
+    fn check_access(&self, pdu: &[u8], limit: Access) -> std::result::Result<(), ExceptionCode> {
+        use self::Table::*;
+
+        // files and FIFO queues are outside of the tables, only the access of the listener applies
+        match pdu[0] {
+            0x14 | 0x18 => limit.check(false)?,
+            0x15 => limit.check(true)?,
+            _ => (),
+        }
+        // table, offset of the address and of the quantity (`None` for single writes), write
+        let ranges: &[(Table, usize, Option<usize>, bool)] = match pdu[0] {
+            0x01 => &[(Coils, 1, Some(3), false)],
+            0x02 => &[(DiscreteInputs, 1, Some(3), false)],
+            0x03 => &[(HoldingRegisters, 1, Some(3), false)],
+            0x04 => &[(InputRegisters, 1, Some(3), false)],
+            0x05 => &[(Coils, 1, None, true)],
+            0x06 => &[(HoldingRegisters, 1, None, true)],
+            0x0f => &[(Coils, 1, Some(3), true)],
+            0x10 => &[(HoldingRegisters, 1, Some(3), true)],
+            0x16 => &[(HoldingRegisters, 1, None, true)],
+            0x17 => &[
+                (HoldingRegisters, 1, Some(3), false),
+                (HoldingRegisters, 5, Some(7), true),
+            ],
+            _ => &[],
+        };
+        for &(table, addr_offset, count_offset, write) in ranges {
+            limit.check(write)?;
+            let count = count_offset.map_or(Ok(1), |offset| word(pdu, offset));
+            if let (Ok(addr), Ok(count)) = (word(pdu, addr_offset), count) {
+                for a in addr as u32..(addr as u32 + count as u32).min(0x10000) {
+                    self.access(table, a as u16).check(write)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Process the request `pdu` (function code and data) and return the response PDU, which is
+    /// an exception response if the request can't be served.
+    pub fn process(&mut self, pdu: &[u8]) -> Vec<u8> {
+        self.process_with_access(pdu, Access::ReadWrite)
+    }
+
+    /// Like `process`, but additionally restrict the whole bank to `access`, e.g. to serve it
+    /// read-only to some clients.
+    pub fn process_with_access(&mut self, pdu: &[u8], access: Access) -> Vec<u8> {
+        let function = match pdu.first() {
+            Some(f) => *f,
+            None => return vec![0x80, ExceptionCode::IllegalFunction as u8],
+        };
+        let res = self
+            .check_access(pdu, access)
+            .and_then(|()| match function {
+                0x01 => read_bits(pdu, &self.coils),
+                0x02 => read_bits(pdu, &self.discrete_inputs),
+                0x03 => read_registers(pdu, &self.holding_registers),
+                0x04 => read_registers(pdu, &self.input_registers),
+                0x05 => self.write_single_coil(pdu),
+                0x06 => self.write_single_register(pdu),
+                0x07 => Ok(vec![0x07, self.exception_status]),
+                // only Return Query Data (echo the request) is supported
+                0x08 if pdu.get(1..3) == Some(&[0x00, 0x00]) => Ok(pdu.to_vec()),
+                0x0f => self.write_multiple_coils(pdu),
+                0x10 => self.write_multiple_registers(pdu),
+                0x11 => Ok(self.slave_id.to_response()),
+                0x14 => self.read_file_records(pdu),
+                0x15 => self.write_file_records(pdu),
+                0x16 => self.mask_write_register(pdu),
+                0x17 => self.write_read_multiple_registers(pdu),
+                0x18 => self.read_fifo_queue(pdu),
+                _ => Err(ExceptionCode::IllegalFunction),
+            });
+        match res {
+            Ok(data) => data,
+            Err(code) => vec![function | 0x80, code as u8],
+        }
+    }
+
+    fn write_single_coil(&mut self, pdu: &[u8]) -> Response {
+        let (addr, value) = (word(pdu, 1)?, word(pdu, 3)?);
+        let value = match value {
+            0xff00 => Coil::On,
+            0x0000 => Coil::Off,
+            _ => return Err(ExceptionCode::IllegalDataValue),
+        };
+        *self
+            .coils
+            .get_mut(addr as usize)
+            .ok_or(ExceptionCode::IllegalDataAddress)? = value;
+        Ok(pdu[..5].to_vec())
+    }
+
+    fn write_single_register(&mut self, pdu: &[u8]) -> Response {
+        let (addr, value) = (word(pdu, 1)?, word(pdu, 3)?);
+        *self
+            .holding_registers
+            .get_mut(addr as usize)
+            .ok_or(ExceptionCode::IllegalDataAddress)? = value;
+        Ok(pdu[..5].to_vec())
+    }
+
+    fn write_multiple_coils(&mut self, pdu: &[u8]) -> Response {
+        let (addr, count) = (word(pdu, 1)?, word(pdu, 3)?);
+        let values = write_data(pdu, count, MAX_WRITE_BITS, count.div_ceil(8) as usize)?;
+        let coils = table_range(&mut self.coils, addr, count)?;
+        coils.copy_from_slice(&binary::unpack_bits(values, count));
+        Ok(pdu[..5].to_vec())
+    }
+
+    fn write_multiple_registers(&mut self, pdu: &[u8]) -> Response {
+        let (addr, count) = (word(pdu, 1)?, word(pdu, 3)?);
+        let values = write_data(pdu, count, MAX_WRITE_REGISTERS, 2 * count as usize)?;
+        let registers = table_range(&mut self.holding_registers, addr, count)?;
+        BigEndian::read_u16_into(values, registers);
+        Ok(pdu[..5].to_vec())
+    }
+
+    fn mask_write_register(&mut self, pdu: &[u8]) -> Response {
+        let (addr, and_mask, or_mask) = (word(pdu, 1)?, word(pdu, 3)?, word(pdu, 5)?);
+        let register = self
+            .holding_registers
+            .get_mut(addr as usize)
+            .ok_or(ExceptionCode::IllegalDataAddress)?;
+        *register = (*register & and_mask) | (or_mask & !and_mask);
+        Ok(pdu[..7].to_vec())
+    }
+
+    // Split the sub-requests of a file record request into their file and record number, record
+    // length and for writes the record data.
+    fn file_requests(
+        pdu: &[u8],
+        write: bool,
+    ) -> std::result::Result<Vec<FileRequest<'_>>, ExceptionCode> {
+        if pdu.len() < 2 || pdu[1] as usize != pdu.len() - 2 {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        let mut requests = vec![];
+        let mut data = &pdu[2..];
+        while !data.is_empty() {
+            if data.len() < 7 || data[0] != 0x06 {
+                return Err(ExceptionCode::IllegalDataValue);
+            }
+            let (file, record, len) = (word(data, 1)?, word(data, 3)?, word(data, 5)? as usize);
+            let values_len = if write { 2 * len } else { 0 };
+            let values = data
+                .get(7..7 + values_len)
+                .ok_or(ExceptionCode::IllegalDataValue)?;
+            requests.push((file, record as usize, len, values));
+            data = &data[7 + values_len..];
+        }
+        Ok(requests)
+    }
+
+    fn file_range(
+        &mut self,
+        file: u16,
+        record: usize,
+        len: usize,
+    ) -> std::result::Result<&mut [u16], ExceptionCode> {
+        self.files
+            .get_mut(&file)
+            .and_then(|f| f.get_mut(record..record + len))
+            .ok_or(ExceptionCode::IllegalDataAddress)
+    }
+
+    fn read_file_records(&mut self, pdu: &[u8]) -> Response {
+        let mut res = vec![pdu[0], 0];
+        for (file, record, len, _) in Bank::file_requests(pdu, false)? {
+            let values = binary::unpack_bytes(self.file_range(file, record, len)?);
+            res.push(values.len() as u8 + 1);
+            res.push(0x06);
+            res.extend_from_slice(&values);
+        }
+        if res.len() > 2 + 0xf5 {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        res[1] = (res.len() - 2) as u8;
+        Ok(res)
+    }
+
+    fn write_file_records(&mut self, pdu: &[u8]) -> Response {
+        let requests = Bank::file_requests(pdu, true)?;
+        // check all records before writing any of them
+        for &(file, record, len, _) in &requests {
+            self.file_range(file, record, len)?;
+        }
+        for (file, record, len, values) in requests {
+            BigEndian::read_u16_into(values, self.file_range(file, record, len)?);
+        }
+        Ok(pdu.to_vec())
+    }
+
+    fn read_fifo_queue(&self, pdu: &[u8]) -> Response {
+        let queue = self
+            .fifo_queues
+            .get(&word(pdu, 1)?)
+            .ok_or(ExceptionCode::IllegalDataAddress)?;
+        if queue.len() > 31 {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        let mut res = vec![pdu[0]];
+        res.extend_from_slice(&(2 * queue.len() as u16 + 2).to_be_bytes());
+        res.extend_from_slice(&(queue.len() as u16).to_be_bytes());
+        res.extend_from_slice(&binary::unpack_bytes(queue));
+        Ok(res)
+    }
+
+    // The write is performed before the read, both address ranges are checked before writing.
+    fn write_read_multiple_registers(&mut self, pdu: &[u8]) -> Response {
+        let (read_addr, read_count) = read_quantity(pdu, MAX_READ_REGISTERS)?;
+        let (write_addr, write_count) = (word(pdu, 5)?, word(pdu, 7)?);
+        let expected_bytes = 2 * write_count as usize;
+        if !(1..=MAX_WRITE_READ_REGISTERS).contains(&write_count)
+            || pdu.get(9).map(|b| *b as usize) != Some(expected_bytes)
+            || pdu.len() != 10 + expected_bytes
+        {
+            return Err(ExceptionCode::IllegalDataValue);
+        }
+        table_range(&mut self.holding_registers, read_addr, read_count)?;
+        let registers = table_range(&mut self.holding_registers, write_addr, write_count)?;
+        BigEndian::read_u16_into(&pdu[10..], registers);
+        read_registers(pdu, &self.holding_registers)
+    }
+}
+
+fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// Read the big endian word at `offset` of the request, a request too short to contain it is an
+// `IllegalDataValue`.
+fn word(pdu: &[u8], offset: usize) -> std::result::Result<u16, ExceptionCode> {
+    pdu.get(offset..offset + 2)
+        .map(BigEndian::read_u16)
+        .ok_or(ExceptionCode::IllegalDataValue)
+}
+
+// Check the quantity and byte count of a write request and return its values.
+fn write_data(
+    pdu: &[u8],
+    count: u16,
+    max_count: u16,
+    expected_bytes: usize,
+) -> std::result::Result<&[u8], ExceptionCode> {
+    if count < 1
+        || count > max_count
+        || pdu.get(5).map(|b| *b as usize) != Some(expected_bytes)
+        || pdu.len() != 6 + expected_bytes
+    {
+        return Err(ExceptionCode::IllegalDataValue);
+    }
+    Ok(&pdu[6..])
+}
+
+fn table_range<T>(
+    table: &mut [T],
+    addr: u16,
+    count: u16,
+) -> std::result::Result<&mut [T], ExceptionCode> {
+    table
+        .get_mut(addr as usize..addr as usize + count as usize)
+        .ok_or(ExceptionCode::IllegalDataAddress)
+}
+
+fn read_quantity(pdu: &[u8], max_count: u16) -> std::result::Result<(u16, u16), ExceptionCode> {
+    let (addr, count) = (word(pdu, 1)?, word(pdu, 3)?);
+    if count < 1 || count > max_count {
+        return Err(ExceptionCode::IllegalDataValue);
+    }
+    Ok((addr, count))
+}
+
+fn read_bits(pdu: &[u8], table: &[Coil]) -> Response {
+    let (addr, count) = read_quantity(pdu, MAX_READ_BITS)?;
+    let bits = table
+        .get(addr as usize..addr as usize + count as usize)
+        .ok_or(ExceptionCode::IllegalDataAddress)?;
+    let bytes = binary::pack_bits(bits);
+    let mut res = vec![pdu[0], bytes.len() as u8];
+    res.extend_from_slice(&bytes);
+    Ok(res)
+}
+
+fn read_registers(pdu: &[u8], table: &[u16]) -> Response {
+    let (addr, count) = read_quantity(pdu, MAX_READ_REGISTERS)?;
+    let registers = table
+        .get(addr as usize..addr as usize + count as usize)
+        .ok_or(ExceptionCode::IllegalDataAddress)?;
+    let mut res = vec![pdu[0], 2 * count as u8];
+    res.extend_from_slice(&binary::unpack_bytes(registers));
+    Ok(res)
+}
+
+// The `len` entries of `table` starting at `addr`.
+fn block<T>(table: &mut [T], addr: u16, len: usize) -> Result<&mut [T]> {
+    table
+        .get_mut(addr as usize..addr as usize + len)
+        .ok_or_else(|| {
+            Error::InvalidData(Reason::Custom(format!(
+                "{} entries starting at address {} exceed the table",
+                len, addr
+            )))
+        })
+}
+
+fn swap<T: Clone>(table: &mut [T], addr: u16, values: &[T]) -> Result<Vec<T>> {
+    let block = block(table, addr, values.len())?;
+    let old = block.to_vec();
+    block.clone_from_slice(values);
+    Ok(old)
+}
+
+fn compare_and_set<T: Clone + PartialEq>(
+    table: &mut [T],
+    addr: u16,
+    current: &[T],
+    new: &[T],
+) -> Result<bool> {
+    if current.len() != new.len() {
+        return Err(Error::InvalidData(Reason::Custom(format!(
+            "{} current and {} new values",
+            current.len(),
+            new.len()
+        ))));
+    }
+    let block = block(table, addr, new.len())?;
+    if block != current {
+        return Ok(false);
+    }
+    block.clone_from_slice(new);
+    Ok(true)
+}
+
+/// Artificial delay of a server response.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Latency {
+    /// Fixed delay before every response
+    pub delay: Duration,
+    /// Upper bound of a random delay added on top of `delay`
+    pub jitter: Duration,
+}
+
+impl Latency {
+    fn sample(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.delay;
+        }
+        // Every `RandomState` is seeded differently, which is random enough for jitter.
+        let random = RandomState::new().build_hasher().finish();
+        let jitter = (random % (self.jitter.as_nanos() as u64 + 1)) as u32;
+        self.delay
+            + self
+                .jitter
+                .mul_f64(jitter as f64 / self.jitter.as_nanos() as f64)
+    }
+}
+
+#[derive(Clone, Default)]
+struct Shaping {
+    latency: Latency,
+    function_latency: HashMap<u8, Latency>,
+    duplicate_cache: usize,
+}
+
+/// The state of a connection to a `Server`, passed to its handler with every request.
+pub struct Session {
+    id: u64,
+    peer_addr: SocketAddr,
+    state: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Session {
+    /// A number identifying the connection, unique among the connections of a server.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address of the client.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Attach `value` to the session, replacing and returning a previous value of the same type.
+    /// It is kept until the connection is closed.
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.state
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|v| v.downcast().ok())
+            .map(|v| *v)
+    }
+
+    /// The value of type `T` attached to the session.
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.state.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// The value of type `T` attached to the session, mutably.
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.state.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    /// Detach and return the value of type `T`.
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.state
+            .remove(&TypeId::of::<T>())?
+            .downcast()
+            .ok()
+            .map(|v| *v)
+    }
+}
+
+/// A handler of requests, see `Server::set_handler`.
+pub type Handler = dyn Fn(&mut Session, &mut Bank, &[u8]) -> Option<Vec<u8>> + Send + Sync;
+
+/// A Modbus TCP server answering requests from a shared `Bank`.
+///
+/// The server can listen on several addresses at once, e.g. on different ports or network
+/// interfaces, each with its own access policy for the bank.
+pub struct Server {
+    listeners: Vec<(TcpListener, Access)>,
+    bank: Arc<Mutex<Bank>>,
+    shaping: Arc<Shaping>,
+    handler: Option<Arc<Handler>>,
+    connections: AtomicU64,
+}
+
+impl Server {
+    /// Create a server listening on `addr` and serving the tables of `bank`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, bank: Bank) -> io::Result<Server> {
+        Ok(Server {
+            listeners: vec![(TcpListener::bind(addr)?, Access::ReadWrite)],
+            bank: Arc::new(Mutex::new(bank)),
+            shaping: Arc::default(),
+            handler: None,
+            connections: AtomicU64::new(0),
+        })
+    }
+
+    /// Delay the responses to all requests by `latency`, unless a latency is set for the
+    /// function of the request with `set_function_latency`.
+    pub fn set_latency(&mut self, latency: Latency) {
+        Arc::make_mut(&mut self.shaping).latency = latency;
+    }
+
+    /// Delay the responses to requests of function code `function` by `latency`.
+    pub fn set_function_latency(&mut self, function: u8, latency: Latency) {
+        Arc::make_mut(&mut self.shaping)
+            .function_latency
+            .insert(function, latency);
+    }
+
+    /// Answer requests which repeat one of the last `size` requests of the same connection, with
+    /// the same transaction id, unit id and PDU, with the cached response instead of executing
+    /// them again. Clients typically retransmit after a timeout, so writes with side effects
+    /// are applied only once. Disabled with a `size` of `0` (Default: `0`)
+    pub fn set_duplicate_cache(&mut self, size: usize) {
+        Arc::make_mut(&mut self.shaping).duplicate_cache = size;
+    }
+
+    /// Pass every request PDU to `handler` before it is processed, together with the `Session`
+    /// of its connection and the locked bank. A response PDU returned by the handler is sent
+    /// as it is, without checking any access rules, on `None` the request is processed by the
+    /// bank as usual.
+    ///
+    /// ```no_run
+    /// use modbus::server::{Bank, Server};
+    /// use modbus::ExceptionCode;
+    ///
+    /// struct LoggedIn;
+    ///
+    /// let mut server = Server::bind("0.0.0.0:5020", Bank::new(100)).unwrap();
+    /// // writes are only accepted after writing the password 0x1234 to register 99
+    /// server.set_handler(|session, _bank, pdu| {
+    ///     if pdu == [0x06, 0, 99, 0x12, 0x34] {
+    ///         session.insert(LoggedIn);
+    ///     } else if [0x05, 0x06, 0x0f, 0x10].contains(&pdu[0])
+    ///         && session.get::<LoggedIn>().is_none()
+    ///     {
+    ///         return Some(vec![pdu[0] | 0x80, ExceptionCode::IllegalFunction as u8]);
+    ///     }
+    ///     None
+    /// });
+    /// server.serve().unwrap();
+    /// ```
+    pub fn set_handler<F>(&mut self, handler: F)
+    where
+        F: Fn(&mut Session, &mut Bank, &[u8]) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+    }
+
+    /// Additionally listen on `addr`, connections accepted there are restricted to `access`
+    /// on top of the access rules of the bank.
+    pub fn add_listener<A: ToSocketAddrs>(&mut self, addr: A, access: Access) -> io::Result<()> {
+        self.listeners.push((TcpListener::bind(addr)?, access));
+        Ok(())
+    }
+
+    /// The address the server is listening on, useful when binding to port `0`. This is the
+    /// address passed to `bind`, see `local_addrs` for all listeners.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].0.local_addr()
+    }
+
+    /// The addresses of all listeners in the order they were added.
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(|(l, _)| l.local_addr()).collect()
+    }
+
+    /// A handle to the served tables, which can be used to inspect or change them while the
+    /// server is running.
+    pub fn bank(&self) -> Arc<Mutex<Bank>> {
+        self.bank.clone()
+    }
+
+    /// Accept connections on all listeners and serve each of them on its own thread. Only
+    /// returns after accepting connections failed on every listener.
+    pub fn serve(&self) -> io::Result<()> {
+        thread::scope(|scope| {
+            let accept_loops: Vec<_> = self
+                .listeners
+                .iter()
+                .map(|(listener, access)| scope.spawn(move || self.accept(listener, *access)))
+                .collect();
+            accept_loops
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .collect::<io::Result<Vec<()>>>()
+                .map(|_| ())
+        })
+    }
+
+    fn accept(&self, listener: &TcpListener, access: Access) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            // the client may already be gone again
+            let peer_addr = match stream.peer_addr() {
+                Ok(addr) => addr,
+                Err(_) => continue,
+            };
+            let session = Session {
+                id: self.connections.fetch_add(1, Ordering::Relaxed),
+                peer_addr,
+                state: HashMap::new(),
+            };
+            let bank = self.bank.clone();
+            let shaping = self.shaping.clone();
+            let handler = self.handler.clone();
+            thread::spawn(move || {
+                serve_connection(stream, session, &bank, access, &shaping, handler.as_deref())
+            });
+        }
+        Ok(())
+    }
+}
+
+// Answer requests on `stream` until the client disconnects or sends an invalid MBAP header.
+fn serve_connection(
+    mut stream: TcpStream,
+    mut session: Session,
+    bank: &Mutex<Bank>,
+    access: Access,
+    shaping: &Shaping,
+    handler: Option<&Handler>,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut header = [0; MODBUS_HEADER_SIZE];
+    // the last requests as (tid, uid, request pdu, response pdu), the newest at the back
+    let mut cache: VecDeque<(u16, u8, Vec<u8>, Vec<u8>)> = VecDeque::new();
+    loop {
+        match stream.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let tid = BigEndian::read_u16(&header[0..2]);
+        let pid = BigEndian::read_u16(&header[2..4]);
+        let len = BigEndian::read_u16(&header[4..6]) as usize;
+        let uid = header[6];
+        // Without a valid header the frame boundaries are lost, so the connection is dropped.
+        if pid != MODBUS_PROTOCOL_TCP || !(2..=MODBUS_MAX_LEN).contains(&len) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid modbus tcp header",
+            ));
+        }
+        let mut pdu = vec![0; len - 1];
+        stream.read_exact(&mut pdu)?;
+
+        let latency = shaping
+            .function_latency
+            .get(&pdu[0])
+            .unwrap_or(&shaping.latency)
+            .sample();
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+        let cached = cache
+            .iter()
+            .find(|(t, u, req, _)| *t == tid && *u == uid && *req == pdu)
+            .map(|(_, _, _, resp)| resp.clone());
+        let pdu = match cached {
+            Some(resp) => resp,
+            None => {
+                let mut bank = bank.lock().unwrap();
+                let resp = match handler.and_then(|h| h(&mut session, &mut bank, &pdu)) {
+                    Some(resp) => resp,
+                    None => bank.process_with_access(&pdu, access),
+                };
+                drop(bank);
+                if shaping.duplicate_cache > 0 {
+                    if cache.len() == shaping.duplicate_cache {
+                        cache.pop_front();
+                    }
+                    cache.push_back((tid, uid, pdu, resp.clone()));
+                }
+                resp
+            }
+        };
+        let mut buff = Vec::with_capacity(MODBUS_HEADER_SIZE + pdu.len());
+        buff.write_u16::<BigEndian>(tid)?;
+        buff.write_u16::<BigEndian>(MODBUS_PROTOCOL_TCP)?;
+        buff.write_u16::<BigEndian>(pdu.len() as u16 + 1)?;
+        buff.write_u8(uid)?;
+        buff.extend_from_slice(&pdu);
+        stream.write_all(&buff)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{tcp, Client, DiagnosticsSubFunction, Error, FileRecord};
+
+    fn start(bank: Bank) -> (Arc<Mutex<Bank>>, tcp::Transport) {
+        let server = Server::bind("127.0.0.1:0", bank).unwrap();
+        let cfg = tcp::Config {
+            tcp_port: server.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let bank = server.bank();
+        thread::spawn(move || server.serve());
+        (
+            bank,
+            tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap(),
+        )
+    }
+
+    #[test]
+    fn read_and_write() {
+        let mut bank = Bank::new(10);
+        bank.discrete_inputs[1] = Coil::On;
+        bank.input_registers[9] = 0xbeef;
+        bank.exception_status = 0x81;
+        bank.fifo_queues.insert(0x04de, vec![0x01b8, 0x1284]);
+        bank.files.insert(4, vec![0; 20]);
+        let (bank, mut client) = start(bank);
+
+        let record = FileRecord {
+            file_number: 4,
+            record_number: 7,
+            record_data: vec![0x06af, 0x04be, 0x100d],
+        };
+        assert!(client
+            .write_file_records(std::slice::from_ref(&record))
+            .is_ok());
+        assert_eq!(
+            client.read_file_records(&[(4, 7, 3), (4, 8, 1)]).unwrap(),
+            vec![
+                record,
+                FileRecord {
+                    file_number: 4,
+                    record_number: 8,
+                    record_data: vec![0x04be],
+                }
+            ]
+        );
+        match client
+            .read_file_records(&[(4, 19, 2)])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(
+            client.read_fifo_queue(0x04de).unwrap(),
+            vec![0x01b8, 0x1284]
+        );
+        match client.read_fifo_queue(0).as_ref().map_err(Error::root) {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert_eq!(client.read_exception_status().unwrap(), 0x81);
+        assert_eq!(client.report_slave_id().unwrap(), SlaveId::default());
+        assert_eq!(
+            client
+                .diagnostics(DiagnosticsSubFunction::ReturnQueryData, &[1, 2])
+                .unwrap(),
+            vec![1, 2]
+        );
+        match client
+            .diagnostics(DiagnosticsSubFunction::ReturnBusMessageCount, &[0])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalFunction)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(client.write_single_coil(2, Coil::On).is_ok());
+        assert!(client
+            .write_multiple_coils(5, &[Coil::On, Coil::Off, Coil::On])
+            .is_ok());
+        assert_eq!(
+            client.read_coils(1, 7).unwrap(),
+            vec![
+                Coil::Off,
+                Coil::On,
+                Coil::Off,
+                Coil::Off,
+                Coil::On,
+                Coil::Off,
+                Coil::On
+            ]
+        );
+        assert_eq!(
+            client.read_discrete_inputs(0, 2).unwrap(),
+            vec![Coil::Off, Coil::On]
+        );
+
+        assert!(client.write_single_register(0, 0x1234).is_ok());
+        assert!(client.write_multiple_registers(8, &[1, 2]).is_ok());
+        assert_eq!(client.read_holding_registers(0, 1).unwrap(), vec![0x1234]);
+        assert_eq!(client.read_input_registers(9, 1).unwrap(), vec![0xbeef]);
+        assert_eq!(bank.lock().unwrap().holding_registers[8..], [1, 2]);
+
+        bank.lock().unwrap().holding_registers[9] = 3;
+        assert_eq!(client.read_holding_registers(8, 2).unwrap(), vec![1, 3]);
+
+        assert!(client.mask_write_register(0, 0xff0f, 0x0050).is_ok());
+        assert_eq!(client.read_holding_registers(0, 1).unwrap(), vec![0x1254]);
+
+        // the write is done before the read
+        assert_eq!(
+            client
+                .write_read_multiple_registers(1, 2, &[4, 5], 0, 3)
+                .unwrap(),
+            vec![0x1254, 4, 5]
+        );
+    }
+
+    #[test]
+    fn exceptions() {
+        let (_, mut client) = start(Bank::new(10));
+        match client
+            .read_holding_registers(9, 2)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match client
+            .write_single_coil(10, Coil::On)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match client
+            .write_read_multiple_registers(9, 2, &[1, 2], 0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // the connection is still usable after exceptions
+        assert_eq!(client.read_holding_registers(9, 1).unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut bank = Bank::new(10);
+        // quantity out of range
+        assert_eq!(bank.process(&[0x01, 0, 0, 0, 0]), vec![0x81, 0x03]);
+        assert_eq!(bank.process(&[0x03, 0, 0, 0, 126]), vec![0x83, 0x03]);
+        // truncated request
+        assert_eq!(bank.process(&[0x06, 0, 0, 0]), vec![0x86, 0x03]);
+        // invalid coil value
+        assert_eq!(bank.process(&[0x05, 0, 0, 0x12, 0x34]), vec![0x85, 0x03]);
+        // byte count doesn't match the quantity
+        assert_eq!(bank.process(&[0x10, 0, 0, 0, 2, 2, 0, 1]), vec![0x90, 0x03]);
+        assert_eq!(bank.process(&[0x2b, 0x0e, 1, 0]), vec![0xab, 0x01]);
+        assert_eq!(bank, Bank::new(10));
+    }
+
+    #[test]
+    fn access_permissions() {
+        let mut bank = Bank::new(10);
+        bank.set_access(Table::HoldingRegisters, 2..6, Access::ReadOnly);
+        bank.set_access(Table::HoldingRegisters, 4..=4, Access::NoAccess);
+        bank.set_access(Table::Coils, 8.., Access::WriteOnly);
+        assert_eq!(bank.access(Table::HoldingRegisters, 3), Access::ReadOnly);
+        assert_eq!(bank.access(Table::HoldingRegisters, 4), Access::NoAccess);
+        assert_eq!(bank.access(Table::HoldingRegisters, 6), Access::ReadWrite);
+        assert_eq!(bank.access(Table::InputRegisters, 4), Access::ReadWrite);
+
+        assert_eq!(bank.process(&[0x03, 0, 2, 0, 2]), vec![0x03, 4, 0, 0, 0, 0]);
+        assert_eq!(bank.process(&[0x03, 0, 3, 0, 2]), vec![0x83, 0x02]);
+        assert_eq!(bank.process(&[0x06, 0, 2, 0, 1]), vec![0x86, 0x01]);
+        assert_eq!(
+            bank.process(&[0x10, 0, 0, 0, 3, 6, 0, 1, 0, 1, 0, 1]),
+            vec![0x90, 0x01]
+        );
+        assert_eq!(bank.process(&[0x06, 0, 6, 0, 1]), vec![0x06, 0, 6, 0, 1]);
+
+        assert_eq!(bank.process(&[0x01, 0, 7, 0, 2]), vec![0x81, 0x01]);
+        assert_eq!(
+            bank.process(&[0x05, 0, 9, 0xff, 0]),
+            vec![0x05, 0, 9, 0xff, 0]
+        );
+        assert_eq!(bank.coils[9], Coil::On);
+    }
+
+    #[test]
+    fn chunked() {
+        let (bank, mut client) = start(Bank::new(1000));
+        let values: Vec<u16> = (0..300).collect();
+        client
+            .write_multiple_registers_chunked(600, &values)
+            .unwrap();
+        assert_eq!(bank.lock().unwrap().holding_registers[600..900], values[..]);
+        assert_eq!(
+            client.read_holding_registers_chunked(600, 300).unwrap(),
+            values
+        );
+        bank.lock().unwrap().input_registers[999] = 7;
+        let inputs = client.read_input_registers_chunked(0, 1000).unwrap();
+        assert_eq!((inputs.len(), inputs[999]), (1000, 7));
+        assert!(client.read_input_registers_chunked(0, 1001).is_err());
+        assert!(client
+            .read_holding_registers_chunked(0xff00, 0x101)
+            .is_err());
+
+        let (bank, mut client) = start(Bank::new(2000));
+        let coils: Vec<Coil> = (0..2000).map(|i| (i % 3 == 0).into()).collect();
+        assert!(client.write_multiple_coils(0, &coils[..1969]).is_err());
+        client.write_multiple_coils_chunked(0, &coils).unwrap();
+        assert_eq!(bank.lock().unwrap().coils, coils);
+    }
+
+    #[test]
+    fn typed_values() {
+        use crate::binary::Endian::{Big, Little};
+
+        let (bank, mut client) = start(Bank::new(8));
+        client
+            .write_f32(0, std::f32::consts::PI, Big, Little)
+            .unwrap();
+        assert_eq!(
+            bank.lock().unwrap().holding_registers[..2],
+            [0x0fdb, 0x4049]
+        );
+        assert_eq!(
+            client.read_f32(0, Big, Little).unwrap(),
+            std::f32::consts::PI
+        );
+        client.write_i64(4, -2, Little, Big).unwrap();
+        assert_eq!(
+            bank.lock().unwrap().holding_registers[4..],
+            [0xffff, 0xffff, 0xffff, 0xfeff]
+        );
+        assert_eq!(client.read_i64(4, Little, Big).unwrap(), -2);
+        client.write_u32(2, 0x1234_5678, Big, Big).unwrap();
+        assert_eq!(client.read_u32(2, Big, Big).unwrap(), 0x1234_5678);
+        assert_eq!(client.read_i32(2, Big, Little).unwrap(), 0x5678_1234);
+        assert!(client.read_f64(6, Big, Big).is_err());
+    }
+
+    #[test]
+    fn torn_reads() {
+        let (bank, mut client) = start(Bank::new(4));
+        let mut reads = 0;
+        // the device updates the value after every read, the third read sees the final value
+        let mut read = || {
+            let value = client.read_holding_registers(0, 2);
+            reads += 1;
+            let mut bank = bank.lock().unwrap();
+            bank.holding_registers[0] = reads.min(3);
+            bank.holding_registers[1] = !reads.min(3);
+            value
+        };
+        assert_eq!(
+            crate::read_validated(&mut read, 3, |v| v[1] == !v[0]).unwrap(),
+            vec![1, !1]
+        );
+        assert_eq!(crate::read_stable(&mut read, 3).unwrap(), vec![3, !3]);
+        assert!(crate::read_stable(&mut read, 1).is_err());
+        assert!(crate::read_validated(&mut read, 2, |v| v[0] == 4).is_err());
+    }
+
+    #[test]
+    fn read_all_clients() {
+        let mut clients = vec![];
+        for value in 1..=3 {
+            let mut bank = Bank::new(4);
+            bank.input_registers[2] = value;
+            clients.push(start(bank).1);
+        }
+        clients.push(start(Bank::new(2)).1);
+        let results = crate::read_all(&mut clients, |c| c.read_input_registers(2, 1));
+        assert_eq!(results.len(), 4);
+        for (value, result) in (1..=3).zip(&results) {
+            assert_eq!(result.as_ref().unwrap(), &vec![value]);
+        }
+        assert!(results[3].is_err());
+    }
+
+    #[test]
+    fn multiple_listeners() {
+        let mut bank = Bank::new(10);
+        bank.files.insert(1, vec![0; 4]);
+        let mut server = Server::bind("127.0.0.1:0", bank).unwrap();
+        server
+            .add_listener("127.0.0.1:0", Access::ReadOnly)
+            .unwrap();
+        let addrs = server.local_addrs().unwrap();
+        assert_eq!(addrs[0], server.local_addr().unwrap());
+        thread::spawn(move || server.serve());
+
+        let connect = |addr: &SocketAddr| {
+            let cfg = tcp::Config {
+                tcp_port: addr.port(),
+                ..Default::default()
+            };
+            tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap()
+        };
+        let mut read_write = connect(&addrs[0]);
+        let mut read_only = connect(&addrs[1]);
+
+        assert!(read_write.write_single_register(1, 7).is_ok());
+        match read_only
+            .write_single_register(1, 8)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalFunction)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(read_only.read_holding_registers(1, 1).unwrap(), vec![7]);
+
+        let record = FileRecord {
+            file_number: 1,
+            record_number: 0,
+            record_data: vec![9],
+        };
+        match read_only
+            .write_file_records(std::slice::from_ref(&record))
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalFunction)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            read_only.read_file_records(&[(1, 0, 1)]).unwrap()[0].record_data,
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn sessions() {
+        struct LoggedIn;
+
+        let mut server = Server::bind("127.0.0.1:0", Bank::new(10)).unwrap();
+        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let seen = peers.clone();
+        server.set_handler(move |session, _, pdu| {
+            seen.lock()
+                .unwrap()
+                .insert(session.id(), session.peer_addr());
+            if pdu == [0x06, 0, 9, 0x12, 0x34] {
+                assert!(session.insert(LoggedIn).is_none());
+            } else if pdu[0] == 0x06 && session.get::<LoggedIn>().is_none() {
+                return Some(vec![0x86, ExceptionCode::IllegalFunction as u8]);
+            }
+            None
+        });
+        let cfg = tcp::Config {
+            tcp_port: server.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let bank = server.bank();
+        thread::spawn(move || server.serve());
+
+        let mut first = tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        let mut second = tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        match first
+            .write_single_register(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalFunction)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(first.write_single_register(9, 0x1234).is_ok());
+        assert!(first.write_single_register(0, 1).is_ok());
+        // the login is only valid for its connection
+        assert!(second.write_single_register(0, 2).is_err());
+        assert!(second.read_holding_registers(0, 1).is_ok());
+        assert_eq!(bank.lock().unwrap().holding_registers[0], 1);
+
+        let peers = peers.lock().unwrap();
+        assert_eq!(peers.len(), 2);
+        assert!(peers.values().all(|addr| addr.ip().is_loopback()));
+    }
+
+    #[test]
+    fn session_state() {
+        let mut session = Session {
+            id: 0,
+            peer_addr: "127.0.0.1:502".parse().unwrap(),
+            state: HashMap::new(),
+        };
+        assert_eq!(session.insert(1u16), None);
+        assert_eq!(session.insert("unit"), None);
+        *session.get_mut::<u16>().unwrap() += 1;
+        assert_eq!(session.insert(7u16), Some(2));
+        assert_eq!(session.get::<&str>(), Some(&"unit"));
+        assert_eq!(session.remove::<u16>(), Some(7));
+        assert_eq!(session.get::<u16>(), None);
+        assert_eq!(session.get::<u32>(), None);
+    }
+
+    #[test]
+    fn latency() {
+        let mut server = Server::bind("127.0.0.1:0", Bank::new(10)).unwrap();
+        let slow = Latency {
+            delay: Duration::from_millis(200),
+            jitter: Duration::from_millis(50),
+        };
+        server.set_latency(slow);
+        server.set_function_latency(0x04, Latency::default());
+        let cfg = tcp::Config {
+            tcp_port: server.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        thread::spawn(move || server.serve());
+
+        let mut client = tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        assert!(client.read_input_registers(0, 1).is_ok());
+        match client
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Timeout) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        for _ in 0..100 {
+            let d = slow.sample();
+            assert!(d >= slow.delay && d <= slow.delay + slow.jitter);
+        }
+    }
+
+    #[test]
+    fn swap_and_compare_and_set() {
+        let mut bank = Bank::new(4);
+        assert_eq!(bank.swap_holding_registers(1, &[1, 2]).unwrap(), vec![0, 0]);
+        assert_eq!(bank.swap_holding_registers(2, &[3, 4]).unwrap(), vec![2, 0]);
+        assert_eq!(bank.holding_registers, vec![0, 1, 3, 4]);
+        assert!(bank.swap_holding_registers(3, &[5, 6]).is_err());
+        assert!(!bank
+            .compare_and_set_holding_registers(1, &[1, 2], &[5, 6])
+            .unwrap());
+        assert!(bank
+            .compare_and_set_holding_registers(1, &[1, 3], &[5, 6])
+            .unwrap());
+        assert_eq!(bank.holding_registers, vec![0, 5, 6, 4]);
+        assert!(bank
+            .compare_and_set_holding_registers(1, &[5, 6], &[7])
+            .is_err());
+
+        assert_eq!(bank.swap_coils(3, &[Coil::On]).unwrap(), vec![Coil::Off]);
+        assert!(bank
+            .compare_and_set_coils(2, &[Coil::Off, Coil::On], &[Coil::On, Coil::Off])
+            .unwrap());
+        assert_eq!(bank.coils, vec![Coil::Off, Coil::Off, Coil::On, Coil::Off]);
+        assert!(bank.swap_coils(4, &[Coil::On]).is_err());
+    }
+
+    #[test]
+    fn duplicate_requests() {
+        let mut server = Server::bind("127.0.0.1:0", Bank::new(10)).unwrap();
+        server.set_duplicate_cache(2);
+        let bank = server.bank();
+        let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        thread::spawn(move || server.serve());
+
+        // write 1 to holding register 0 with transaction id `tid`
+        let mut write = |tid: u8| {
+            let req = [0, tid, 0, 0, 0, 6, 1, 0x06, 0, 0, 0, 1];
+            stream.write_all(&req).unwrap();
+            let mut resp = [0; 12];
+            stream.read_exact(&mut resp).unwrap();
+            assert_eq!(resp, req);
+        };
+        write(1);
+        write(2);
+        bank.lock().unwrap().holding_registers[0] = 7;
+        // retransmissions are answered from the cache
+        write(2);
+        write(1);
+        assert_eq!(bank.lock().unwrap().holding_registers[0], 7);
+        write(3);
+        assert_eq!(bank.lock().unwrap().holding_registers[0], 1);
+        // only the last two requests are cached
+        bank.lock().unwrap().holding_registers[0] = 7;
+        write(1);
+        assert_eq!(bank.lock().unwrap().holding_registers[0], 1);
+    }
+
+    #[test]
+    fn csv_image() {
+        let image = "# comment\n\ncoil,1,1\ndiscrete_input, 9, 1\nholding_register,0,0x1234\n\
+                     input_register,5,65535\n";
+        let mut bank = Bank::new(10);
+        bank.read_csv(image.as_bytes()).unwrap();
+        assert_eq!(bank.coils[1], Coil::On);
+        assert_eq!(bank.discrete_inputs[9], Coil::On);
+        assert_eq!(bank.holding_registers[0], 0x1234);
+        assert_eq!(bank.input_registers[5], 0xffff);
+
+        let mut dump = vec![];
+        bank.write_csv(&mut dump).unwrap();
+        let mut restored = Bank::new(10);
+        restored.read_csv(&dump[..]).unwrap();
+        assert_eq!(restored, bank);
+
+        for invalid in [
+            "coil,10,1",
+            "coil,1,2",
+            "holding_register,1",
+            "holding_register,1,65536",
+            "registers,1,1",
+        ] {
+            assert!(Bank::new(10).read_csv(invalid.as_bytes()).is_err());
+        }
+    }
+}
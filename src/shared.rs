@@ -0,0 +1,223 @@
+//! A client which can be shared between threads
+//!
+//! A `SyncClient` wraps a client into an `Arc<Mutex<_>>` and implements `Client` on shared
+//! references, so clones of it can be handed to multiple threads which use the same connection.
+//! Every request locks the client for the whole transaction, so requests and responses of
+//! different threads are never interleaved and the transaction ids stay consistent.
+//!
+//! This is the way to talk to devices which accept a single connection: a pool of one
+//! connection whose requests are queued on the lock. Further connections to such devices are
+//! refused or closed, `tcp::Config::connection_limit_retry` retries them and reports
+//! `Error::ConnectionLimit` if the device stays taken.
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate modbus;
+//! # fn main() {
+//! use modbus::shared::SyncClient;
+//! use modbus::{tcp, Client};
+//! use std::thread;
+//! # #[cfg(feature = "server")] {
+//! # let server = modbus::server::Server::bind("127.0.0.1:0", modbus::server::Bank::new(500)).unwrap();
+//! # let port = server.local_addr().unwrap().port();
+//! # std::thread::spawn(move || server.serve());
+//!
+//! let mut cfg = tcp::Config::default();
+//! # cfg.tcp_port = port;
+//! let client = SyncClient::new(tcp::Transport::new_with_cfg("127.0.0.1", cfg).unwrap());
+//! let threads: Vec<_> = (0..4)
+//!     .map(|i| {
+//!         let client = client.clone();
+//!         thread::spawn(move || (&client).write_single_register(i, i + 1))
+//!     })
+//!     .collect();
+//! for t in threads {
+//!     t.join().unwrap().unwrap();
+//! }
+//! assert_eq!((&client).read_holding_registers(0, 4).unwrap(), vec![1, 2, 3, 4]);
+//! # }
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::{Client, Coil, DiagnosticsSubFunction, FileRecord, Result, SlaveId};
+
+#[cfg(feature = "read-device-info")]
+use crate::mei;
+
+/// A client shared between threads, cloning it yields another handle to the same client.
+pub struct SyncClient<C> {
+    inner: Arc<Mutex<C>>,
+}
+
+impl<C> Clone for SyncClient<C> {
+    fn clone(&self) -> Self {
+        SyncClient {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C: Client> SyncClient<C> {
+    /// Share `client`.
+    pub fn new(client: C) -> SyncClient<C> {
+        SyncClient {
+            inner: Arc::new(Mutex::new(client)),
+        }
+    }
+
+    /// Lock the client, e.g. to issue several requests without requests of other threads in
+    /// between. A client locked by a thread which panicked is handed out nevertheless, a
+    /// transaction is either completed or failed before the lock is released.
+    pub fn lock(&self) -> MutexGuard<'_, C> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Implement `Client` for `$ty` by locking the shared client for every request.
+macro_rules! forward_client {
+    ($ty:ty) => {
+        impl<C: Client> Client for $ty {
+            fn read_discrete_inputs(&mut self, address: u16, quantity: u16) -> Result<Vec<Coil>> {
+                self.lock().read_discrete_inputs(address, quantity)
+            }
+
+            fn read_coils(&mut self, address: u16, quantity: u16) -> Result<Vec<Coil>> {
+                self.lock().read_coils(address, quantity)
+            }
+
+            fn write_single_coil(&mut self, address: u16, value: Coil) -> Result<()> {
+                self.lock().write_single_coil(address, value)
+            }
+
+            fn write_multiple_coils(&mut self, address: u16, coils: &[Coil]) -> Result<()> {
+                self.lock().write_multiple_coils(address, coils)
+            }
+
+            fn read_input_registers(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>> {
+                self.lock().read_input_registers(address, quantity)
+            }
+
+            fn read_holding_registers(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>> {
+                self.lock().read_holding_registers(address, quantity)
+            }
+
+            fn write_single_register(&mut self, address: u16, value: u16) -> Result<()> {
+                self.lock().write_single_register(address, value)
+            }
+
+            fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> Result<()> {
+                self.lock().write_multiple_registers(address, values)
+            }
+
+            fn read_exception_status(&mut self) -> Result<u8> {
+                self.lock().read_exception_status()
+            }
+
+            fn diagnostics(
+                &mut self,
+                sub: DiagnosticsSubFunction,
+                data: &[u16],
+            ) -> Result<Vec<u16>> {
+                self.lock().diagnostics(sub, data)
+            }
+
+            fn report_slave_id(&mut self) -> Result<SlaveId> {
+                self.lock().report_slave_id()
+            }
+
+            fn mask_write_register(
+                &mut self,
+                address: u16,
+                and_mask: u16,
+                or_mask: u16,
+            ) -> Result<()> {
+                self.lock().mask_write_register(address, and_mask, or_mask)
+            }
+
+            fn write_read_multiple_registers(
+                &mut self,
+                write_address: u16,
+                write_quantity: u16,
+                write_values: &[u16],
+                read_address: u16,
+                read_quantity: u16,
+            ) -> Result<Vec<u16>> {
+                self.lock().write_read_multiple_registers(
+                    write_address,
+                    write_quantity,
+                    write_values,
+                    read_address,
+                    read_quantity,
+                )
+            }
+
+            fn read_fifo_queue(&mut self, address: u16) -> Result<Vec<u16>> {
+                self.lock().read_fifo_queue(address)
+            }
+
+            fn read_file_records(
+                &mut self,
+                records: &[(u16, u16, u16)],
+            ) -> Result<Vec<FileRecord>> {
+                self.lock().read_file_records(records)
+            }
+
+            fn write_file_records(&mut self, records: &[FileRecord]) -> Result<()> {
+                self.lock().write_file_records(records)
+            }
+
+            /// Set the unit identifier of the shared client, for all of its handles.
+            fn set_uid(&mut self, uid: u8) {
+                self.lock().set_uid(uid)
+            }
+
+            #[cfg(feature = "read-device-info")]
+            fn read_device_info(
+                &mut self,
+                obj_category: mei::DeviceInfoCategory,
+            ) -> Result<Vec<mei::DeviceInfoObject>> {
+                self.lock().read_device_info(obj_category)
+            }
+        }
+    };
+}
+
+forward_client!(SyncClient<C>);
+forward_client!(&SyncClient<C>);
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockClient;
+    use crate::server::Bank;
+    use std::thread;
+
+    #[test]
+    fn shared_between_threads() {
+        let client = SyncClient::new(MockClient::new(Bank::new(100)));
+        let threads: Vec<_> = (0..10u16)
+            .map(|i| {
+                let mut client = client.clone();
+                thread::spawn(move || {
+                    for j in 0..10 {
+                        client.write_single_register(10 * i + j, i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let values = (&client).read_holding_registers(0, 100).unwrap();
+        assert_eq!(values, (0..100).map(|a| a / 10).collect::<Vec<_>>());
+        assert_eq!(client.lock().requests().len(), 101);
+
+        let mut other = client.clone();
+        other.set_uid(7);
+        (&client).write_single_coil(0, Coil::On).unwrap();
+        assert_eq!(client.lock().requests()[101].uid, 7);
+    }
+}
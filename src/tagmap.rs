@@ -0,0 +1,616 @@
+//! Named tags mapped onto the data tables of a device
+//!
+//! A `TagMap` describes where a device keeps its values: every `Tag` has a name, a table and
+//! address, a data type with byte and word order and a linear scaling. Values are read and
+//! written as `f64` in engineering units, so a poller only deals with names.
+//!
+//! Maps can be declared in code or loaded from a CSV file with `TagMap::read_csv`. Counter tags
+//! handle the rollover of their registers, see `Tag::delta` and `Counter`. Tags carry the
+//! description, unit and state labels of the register documentation, so values read with
+//! `TagMap::read_value` are displayed without a separate lookup table.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use modbus::tagmap::{DataType, Tag, TagMap};
+//! use modbus::{tcp, Table};
+//!
+//! let mut tags = TagMap::new();
+//! let mut flow = Tag::new("flow_rate", Table::InputRegisters, 10, DataType::U16);
+//! flow.scale = 0.1;
+//! tags.add(flow).unwrap();
+//!
+//! let mut client = tcp::Transport::new("192.168.0.10").unwrap();
+//! println!("{} l/min", tags.read(&mut client, "flow_rate").unwrap());
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::binary::{Decoder, Encoder, Endian, RegisterOrder};
+use crate::{Client, Coil, Error, Reason, Result, Table};
+
+/// The type of the value stored at the address of a `Tag`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataType {
+    /// A coil or discrete input, `0` or `1`
+    Bool,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+}
+
+impl DataType {
+    /// The number of registers a value occupies, `1` for `Bool`.
+    pub fn registers(self) -> u16 {
+        match self {
+            DataType::Bool | DataType::U16 | DataType::I16 => 1,
+            DataType::U32 | DataType::I32 | DataType::F32 => 2,
+            DataType::U64 | DataType::I64 | DataType::F64 => 4,
+        }
+    }
+}
+
+impl FromStr for DataType {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<DataType> {
+        match s.to_ascii_lowercase().as_str() {
+            "bool" => Ok(DataType::Bool),
+            "u16" => Ok(DataType::U16),
+            "i16" => Ok(DataType::I16),
+            "u32" => Ok(DataType::U32),
+            "i32" => Ok(DataType::I32),
+            "u64" => Ok(DataType::U64),
+            "i64" => Ok(DataType::I64),
+            "f32" => Ok(DataType::F32),
+            "f64" => Ok(DataType::F64),
+            _ => Err(Error::InvalidData(Reason::Custom(format!(
+                "invalid data type '{}'",
+                s
+            )))),
+        }
+    }
+}
+
+/// A named value of a device.
+///
+/// The value in engineering units is `raw * scale + offset`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub table: Table,
+    pub address: u16,
+    pub data_type: DataType,
+    pub scale: f64,
+    pub offset: f64,
+    /// Order of the bytes within a register
+    pub byte_order: Endian,
+    /// Order of the registers of values wider than 16 bits
+    pub word_order: Endian,
+    /// Human readable description, e.g. from the register documentation of the device
+    pub description: String,
+    /// Unit of the value in engineering units, e.g. `kWh`
+    pub unit: String,
+    /// Labels of states encoded as values, e.g. `0` = `Stop` and `1` = `Run`
+    pub labels: BTreeMap<i64, String>,
+}
+
+impl Tag {
+    /// Create a tag without scaling, in big endian byte and word order.
+    pub fn new(name: &str, table: Table, address: u16, data_type: DataType) -> Tag {
+        Tag {
+            name: name.to_string(),
+            table,
+            address,
+            data_type,
+            scale: 1.0,
+            offset: 0.0,
+            byte_order: Endian::Big,
+            word_order: Endian::Big,
+            description: String::new(),
+            unit: String::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// The label of the state encoded as `value`, `None` for values without label.
+    pub fn label(&self, value: f64) -> Option<&str> {
+        if value.fract() != 0.0 {
+            return None;
+        }
+        self.labels.get(&(value as i64)).map(String::as_str)
+    }
+
+    /// The increase of a counter from the value `previous` to `current` in engineering units.
+    ///
+    /// Integer counters roll over at the width of their data type, so a value below the previous
+    /// one is taken as rollover, e.g. from `65535` to `2` is an increase by `3` for a `U16` tag.
+    /// A counter reset to `0` is taken as rollover, too. `Bool` and float values are subtracted.
+    pub fn delta(&self, previous: f64, current: f64) -> f64 {
+        let raw = |value: f64| (value - self.offset) / self.scale;
+        let mut delta = raw(current) - raw(previous);
+        let bits = match self.data_type {
+            DataType::U16 | DataType::I16 => 16,
+            DataType::U32 | DataType::I32 => 32,
+            DataType::U64 | DataType::I64 => 64,
+            DataType::Bool | DataType::F32 | DataType::F64 => 0,
+        };
+        if bits > 0 && delta < 0.0 {
+            delta += 2f64.powi(bits);
+        }
+        delta * self.scale
+    }
+
+    /// The increase of a counter per second from the value `previous` to `current`, read
+    /// `elapsed` apart, see `delta`.
+    pub fn rate(&self, previous: f64, current: f64, elapsed: Duration) -> f64 {
+        self.delta(previous, current) / elapsed.as_secs_f64()
+    }
+
+    fn is_bit(&self) -> bool {
+        matches!(self.table, Table::Coils | Table::DiscreteInputs)
+    }
+
+    fn decode(&self, registers: &[u16]) -> Result<f64> {
+        let mut decoder = Decoder::new(registers, self.byte_order, self.word_order);
+        Ok(match self.data_type {
+            DataType::Bool => unreachable!("bool tags are read from bit tables"),
+            DataType::U16 => decoder.read_u16()? as f64,
+            DataType::I16 => decoder.read_i16()? as f64,
+            DataType::U32 => decoder.read_u32()? as f64,
+            DataType::I32 => decoder.read_i32()? as f64,
+            DataType::U64 => decoder.read_u64()? as f64,
+            DataType::I64 => decoder.read_i64()? as f64,
+            DataType::F32 => decoder.read_f32()? as f64,
+            DataType::F64 => decoder.read_f64()?,
+        })
+    }
+
+    fn encode(&self, raw: f64) -> Result<Vec<u16>> {
+        let out_of_range = || {
+            Error::InvalidData(Reason::Custom(format!(
+                "value {} out of range of tag '{}'",
+                raw, self.name
+            )))
+        };
+        // `as` saturates silently, so the range is checked before converting
+        let int = |min: f64, max: f64| {
+            let raw = raw.round();
+            if (min..=max).contains(&raw) {
+                Ok(raw)
+            } else {
+                Err(out_of_range())
+            }
+        };
+        let mut encoder = Encoder::new(self.byte_order, self.word_order);
+        match self.data_type {
+            DataType::Bool => unreachable!("bool tags are written to coils"),
+            DataType::U16 => encoder.add_u16(int(0.0, u16::MAX as f64)? as u16),
+            DataType::I16 => encoder.add_i16(int(i16::MIN as f64, i16::MAX as f64)? as i16),
+            DataType::U32 => encoder.add_u32(int(0.0, u32::MAX as f64)? as u32),
+            DataType::I32 => encoder.add_i32(int(i32::MIN as f64, i32::MAX as f64)? as i32),
+            DataType::U64 => encoder.add_u64(int(0.0, u64::MAX as f64)? as u64),
+            DataType::I64 => encoder.add_i64(int(i64::MIN as f64, i64::MAX as f64)? as i64),
+            DataType::F32 => encoder.add_f32(raw as f32),
+            DataType::F64 => encoder.add_f64(raw),
+        };
+        Ok(encoder.into_registers())
+    }
+}
+
+/// A value read with `TagMap::read_value`, displayed with its label or unit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Value<'a> {
+    pub tag: &'a Tag,
+    /// The value in engineering units
+    pub value: f64,
+}
+
+impl Value<'_> {
+    /// The label of the value, see `Tag::label`.
+    pub fn label(&self) -> Option<&str> {
+        self.tag.label(self.value)
+    }
+}
+
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label() {
+            Some(label) => write!(f, "{}", label),
+            None if self.tag.unit.is_empty() => write!(f, "{}", self.value),
+            None => write!(f, "{} {}", self.value, self.tag.unit),
+        }
+    }
+}
+
+/// The values of a counter tag read over time, with the increase and rate between two reads,
+/// e.g. of energy or production counters. Created with `TagMap::counter`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Counter {
+    tag: Tag,
+    last: Option<(Instant, f64)>,
+}
+
+impl Counter {
+    pub fn new(tag: Tag) -> Counter {
+        Counter { tag, last: None }
+    }
+
+    /// The counted tag.
+    pub fn tag(&self) -> &Tag {
+        &self.tag
+    }
+
+    /// Add the `value` read at `time`. Returns the increase since the previous value and the
+    /// rate per second, see `Tag::delta`, `None` for the first value.
+    pub fn update(&mut self, time: Instant, value: f64) -> Option<(f64, f64)> {
+        let (last_time, last) = self.last.replace((time, value))?;
+        let delta = self.tag.delta(last, value);
+        Some((delta, delta / time.duration_since(last_time).as_secs_f64()))
+    }
+}
+
+/// A set of tags with unique names.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagMap {
+    tags: Vec<Tag>,
+}
+
+impl TagMap {
+    pub fn new() -> TagMap {
+        TagMap::default()
+    }
+
+    /// Add `tag`, which fails if the name is already taken or the data type doesn't fit the
+    /// table: `Bool` for coils and discrete inputs, any other type for registers.
+    pub fn add(&mut self, tag: Tag) -> Result<()> {
+        let err = |reason: &str| {
+            Error::InvalidData(Reason::Custom(format!("tag '{}': {}", tag.name, reason)))
+        };
+        if self.get(&tag.name).is_some() {
+            return Err(err("duplicate name"));
+        }
+        if tag.is_bit() != (tag.data_type == DataType::Bool) {
+            return Err(err("data type doesn't match the table"));
+        }
+        if tag.address as u32 + tag.data_type.registers() as u32 > 0x10000 {
+            return Err(err("exceeds the address range"));
+        }
+        self.tags.push(tag);
+        Ok(())
+    }
+
+    /// The tag named `name`.
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        self.tags.iter().find(|t| t.name == name)
+    }
+
+    /// All tags in the order they were added.
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    /// Load a map from CSV lines read from `reader`.
+    ///
+    /// Every line has the form `name,table,address,type[,scale,offset,order,unit,description,
+    /// labels]` where `table` is one of `coil`, `discrete_input`, `holding_register` or
+    /// `input_register`, `type` one of `bool`, `u16`, `i16`, `u32`, `i32`, `u64`, `i64`, `f32` or
+    /// `f64` and `order` the byte and register order as `abcd`, `badc`, `cdab` or `dcba`.
+    /// Addresses are decimal or `0x` prefixed hexadecimal numbers. The labels are listed as
+    /// `value=label` separated by `;`, e.g. `0=Stop;1=Run`. Empty optional fields keep their
+    /// defaults. Empty lines and lines starting with `#` are ignored.
+    ///
+    /// The order names the bytes of a 32 bit value, so `badc` swaps the bytes within and `cdab`
+    /// the registers. For the 64 bit types only `abcd` (big endian) and `dcba` (little endian)
+    /// are accepted, as `badc` and `cdab` name register orders there, see
+    /// `binary::RegisterOrder`. Other layouts of 64 bit tags are set with `byte_order` and
+    /// `word_order` in code.
+    ///
+    /// ```
+    /// # use modbus::tagmap::TagMap;
+    /// let csv = "# name,table,address,type,scale,offset,order\n\
+    ///            pump_running,coil,3,bool\n\
+    ///            flow_rate,input_register,0x10,f32,60,0,cdab,l/h,Outlet flow\n\
+    ///            mode,holding_register,0x20,u16,,,,,,0=Stop;1=Run\n";
+    /// let tags = TagMap::read_csv(csv.as_bytes()).unwrap();
+    /// assert_eq!(tags.get("flow_rate").unwrap().scale, 60.0);
+    /// assert_eq!(tags.get("mode").unwrap().label(1.0), Some("Run"));
+    /// ```
+    pub fn read_csv<R: BufRead>(reader: R) -> Result<TagMap> {
+        let mut map = TagMap::new();
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |reason: &str| {
+                Error::InvalidData(Reason::Custom(format!("tag line {}: {}", n + 1, reason)))
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            if fields.len() < 4 || fields.len() > 10 {
+                return Err(err(
+                    "expected name,table,address,type[,scale,offset,order,unit,description,labels]",
+                ));
+            }
+            let field = |i: usize| fields.get(i).copied().filter(|f| !f.is_empty());
+            let table = match fields[1] {
+                "coil" => Table::Coils,
+                "discrete_input" => Table::DiscreteInputs,
+                "holding_register" => Table::HoldingRegisters,
+                "input_register" => Table::InputRegisters,
+                _ => return Err(err("unknown table")),
+            };
+            let address = match fields[2].strip_prefix("0x") {
+                Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                None => fields[2].parse().ok(),
+            }
+            .ok_or_else(|| err("invalid address"))?;
+            let data_type = fields[3].parse().map_err(|_| err("invalid data type"))?;
+            let mut tag = Tag::new(fields[0], table, address, data_type);
+            if let Some(scale) = field(4) {
+                tag.scale = scale.parse().map_err(|_| err("invalid scale"))?;
+            }
+            if let Some(offset) = field(5) {
+                tag.offset = offset.parse().map_err(|_| err("invalid offset"))?;
+            }
+            if let Some(order) = field(6) {
+                let order = order.parse().map_err(|_| err("invalid order"))?;
+                if data_type.registers() == 4
+                    && matches!(order, RegisterOrder::Badc | RegisterOrder::Cdab)
+                {
+                    return Err(err("ambiguous order of a 64 bit type, use abcd or dcba"));
+                }
+                (tag.byte_order, tag.word_order) = match order {
+                    RegisterOrder::Abcd => (Endian::Big, Endian::Big),
+                    RegisterOrder::Badc => (Endian::Little, Endian::Big),
+                    RegisterOrder::Cdab => (Endian::Big, Endian::Little),
+                    RegisterOrder::Dcba => (Endian::Little, Endian::Little),
+                };
+            }
+            tag.unit = field(7).unwrap_or_default().to_string();
+            tag.description = field(8).unwrap_or_default().to_string();
+            for label in field(9).into_iter().flat_map(|f| f.split(';')) {
+                let (value, label) = label
+                    .split_once('=')
+                    .and_then(|(value, label)| Some((value.trim().parse().ok()?, label.trim())))
+                    .ok_or_else(|| err("invalid label"))?;
+                tag.labels.insert(value, label.to_string());
+            }
+            map.add(tag).map_err(|e| match e {
+                Error::InvalidData(Reason::Custom(reason)) => err(&reason),
+                e => e,
+            })?;
+        }
+        Ok(map)
+    }
+
+    /// A counter of the tag `name`.
+    pub fn counter(&self, name: &str) -> Result<Counter> {
+        self.tag(name).cloned().map(Counter::new)
+    }
+
+    fn tag(&self, name: &str) -> Result<&Tag> {
+        self.get(name)
+            .ok_or_else(|| Error::InvalidData(Reason::Custom(format!("unknown tag '{}'", name))))
+    }
+
+    /// Read the value of the tag `name` with `client`, scaled to engineering units. `Bool` tags
+    /// are `0` or `1`.
+    pub fn read<C: Client>(&self, client: &mut C, name: &str) -> Result<f64> {
+        let tag = self.tag(name)?;
+        let count = tag.data_type.registers();
+        let bit = |coils: Vec<Coil>| (coils[0] == Coil::On) as u8 as f64;
+        let raw = match tag.table {
+            Table::Coils => bit(client.read_coils(tag.address, 1)?),
+            Table::DiscreteInputs => bit(client.read_discrete_inputs(tag.address, 1)?),
+            Table::HoldingRegisters => {
+                tag.decode(&client.read_holding_registers(tag.address, count)?)?
+            }
+            Table::InputRegisters => {
+                tag.decode(&client.read_input_registers(tag.address, count)?)?
+            }
+        };
+        Ok(raw * tag.scale + tag.offset)
+    }
+
+    /// Read the value of the tag `name` with `client` like `read`, together with the tag for
+    /// displaying it with its label or unit.
+    ///
+    /// ```no_run
+    /// # use modbus::tagmap::TagMap;
+    /// # use modbus::tcp;
+    /// # let tags = TagMap::new();
+    /// let mut client = tcp::Transport::new("192.168.0.10").unwrap();
+    /// let mode = tags.read_value(&mut client, "mode").unwrap();
+    /// println!("{}: {}", mode.tag.description, mode);
+    /// ```
+    pub fn read_value<C: Client>(&self, client: &mut C, name: &str) -> Result<Value<'_>> {
+        Ok(Value {
+            value: self.read(client, name)?,
+            tag: self.tag(name)?,
+        })
+    }
+
+    /// Write `value` in engineering units to the tag `name` with `client`. Integer values are
+    /// rounded, values outside of the range of the data type are rejected. Writing a `Bool`
+    /// tag switches the coil `On` for any value other than `0`.
+    pub fn write<C: Client>(&self, client: &mut C, name: &str, value: f64) -> Result<()> {
+        let tag = self.tag(name)?;
+        let raw = (value - tag.offset) / tag.scale;
+        match tag.table {
+            Table::Coils => client.write_single_coil(tag.address, Coil::from(raw != 0.0)),
+            Table::HoldingRegisters => match &tag.encode(raw)?[..] {
+                [register] => client.write_single_register(tag.address, *register),
+                registers => client.write_multiple_registers(tag.address, registers),
+            },
+            Table::DiscreteInputs | Table::InputRegisters => Err(Error::InvalidData(
+                Reason::Custom(format!("tag '{}' is read-only", name)),
+            )),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::binary;
+    use crate::mock::MockClient;
+    use crate::server::Bank;
+
+    #[test]
+    fn read_and_write() {
+        let csv = "# name,table,address,type,scale,offset,order\n\
+                   \n\
+                   running,coil,1,bool\n\
+                   alarm,discrete_input,2,bool\n\
+                   level,holding_register,0,i16,0.5,-10\n\
+                   flow,input_register,0x4,f32,1,0,cdab\n\
+                   count,holding_register,2,u32,1,0,dcba\n\
+                   energy,holding_register,4,u64,1,0,dcba\n";
+        let tags = TagMap::read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(tags.tags().len(), 6);
+        assert_eq!(tags.get("flow").unwrap().word_order, Endian::Little);
+
+        let mut client = MockClient::new(Bank::new(8));
+        client.bank.discrete_inputs[2] = Coil::On;
+        client.bank.input_registers[4..6].copy_from_slice(&[0x0000, 0x3fc0]);
+        client.bank.holding_registers[0] = (-4i16) as u16;
+        assert_eq!(tags.read(&mut client, "alarm").unwrap(), 1.0);
+        assert_eq!(tags.read(&mut client, "running").unwrap(), 0.0);
+        assert_eq!(tags.read(&mut client, "flow").unwrap(), 1.5);
+        assert_eq!(tags.read(&mut client, "level").unwrap(), -12.0);
+
+        tags.write(&mut client, "running", 1.0).unwrap();
+        assert_eq!(client.bank.coils[1], Coil::On);
+        tags.write(&mut client, "level", 5.2).unwrap();
+        assert_eq!(client.bank.holding_registers[0], 30);
+        tags.write(&mut client, "count", 0x1234_5678 as f64)
+            .unwrap();
+        assert_eq!(client.bank.holding_registers[2..4], [0x7856, 0x3412]);
+        assert_eq!(tags.read(&mut client, "count").unwrap(), 0x1234_5678 as f64);
+
+        // little endian, the least significant register first with swapped bytes
+        client.bank.holding_registers[4..8].copy_from_slice(&[0x0800, 0x0700, 0x0600, 0x0500]);
+        let energy = 0x0005_0006_0007_0008u64;
+        assert_eq!(tags.read(&mut client, "energy").unwrap(), energy as f64);
+        let registers: [u16; 4] = client.bank.holding_registers[4..8].try_into().unwrap();
+        assert_eq!(
+            binary::decode_u64(registers, RegisterOrder::Dcba, Endian::Little),
+            energy
+        );
+
+        assert!(tags.write(&mut client, "level", 100000.0).is_err());
+        assert!(tags.write(&mut client, "flow", 1.0).is_err());
+        assert!(tags.read(&mut client, "missing").is_err());
+    }
+
+    #[test]
+    fn invalid_tags() {
+        let mut tags = TagMap::new();
+        tags.add(Tag::new("a", Table::Coils, 0, DataType::Bool))
+            .unwrap();
+        assert!(tags
+            .add(Tag::new("a", Table::Coils, 1, DataType::Bool))
+            .is_err());
+        assert!(tags
+            .add(Tag::new("b", Table::Coils, 1, DataType::U16))
+            .is_err());
+        assert!(tags
+            .add(Tag::new(
+                "b",
+                Table::HoldingRegisters,
+                0xfffe,
+                DataType::F64
+            ))
+            .is_err());
+        for line in [
+            "c,coil,1",
+            "c,register,1,u16",
+            "c,holding_register,x,u16",
+            "c,holding_register,1,u8",
+            "c,holding_register,1,u16,1,0,abdc",
+            "c,holding_register,1,u64,1,0,cdab",
+            "c,holding_register,1,f64,1,0,badc",
+            "c,input_register,1,bool",
+        ] {
+            assert!(TagMap::read_csv(line.as_bytes()).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn counters() {
+        let mut tags = TagMap::new();
+        tags.add(Tag::new("u16", Table::InputRegisters, 0, DataType::U16))
+            .unwrap();
+        let mut energy = Tag::new("energy", Table::InputRegisters, 1, DataType::U32);
+        energy.scale = 0.1;
+        tags.add(energy).unwrap();
+        let u16 = tags.get("u16").unwrap();
+        assert_eq!(u16.delta(10.0, 15.0), 5.0);
+        assert_eq!(u16.delta(65535.0, 2.0), 3.0);
+        assert_eq!(
+            Tag::new("i16", Table::InputRegisters, 0, DataType::I16).delta(32767.0, -32768.0),
+            1.0
+        );
+        assert_eq!(
+            Tag::new("f32", Table::InputRegisters, 0, DataType::F32).delta(5.0, 2.0),
+            -3.0
+        );
+        assert_eq!(u16.rate(0.0, 10.0, Duration::from_secs(4)), 2.5);
+
+        // in engineering units, the rollover at 2^32 - 1 raw
+        let mut counter = tags.counter("energy").unwrap();
+        let start = Instant::now();
+        let max = u32::MAX as f64 * 0.1;
+        assert_eq!(counter.update(start, max - 1.0), None);
+        let (delta, rate) = counter.update(start + Duration::from_secs(2), 2.0).unwrap();
+        assert!((delta - 3.1).abs() < 1e-6, "{}", delta);
+        assert!((rate - 1.55).abs() < 1e-6, "{}", rate);
+        assert!(tags.counter("missing").is_err());
+    }
+
+    #[test]
+    fn annotations() {
+        let csv = "power,input_register,0,u16,0.1,,,kW,Active power\n\
+                   mode,holding_register,1,u16,,,,,Operating mode,0=Stop; 1=Run\n\
+                   raw,holding_register,2,u16\n";
+        let tags = TagMap::read_csv(csv.as_bytes()).unwrap();
+        let power = tags.get("power").unwrap();
+        assert_eq!(power.scale, 0.1);
+        assert_eq!(power.offset, 0.0);
+        assert_eq!(power.unit, "kW");
+        assert_eq!(power.description, "Active power");
+        let mode = tags.get("mode").unwrap();
+        assert_eq!(mode.unit, "");
+        assert_eq!(mode.label(1.0), Some("Run"));
+        assert_eq!(mode.label(1.5), None);
+        assert_eq!(mode.label(2.0), None);
+
+        let mut client = MockClient::new(Bank::new(3));
+        client.bank.input_registers[0] = 125;
+        client.bank.holding_registers[1..3].copy_from_slice(&[1, 7]);
+        let value = tags.read_value(&mut client, "power").unwrap();
+        assert_eq!(value.tag, power);
+        assert_eq!(value.to_string(), "12.5 kW");
+        let value = tags.read_value(&mut client, "mode").unwrap();
+        assert_eq!(value.label(), Some("Run"));
+        assert_eq!(value.to_string(), "Run");
+        assert_eq!(
+            tags.read_value(&mut client, "raw").unwrap().to_string(),
+            "7"
+        );
+
+        for line in ["m,coil,1,bool,,,,,,on", "m,coil,1,bool,,,,,,x=on"] {
+            assert!(TagMap::read_csv(line.as_bytes()).is_err(), "{}", line);
+        }
+    }
+}
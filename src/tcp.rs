@@ -1,22 +1,42 @@
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use enum_primitive::FromPrimitive;
-use std::borrow::BorrowMut;
 use std::io::{self, Cursor, Read, Write};
-use std::net::{Shutdown, TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use crate::{binary, Client, Coil, Error, ExceptionCode, Function, Reason, Result};
+use crate::pdu::{self, Function};
+use crate::{
+    binary, Client, Coil, DiagnosticsSubFunction, Error, ErrorContext, ExceptionCode, FileRecord,
+    Reason, Result, SlaveId,
+};
 
 #[cfg(feature = "read-device-info")]
 use crate::mei;
 
 const MODBUS_PROTOCOL_TCP: u16 = 0x0000;
 const MODBUS_TCP_DEFAULT_PORT: u16 = 502;
+/// The registered port of Modbus/TCP Security
+#[cfg(feature = "tls")]
+pub const MODBUS_TLS_DEFAULT_PORT: u16 = 802;
 const MODBUS_HEADER_SIZE: usize = 7;
 const MODBUS_MAX_PACKET_SIZE: usize = 260;
+// time without incoming data after which `resync` considers the connection drained
+const RESYNC_QUIET_TIME: Duration = Duration::from_millis(100);
 
 /// Config structure for more control over the tcp socket settings
+///
+/// New options may be added in minor releases, so a `Config` can't be created with a struct
+/// expression outside of this crate. Use `Config::builder()`, or change the fields of
+/// `Config::default()`.
+///
+/// The fields `socket_options` and `tcp_keepalive` only exist with the `socket2` feature, which
+/// is not enabled by default.
 #[derive(Clone, Copy)]
+#[non_exhaustive]
 pub struct Config {
     /// The TCP port to use for communication (Default: `502`)
     pub tcp_port: u16,
@@ -28,6 +48,90 @@ pub struct Config {
     pub tcp_write_timeout: Option<Duration>,
     /// The modbus Unit Identifier used in the modbus layer (Default: `1`)
     pub modbus_uid: u8,
+    /// Reconnect and retry the request when the connection breaks (Default: `None`)
+    pub auto_reconnect: Option<ReconnectPolicy>,
+    /// Retry connecting when the device refuses the connection or closes it before it ever
+    /// answered a request, as devices limited to one or a few connections do while another
+    /// client is connected. Requests which still fail after the attempts return
+    /// `Error::ConnectionLimit`, without it the closed connection is reported as is
+    /// (Default: `None`)
+    pub connection_limit_retry: Option<ReconnectPolicy>,
+    /// Retry requests answered with the exception `SlaveOrServerBusy`, instead of failing with
+    /// the exception. As with `auto_reconnect`, writes are sent again. After the exception
+    /// `Acknowledge` the device is polled with Get Comm Event Counter until it completed the
+    /// command, which is not sent again: writes then succeed, other requests fail with the
+    /// exception as they have no response data. The attempts count both retries and polls
+    /// (Default: `None`)
+    pub busy_retry: Option<ReconnectPolicy>,
+    /// Accept responses to multiple writes which echo a wrong quantity, for devices which apply
+    /// the write nevertheless (Default: `false`)
+    pub lenient_write_echo: bool,
+    /// Number of responses with a wrong transaction id, e.g. late answers to timed out
+    /// requests, which are discarded while waiting for the response to a request. Further
+    /// mismatches fail with `InvalidResponse` (Default: `0`)
+    pub max_stale_responses: u8,
+    /// Read coils and registers back after writing them and fail with `VerificationFailed` if
+    /// they differ, for gateways which silently drop writes. Doubles the number of requests
+    /// (Default: `false`)
+    pub verify_writes: bool,
+    /// Deviations from the protocol of buggy devices which are tolerated (Default: none)
+    pub quirks: Quirks,
+    /// Further options of the TCP socket, applied before connecting. Requires the `socket2`
+    /// feature (Default: none set)
+    #[cfg(feature = "socket2")]
+    pub socket_options: SocketOptions,
+    /// Enable TCP keep-alive probes after the connection was idle for this time, to detect
+    /// half-open connections, e.g. through NAT gateways which dropped their mapping. Requires the
+    /// `socket2` feature (Default: `None`)
+    #[cfg(feature = "socket2")]
+    pub tcp_keepalive: Option<Duration>,
+    /// The request sent by `Transport::ping` (Default: `Ping::ReturnQueryData`)
+    pub ping: Ping,
+    /// The framing of the requests and responses on the connection (Default: `Framing::Mbap`)
+    pub framing: Framing,
+}
+
+/// The framing of the Modbus frames on a TCP connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// Modbus TCP, with the MBAP header in front of the PDU
+    Mbap,
+    /// Raw Modbus RTU frames, unit id, PDU and CRC, as serial device servers pass them through
+    /// without a header. Responses don't carry their length, so only the functions known to
+    /// `pdu::response_len` can be used, others fail with `InvalidFunction` before sending.
+    /// There are no transaction ids to match, stale responses can't be skipped
+    RtuOverTcp,
+}
+
+/// A harmless request checking that a device answers, see `Transport::ping`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ping {
+    /// The Return Query Data diagnostics function, which echoes its data without touching the
+    /// state of the device
+    ReturnQueryData,
+    /// Read a single holding register at the address, for devices without diagnostics
+    ReadHoldingRegister(u16),
+    /// Read a single input register at the address
+    ReadInputRegister(u16),
+}
+
+/// Options of the TCP socket which the standard library doesn't cover, e.g. for plant networks
+/// with QoS policies. Options which are `None` or `false` are left at the OS default.
+#[cfg(feature = "socket2")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SocketOptions {
+    /// Set `SO_REUSEADDR`
+    pub reuse_address: bool,
+    /// Size of the receive buffer (`SO_RCVBUF`)
+    pub recv_buffer_size: Option<usize>,
+    /// Size of the send buffer (`SO_SNDBUF`)
+    pub send_buffer_size: Option<usize>,
+    /// Time unacknowledged data may stay in the send buffer before the connection is dropped
+    /// (`TCP_USER_TIMEOUT`), only supported on Linux and Android
+    pub tcp_user_timeout: Option<Duration>,
+    /// Differentiated services code point of the sent packets, the upper six bits of the IPv4
+    /// type of service or the IPv6 traffic class
+    pub dscp: Option<u8>,
 }
 
 impl Default for Config {
@@ -38,10 +142,564 @@ impl Default for Config {
             tcp_read_timeout: None,
             tcp_write_timeout: None,
             modbus_uid: 1,
+            auto_reconnect: None,
+            connection_limit_retry: None,
+            busy_retry: None,
+            lenient_write_echo: false,
+            max_stale_responses: 0,
+            verify_writes: false,
+            quirks: Quirks::STRICT,
+            #[cfg(feature = "socket2")]
+            socket_options: SocketOptions::default(),
+            #[cfg(feature = "socket2")]
+            tcp_keepalive: None,
+            ping: Ping::ReturnQueryData,
+            framing: Framing::Mbap,
+        }
+    }
+}
+
+/// Deviations from the protocol which are accepted in responses, to talk to buggy devices.
+///
+/// Profiles of known quirks can be selected by name with `Quirks::profile`, the flags can also
+/// be combined freely.
+///
+/// ```
+/// use modbus::tcp::{Config, Quirks};
+///
+/// let cfg = Config::builder()
+///     .quirks(Quirks::profile("tid-zero").unwrap())
+///     .build()
+///     .unwrap();
+/// assert!(cfg.quirks.zero_transaction_id);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Accept responses with any protocol id instead of `0`
+    pub any_protocol_id: bool,
+    /// Accept responses with the transaction id `0` instead of the one of the request
+    pub zero_transaction_id: bool,
+    /// Accept read responses whose byte count is one off the actual number of data bytes
+    pub byte_count_off_by_one: bool,
+}
+
+impl Quirks {
+    /// Accept only responses conforming to the protocol.
+    pub const STRICT: Quirks = Quirks {
+        any_protocol_id: false,
+        zero_transaction_id: false,
+        byte_count_off_by_one: false,
+    };
+
+    /// Accept all known deviations.
+    pub const LENIENT: Quirks = Quirks {
+        any_protocol_id: true,
+        zero_transaction_id: true,
+        byte_count_off_by_one: true,
+    };
+
+    /// The names of the profiles known to `profile`.
+    pub const PROFILES: &'static [&'static str] = &[
+        "strict",
+        "wrong-pid",
+        "tid-zero",
+        "byte-count-off-by-one",
+        "lenient",
+    ];
+
+    /// The profile `name`, one of `PROFILES`:
+    ///
+    /// * `strict`: no deviations
+    /// * `wrong-pid`: responses carry a garbage protocol id
+    /// * `tid-zero`: responses always carry the transaction id `0`
+    /// * `byte-count-off-by-one`: the byte count of read responses is one off
+    /// * `lenient`: all of the above
+    pub fn profile(name: &str) -> Option<Quirks> {
+        let quirks = Quirks::STRICT;
+        Some(match name {
+            "strict" => quirks,
+            "wrong-pid" => Quirks {
+                any_protocol_id: true,
+                ..quirks
+            },
+            "tid-zero" => Quirks {
+                zero_transaction_id: true,
+                ..quirks
+            },
+            "byte-count-off-by-one" => Quirks {
+                byte_count_off_by_one: true,
+                ..quirks
+            },
+            "lenient" => Quirks::LENIENT,
+            _ => return None,
+        })
+    }
+
+    // Whether the header `resp` answers the request with the header `req`.
+    fn matches(&self, req: &Header, resp: &Header) -> bool {
+        (resp.tid == req.tid || self.zero_transaction_id && resp.tid == 0)
+            && (resp.pid == MODBUS_PROTOCOL_TCP || self.any_protocol_id)
+    }
+
+    // Correct a byte count of the read response `resp` which is one off.
+    fn fix_byte_count(&self, resp: &mut [u8]) {
+        if !self.byte_count_off_by_one || resp.len() < 2 {
+            return;
+        }
+        let bytes = resp.len() - 2;
+        if matches!(resp[0], 0x01..=0x04 | 0x17) && (resp[1] as usize).abs_diff(bytes) == 1 {
+            resp[1] = bytes as u8;
+        }
+    }
+}
+
+#[cfg(feature = "socket2")]
+impl SocketOptions {
+    fn apply(&self, socket: &socket2::Socket, ipv6: bool) -> io::Result<()> {
+        if self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(timeout) = self.tcp_user_timeout {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            socket.set_tcp_user_timeout(Some(timeout))?;
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            return Err(unsupported(&format!("TCP_USER_TIMEOUT of {:?}", timeout)));
+        }
+        if let Some(dscp) = self.dscp {
+            let class = (dscp as u32) << 2;
+            if ipv6 {
+                #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+                socket.set_tclass_v6(class)?;
+                #[cfg(not(any(
+                    target_os = "linux",
+                    target_os = "android",
+                    target_os = "macos"
+                )))]
+                return Err(unsupported(&format!("IPv6 traffic class {}", class)));
+            } else {
+                #[cfg(any(unix, windows))]
+                socket.set_tos(class)?;
+                #[cfg(not(any(unix, windows)))]
+                return Err(unsupported(&format!("IPv4 type of service {}", class)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "socket2")]
+fn set_keepalive(socket: &socket2::Socket, idle: Option<Duration>) -> io::Result<()> {
+    match idle {
+        Some(idle) => socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle)),
+        None => Ok(()),
+    }
+}
+
+// Unused on the platforms supporting all socket options.
+#[cfg(feature = "socket2")]
+#[allow(dead_code)]
+fn unsupported(option: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{} is not supported on this platform", option),
+    )
+}
+
+// The response to the write request `req`, which the device acknowledged instead of answering
+// it, or `None` for requests whose response carries data.
+fn acknowledged_response(req: &[u8]) -> Option<Vec<u8>> {
+    match req[0] {
+        0x05 | 0x06 | 0x15 | 0x16 => Some(req.to_vec()),
+        0x0f | 0x10 => req.get(..5).map(<[u8]>::to_vec),
+        _ => None,
+    }
+}
+
+/// How a `Transport` reestablishes a broken connection.
+///
+/// When a request fails because the connection was reset or closed, the transport reconnects
+/// and sends the request again, up to `max_attempts` times. The first attempt is delayed by
+/// `backoff`, which is doubled for every further attempt up to `max_backoff`.
+///
+/// The host name is resolved again for every attempt, so a device which got a new address, e.g.
+/// by DHCP, is found once its DNS entry is updated. Caching of DNS answers is left to the
+/// resolver of the system, which is expected to respect their TTL.
+///
+/// Note that a write request is sent again even if the device already executed it before the
+/// connection broke.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Number of attempts after the first failure, at least `1` (Default: `3`)
+    pub max_attempts: u32,
+    /// Delay before the first attempt, doubled for every further one (Default: `100ms`)
+    pub backoff: Duration,
+    /// Upper bound of the delay between attempts (Default: `5s`)
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Config {
+    /// Start building a `Config` from the defaults.
+    ///
+    /// ```
+    /// use modbus::tcp::Config;
+    /// use std::time::Duration;
+    ///
+    /// let cfg = Config::builder()
+    ///     .port(1502)
+    ///     .uid(3)
+    ///     .connect_timeout(Duration::from_secs(2))
+    ///     .read_timeout(Duration::from_millis(500))
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(cfg.tcp_port, 1502);
+    /// ```
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            cfg: Config::default(),
+        }
+    }
+
+    /// Parse an endpoint url like `tcp://10.0.0.5:502?uid=3&timeout=500ms` into the host and a
+    /// `Config`.
+    ///
+    /// The port defaults to `502`, IPv6 addresses have to be enclosed in brackets
+    /// (`tcp://[::1]:502`). Supported query parameters are `uid`, `timeout` (sets the read and
+    /// write timeout), `connect_timeout`, `read_timeout` and `write_timeout`. Durations are given
+    /// with a `ms` or `s` suffix, plain numbers are milliseconds.
+    pub fn from_url(url: &str) -> Result<(String, Config)> {
+        let err = |reason: &str| Error::ParseUrlError(format!("{} in '{}'", reason, url));
+        let rest = match url.split_once("://") {
+            Some(("tcp", rest)) => rest,
+            Some((scheme, _)) => return Err(err(&format!("unsupported scheme '{}'", scheme))),
+            None => return Err(err("missing scheme")),
+        };
+        let (authority, query) = match rest.split_once('?') {
+            Some((authority, query)) => (authority.trim_end_matches('/'), query),
+            None => (rest.trim_end_matches('/'), ""),
+        };
+
+        let mut cfg = Config::default();
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => match v6.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+                None => return Err(err("unterminated IPv6 address")),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(err("missing host"));
+        }
+        if let Some(port) = port {
+            cfg.tcp_port = port.parse().map_err(|_| err("invalid port"))?;
+        }
+
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| err(&format!("missing value for '{}'", param)))?;
+            let duration = || {
+                parse_duration(value).ok_or_else(|| err(&format!("invalid duration '{}'", value)))
+            };
+            match key {
+                "uid" => cfg.modbus_uid = value.parse().map_err(|_| err("invalid uid"))?,
+                "timeout" => {
+                    cfg.tcp_read_timeout = Some(duration()?);
+                    cfg.tcp_write_timeout = cfg.tcp_read_timeout;
+                }
+                "connect_timeout" => cfg.tcp_connect_timeout = Some(duration()?),
+                "read_timeout" => cfg.tcp_read_timeout = Some(duration()?),
+                "write_timeout" => cfg.tcp_write_timeout = Some(duration()?),
+                _ => return Err(err(&format!("unknown parameter '{}'", key))),
+            }
+        }
+        Ok((host.to_string(), cfg))
+    }
+}
+
+/// Builder of a `Config`, see `Config::builder`.
+#[derive(Clone, Copy)]
+pub struct ConfigBuilder {
+    cfg: Config,
+}
+
+impl ConfigBuilder {
+    /// The TCP port to connect to.
+    pub fn port(mut self, port: u16) -> ConfigBuilder {
+        self.cfg.tcp_port = port;
+        self
+    }
+
+    /// Timeout of establishing the connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> ConfigBuilder {
+        self.cfg.tcp_connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of reading from the socket.
+    pub fn read_timeout(mut self, timeout: Duration) -> ConfigBuilder {
+        self.cfg.tcp_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of writing to the socket.
+    pub fn write_timeout(mut self, timeout: Duration) -> ConfigBuilder {
+        self.cfg.tcp_write_timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout of both reading from and writing to the socket.
+    pub fn timeout(self, timeout: Duration) -> ConfigBuilder {
+        self.read_timeout(timeout).write_timeout(timeout)
+    }
+
+    /// The Unit Identifier of the requests.
+    pub fn uid(mut self, uid: u8) -> ConfigBuilder {
+        self.cfg.modbus_uid = uid;
+        self
+    }
+
+    /// Reconnect and retry requests when the connection breaks.
+    pub fn auto_reconnect(mut self, policy: ReconnectPolicy) -> ConfigBuilder {
+        self.cfg.auto_reconnect = Some(policy);
+        self
+    }
+
+    /// Retry connecting to devices whose connections are all taken.
+    pub fn connection_limit_retry(mut self, policy: ReconnectPolicy) -> ConfigBuilder {
+        self.cfg.connection_limit_retry = Some(policy);
+        self
+    }
+
+    /// Retry requests while the device is busy, see `Config::busy_retry`.
+    pub fn busy_retry(mut self, policy: ReconnectPolicy) -> ConfigBuilder {
+        self.cfg.busy_retry = Some(policy);
+        self
+    }
+
+    /// See `Config::lenient_write_echo`.
+    pub fn lenient_write_echo(mut self, lenient: bool) -> ConfigBuilder {
+        self.cfg.lenient_write_echo = lenient;
+        self
+    }
+
+    /// See `Config::max_stale_responses`.
+    pub fn max_stale_responses(mut self, max: u8) -> ConfigBuilder {
+        self.cfg.max_stale_responses = max;
+        self
+    }
+
+    /// See `Config::verify_writes`.
+    pub fn verify_writes(mut self, verify: bool) -> ConfigBuilder {
+        self.cfg.verify_writes = verify;
+        self
+    }
+
+    /// Deviations from the protocol which are tolerated.
+    pub fn quirks(mut self, quirks: Quirks) -> ConfigBuilder {
+        self.cfg.quirks = quirks;
+        self
+    }
+
+    /// Further options of the TCP socket.
+    #[cfg(feature = "socket2")]
+    pub fn socket_options(mut self, options: SocketOptions) -> ConfigBuilder {
+        self.cfg.socket_options = options;
+        self
+    }
+
+    /// Send TCP keep-alive probes after the connection was idle for `idle`. Requires the `socket2`
+    /// feature.
+    #[cfg(feature = "socket2")]
+    pub fn tcp_keepalive(mut self, idle: Duration) -> ConfigBuilder {
+        self.cfg.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// The request sent by `Transport::ping`.
+    pub fn ping(mut self, ping: Ping) -> ConfigBuilder {
+        self.cfg.ping = ping;
+        self
+    }
+
+    /// The framing of the frames, e.g. RTU frames through a serial device server.
+    pub fn framing(mut self, framing: Framing) -> ConfigBuilder {
+        self.cfg.framing = framing;
+        self
+    }
+
+    /// Check the options and return the `Config`.
+    ///
+    /// Fails with `InvalidData` for the port `0`, zero timeouts, which the sockets reject, and
+    /// reconnect policies without attempts or with a backoff above its maximum.
+    pub fn build(self) -> Result<Config> {
+        let cfg = self.cfg;
+        let err = |reason: &str| Err(Error::InvalidData(Reason::Custom(reason.to_string())));
+        if cfg.tcp_port == 0 {
+            return err("the port must not be 0");
+        }
+        let timeouts = [
+            ("connect", cfg.tcp_connect_timeout),
+            ("read", cfg.tcp_read_timeout),
+            ("write", cfg.tcp_write_timeout),
+        ];
+        for (name, timeout) in timeouts {
+            if timeout == Some(Duration::ZERO) {
+                return err(&format!("the {} timeout must not be zero", name));
+            }
+        }
+        let policies = [
+            cfg.auto_reconnect,
+            cfg.connection_limit_retry,
+            cfg.busy_retry,
+        ];
+        for policy in policies.iter().flatten() {
+            if policy.max_attempts == 0 {
+                return err("the reconnect policy needs at least one attempt");
+            }
+            if policy.backoff > policy.max_backoff {
+                return err("the reconnect backoff exceeds its maximum");
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        secs.parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite() && *s >= 0.0)
+            .map(Duration::from_secs_f64)
+    } else {
+        s.parse().ok().map(Duration::from_millis)
+    }
+}
+
+// Order addresses to connect to like RFC 8305 does: IPv6 and IPv4 alternately, starting with
+// IPv6, keeping the order of the resolver within each family.
+fn interleave(addrs: impl Iterator<Item = SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.partition(SocketAddr::is_ipv6);
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
         }
     }
 }
 
+fn is_broken_connection(err: &Error) -> bool {
+    match *err {
+        Error::ConnectionClosed => true,
+        Error::Io(ref e) => matches!(
+            e.kind(),
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+        ),
+        _ => false,
+    }
+}
+
+/// Whether a frame was sent or received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A frame sent or received by a `Transport`, see `Transport::set_frame_observer`.
+#[derive(Debug)]
+pub struct FrameEvent<'a> {
+    pub direction: Direction,
+    /// The complete frame including the MBAP header
+    pub frame: &'a [u8],
+    /// The time the frame was completely sent or received
+    pub time: SystemTime,
+}
+
+type FrameObserver = Arc<dyn Fn(&FrameEvent) + Send + Sync>;
+
+// Format `bytes` as a hexdump in brackets.
+#[cfg(any(feature = "debug-frames", feature = "log"))]
+fn hexdump(bytes: &[u8]) -> String {
+    use std::fmt::Write;
+
+    let mut dump = "[".to_string();
+    for (i, b) in bytes.iter().enumerate() {
+        let sep = if i == 0 { "" } else { " " };
+        let _ = write!(dump, "{}{:02x}", sep, b);
+    }
+    dump.push(']');
+    dump
+}
+
+// Log a completed transaction of the request `req` with the response frame `resp` at debug
+// level, with the address and quantity or value for the functions which have them.
+#[cfg(feature = "log")]
+fn log_transaction(header: &Header, req: &[u8], resp: &[u8], framing: Framing, elapsed: Duration) {
+    let args = match *req {
+        [0x01..=0x06 | 0x0f | 0x10 | 0x17, a0, a1, q0, q1, ..] => format!(
+            " address={} quantity={}",
+            u16::from_be_bytes([a0, a1]),
+            u16::from_be_bytes([q0, q1])
+        ),
+        _ => String::new(),
+    };
+    log::debug!(
+        "tid={} uid={} function=0x{:02x}{} took {:?}, request: {}, response: {}",
+        header.tid,
+        header.uid,
+        req[0],
+        args,
+        elapsed,
+        hexdump(req),
+        dump_frame(resp, framing)
+    );
+}
+
+// Format a frame as its decoded MBAP header and function code followed by a hexdump, RTU
+// frames as the hexdump only.
+#[cfg(any(feature = "debug-frames", feature = "log"))]
+fn dump_frame(frame: &[u8], framing: Framing) -> String {
+    if framing == Framing::RtuOverTcp {
+        return hexdump(frame);
+    }
+    let mut dump = match (Header::unpack(frame), frame.get(MODBUS_HEADER_SIZE)) {
+        (Ok(h), Some(function)) => format!(
+            "tid={} pid={} len={} uid={} function=0x{:02x} ",
+            h.tid, h.pid, h.len, h.uid, function
+        ),
+        (Ok(h), None) => format!("tid={} pid={} len={} uid={} ", h.tid, h.pid, h.len, h.uid),
+        (Err(_), _) => String::new(),
+    };
+    dump.push_str(&hexdump(frame));
+    dump
+}
+
 #[derive(Debug, PartialEq)]
 struct Header {
     tid: u16,
@@ -80,11 +738,99 @@ impl Header {
     }
 }
 
+// The connection of a transport, a plain TCP stream, a TLS session on top of one or a Unix
+// domain socket.
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn tcp(&self) -> Option<&TcpStream> {
+        match *self {
+            Stream::Tcp(ref s) => Some(s),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref s) => Some(s.get_ref()),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            #[cfg(unix)]
+            Stream::Unix(ref s) => s.set_read_timeout(timeout),
+            _ => self.tcp().unwrap().set_read_timeout(timeout),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match *self {
+            #[cfg(unix)]
+            Stream::Unix(ref s) => s.shutdown(Shutdown::Both),
+            _ => self.tcp().unwrap().shutdown(Shutdown::Both),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut s) => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut s) => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(ref mut s) => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
 /// Context object which holds state for all modbus operations.
+///
+/// Clones created with `try_clone` share the transaction id counter, so requests sent from
+/// different clones never use the same transaction id.
 pub struct Transport {
-    tid: u16,
+    tid: Arc<AtomicU16>,
     uid: u8,
-    stream: TcpStream,
+    stream: Stream,
+    // the address and configuration to reconnect with
+    addr: String,
+    cfg: Config,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ClientConfig>>,
+    observer: Option<FrameObserver>,
+    // whether the device answered any request, on this or an earlier connection
+    answered: bool,
+    // the last request sent in the current transaction, kept after it failed
+    context: Option<ErrorContext>,
+    // the request and response frames of the current transaction
+    #[cfg(feature = "debug-frames")]
+    frames: (Vec<u8>, Vec<u8>),
 }
 
 impl Transport {
@@ -94,354 +840,803 @@ impl Transport {
         Self::new_with_cfg(addr, Config::default())
     }
 
+    /// Create a new context object and connect it to the endpoint described by `url`, see
+    /// `Config::from_url` for the syntax.
+    pub fn new_from_url(url: &str) -> Result<Transport> {
+        let (addr, cfg) = Config::from_url(url)?;
+        Ok(Self::new_with_cfg(&addr, cfg)?)
+    }
+
     /// Create a new context object and connect it to `addr` on port `port`
+    ///
+    /// All addresses the host name resolves to are tried in turn, each with the connect timeout
+    /// of `cfg`. IPv6 and IPv4 addresses are tried alternately, starting with IPv6, so a host
+    /// without a working IPv6 route is reached after a single timeout.
     pub fn new_with_cfg(addr: &str, cfg: Config) -> io::Result<Transport> {
-        let stream = match cfg.tcp_connect_timeout {
-            Some(timeout) => {
-                // Call to connect_timeout needs to be done on a single address
-                let mut socket_addrs = (addr, cfg.tcp_port).to_socket_addrs()?;
-                TcpStream::connect_timeout(&socket_addrs.next().unwrap(), timeout)
-            }
-            None => TcpStream::connect((addr, cfg.tcp_port)),
-        };
+        Ok(Transport {
+            tid: Arc::new(AtomicU16::new(0)),
+            uid: cfg.modbus_uid,
+            stream: Stream::Tcp(Transport::connect_host(addr, &cfg)?),
+            addr: addr.to_string(),
+            cfg,
+            #[cfg(feature = "tls")]
+            tls: None,
+            observer: None,
+            answered: false,
+            context: None,
+            #[cfg(feature = "debug-frames")]
+            frames: Default::default(),
+        })
+    }
 
-        match stream {
-            Ok(s) => {
-                s.set_read_timeout(cfg.tcp_read_timeout)?;
-                s.set_write_timeout(cfg.tcp_write_timeout)?;
-                s.set_nodelay(true)?;
-                Ok(Transport {
-                    tid: 0,
-                    uid: cfg.modbus_uid,
-                    stream: s,
-                })
-            }
-            Err(e) => Err(e),
-        }
+    /// Create a new context object and connect it to the first of the addresses `addr` resolves
+    /// to which accepts the connection, each tried with the connect timeout of `cfg`.
+    ///
+    /// Unlike with `new_with_cfg`, the port is taken from `addr`, e.g. a `SocketAddr` or
+    /// `("plc.local", 1502)`, and not from `cfg`. `reconnect` connects to the address which
+    /// accepted the connection without resolving `addr` again.
+    pub fn connect<A: ToSocketAddrs>(addr: A, cfg: Config) -> io::Result<Transport> {
+        let stream = Transport::connect_addrs(addr.to_socket_addrs()?, &cfg)?;
+        Transport::from_connected(stream, cfg)
     }
 
-    // Create a new transaction Id, incrementing the previous one.
-    // The Id is wrapping around if the Id reaches `u16::MAX`.
-    fn new_tid(&mut self) -> u16 {
-        self.tid = self.tid.wrapping_add(1);
-        self.tid
+    /// Create a new context object using an already connected `stream`, for applications which
+    /// set up their sockets themselves.
+    ///
+    /// The read and write timeouts and the keep-alive of `cfg` are applied to the stream, its
+    /// port, connect timeout and socket options are ignored. `reconnect` connects to the peer address of the stream.
+    pub fn from_stream(stream: TcpStream, cfg: Config) -> io::Result<Transport> {
+        #[cfg(feature = "socket2")]
+        set_keepalive(&socket2::SockRef::from(&stream), cfg.tcp_keepalive)?;
+        stream.set_read_timeout(cfg.tcp_read_timeout)?;
+        stream.set_write_timeout(cfg.tcp_write_timeout)?;
+        stream.set_nodelay(true)?;
+        Transport::from_connected(stream, cfg)
     }
 
-    fn read(&mut self, fun: &Function) -> Result<Vec<u8>> {
-        let packed_size = |v: u16| v / 8 + if v % 8 > 0 { 1 } else { 0 };
-        let (addr, count, expected_bytes) = match *fun {
-            Function::ReadCoils(a, c) | Function::ReadDiscreteInputs(a, c) => {
-                (a, c, packed_size(c) as usize)
-            }
-            Function::ReadHoldingRegisters(a, c) | Function::ReadInputRegisters(a, c) => {
-                (a, c, 2 * c as usize)
-            }
-            _ => return Err(Error::InvalidFunction),
-        };
+    // Remember the peer of `stream` to reconnect to it.
+    fn from_connected(stream: TcpStream, mut cfg: Config) -> io::Result<Transport> {
+        let peer = stream.peer_addr()?;
+        cfg.tcp_port = peer.port();
+        Ok(Transport {
+            tid: Arc::new(AtomicU16::new(0)),
+            uid: cfg.modbus_uid,
+            stream: Stream::Tcp(stream),
+            addr: peer.ip().to_string(),
+            cfg,
+            #[cfg(feature = "tls")]
+            tls: None,
+            observer: None,
+            answered: false,
+            context: None,
+            #[cfg(feature = "debug-frames")]
+            frames: Default::default(),
+        })
+    }
 
-        if count < 1 {
-            return Err(Error::InvalidData(Reason::RecvBufferEmpty));
-        }
+    /// Create a new context object and connect it to `addr` on port `port` using Modbus/TCP
+    /// Security, which runs the protocol over a TLS session.
+    ///
+    /// `tls` holds the trusted root certificates and the client certificate. The certificate of
+    /// the server is verified for `addr`. Note that secured servers usually listen on port
+    /// `MODBUS_TLS_DEFAULT_PORT` (802), which has to be set in `cfg`.
+    #[cfg(feature = "tls")]
+    pub fn new_tls_with_cfg(
+        addr: &str,
+        cfg: Config,
+        tls: Arc<rustls::ClientConfig>,
+    ) -> io::Result<Transport> {
+        Ok(Transport {
+            tid: Arc::new(AtomicU16::new(0)),
+            uid: cfg.modbus_uid,
+            stream: Transport::connect_tls(addr, &cfg, tls.clone())?,
+            addr: addr.to_string(),
+            cfg,
+            tls: Some(tls),
+            observer: None,
+            answered: false,
+            context: None,
+            #[cfg(feature = "debug-frames")]
+            frames: Default::default(),
+        })
+    }
 
-        if count as usize > MODBUS_MAX_PACKET_SIZE {
-            return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+    #[cfg(feature = "tls")]
+    fn connect_tls(addr: &str, cfg: &Config, tls: Arc<rustls::ClientConfig>) -> io::Result<Stream> {
+        let name = rustls::pki_types::ServerName::try_from(addr.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut conn = rustls::ClientConnection::new(tls, name).map_err(io::Error::other)?;
+        let mut tcp = Transport::connect_host(addr, cfg)?;
+        // complete the handshake here, so that certificate errors are reported by the
+        // constructor and not by the first request
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp)?;
         }
+        Ok(Stream::Tls(Box::new(rustls::StreamOwned::new(conn, tcp))))
+    }
 
-        let header = Header::new(self, MODBUS_HEADER_SIZE as u16 + 6u16);
-        let mut buff = header.pack()?;
-        buff.write_u8(fun.code())?;
-        buff.write_u16::<BigEndian>(addr)?;
-        buff.write_u16::<BigEndian>(count)?;
-
-        match self.stream.write_all(&buff) {
-            Ok(_s) => {
-                let mut reply = vec![0; MODBUS_HEADER_SIZE + expected_bytes + 2];
-                match self.stream.read(&mut reply) {
-                    Ok(_s) => {
-                        let resp_hd = Header::unpack(&reply[..MODBUS_HEADER_SIZE])?;
-                        Transport::validate_response_header(&header, &resp_hd)?;
-                        Transport::validate_response_code(&buff, &reply)?;
-                        Transport::get_reply_data(&reply, expected_bytes)
-                    }
-                    Err(e) => Err(Error::Io(e)),
+    fn connect_host(addr: &str, cfg: &Config) -> io::Result<TcpStream> {
+        let connect = || Transport::connect_addrs((addr, cfg.tcp_port).to_socket_addrs()?, cfg);
+        let mut result = connect();
+        if let Some(policy) = cfg.connection_limit_retry {
+            let mut backoff = policy.backoff;
+            for _ in 0..policy.max_attempts {
+                match result {
+                    Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => (),
+                    _ => break,
                 }
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(policy.max_backoff);
+                result = connect();
             }
-            Err(e) => Err(Error::Io(e)),
         }
+        result
     }
 
-    fn validate_response_header(req: &Header, resp: &Header) -> Result<()> {
-        if req.tid != resp.tid || resp.pid != MODBUS_PROTOCOL_TCP {
-            Err(Error::InvalidResponse)
-        } else {
-            Ok(())
-        }
-    }
+    #[cfg(feature = "socket2")]
+    fn connect_addrs(
+        addrs: impl Iterator<Item = SocketAddr>,
+        cfg: &Config,
+    ) -> io::Result<TcpStream> {
+        use socket2::{Domain, Protocol, Socket, Type};
 
-    fn validate_response_code(req: &[u8], resp: &[u8]) -> Result<()> {
-        if req[7] + 0x80 == resp[7] {
-            match ExceptionCode::from_u8(resp[8]) {
-                Some(code) => Err(Error::Exception(code)),
-                None => Err(Error::InvalidResponse),
+        let mut last_err = None;
+        for socket_addr in interleave(addrs) {
+            let socket = Socket::new(
+                Domain::for_address(socket_addr),
+                Type::STREAM,
+                Some(Protocol::TCP),
+            )?;
+            cfg.socket_options.apply(&socket, socket_addr.is_ipv6())?;
+            set_keepalive(&socket, cfg.tcp_keepalive)?;
+            let res = match cfg.tcp_connect_timeout {
+                Some(timeout) => socket.connect_timeout(&socket_addr.into(), timeout),
+                None => socket.connect(&socket_addr.into()),
+            };
+            match res {
+                Ok(()) => {
+                    let stream = TcpStream::from(socket);
+                    stream.set_read_timeout(cfg.tcp_read_timeout)?;
+                    stream.set_write_timeout(cfg.tcp_write_timeout)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e),
             }
-        } else if req[7] == resp[7] {
-            Ok(())
-        } else {
-            Err(Error::InvalidResponse)
         }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+        }))
     }
 
-    fn get_reply_data(reply: &[u8], expected_bytes: usize) -> Result<Vec<u8>> {
-        if reply[8] as usize != expected_bytes
-            || reply.len() != MODBUS_HEADER_SIZE + expected_bytes + 2
-        {
-            Err(Error::InvalidData(Reason::UnexpectedReplySize))
-        } else {
-            let mut d = Vec::new();
-            d.extend_from_slice(&reply[MODBUS_HEADER_SIZE + 2..]);
-            Ok(d)
+    #[cfg(not(feature = "socket2"))]
+    fn connect_addrs(
+        addrs: impl Iterator<Item = SocketAddr>,
+        cfg: &Config,
+    ) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for socket_addr in interleave(addrs) {
+            let res = match cfg.tcp_connect_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&socket_addr, timeout),
+                None => TcpStream::connect(socket_addr),
+            };
+            match res {
+                Ok(stream) => {
+                    stream.set_read_timeout(cfg.tcp_read_timeout)?;
+                    stream.set_write_timeout(cfg.tcp_write_timeout)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e),
+            }
         }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to")
+        }))
     }
 
-    fn write_single(&mut self, fun: &Function) -> Result<()> {
-        let (addr, value) = match *fun {
-            Function::WriteSingleCoil(a, v) | Function::WriteSingleRegister(a, v) => (a, v),
-            _ => return Err(Error::InvalidFunction),
-        };
-
-        let mut buff = vec![0; MODBUS_HEADER_SIZE]; // Header gets filled in later
-        buff.write_u8(fun.code())?;
-        buff.write_u16::<BigEndian>(addr)?;
-        buff.write_u16::<BigEndian>(value)?;
-        self.write(&mut buff)
+    /// Create a new context object and connect it to the Unix domain socket at `path`, e.g. of a
+    /// local simulator or gateway.
+    ///
+    /// The Modbus TCP protocol is used on the socket. The port and connect timeout of `cfg` are
+    /// ignored.
+    #[cfg(unix)]
+    pub fn new_unix_with_cfg(path: &str, cfg: Config) -> io::Result<Transport> {
+        Ok(Transport {
+            tid: Arc::new(AtomicU16::new(0)),
+            uid: cfg.modbus_uid,
+            stream: Stream::Unix(Transport::connect_unix(path, &cfg)?),
+            addr: path.to_string(),
+            cfg,
+            #[cfg(feature = "tls")]
+            tls: None,
+            observer: None,
+            answered: false,
+            context: None,
+            #[cfg(feature = "debug-frames")]
+            frames: Default::default(),
+        })
     }
 
-    fn write_read_multiple(&mut self, fun: &Function) -> Result<Vec<u8>> {
-        if let Function::WriteReadMultipleRegisters(
-            write_addr,
-            write_quantity,
-            write_values,
-            read_addr,
-            read_quantity,
-        ) = *fun
-        {
-            let expected_bytes = 2 * read_quantity as usize;
+    #[cfg(unix)]
+    fn connect_unix(path: &str, cfg: &Config) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(cfg.tcp_read_timeout)?;
+        stream.set_write_timeout(cfg.tcp_write_timeout)?;
+        Ok(stream)
+    }
 
-            let header = Header::new(
-                self,
-                MODBUS_HEADER_SIZE as u16 + 10u16 + write_quantity * 2 + 1u16,
-            );
-            let mut buff = header.pack()?;
-
-            buff.write_u8(fun.code())?;
-            buff.write_u16::<BigEndian>(read_addr)?;
-            buff.write_u16::<BigEndian>(read_quantity)?;
-            buff.write_u16::<BigEndian>(write_addr)?;
-            buff.write_u16::<BigEndian>(write_quantity)?;
-            buff.write_u8((write_values.len()) as u8)?;
-            for v in write_values {
-                buff.write_u8(*v)?;
-            }
-
-            match self.stream.write_all(&buff) {
-                Ok(_s) => {
-                    let mut reply = vec![0; MODBUS_HEADER_SIZE + expected_bytes + 2];
-                    match self.stream.read(&mut reply) {
-                        Ok(_s) => {
-                            let resp_hd = Header::unpack(&reply[..MODBUS_HEADER_SIZE])?;
-                            Transport::validate_response_header(&header, &resp_hd)?;
-                            Transport::validate_response_code(&buff, &reply)?;
-                            Transport::get_reply_data(&reply, expected_bytes)
+    /// Check that the connection is alive by sending the `Ping` request of the `Config` and
+    /// return the round trip time, e.g. before issuing writes on a connection which was idle for
+    /// a while. Exception responses count as an answer, any answer proves the device is
+    /// reachable. Except for the `GatewayPath` and `GatewayTarget` exceptions, which are
+    /// returned as errors: the gateway answered, but the device behind it is unreachable.
+    pub fn ping(&mut self) -> Result<Duration> {
+        let start = Instant::now();
+        let result = match self.cfg.ping {
+            Ping::ReturnQueryData => {
+                let data = [0xa537];
+                self.diagnostics(DiagnosticsSubFunction::ReturnQueryData, &data)
+                    .and_then(|echo| {
+                        if echo == data {
+                            Ok(())
+                        } else {
+                            Err(Error::InvalidResponse)
                         }
-                        Err(e) => Err(Error::Io(e)),
-                    }
-                }
-                Err(e) => Err(Error::Io(e)),
+                    })
             }
-        } else {
-            Err(Error::InvalidFunction)
+            Ping::ReadHoldingRegister(addr) => self.read_holding_registers(addr, 1).map(drop),
+            Ping::ReadInputRegister(addr) => self.read_input_registers(addr, 1).map(drop),
+        };
+        match result {
+            Ok(()) => Ok(start.elapsed()),
+            Err(e) => match e.root() {
+                Error::Exception(ExceptionCode::GatewayPath | ExceptionCode::GatewayTarget) => {
+                    Err(e)
+                }
+                Error::Exception(_) => Ok(start.elapsed()),
+                _ => Err(e),
+            },
         }
     }
 
-    fn write_multiple(&mut self, fun: &Function) -> Result<()> {
-        let (addr, quantity, values) = match *fun {
-            Function::WriteMultipleCoils(a, q, v) | Function::WriteMultipleRegisters(a, q, v) => {
-                (a, q, v)
-            }
-            _ => return Err(Error::InvalidFunction),
-        };
-
-        let mut buff = vec![0; MODBUS_HEADER_SIZE]; // Header gets filled in later
-        buff.write_u8(fun.code())?;
-        buff.write_u16::<BigEndian>(addr)?;
-        buff.write_u16::<BigEndian>(quantity)?;
-        buff.write_u8(values.len() as u8)?;
-        for v in values {
-            buff.write_u8(*v)?;
-        }
-        self.write(&mut buff)
+    /// The request the last failed request of a `Client` method was sent as, e.g. its
+    /// transaction id to find it in a capture, or `None` if the last request succeeded or
+    /// failed before it was sent.
+    pub fn last_error_context(&self) -> Option<ErrorContext> {
+        self.context
     }
 
-    fn write(&mut self, buff: &mut [u8]) -> Result<()> {
-        if buff.is_empty() {
-            return Err(Error::InvalidData(Reason::SendBufferEmpty));
+    /// The address of the device the transport is connected to, e.g. to see which of the
+    /// addresses of a host name was used. Fails for Unix domain sockets.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self.stream.tcp() {
+            Some(s) => s.peer_addr(),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets have no peer address",
+            )),
         }
+    }
 
-        if buff.len() > MODBUS_MAX_PACKET_SIZE {
-            return Err(Error::InvalidData(Reason::SendBufferTooBig));
+    /// Replace the connection by a new one to the same address, resolving the host name again.
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        #[cfg(unix)]
+        if let Stream::Unix(_) = self.stream {
+            self.stream = Stream::Unix(Transport::connect_unix(&self.addr, &self.cfg)?);
+            return Ok(());
         }
-
-        let header = Header::new(self, buff.len() as u16 + 1u16);
-        let head_buff = header.pack()?;
-        {
-            let mut start = Cursor::new(buff.borrow_mut());
-            start.write_all(&head_buff)?;
-        }
-        match self.stream.write_all(buff) {
-            Ok(_s) => {
-                let reply = &mut [0; 12];
-                match self.stream.read(reply) {
-                    Ok(_s) => {
-                        let resp_hd = Header::unpack(reply)?;
-                        Transport::validate_response_header(&header, &resp_hd)?;
-                        Transport::validate_response_code(buff, reply)
-                    }
-                    Err(e) => Err(Error::Io(e)),
-                }
-            }
-            Err(e) => Err(Error::Io(e)),
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.tls {
+            self.stream = Transport::connect_tls(&self.addr, &self.cfg, tls.clone())?;
+            return Ok(());
         }
+        self.stream = Stream::Tcp(Transport::connect_host(&self.addr, &self.cfg)?);
+        Ok(())
     }
 
-    pub fn close(&mut self) -> Result<()> {
-        self.stream.shutdown(Shutdown::Both).map_err(Error::Io)
-    }
-
-    pub fn try_clone(&self) -> Result<Self> {
-        Ok(Self {
-            tid: self.tid,
-            uid: self.uid,
-            stream: self.stream.try_clone()?,
-        })
+    /// Bring a connection back in sync after a protocol error without reconnecting, which some
+    /// devices rate-limit.
+    ///
+    /// Bytes arriving until the connection is quiet for 100ms, e.g. late responses to timed out
+    /// requests, are discarded. The transaction id is advanced by `tid_gap`, so responses still
+    /// in flight can't be mistaken for the next ones, and `verify` is run to check that the
+    /// device answers again, e.g. `|t| t.read_holding_registers(0, 1)`.
+    pub fn resync<T>(
+        &mut self,
+        tid_gap: u16,
+        verify: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        self.drain()?;
+        self.tid.fetch_add(tid_gap, Ordering::Relaxed);
+        verify(self)
     }
 
-    #[cfg(feature = "read-device-info")]
-    /**
-    Some devices support modbus function 43 (Modbus Encasulated Interface) to read device information as strings.
-    This will return an `IllegalFunction (0x01)` exception code if this request is not supported by the device.
-    */
-    pub fn read_device_info(
+    /// Run `f` with the read timeout set to `timeout`, e.g. for requests to registers which a
+    /// device answers slower than others. The timeout applies to connections reestablished
+    /// within `f` as well, the configured timeout is restored afterwards.
+    pub fn with_response_timeout<T>(
         &mut self,
-        obj_category: mei::DeviceInfoCategory,
-    ) -> Result<Vec<mei::DeviceInfoObject>> {
-        let mut info: Vec<mei::DeviceInfoObject> = vec![];
-        let mut buff = vec![0; MODBUS_HEADER_SIZE]; // Header gets filled in later
-        buff.write_u8(0x2B)?; // Modbus Encapsulated Interface (Function code 43)
-        buff.write_u8(0x0E)?; // MEI Type 14 (Read Device Identification)
-        buff.write_u8(match obj_category {
-            mei::DeviceInfoCategory::Basic => 0x01,
-            mei::DeviceInfoCategory::Regular => 0x02,
-            mei::DeviceInfoCategory::Extended => 0x03,
-        })?;
-        buff.write_u8(0x00)?; // Object ID
-
-        let header = Header::new(self, buff.len() as u16 + 1u16);
-        let head_buff = header.pack()?;
-        {
-            let mut start: Cursor<&mut Vec<u8>> = Cursor::new(buff.borrow_mut());
-            start.write_all(&head_buff)?;
-        }
-
-        self.stream.write_all(&buff)?;
-        let reply = &mut [0; MODBUS_MAX_PACKET_SIZE];
-
-        self.stream.read(reply)?;
-        let resp_hd = Header::unpack(reply)?;
-        Transport::validate_response_header(&header, &resp_hd)?;
-        Transport::validate_response_code(&buff, reply)?;
+        timeout: Duration,
+        f: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        // new connections take their read timeout from the config
+        let configured = self.cfg.tcp_read_timeout.replace(timeout);
+        let res = match self.stream.set_read_timeout(Some(timeout)) {
+            Ok(()) => f(self),
+            Err(e) => Err(e.into()),
+        };
+        self.cfg.tcp_read_timeout = configured;
+        self.stream.set_read_timeout(configured)?;
+        res
+    }
 
-        let resp_body = reply[7..(6 + resp_hd.len) as usize].to_vec();
-        let obj_count = resp_body[6] as usize;
-        let mut cursor: usize = 6;
-        for _ in 0..obj_count {
-            cursor += 1;
-            let id = resp_body[cursor];
+    // Discard incoming bytes until the connection is quiet for `RESYNC_QUIET_TIME`.
+    fn drain(&mut self) -> Result<()> {
+        self.stream.set_read_timeout(Some(RESYNC_QUIET_TIME))?;
+        let mut buf = [0; MODBUS_MAX_PACKET_SIZE];
+        let res = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err(Error::ConnectionClosed),
+                Ok(_) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    break Ok(())
+                }
+                Err(e) => break Err(e.into()),
+            }
+        };
+        self.stream.set_read_timeout(self.cfg.tcp_read_timeout)?;
+        res
+    }
 
-            cursor += 1;
-            let len = resp_body[cursor] as usize;
+    // Create a new transaction Id, incrementing the previous one.
+    // The Id is wrapping around if the Id reaches `u16::MAX`.
+    fn new_tid(&self) -> u16 {
+        self.tid.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
+    }
 
-            let mut val_buf: Vec<u8> = vec![];
-            for _ in 0..len {
-                cursor += 1;
-                val_buf.push(resp_body[cursor])
+    // Read the response frame to the request with the header `req`, skipping up to
+    // `max_stale_responses` frames with another transaction id.
+    fn read_response(&mut self, req: &Header) -> Result<Vec<u8>> {
+        let mut stale = 0;
+        loop {
+            let (reply, resp_hd) = self.read_frame()?;
+            if !self.cfg.quirks.matches(req, &resp_hd)
+                && (resp_hd.pid == MODBUS_PROTOCOL_TCP || self.cfg.quirks.any_protocol_id)
+                && stale < self.cfg.max_stale_responses
+            {
+                stale += 1;
+                continue;
             }
-
-            let object = mei::DeviceInfoObject::new(
-                id,
-                match String::from_utf8(val_buf) {
-                    Ok(val) => val,
-                    Err(_) => return Err(Error::ParseInfoError),
-                },
-            );
-            info.push(object)
+            if !self.cfg.quirks.matches(req, &resp_hd) {
+                return Err(Error::InvalidResponse);
+            }
+            self.answered = true;
+            return Ok(reply);
         }
-        Ok(info)
     }
-}
 
-impl Client for Transport {
-    /// Read `count` bits starting at address `addr`.
-    fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
-        let bytes = self.read(&Function::ReadCoils(addr, count))?;
-        Ok(binary::unpack_bits(&bytes, count))
+    // Read a complete frame: the MBAP header first and then exactly the number of bytes
+    // announced in its `len` field, so the stream stays in sync even if the response is shorter
+    // or longer than expected.
+    fn read_frame(&mut self) -> Result<(Vec<u8>, Header)> {
+        let mut reply = vec![0; MODBUS_HEADER_SIZE];
+        crate::read_exact(&mut self.stream, &mut reply)?;
+        #[cfg(feature = "debug-frames")]
+        self.frames.1.clone_from(&reply);
+        let resp_hd = Header::unpack(&reply)?;
+        // the shortest valid response is an exception: unit id, function and exception code
+        if resp_hd.len < 3 || resp_hd.len as usize > MODBUS_MAX_PACKET_SIZE - MODBUS_HEADER_SIZE + 1
+        {
+            return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+        }
+        reply.resize(MODBUS_HEADER_SIZE + resp_hd.len as usize - 1, 0);
+        crate::read_exact(&mut self.stream, &mut reply[MODBUS_HEADER_SIZE..])?;
+        #[cfg(feature = "debug-frames")]
+        self.frames.1.clone_from(&reply);
+        self.notify(Direction::Received, &reply);
+        Ok((reply, resp_hd))
     }
 
-    /// Read `count` input bits starting at address `addr`.
-    fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
-        let bytes = self.read(&Function::ReadDiscreteInputs(addr, count))?;
-        Ok(binary::unpack_bits(&bytes, count))
+    // Read the RTU frame answering `req`, by the length expected from its first bytes, and check
+    // its CRC and unit id.
+    fn read_rtu_frame(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        // unit id, function and two bytes are the shortest frame, an exception
+        let mut reply = vec![0; 4];
+        crate::read_exact(&mut self.stream, &mut reply)?;
+        #[cfg(feature = "debug-frames")]
+        self.frames.1.clone_from(&reply);
+        let len = pdu::response_len(req, &reply[1..])
+            .ok_or(Error::InvalidData(Reason::UnexpectedReplySize))?;
+        if len > pdu::MAX_PDU_SIZE {
+            return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+        }
+        reply.resize(1 + len + 2, 0);
+        crate::read_exact(&mut self.stream, &mut reply[4..])?;
+        #[cfg(feature = "debug-frames")]
+        self.frames.1.clone_from(&reply);
+        self.notify(Direction::Received, &reply);
+        let (frame, crc) = reply.split_at(1 + len);
+        if binary::crc16(frame).to_le_bytes() != crc {
+            return Err(Error::InvalidData(Reason::InvalidChecksum));
+        }
+        if frame[0] != self.uid {
+            return Err(Error::InvalidResponse);
+        }
+        self.answered = true;
+        Ok(reply)
     }
 
-    /// Read `count` 16bit registers starting at address `addr`.
-    fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
-        let bytes = self.read(&Function::ReadHoldingRegisters(addr, count))?;
-        binary::pack_bytes(&bytes[..])
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        #[cfg(feature = "debug-frames")]
+        {
+            self.frames.0 = frame.to_vec();
+        }
+        self.stream.write_all(frame)?;
+        self.notify(Direction::Sent, frame);
+        Ok(())
     }
 
-    /// Read `count` 16bit input registers starting at address `addr`.
-    fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
-        let bytes = self.read(&Function::ReadInputRegisters(addr, count))?;
-        binary::pack_bytes(&bytes[..])
+    fn notify(&self, direction: Direction, frame: &[u8]) {
+        if let Some(ref observer) = self.observer {
+            observer(&FrameEvent {
+                direction,
+                frame,
+                time: SystemTime::now(),
+            });
+        }
     }
 
-    /// Write a single coil (bit) to address `addr`.
-    fn write_single_coil(&mut self, addr: u16, value: Coil) -> Result<()> {
-        self.write_single(&Function::WriteSingleCoil(addr, value.code()))
+    /// Call `observer` with every frame sent or received, e.g. to capture the traffic for
+    /// debugging. Clones made with `try_clone` share the observer.
+    pub fn set_frame_observer(&mut self, observer: impl Fn(&FrameEvent) + Send + Sync + 'static) {
+        self.observer = Some(Arc::new(observer));
     }
 
-    /// Write a single 16bit register to address `addr`.
-    fn write_single_register(&mut self, addr: u16, value: u16) -> Result<()> {
-        self.write_single(&Function::WriteSingleRegister(addr, value))
+    /// Stop calling the frame observer.
+    pub fn clear_frame_observer(&mut self) {
+        self.observer = None;
+    }
+
+    // Run the transaction `f`, repeated on a new connection if the connection broke and
+    // `auto_reconnect` is configured. The last request sent is kept as the context of a failed
+    // transaction, errors are returned with the `debug-frames` feature as `Error::Frames` with a
+    // dump of the request and response frames.
+    fn transaction<T>(&mut self, f: impl Fn(&mut Self) -> Result<T>) -> Result<T> {
+        #[cfg(feature = "debug-frames")]
+        {
+            self.frames = Default::default();
+        }
+        self.context = None;
+        let mut result = f(self);
+        let policies = [
+            // connections closed before the first response are likely due to a connection limit
+            (self.cfg.connection_limit_retry, true),
+            (self.cfg.auto_reconnect, false),
+        ];
+        for (policy, limited) in policies {
+            let Some(policy) = policy else { continue };
+            let mut backoff = policy.backoff;
+            for _ in 0..policy.max_attempts {
+                match result {
+                    Err(ref e) if is_broken_connection(e) && (!limited || !self.answered) => (),
+                    _ => break,
+                }
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(policy.max_backoff);
+                result = match self.reconnect() {
+                    Ok(()) => f(self),
+                    Err(e) => Err(e.into()),
+                };
+            }
+        }
+        let result = match result {
+            Err(e)
+                if self.cfg.connection_limit_retry.is_some()
+                    && is_broken_connection(&e)
+                    && !self.answered =>
+            {
+                Err(Error::ConnectionLimit(Box::new(e)))
+            }
+            result => result,
+        };
+        match result {
+            Ok(_) => self.context = None,
+            #[cfg(feature = "log")]
+            Err(ref err) => match self.context {
+                Some(context) => log::debug!("transaction failed: {} ({})", err, context),
+                None => log::debug!("transaction failed: {}", err),
+            },
+            #[cfg(not(feature = "log"))]
+            Err(_) => (),
+        }
+        #[cfg(feature = "debug-frames")]
+        let result = result.map_err(|err| {
+            let (req, resp) = &self.frames;
+            let framing = self.cfg.framing;
+            Error::Frames(
+                Box::new(err),
+                dump_frame(req, framing),
+                dump_frame(resp, framing),
+            )
+        });
+        result
+    }
+
+    // Send the request `req` without waiting for a response.
+    fn send(&mut self, req: &[u8]) -> Result<Header> {
+        let header = Header::new(self, (MODBUS_HEADER_SIZE + req.len() + 1) as u16);
+        let buff = match self.cfg.framing {
+            Framing::Mbap => {
+                self.context = Some(ErrorContext::new(req, header.uid, Some(header.tid)));
+                let mut buff = header.pack()?;
+                buff.extend_from_slice(req);
+                buff
+            }
+            Framing::RtuOverTcp => {
+                self.context = Some(ErrorContext::new(req, header.uid, None));
+                let mut buff = vec![header.uid];
+                buff.extend_from_slice(req);
+                let crc = binary::crc16(&buff);
+                buff.extend_from_slice(&crc.to_le_bytes());
+                buff
+            }
+        };
+        self.write_frame(&buff)?;
+        Ok(header)
+    }
+
+    // Send the request `req` and return the PDU of the response, exception responses are
+    // returned as `Error::Exception`. Requests to busy devices are retried with `busy_retry`.
+    fn transact(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        let mut result = self.exchange(req);
+        let Some(policy) = self.cfg.busy_retry else {
+            return result;
+        };
+        let mut backoff = policy.backoff;
+        let mut attempts = 0..policy.max_attempts;
+        let mut wait = || {
+            let more = attempts.next().is_some();
+            if more {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(policy.max_backoff);
+            }
+            more
+        };
+        loop {
+            match result {
+                Err(Error::Exception(ExceptionCode::SlaveOrServerBusy)) => {
+                    if !wait() {
+                        return result;
+                    }
+                }
+                // the device accepted a long running command, wait until it's done
+                Err(Error::Exception(ExceptionCode::Acknowledge)) => loop {
+                    if !wait() {
+                        return result;
+                    }
+                    if !self.command_running()? {
+                        return match acknowledged_response(req) {
+                            Some(resp) => Ok(resp),
+                            None => result,
+                        };
+                    }
+                },
+                _ => return result,
+            }
+            result = self.exchange(req);
+        }
+    }
+
+    // Whether the device is still processing a command, by the status word of Get Comm Event
+    // Counter, which is `0xffff` while it's busy.
+    fn command_running(&mut self) -> Result<bool> {
+        let context = self.context;
+        let resp = self.exchange(&[0x0b]);
+        self.context = context;
+        let resp = resp?;
+        if resp.len() != 5 {
+            return Err(Error::InvalidData(Reason::UnexpectedReplySize));
+        }
+        Ok(resp[1..3] == [0xff, 0xff])
+    }
+
+    fn exchange(&mut self, req: &[u8]) -> Result<Vec<u8>> {
+        let framing = self.cfg.framing;
+        // RTU responses have to be read by their expected length, as they have no length field
+        if framing == Framing::RtuOverTcp && pdu::response_len(req, &[req[0], 0, 0]).is_none() {
+            return Err(Error::InvalidFunction);
+        }
+        #[cfg(feature = "log")]
+        let start = Instant::now();
+        let header = self.send(req)?;
+        let (mut resp, pdu_start) = match framing {
+            Framing::Mbap => (self.read_response(&header)?, MODBUS_HEADER_SIZE),
+            Framing::RtuOverTcp => (self.read_rtu_frame(req)?, 1),
+        };
+        #[cfg(feature = "log")]
+        log_transaction(&header, req, &resp, framing, start.elapsed());
+        let mut resp = resp.split_off(pdu_start);
+        if framing == Framing::RtuOverTcp {
+            resp.truncate(resp.len() - 2);
+        }
+        pdu::check_response(req, &resp)?;
+        self.cfg.quirks.fix_byte_count(&mut resp);
+        Ok(resp)
+    }
+
+    // Send the write request `req` and check the echo in the response.
+    fn write(&mut self, req: &[u8]) -> Result<()> {
+        let resp = self.transact(req)?;
+        pdu::check_write_echo(req, &resp, self.cfg.lenient_write_echo)
+    }
+
+    // Read back the coils written starting at `addr` if `verify_writes` is set.
+    fn verify_coils(&mut self, addr: u16, values: &[Coil]) -> Result<()> {
+        if !self.cfg.verify_writes {
+            return Ok(());
+        }
+        let bits = |coils: &[Coil]| coils.iter().map(|&c| (c == Coil::On) as u16).collect();
+        let actual: Vec<u16> = bits(&self.read_coils(addr, values.len() as u16)?);
+        verify(addr, &bits(values), &actual)
+    }
+
+    // Read back the registers written starting at `addr` if `verify_writes` is set.
+    fn verify_registers(&mut self, addr: u16, values: &[u16]) -> Result<()> {
+        if !self.cfg.verify_writes {
+            return Ok(());
+        }
+        let actual = self.read_holding_registers(addr, values.len() as u16)?;
+        verify(addr, values, &actual)
+    }
+
+    /// Send a request with the function `code`, e.g. a vendor specific one, and `data`. The data
+    /// of the response is returned without the function code, exception responses are returned
+    /// as `Error::Exception`.
+    pub fn custom_function(&mut self, code: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let req = Function::Custom(code, data).request()?;
+        self.transaction(|t| Ok(t.transact(&req)?.split_off(1)))
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        #[cfg(feature = "tls")]
+        if let Stream::Tls(ref mut s) = self.stream {
+            s.conn.send_close_notify();
+            let _ = s.flush();
+        }
+        self.stream.shutdown().map_err(Error::Io)
+    }
+
+    /// Create a second transport on the same connection, TLS connections can't be cloned.
+    pub fn try_clone(&self) -> Result<Self> {
+        let stream = match self.stream {
+            Stream::Tcp(ref s) => Stream::Tcp(s.try_clone()?),
+            #[cfg(unix)]
+            Stream::Unix(ref s) => Stream::Unix(s.try_clone()?),
+            #[cfg(feature = "tls")]
+            Stream::Tls(_) => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "TLS connections can't be cloned",
+                )))
+            }
+        };
+        Ok(Self {
+            tid: self.tid.clone(),
+            uid: self.uid,
+            stream,
+            addr: self.addr.clone(),
+            cfg: self.cfg,
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone(),
+            observer: self.observer.clone(),
+            answered: self.answered,
+            context: None,
+            #[cfg(feature = "debug-frames")]
+            frames: Default::default(),
+        })
+    }
+}
+
+// Compare the `expected` values written starting at `addr` with the ones read back.
+fn verify(addr: u16, expected: &[u16], actual: &[u16]) -> Result<()> {
+    match expected.iter().zip(actual).position(|(e, a)| e != a) {
+        Some(i) => Err(Error::VerificationFailed {
+            addr: addr.wrapping_add(i as u16),
+            expected: expected[i],
+            actual: actual[i],
+        }),
+        None => Ok(()),
+    }
+}
+
+impl Client for Transport {
+    /// Read `count` bits starting at address `addr`.
+    fn read_coils(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+        let req = Function::ReadCoils(addr, count).request()?;
+        self.transaction(|t| pdu::parse_bits(&t.transact(&req)?, count))
+    }
+
+    /// Read `count` input bits starting at address `addr`.
+    fn read_discrete_inputs(&mut self, addr: u16, count: u16) -> Result<Vec<Coil>> {
+        let req = Function::ReadDiscreteInputs(addr, count).request()?;
+        self.transaction(|t| pdu::parse_bits(&t.transact(&req)?, count))
+    }
+
+    /// Read `count` 16bit registers starting at address `addr`.
+    fn read_holding_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadHoldingRegisters(addr, count).request()?;
+        self.transaction(|t| pdu::parse_registers(&t.transact(&req)?, count))
+    }
+
+    /// Read `count` 16bit input registers starting at address `addr`.
+    fn read_input_registers(&mut self, addr: u16, count: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadInputRegisters(addr, count).request()?;
+        self.transaction(|t| pdu::parse_registers(&t.transact(&req)?, count))
+    }
+
+    /// Write a single coil (bit) to address `addr`.
+    fn write_single_coil(&mut self, addr: u16, value: Coil) -> Result<()> {
+        let req = Function::WriteSingleCoil(addr, value.code()).request()?;
+        self.transaction(|t| t.write(&req))?;
+        self.verify_coils(addr, &[value])
+    }
+
+    /// Write a single 16bit register to address `addr`.
+    fn write_single_register(&mut self, addr: u16, value: u16) -> Result<()> {
+        let req = Function::WriteSingleRegister(addr, value).request()?;
+        self.transaction(|t| t.write(&req))?;
+        self.verify_registers(addr, &[value])
     }
 
     /// Write a multiple coils (bits) starting at address `addr`.
     fn write_multiple_coils(&mut self, addr: u16, values: &[Coil]) -> Result<()> {
         let bytes = binary::pack_bits(values);
-        self.write_multiple(&Function::WriteMultipleCoils(
-            addr,
-            values.len() as u16,
-            &bytes,
-        ))
+        let req = Function::WriteMultipleCoils(addr, values.len() as u16, &bytes).request()?;
+        self.transaction(|t| t.write(&req))?;
+        self.verify_coils(addr, values)
     }
 
     /// Write a multiple 16bit registers starting at address `addr`.
     fn write_multiple_registers(&mut self, addr: u16, values: &[u16]) -> Result<()> {
         let bytes = binary::unpack_bytes(values);
-        self.write_multiple(&Function::WriteMultipleRegisters(
-            addr,
-            values.len() as u16,
-            &bytes,
-        ))
+        let req = Function::WriteMultipleRegisters(addr, values.len() as u16, &bytes).request()?;
+        self.transaction(|t| t.write(&req))?;
+        self.verify_registers(addr, values)
+    }
+
+    /// Read the eight exception status outputs.
+    fn read_exception_status(&mut self) -> Result<u8> {
+        let req = Function::ReadExceptionStatus.request()?;
+        self.transaction(|t| pdu::parse_exception_status(&t.transact(&req)?))
+    }
+
+    /// Run the diagnostics sub-function `sub` with `data`.
+    fn diagnostics(&mut self, sub: DiagnosticsSubFunction, data: &[u16]) -> Result<Vec<u16>> {
+        let req = Function::Diagnostics(sub, data).request()?;
+        self.transaction(|t| {
+            if sub == DiagnosticsSubFunction::ForceListenOnlyMode {
+                t.send(&req)?;
+                return Ok(vec![]);
+            }
+            pdu::parse_diagnostics(sub, &t.transact(&req)?)
+        })
+    }
+
+    /// Read the identification of the device.
+    fn report_slave_id(&mut self) -> Result<SlaveId> {
+        let req = Function::ReportSlaveId.request()?;
+        self.transaction(|t| pdu::parse_slave_id(&t.transact(&req)?))
+    }
+
+    /// Modify the holding register at `addr` with `and_mask` and `or_mask`.
+    fn mask_write_register(&mut self, addr: u16, and_mask: u16, or_mask: u16) -> Result<()> {
+        let req = Function::MaskWriteRegister(addr, and_mask, or_mask).request()?;
+        self.transaction(|t| t.write(&req))
     }
 
     /// Write a multiple 16bit registers starting at address `write_addr` and read starting at address `read_addr`.
@@ -454,27 +1649,757 @@ impl Client for Transport {
         read_quantity: u16,
     ) -> Result<Vec<u16>> {
         let write_bytes = binary::unpack_bytes(write_values);
-        let read_bytes = self.write_read_multiple(&Function::WriteReadMultipleRegisters(
+        let req = Function::WriteReadMultipleRegisters(
             write_address,
             write_quantity,
             &write_bytes,
             read_address,
             read_quantity,
-        ))?;
-        binary::pack_bytes(&read_bytes[..])
+        )
+        .request()?;
+        self.transaction(|t| pdu::parse_registers(&t.transact(&req)?, read_quantity))
+    }
+
+    /// Read the values of the FIFO queue at `addr`.
+    fn read_fifo_queue(&mut self, addr: u16) -> Result<Vec<u16>> {
+        let req = Function::ReadFifoQueue(addr).request()?;
+        self.transaction(|t| pdu::parse_fifo_queue(&t.transact(&req)?))
+    }
+
+    /// Read the file records given as `(file_number, record_number, record_length)`.
+    fn read_file_records(&mut self, records: &[(u16, u16, u16)]) -> Result<Vec<FileRecord>> {
+        let req = Function::ReadFileRecord(records).request()?;
+        self.transaction(|t| pdu::parse_file_records(records, &t.transact(&req)?))
+    }
+
+    /// Write the file records `records`.
+    fn write_file_records(&mut self, records: &[FileRecord]) -> Result<()> {
+        let req = Function::WriteFileRecord(records).request()?;
+        self.transaction(|t| t.write(&req))
     }
 
     /// Set the unit identifier.
     fn set_uid(&mut self, uid: u8) {
         self.uid = uid;
     }
+
+    #[cfg(feature = "read-device-info")]
+    /// Read the device identification objects of `obj_category` (function 43 / MEI type 14).
+    fn read_device_info(
+        &mut self,
+        obj_category: mei::DeviceInfoCategory,
+    ) -> Result<Vec<mei::DeviceInfoObject>> {
+        let req = Function::ReadDeviceInfo(obj_category).request()?;
+        self.transaction(|t| pdu::parse_device_info(&t.transact(&req)?))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Read;
     use std::net::{TcpListener, TcpStream};
+    use std::sync::Mutex;
     use std::thread;
+
+    /// Start a server in a thread which answers every request frame with `respond(request)` and
+    /// return a transport connected to it.
+    fn mock_server<F>(respond: F) -> Transport
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            loop {
+                let mut req = vec![0; MODBUS_HEADER_SIZE];
+                if stream.read_exact(&mut req).is_err() {
+                    break;
+                }
+                let len = u16::from_be_bytes([req[4], req[5]]) as usize;
+                req.resize(MODBUS_HEADER_SIZE + len - 1, 0);
+                if stream.read_exact(&mut req[MODBUS_HEADER_SIZE..]).is_err() {
+                    break;
+                }
+                if stream.write_all(&respond(&req)).is_err() {
+                    break;
+                }
+            }
+        });
+        Transport::new_with_cfg("127.0.0.1", cfg).unwrap()
+    }
+
+    /// Build a response frame for `req` with the PDU `pdu`.
+    fn reply(req: &[u8], pdu: &[u8]) -> Vec<u8> {
+        let mut frame = req[..4].to_vec();
+        frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+        frame.push(req[6]);
+        frame.extend_from_slice(pdu);
+        frame
+    }
+
+    #[test]
+    fn read_holding_registers() {
+        let mut transport = mock_server(|req| {
+            assert_eq!(
+                &req[2..],
+                &[0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x10, 0x00, 0x02]
+            );
+            reply(req, &[0x03, 0x04, 0xbe, 0xef, 0x00, 0x01])
+        });
+        assert_eq!(
+            transport.read_holding_registers(0x10, 2).unwrap(),
+            vec![0xbeef, 0x0001]
+        );
+    }
+
+    #[test]
+    fn throttled_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_nodelay(true).unwrap();
+            for _ in 0..2 {
+                let mut req = [0; 12];
+                stream.read_exact(&mut req).unwrap();
+                let mut pdu = vec![0x03, 250];
+                pdu.extend((0..250).map(|i| i as u8));
+                // every byte in its own segment, with pauses in between
+                for b in reply(&req, &pdu) {
+                    stream.write_all(&[b]).unwrap();
+                    thread::sleep(Duration::from_micros(200));
+                }
+            }
+        });
+        let mut transport = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        for _ in 0..2 {
+            let values = transport.read_holding_registers(0, 125).unwrap();
+            assert_eq!(values.len(), 125);
+            assert_eq!(values[124], 0xf8f9);
+        }
+    }
+
+    #[test]
+    fn stale_responses() {
+        let mut transport = mock_server(|req| {
+            // two late answers with older transaction ids before the actual response
+            let mut frames = vec![];
+            for tid in [req[1].wrapping_sub(2), req[1].wrapping_sub(1), req[1]] {
+                let mut stale = req.to_vec();
+                stale[1] = tid;
+                frames.extend(reply(&stale, &[0x03, 0x02, 0x00, tid]));
+            }
+            frames
+        });
+        transport.cfg.max_stale_responses = 2;
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![1]);
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![2]);
+        transport.cfg.max_stale_responses = 1;
+        match transport
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn frame_observer() {
+        let mut transport = mock_server(|req| reply(req, &[0x03, 0x04, 0xbe, 0xef, 0x00, 0x01]));
+        let events = Arc::new(Mutex::new(vec![]));
+        let captured = events.clone();
+        transport.set_frame_observer(move |e| {
+            captured
+                .lock()
+                .unwrap()
+                .push((e.direction, e.frame.to_vec()))
+        });
+        transport.read_holding_registers(0x10, 2).unwrap();
+        transport.clear_frame_observer();
+        transport.read_holding_registers(0x10, 2).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (
+                    Direction::Sent,
+                    vec![0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x10, 0x00, 0x02]
+                ),
+                (
+                    Direction::Received,
+                    vec![
+                        0x00, 0x01, 0x00, 0x00, 0x00, 0x07, 0x01, 0x03, 0x04, 0xbe, 0xef, 0x00,
+                        0x01
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn quirks() {
+        let mut transport = mock_server(|req| {
+            let mut resp = req.to_vec();
+            // transaction id 0, protocol id 1 and a byte count one too high
+            resp[..4].copy_from_slice(&[0x00, 0x00, 0x00, 0x01]);
+            reply(&resp, &[0x03, 0x05, 0x00, 0x01, 0x00, 0x02])
+        });
+        match transport
+            .read_holding_registers(0, 2)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        transport.cfg.quirks = Quirks {
+            byte_count_off_by_one: false,
+            ..Quirks::LENIENT
+        };
+        match transport
+            .read_holding_registers(0, 2)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::UnexpectedReplySize)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        transport.cfg.quirks = Quirks::profile("lenient").unwrap();
+        assert_eq!(transport.read_holding_registers(0, 2).unwrap(), vec![1, 2]);
+        assert!(Quirks::PROFILES
+            .iter()
+            .all(|p| Quirks::profile(p).is_some()));
+        assert_eq!(Quirks::profile("unknown"), None);
+    }
+
+    #[test]
+    fn custom_function() {
+        let mut transport = mock_server(|req| match req[7] {
+            0x41 => {
+                assert_eq!(&req[8..], &[0x01, 0x02]);
+                reply(req, &[0x41, 0xca, 0xfe])
+            }
+            code => reply(req, &[code | 0x80, 0x01]),
+        });
+        assert_eq!(
+            transport.custom_function(0x41, &[0x01, 0x02]).unwrap(),
+            vec![0xca, 0xfe]
+        );
+        match transport
+            .custom_function(0x64, &[])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalFunction)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(transport.custom_function(0xc1, &[]).is_err());
+    }
+
+    #[test]
+    fn resync() {
+        let mut transport = mock_server(|req| {
+            let mut frame = reply(req, &[0x03, 0x02, 0x00, req[1]]);
+            if req[1] == 1 {
+                // a stray response confusing the next transaction
+                frame.extend_from_slice(&reply(
+                    &[0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0x01],
+                    &[0x03, 0x02, 0x00, 0x07],
+                ));
+            }
+            frame
+        });
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![1]);
+        thread::sleep(Duration::from_millis(50));
+        assert!(transport.read_holding_registers(0, 1).is_err());
+        // the response to the second request is discarded, the third skips the gap of 5
+        assert_eq!(
+            transport
+                .resync(5, |t| t.read_holding_registers(0, 1))
+                .unwrap(),
+            vec![8]
+        );
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![9]);
+    }
+
+    #[test]
+    fn response_timeout() {
+        let mut transport = mock_server(|req| {
+            thread::sleep(Duration::from_millis(300));
+            reply(req, &[0x03, 0x02, 0x00, 0x01])
+        });
+        transport.cfg.tcp_read_timeout = Some(Duration::from_millis(100));
+        transport
+            .stream
+            .set_read_timeout(transport.cfg.tcp_read_timeout)
+            .unwrap();
+        assert_eq!(
+            transport
+                .with_response_timeout(Duration::from_secs(5), |t| t.read_holding_registers(0, 1))
+                .unwrap(),
+            vec![1]
+        );
+        match transport
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Timeout) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // the request of the failed read is kept
+        match transport
+            .read_holding_registers(3, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Timeout) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(
+            transport.last_error_context(),
+            Some(ErrorContext {
+                function: 0x03,
+                address: Some(3),
+                uid: 1,
+                transaction_id: Some(3),
+            })
+        );
+    }
+
+    #[cfg(feature = "debug-frames")]
+    #[test]
+    fn errors_with_frame_dumps() {
+        let mut transport = mock_server(|req| reply(req, &[0x03, 0x04, 0x00, 0x01]));
+        let err = transport.read_holding_registers(0x10, 2).unwrap_err();
+        assert!(matches!(
+            err.root(),
+            Error::InvalidData(Reason::UnexpectedReplySize)
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid data: UnexpectedReplySize \
+             (request: tid=1 pid=0 len=6 uid=1 function=0x03 \
+             [00 01 00 00 00 06 01 03 00 10 00 02], response: tid=1 pid=0 len=5 uid=1 \
+             function=0x03 [00 01 00 00 00 05 01 03 04 00 01])"
+        );
+    }
+
+    #[test]
+    fn read_coil_range() {
+        // every third coil is set
+        let mut transport = mock_server(|req| {
+            let addr = u16::from_be_bytes([req[8], req[9]]);
+            let count = u16::from_be_bytes([req[10], req[11]]);
+            let coils: Vec<Coil> = (addr..addr + count).map(|a| (a % 3 == 0).into()).collect();
+            let bytes = binary::pack_bits(&coils);
+            let mut pdu = vec![0x01, bytes.len() as u8];
+            pdu.extend_from_slice(&bytes);
+            reply(req, &pdu)
+        });
+        assert_eq!(
+            transport.read_coil_range(3..13).unwrap(),
+            vec![0b0100_1001, 0b10]
+        );
+        assert_eq!(transport.read_coil_range(5..5).unwrap(), vec![]);
+
+        let bytes = transport.read_coil_range(1..4501).unwrap();
+        assert_eq!(bytes.len(), 563);
+        assert_eq!(
+            binary::unpack_bits(&bytes, 4500),
+            (1..4501u16)
+                .map(|a| (a % 3 == 0).into())
+                .collect::<Vec<Coil>>()
+        );
+    }
+
+    #[test]
+    fn response_length_from_header() {
+        let mut transport = mock_server(|req| match req[1] {
+            // one register less than requested
+            1 => reply(req, &[0x03, 0x02, 0x00, 0x01]),
+            // byte count disagrees with the header length
+            2 => reply(req, &[0x03, 0x06, 0x00, 0x01, 0x00, 0x02]),
+            _ => reply(req, &[0x03, 0x04, 0x00, 0x01, 0x00, 0x02]),
+        });
+        match transport
+            .read_holding_registers(0, 2)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::UnexpectedReplySize)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .read_holding_registers(0, 2)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::UnexpectedReplySize)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // the stream is still in sync after the invalid responses
+        assert_eq!(transport.read_holding_registers(0, 2).unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn exception_responses() {
+        let mut transport = mock_server(|req| match req[1] {
+            1 => reply(req, &[req[7] | 0x80, 0x02]),
+            // exception with trailing garbage
+            2 => reply(req, &[req[7] | 0x80, 0x02, 0x00]),
+            // unknown exception code
+            3 => reply(req, &[req[7] | 0x80, 0x42]),
+            // exception of a different function
+            4 => reply(req, &[0x81, 0x02]),
+            _ => reply(req, &[req[7] | 0x80, 0x06]),
+        });
+        match transport
+            .read_holding_registers(0, 125)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::IllegalDataAddress)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .read_holding_registers(0, 125)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::UnexpectedReplySize)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .read_input_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .write_multiple_registers(0, &[1, 2, 3])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::SlaveOrServerBusy)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rtu_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config::builder()
+            .port(listener.local_addr().unwrap().port())
+            .uid(0x11)
+            .read_timeout(Duration::from_secs(5))
+            .framing(Framing::RtuOverTcp)
+            .build()
+            .unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let responses: [&[u8]; 3] = [
+                // from the specification of Modbus over serial line
+                &[
+                    0x11, 0x03, 0x06, 0xae, 0x41, 0x56, 0x52, 0x43, 0x40, 0x49, 0xad,
+                ],
+                &[0x11, 0x83, 0x02, 0xc1, 0x34],
+                &[0x11, 0x83, 0x02, 0xc1, 0x35],
+            ];
+            for resp in responses {
+                let mut req = [0; 8];
+                stream.read_exact(&mut req).unwrap();
+                assert_eq!(req, [0x11, 0x03, 0x00, 0x6b, 0x00, 0x03, 0x76, 0x87]);
+                stream.write_all(resp).unwrap();
+            }
+        });
+        let mut transport = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        assert_eq!(
+            transport.read_holding_registers(0x6b, 3).unwrap(),
+            vec![0xae41, 0x5652, 0x4340]
+        );
+        let err = transport.read_holding_registers(0x6b, 3).unwrap_err();
+        assert!(matches!(
+            err.root(),
+            Error::Exception(ExceptionCode::IllegalDataAddress)
+        ));
+        assert_eq!(transport.last_error_context().unwrap().transaction_id, None);
+        match transport
+            .read_holding_registers(0x6b, 3)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::InvalidChecksum)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .custom_function(0x41, &[])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidFunction) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn busy_retry() {
+        // busy, then done; a write acknowledged and processing for one poll, then done; a read
+        // acknowledged and done
+        let replies = Mutex::new(vec![
+            vec![0x83, 0x06],
+            vec![0x03, 0x02, 0x00, 0x2a],
+            vec![0x86, 0x05],
+            vec![0x0b, 0xff, 0xff, 0x00, 0x01],
+            vec![0x0b, 0x00, 0x00, 0x00, 0x02],
+            vec![0x83, 0x05],
+            vec![0x0b, 0x00, 0x00, 0x00, 0x03],
+            vec![0x83, 0x06],
+            vec![0x83, 0x06],
+        ]);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = requests.clone();
+        let mut transport = mock_server(move |req| {
+            seen.lock().unwrap().push(req[7]);
+            reply(req, &replies.lock().unwrap().remove(0))
+        });
+        transport.cfg.busy_retry = Some(ReconnectPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        });
+        assert_eq!(transport.read_holding_registers(3, 1).unwrap(), vec![42]);
+        assert_eq!(*requests.lock().unwrap(), [0x03, 0x03]);
+        // the acknowledged write is not sent again
+        requests.lock().unwrap().clear();
+        assert!(transport.write_single_register(3, 7).is_ok());
+        assert_eq!(*requests.lock().unwrap(), [0x06, 0x0b, 0x0b]);
+        requests.lock().unwrap().clear();
+        match transport
+            .read_holding_registers(3, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::Exception(ExceptionCode::Acknowledge)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(*requests.lock().unwrap(), [0x03, 0x0b]);
+
+        transport.cfg.busy_retry.as_mut().unwrap().max_attempts = 1;
+        let err = transport.read_holding_registers(3, 1).unwrap_err();
+        assert!(matches!(
+            err.root(),
+            Error::Exception(ExceptionCode::SlaveOrServerBusy)
+        ));
+        assert_eq!(transport.last_error_context().unwrap().function, 0x03);
+    }
+
+    #[test]
+    fn ping() {
+        let mut transport = mock_server(|req| match req[7] {
+            0x08 => req.to_vec(),
+            0x03 => reply(req, &[0x83, 0x02]),
+            0x04 if req[9] == 1 => reply(req, &[0x84, 0x0b]),
+            0x04 if req[9] == 2 => reply(req, &[0x84, 0x0a]),
+            _ => reply(req, &[0x04, 0x02, 0xa5, 0x36]),
+        });
+        assert!(transport.ping().unwrap() < Duration::from_secs(5));
+        // an exception is an answer as well
+        transport.cfg.ping = Ping::ReadHoldingRegister(7);
+        assert!(transport.ping().is_ok());
+        transport.cfg.ping = Ping::ReadInputRegister(0);
+        assert!(transport.ping().is_ok());
+        // but not a gateway without a path to the device
+        transport.cfg.ping = Ping::ReadInputRegister(1);
+        match transport.ping().as_ref().map_err(Error::root) {
+            Err(Error::Exception(ExceptionCode::GatewayTarget)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        transport.cfg.ping = Ping::ReadInputRegister(2);
+        match transport.ping().as_ref().map_err(Error::root) {
+            Err(Error::Exception(ExceptionCode::GatewayPath)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // a wrong echo of the query data
+        let mut transport = mock_server(|req| reply(req, &[0x08, 0x00, 0x00, 0xa5, 0x36]));
+        match transport.ping().as_ref().map_err(Error::root) {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn diagnostics() {
+        let mut transport = mock_server(|req| match req[9] {
+            0x00 => {
+                assert_eq!(&req[7..], &[0x08, 0x00, 0x00, 0xa5, 0x37]);
+                req.to_vec()
+            }
+            // wrong sub-function in the response
+            _ => reply(req, &[0x08, 0x00, 0x00, 0x00, 0x01]),
+        });
+        assert_eq!(
+            transport
+                .diagnostics(DiagnosticsSubFunction::ReturnQueryData, &[0xa537])
+                .unwrap(),
+            vec![0xa537]
+        );
+        match transport
+            .diagnostics(DiagnosticsSubFunction::ReturnBusMessageCount, &[0])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn read_exception_status() {
+        let mut transport = mock_server(|req| {
+            assert_eq!(&req[2..], &[0x00, 0x00, 0x00, 0x02, 0x01, 0x07]);
+            match req[1] {
+                1 => reply(req, &[0x07, 0x6d]),
+                _ => reply(req, &[0x07, 0x6d, 0x00]),
+            }
+        });
+        assert_eq!(transport.read_exception_status().unwrap(), 0x6d);
+        match transport
+            .read_exception_status()
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidData(Reason::UnexpectedReplySize)) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn mask_write_register() {
+        let mut transport = mock_server(|req| {
+            assert_eq!(
+                &req[2..],
+                &[0x00, 0x00, 0x00, 0x08, 0x01, 0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x25]
+            );
+            match req[1] {
+                1 => req[..].to_vec(),
+                // the masks are not echoed correctly
+                _ => reply(req, &[0x16, 0x00, 0x04, 0x00, 0xf2, 0x00, 0x00]),
+            }
+        });
+        assert!(transport.mask_write_register(4, 0x00f2, 0x0025).is_ok());
+        match transport
+            .mask_write_register(4, 0x00f2, 0x0025)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::InvalidResponse) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn write_read_multiple_registers() {
+        let mut transport = mock_server(|req| {
+            assert_eq!(
+                &req[2..],
+                &[
+                    0x00, 0x00, 0x00, 0x0f, 0x01, 0x17, 0x00, 0x03, 0x00, 0x02, 0x00, 0x0e, 0x00,
+                    0x02, 0x04, 0x00, 0xff, 0x12, 0x34
+                ]
+            );
+            reply(req, &[0x17, 0x04, 0x00, 0xfe, 0x00, 0x01])
+        });
+        assert_eq!(
+            transport
+                .write_read_multiple_registers(0x0e, 2, &[0x00ff, 0x1234], 0x03, 2)
+                .unwrap(),
+            vec![0x00fe, 0x0001]
+        );
+        // a response with fewer registers than requested
+        assert!(transport
+            .write_read_multiple_registers(0x0e, 2, &[0x00ff, 0x1234], 0x03, 3)
+            .is_err());
+    }
+
+    #[test]
+    fn write_read_multiple_registers_quantities() {
+        let mut transport = mock_server(|_| panic!("invalid request sent"));
+        for (write_quantity, write_values, read_quantity) in [
+            (0, &[][..], 1),
+            (1, &[1][..], 0),
+            (1, &[1][..], 126),
+            (122, &[0; 122][..], 1),
+            (2, &[1][..], 1),
+        ] {
+            let result = transport.write_read_multiple_registers(
+                0,
+                write_quantity,
+                write_values,
+                0,
+                read_quantity,
+            );
+            match result.as_ref().map_err(Error::root) {
+                Err(Error::InvalidData(_)) => (),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+    }
+
+    #[cfg(feature = "read-device-info")]
+    #[test]
+    fn read_device_info() {
+        let mut transport = mock_server(|req| {
+            assert_eq!(&req[7..], &[0x2b, 0x0e, 0x01, 0x00]);
+            reply(
+                req,
+                &[
+                    0x2b, 0x0e, 0x01, 0x01, 0x00, 0x00, 0x02, 0x00, 0x03, b'A', b'c', b'm', 0x01,
+                    0x02, b'X', b'1',
+                ],
+            )
+        });
+        let info = transport
+            .read_device_info(mei::DeviceInfoCategory::Basic)
+            .unwrap();
+        assert_eq!(info.len(), 2);
+        assert_eq!((info[0].id(), info[0].to_string()), (0, "Acm".to_string()));
+        assert_eq!((info[1].id(), info[1].to_string()), (1, "X1".to_string()));
+
+        // an object exceeding the frame is rejected instead of panicking
+        let mut transport = mock_server(|req| {
+            reply(
+                req,
+                &[0x2b, 0x0e, 0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x09, b'A'],
+            )
+        });
+        assert!(transport
+            .read_device_info(mei::DeviceInfoCategory::Basic)
+            .is_err());
+    }
+
     #[test]
     fn serialize_header() {
         let header = Header {
@@ -484,15 +2409,552 @@ mod tests {
             uid: 68,
         };
         let serialized = header.pack().unwrap();
-        let deserialized = Header::unpack(&vec![50, 16, 15, 90, 0, 99, 68]).unwrap();
+        let deserialized = Header::unpack(&[50, 16, 15, 90, 0, 99, 68]).unwrap();
         let re_deserialized = Header::unpack(&serialized).unwrap();
         assert_eq!(serialized, vec![50, 16, 15, 90, 0, 99, 68]);
         assert_eq!(deserialized, header);
         assert_eq!(re_deserialized, header);
     }
+    #[test]
+    fn config_from_url() {
+        let (host, cfg) = Config::from_url("tcp://10.0.0.5").unwrap();
+        assert_eq!(host, "10.0.0.5");
+        assert_eq!(cfg.tcp_port, 502);
+        assert_eq!(cfg.modbus_uid, 1);
+        assert_eq!(cfg.tcp_read_timeout, None);
+
+        let (host, cfg) = Config::from_url("tcp://plc.local:1502/?uid=3&timeout=500ms").unwrap();
+        assert_eq!(host, "plc.local");
+        assert_eq!(cfg.tcp_port, 1502);
+        assert_eq!(cfg.modbus_uid, 3);
+        assert_eq!(cfg.tcp_read_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(cfg.tcp_write_timeout, Some(Duration::from_millis(500)));
+
+        let (host, cfg) = Config::from_url(
+            "tcp://[::1]:503?connect_timeout=2s&read_timeout=1.5s&write_timeout=20",
+        )
+        .unwrap();
+        assert_eq!(host, "::1");
+        assert_eq!(cfg.tcp_port, 503);
+        assert_eq!(cfg.tcp_connect_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(cfg.tcp_read_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(cfg.tcp_write_timeout, Some(Duration::from_millis(20)));
+
+        assert_eq!(Config::from_url("tcp://[fe80::1]").unwrap().0, "fe80::1");
+
+        assert!(Config::from_url("10.0.0.5:502").is_err());
+        assert!(Config::from_url("rtu:///dev/ttyUSB0?baud=19200&uid=7").is_err());
+        assert!(Config::from_url("tcp://:502").is_err());
+        assert!(Config::from_url("tcp://10.0.0.5:port").is_err());
+        assert!(Config::from_url("tcp://[::1:502").is_err());
+        assert!(Config::from_url("tcp://10.0.0.5?uid=256").is_err());
+        assert!(Config::from_url("tcp://10.0.0.5?timeout=soon").is_err());
+        assert!(Config::from_url("tcp://10.0.0.5?baud=19200").is_err());
+    }
+
+    #[test]
+    fn config_builder() {
+        let cfg = Config::builder()
+            .port(1502)
+            .uid(3)
+            .timeout(Duration::from_millis(500))
+            .auto_reconnect(ReconnectPolicy::default())
+            .build()
+            .unwrap();
+        assert_eq!(cfg.tcp_port, 1502);
+        assert_eq!(cfg.modbus_uid, 3);
+        assert_eq!(cfg.tcp_connect_timeout, None);
+        assert_eq!(cfg.tcp_read_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(cfg.tcp_write_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(cfg.auto_reconnect, Some(ReconnectPolicy::default()));
+
+        assert!(Config::builder().port(0).build().is_err());
+        assert!(Config::builder()
+            .read_timeout(Duration::ZERO)
+            .build()
+            .is_err());
+        let policy = ReconnectPolicy {
+            max_attempts: 0,
+            ..Default::default()
+        };
+        assert!(Config::builder().auto_reconnect(policy).build().is_err());
+    }
+
+    #[cfg(feature = "socket2")]
+    #[test]
+    fn socket_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            socket_options: SocketOptions {
+                reuse_address: true,
+                recv_buffer_size: Some(32768),
+                tcp_user_timeout: Some(Duration::from_secs(3)),
+                dscp: Some(46),
+                ..Default::default()
+            },
+            tcp_keepalive: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let transport = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        let socket = socket2::SockRef::from(transport.stream.tcp().unwrap());
+        assert!(socket.keepalive().unwrap());
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert!(socket.reuse_address().unwrap());
+        assert!(socket.recv_buffer_size().unwrap() >= 32768);
+        assert_eq!(
+            socket.tcp_user_timeout().unwrap(),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(socket.tos().unwrap(), 46 << 2);
+        assert!(socket.nodelay().unwrap());
+    }
+
+    #[test]
+    fn connection_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let server = thread::spawn(move || {
+            // read the request and close the connection without answering
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = [0; MODBUS_HEADER_SIZE + 5];
+            stream.read_exact(&mut req).unwrap();
+        });
+        let mut transport = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        match transport
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::ConnectionClosed) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn write_echo() {
+        // echo the address of the request, but always a quantity of 1
+        let mut transport = mock_server(|req| {
+            let fun = req[7];
+            reply(req, &[fun, req[8], req[9], 0x00, 0x01])
+        });
+        assert!(transport.write_multiple_registers(4, &[1]).is_ok());
+        for res in [
+            transport.write_multiple_registers(4, &[1, 2]),
+            transport.write_multiple_coils(4, &[Coil::On, Coil::Off]),
+            transport.write_single_register(4, 2),
+        ] {
+            match res.as_ref().map_err(Error::root) {
+                Err(Error::InvalidResponse) => (),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+
+        transport.cfg.lenient_write_echo = true;
+        assert!(transport.write_multiple_registers(4, &[1, 2]).is_ok());
+        assert!(transport
+            .write_multiple_coils(4, &[Coil::On, Coil::Off])
+            .is_ok());
+        assert!(transport.write_single_register(4, 2).is_err());
+    }
+
+    #[test]
+    fn verify_writes() {
+        // acknowledge all writes, but only apply them to the first register and coil
+        let mut transport = mock_server(|req| match req[7] {
+            0x05 | 0x06 | 0x0f | 0x10 => reply(req, &req[7..12]),
+            0x01 => reply(req, &[0x01, 0x01, 0x01]),
+            0x03 => {
+                let count = req[11] as usize;
+                reply(
+                    req,
+                    &[0x03, 2 * count as u8, 0x00, 0x07, 0x00, 0x00][..2 + 2 * count],
+                )
+            }
+            _ => panic!("unexpected request {:?}", req),
+        });
+        transport.cfg.verify_writes = true;
+        assert!(transport.write_single_register(0, 7).is_ok());
+        assert!(transport.write_single_coil(0, Coil::On).is_ok());
+        assert!(transport.write_multiple_registers(0, &[7, 0]).is_ok());
+        match transport
+            .write_multiple_registers(0, &[7, 8])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(&Error::VerificationFailed {
+                addr: 1,
+                expected: 8,
+                actual: 0,
+            }) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match transport
+            .write_multiple_coils(0, &[Coil::On, Coil::On])
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(&Error::VerificationFailed {
+                addr: 1,
+                expected: 1,
+                actual: 0,
+            }) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn tls() {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+        let cert = |pem: &[u8]| vec![CertificateDer::from_pem_slice(pem).unwrap()];
+        let key = |pem: &[u8]| PrivateKeyDer::from_pem_slice(pem).unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_parsable_certificates(cert(include_bytes!("../tests/certs/ca.pem")));
+        let roots = Arc::new(roots);
+        let verifier = rustls::server::WebPkiClientVerifier::builder(roots.clone())
+            .build()
+            .unwrap();
+        let server_tls = rustls::ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                cert(include_bytes!("../tests/certs/server.pem")),
+                key(include_bytes!("../tests/certs/server.key")),
+            )
+            .unwrap();
+        let client_tls = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(
+                cert(include_bytes!("../tests/certs/client.pem")),
+                key(include_bytes!("../tests/certs/client.key")),
+            )
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let server = thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(Arc::new(server_tls)).unwrap();
+            let mut stream = rustls::StreamOwned::new(conn, tcp);
+            let mut req = [0; MODBUS_HEADER_SIZE + 5];
+            stream.read_exact(&mut req).unwrap();
+            assert!(stream.conn.peer_certificates().is_some());
+            stream
+                .write_all(&reply(&req, &[0x03, 0x02, 0x12, 0x34]))
+                .unwrap();
+            stream.flush().unwrap();
+        });
+        let mut transport =
+            Transport::new_tls_with_cfg("127.0.0.1", cfg, Arc::new(client_tls)).unwrap();
+        assert_eq!(
+            transport.read_holding_registers(0, 1).unwrap(),
+            vec![0x1234]
+        );
+        assert!(transport.try_clone().is_err());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn auto_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_secs(5)),
+            auto_reconnect: Some(ReconnectPolicy {
+                max_attempts: 2,
+                backoff: Duration::from_millis(10),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let server = thread::spawn(move || {
+            // close the first two connections without answering, answer on the third one
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut req = [0; MODBUS_HEADER_SIZE + 5];
+                stream.read_exact(&mut req).unwrap();
+            }
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = [0; MODBUS_HEADER_SIZE + 5];
+            stream.read_exact(&mut req).unwrap();
+            stream
+                .write_all(&reply(&req, &[0x03, 0x02, 0x12, 0x34]))
+                .unwrap();
+            drop(stream);
+            // the next request fails after both retries on new connections
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut req = [0; MODBUS_HEADER_SIZE + 5];
+                stream.read_exact(&mut req).unwrap();
+            }
+        });
+        let mut transport = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        assert_eq!(
+            transport.read_holding_registers(0, 1).unwrap(),
+            vec![0x1234]
+        );
+        match transport
+            .read_holding_registers(0, 1)
+            .as_ref()
+            .map_err(Error::root)
+        {
+            Err(Error::ConnectionClosed) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn response_timeout_after_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let cfg = Config {
+            tcp_port: listener.local_addr().unwrap().port(),
+            tcp_read_timeout: Some(Duration::from_millis(100)),
+            auto_reconnect: Some(ReconnectPolicy {
+                max_attempts: 1,
+                backoff: Duration::from_millis(10),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let server = thread::spawn(move || {
+            // close the first connection without answering, answer slowly on the second one
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut req = [0; MODBUS_HEADER_SIZE + 5];
+            stream.read_exact(&mut req).unwrap();
+            drop(stream);
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_exact(&mut req).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            stream
+                .write_all(&reply(&req, &[0x03, 0x02, 0x12, 0x34]))
+                .unwrap();
+            stream
+        });
+        let mut transport = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        assert_eq!(
+            transport
+                .with_response_timeout(Duration::from_secs(5), |t| t.read_holding_registers(0, 1))
+                .unwrap(),
+            vec![0x1234]
+        );
+        assert_eq!(
+            transport.cfg.tcp_read_timeout,
+            Some(Duration::from_millis(100))
+        );
+        let _stream = server.join().unwrap();
+    }
+
+    #[test]
+    fn dual_stack() {
+        let addrs = [
+            "10.0.0.1:502",
+            "10.0.0.2:502",
+            "[fe80::1]:502",
+            "[fe80::2]:502",
+        ];
+        let ordered = interleave(addrs.iter().map(|a| a.parse().unwrap()));
+        let expected: Vec<SocketAddr> = [
+            "[fe80::1]:502",
+            "10.0.0.1:502",
+            "[fe80::2]:502",
+            "10.0.0.2:502",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        assert_eq!(ordered, expected);
+
+        let serve = |listener: TcpListener| {
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut req = [0; 12];
+                    if stream.read_exact(&mut req).is_ok() {
+                        let _ = stream.write_all(&reply(&req, &[0x03, 0x02, 0x00, 0x06]));
+                    }
+                }
+            })
+        };
+        let cfg = Config::builder()
+            .read_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        // host names may resolve to IPv6 and IPv4 addresses, one of which accepts
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        serve(listener);
+        let mut transport = Transport::connect(("localhost", port), cfg).unwrap();
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![6]);
+        assert_eq!(
+            transport.peer_addr().unwrap(),
+            ([127, 0, 0, 1], port).into()
+        );
+
+        // hosts without IPv6 loopback can't run the rest
+        let Ok(listener) = TcpListener::bind("[::1]:0") else {
+            return;
+        };
+        let v6 = listener.local_addr().unwrap();
+        serve(listener);
+        let v4: SocketAddr = ([127, 0, 0, 1], v6.port()).into();
+        let mut transport = Transport::connect(&[v4, v6][..], cfg).unwrap();
+        assert_eq!(transport.peer_addr().unwrap(), v6);
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![6]);
+        let cfg = Config::builder().port(v6.port()).build().unwrap();
+        assert_eq!(
+            Transport::new_with_cfg("::1", cfg)
+                .unwrap()
+                .peer_addr()
+                .unwrap(),
+            v6
+        );
+    }
+
+    #[test]
+    fn connection_limit() {
+        // a device serving a single connection, which closes further connections right away
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut first, _) = listener.accept().unwrap();
+            let mut req = [0; 12];
+            first.read_exact(&mut req).unwrap();
+            first
+                .write_all(&reply(&req, &[0x03, 0x02, 0x00, 0x01]))
+                .unwrap();
+            // the connection of the second client and its first retry
+            listener.accept().unwrap();
+            listener.accept().unwrap();
+            // the first client disconnects, the slot is free again
+            drop(first);
+            let (mut retried, _) = listener.accept().unwrap();
+            retried.read_exact(&mut req).unwrap();
+            retried
+                .write_all(&reply(&req, &[0x03, 0x02, 0x00, 0x03]))
+                .unwrap();
+        });
+        let cfg = Config::builder()
+            .port(port)
+            .read_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+        let mut first = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        assert_eq!(first.read_holding_registers(0, 1).unwrap(), vec![1]);
+
+        // a single retry, which is closed too
+        let cfg = Config::builder()
+            .port(port)
+            .read_timeout(Duration::from_secs(5))
+            .connection_limit_retry(ReconnectPolicy {
+                max_attempts: 1,
+                backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            })
+            .build()
+            .unwrap();
+        let mut second = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        let err = second.read_holding_registers(0, 1).unwrap_err();
+        match *err.root() {
+            Error::ConnectionLimit(ref cause) => assert!(is_broken_connection(cause)),
+            ref e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(err.code(), crate::ErrorCode::CONNECTION_LIMIT);
+
+        // retried until the slot is free
+        let cfg = Config::builder()
+            .port(port)
+            .read_timeout(Duration::from_secs(5))
+            .connection_limit_retry(ReconnectPolicy {
+                max_attempts: 5,
+                backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(100),
+            })
+            .build()
+            .unwrap();
+        second = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        assert_eq!(second.read_holding_registers(0, 1).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn connect_to_resolved_addresses() {
+        // nothing listens on the port of a dropped listener
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(3) {
+                let mut stream = stream.unwrap();
+                let mut req = [0; 12];
+                stream.read_exact(&mut req).unwrap();
+                stream
+                    .write_all(&reply(&req, &[0x03, 0x02, 0x00, 0x2a]))
+                    .unwrap();
+            }
+        });
+        let cfg = Config::builder()
+            .connect_timeout(Duration::from_secs(1))
+            .read_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        let mut transport = Transport::connect(&[closed, open][..], cfg).unwrap();
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![42]);
+        transport.reconnect().unwrap();
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![42]);
+
+        let stream = TcpStream::connect(open).unwrap();
+        let mut transport = Transport::from_stream(stream, cfg).unwrap();
+        assert_eq!(transport.read_holding_registers(0, 1).unwrap(), vec![42]);
+
+        assert!(Transport::connect(closed, cfg).is_err());
+    }
+
+    #[test]
+    fn cloned_transaction_ids() {
+        let mut transport = mock_server(|req| reply(req, &[0x06, 0x00, 0x00, 0x00, 0x00]));
+        let mut clone = transport.try_clone().unwrap();
+        for _ in 0..3 {
+            assert!(transport.write_single_register(0, 0).is_ok());
+            assert!(clone.write_single_register(0, 0).is_ok());
+        }
+        assert_eq!(transport.new_tid(), 7);
+
+        // concurrent clones never hand out the same transaction id
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let clone = transport.try_clone().unwrap();
+                thread::spawn(move || (0..1000).map(|_| clone.new_tid()).collect::<Vec<_>>())
+            })
+            .collect();
+        let mut tids: Vec<u16> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        tids.sort_unstable();
+        tids.dedup();
+        assert_eq!(tids.len(), 4000);
+    }
+
     #[test]
     fn try_clone() {
-        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::atomic::AtomicBool;
 
         static STARTED: AtomicBool = AtomicBool::new(false);
         static CLOSED: AtomicBool = AtomicBool::new(false);
@@ -502,33 +2964,45 @@ mod tests {
             STARTED.store(true, Ordering::Relaxed);
             listener
                 .accept()
-                .and_then(|_| {
-                    while !CLOSED.load(Ordering::Relaxed) {}
-                    Ok(())
+                .map(|_| {
+                    while !CLOSED.load(Ordering::Relaxed) {
+                        std::hint::spin_loop()
+                    }
                 })
                 .unwrap();
         });
 
-        while !STARTED.load(Ordering::Relaxed) {}
+        while !STARTED.load(Ordering::Relaxed) {
+            std::hint::spin_loop()
+        }
 
         let new_stream = TcpStream::connect("localhost:34254").unwrap();
         let mut transport = Transport {
-            tid: 1,
+            tid: Arc::new(AtomicU16::new(1)),
             uid: 2,
-            stream: new_stream,
+            stream: Stream::Tcp(new_stream),
+            addr: "localhost".to_string(),
+            cfg: Config::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            observer: None,
+            answered: false,
+            context: None,
+            #[cfg(feature = "debug-frames")]
+            frames: Default::default(),
         };
 
         match transport.try_clone() {
             Ok(mut cl) => {
-                assert_eq!(cl.tid, transport.tid);
+                assert!(Arc::ptr_eq(&cl.tid, &transport.tid));
                 assert_eq!(cl.uid, transport.uid);
                 assert_eq!(
-                    cl.stream.local_addr().unwrap(),
-                    transport.stream.local_addr().unwrap()
+                    cl.stream.tcp().unwrap().local_addr().unwrap(),
+                    transport.stream.tcp().unwrap().local_addr().unwrap()
                 );
                 assert_eq!(
-                    cl.stream.peer_addr().unwrap(),
-                    transport.stream.peer_addr().unwrap()
+                    cl.stream.tcp().unwrap().peer_addr().unwrap(),
+                    transport.stream.tcp().unwrap().peer_addr().unwrap()
                 );
                 cl.close().expect("unable to close TcpStream clone");
                 assert!(transport.stream.write(b"data").is_err());
@@ -539,4 +3013,44 @@ mod tests {
         CLOSED.store(true, Ordering::Relaxed);
         jh.join().unwrap();
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let path = std::env::temp_dir().join(format!("modbus-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            // the second connection is made by `reconnect`
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut req = [0; 12];
+                stream.read_exact(&mut req).unwrap();
+                stream
+                    .write_all(&reply(&req, &[0x03, 0x02, 0x12, 0x34]))
+                    .unwrap();
+            }
+        });
+
+        let cfg = Config {
+            tcp_read_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let mut transport = Transport::new_unix_with_cfg(path.to_str().unwrap(), cfg).unwrap();
+        assert_eq!(
+            transport.read_holding_registers(0, 1).unwrap(),
+            vec![0x1234]
+        );
+        transport.reconnect().unwrap();
+        assert_eq!(
+            transport.read_holding_registers(0, 1).unwrap(),
+            vec![0x1234]
+        );
+        assert!(transport.try_clone().is_ok());
+        transport.close().unwrap();
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,313 @@
+//! Enforce timeouts on the requests of any client
+//!
+//! A `Watchdog` runs the requests of a client on a worker thread and fails them with
+//! `Error::Timeout` if they don't complete before their deadline, independent of the
+//! timeouts the transport itself supports. The same policy therefore works for TCP, ASCII or
+//! mock clients.
+//!
+//! A timed out request keeps running on the worker until the transport gives up, its result is
+//! discarded. Afterwards the `on_timeout` handler is run on the client, e.g. to resynchronize
+//! the connection, before the next request is executed. Requests issued while the worker is
+//! still busy wait for it within their own deadline. Requests whose deadline passed while they
+//! were waiting are never sent, so writes which already failed aren't replayed once a stalled
+//! device recovers.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use modbus::watchdog::Watchdog;
+//! use modbus::{Client, Transport};
+//! use std::time::{Duration, Instant};
+//!
+//! let client = Transport::new("192.168.0.10").unwrap();
+//! let mut watchdog = Watchdog::new(client, Duration::from_millis(500));
+//! watchdog.on_timeout(|t: &mut Transport| drop(t.resync(16, |t| t.read_coils(0, 1))));
+//! let values = watchdog.read_holding_registers(0, 10);
+//!
+//! // several requests which have to complete within one second altogether
+//! let deadline = Instant::now() + Duration::from_secs(1);
+//! let values = watchdog.run_until(deadline, |t| {
+//!     let a = t.read_holding_registers(0, 10)?;
+//!     let b = t.read_input_registers(0, 10)?;
+//!     Ok((a, b))
+//! });
+//! ```
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{Client, Coil, DiagnosticsSubFunction, Error, FileRecord, Result, SlaveId};
+
+#[cfg(feature = "read-device-info")]
+use crate::mei;
+
+type Job<C> = Box<dyn FnOnce(&mut C) + Send>;
+type Handler<C> = Arc<Mutex<Option<Box<dyn FnMut(&mut C) + Send>>>>;
+
+/// A client whose requests fail with `Error::Timeout` after a timeout.
+pub struct Watchdog<C> {
+    jobs: Sender<Job<C>>,
+    timeout: Duration,
+    on_timeout: Handler<C>,
+}
+
+impl<C: Client + Send + 'static> Watchdog<C> {
+    /// Run the requests to `client` on a worker thread, each with the given `timeout`.
+    pub fn new(mut client: C, timeout: Duration) -> Watchdog<C> {
+        let (jobs, queue) = mpsc::channel::<Job<C>>();
+        thread::spawn(move || {
+            for job in queue {
+                job(&mut client);
+            }
+        });
+        Watchdog {
+            jobs,
+            timeout,
+            on_timeout: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Set the timeout of the following requests.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Run `handler` on the client after a timed out request completed, before the next
+    /// request.
+    pub fn on_timeout(&mut self, handler: impl FnMut(&mut C) + Send + 'static) {
+        *self.on_timeout.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Run `f` on the client, failing with `Error::Timeout` if it doesn't complete before
+    /// `deadline`. The client is exclusively used by `f`, so several requests can be combined.
+    pub fn run_until<T, F>(&mut self, deadline: Instant, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut C) -> Result<T> + Send + 'static,
+    {
+        let (done, result) = mpsc::channel();
+        let job: Job<C> = Box::new(move |client| {
+            let result = if Instant::now() >= deadline {
+                Err(Error::Timeout)
+            } else {
+                f(client)
+            };
+            let _ = done.send(result);
+        });
+        if self.jobs.send(job).is_err() {
+            return Err(worker_died());
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match result.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => {
+                let handler = self.on_timeout.clone();
+                let _ = self.jobs.send(Box::new(move |client| {
+                    if let Some(ref mut handler) = *handler.lock().unwrap() {
+                        handler(client);
+                    }
+                }));
+                Err(Error::Timeout)
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(worker_died()),
+        }
+    }
+
+    // Run `f` with the configured timeout.
+    fn run<T, F>(&mut self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut C) -> Result<T> + Send + 'static,
+    {
+        let deadline = Instant::now() + self.timeout;
+        self.run_until(deadline, f)
+    }
+}
+
+fn worker_died() -> Error {
+    Error::Io(io::Error::other(
+        "the client panicked in an earlier request",
+    ))
+}
+
+impl<C: Client + Send + 'static> Client for Watchdog<C> {
+    fn read_discrete_inputs(&mut self, address: u16, quantity: u16) -> Result<Vec<Coil>> {
+        self.run(move |c| c.read_discrete_inputs(address, quantity))
+    }
+
+    fn read_coils(&mut self, address: u16, quantity: u16) -> Result<Vec<Coil>> {
+        self.run(move |c| c.read_coils(address, quantity))
+    }
+
+    fn write_single_coil(&mut self, address: u16, value: Coil) -> Result<()> {
+        self.run(move |c| c.write_single_coil(address, value))
+    }
+
+    fn write_multiple_coils(&mut self, address: u16, coils: &[Coil]) -> Result<()> {
+        let coils = coils.to_vec();
+        self.run(move |c| c.write_multiple_coils(address, &coils))
+    }
+
+    fn read_input_registers(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>> {
+        self.run(move |c| c.read_input_registers(address, quantity))
+    }
+
+    fn read_holding_registers(&mut self, address: u16, quantity: u16) -> Result<Vec<u16>> {
+        self.run(move |c| c.read_holding_registers(address, quantity))
+    }
+
+    fn write_single_register(&mut self, address: u16, value: u16) -> Result<()> {
+        self.run(move |c| c.write_single_register(address, value))
+    }
+
+    fn write_multiple_registers(&mut self, address: u16, values: &[u16]) -> Result<()> {
+        let values = values.to_vec();
+        self.run(move |c| c.write_multiple_registers(address, &values))
+    }
+
+    fn read_exception_status(&mut self) -> Result<u8> {
+        self.run(|c| c.read_exception_status())
+    }
+
+    fn diagnostics(&mut self, sub: DiagnosticsSubFunction, data: &[u16]) -> Result<Vec<u16>> {
+        let data = data.to_vec();
+        self.run(move |c| c.diagnostics(sub, &data))
+    }
+
+    fn report_slave_id(&mut self) -> Result<SlaveId> {
+        self.run(|c| c.report_slave_id())
+    }
+
+    fn mask_write_register(&mut self, address: u16, and_mask: u16, or_mask: u16) -> Result<()> {
+        self.run(move |c| c.mask_write_register(address, and_mask, or_mask))
+    }
+
+    fn write_read_multiple_registers(
+        &mut self,
+        write_address: u16,
+        write_quantity: u16,
+        write_values: &[u16],
+        read_address: u16,
+        read_quantity: u16,
+    ) -> Result<Vec<u16>> {
+        let write_values = write_values.to_vec();
+        self.run(move |c| {
+            c.write_read_multiple_registers(
+                write_address,
+                write_quantity,
+                &write_values,
+                read_address,
+                read_quantity,
+            )
+        })
+    }
+
+    fn read_fifo_queue(&mut self, address: u16) -> Result<Vec<u16>> {
+        self.run(move |c| c.read_fifo_queue(address))
+    }
+
+    fn read_file_records(&mut self, records: &[(u16, u16, u16)]) -> Result<Vec<FileRecord>> {
+        let records = records.to_vec();
+        self.run(move |c| c.read_file_records(&records))
+    }
+
+    fn write_file_records(&mut self, records: &[FileRecord]) -> Result<()> {
+        let records = records.to_vec();
+        self.run(move |c| c.write_file_records(&records))
+    }
+
+    /// Set the unit identifier of the following requests, queued behind a still running
+    /// request.
+    fn set_uid(&mut self, uid: u8) {
+        let _ = self.jobs.send(Box::new(move |c| c.set_uid(uid)));
+    }
+
+    #[cfg(feature = "read-device-info")]
+    fn read_device_info(
+        &mut self,
+        obj_category: mei::DeviceInfoCategory,
+    ) -> Result<Vec<mei::DeviceInfoObject>> {
+        self.run(move |c| c.read_device_info(obj_category))
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod tests {
+    use super::*;
+    use crate::server::{Bank, Latency, Server};
+    use crate::tcp::{Config, Transport};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn timeouts() {
+        let mut server = Server::bind("127.0.0.1:0", Bank::new(10)).unwrap();
+        server.set_function_latency(
+            0x03,
+            Latency {
+                delay: Duration::from_millis(300),
+                ..Default::default()
+            },
+        );
+        let cfg = Config {
+            tcp_port: server.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        thread::spawn(move || server.serve());
+        let client = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+
+        let mut watchdog = Watchdog::new(client, Duration::from_millis(100));
+        let timeouts = Arc::new(AtomicUsize::new(0));
+        let counter = timeouts.clone();
+        watchdog.on_timeout(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        match watchdog.read_holding_registers(0, 2) {
+            Err(Error::Timeout) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(timeouts.load(Ordering::SeqCst), 0);
+
+        // the late response is received before the next request is sent
+        watchdog.set_timeout(Duration::from_secs(5));
+        watchdog.write_single_register(1, 7).unwrap();
+        assert_eq!(timeouts.load(Ordering::SeqCst), 1);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let values = watchdog.run_until(deadline, |c| c.read_holding_registers(0, 2));
+        assert_eq!(values.unwrap(), vec![0, 7]);
+    }
+
+    #[test]
+    fn expired_requests_are_skipped() {
+        let mut server = Server::bind("127.0.0.1:0", Bank::new(10)).unwrap();
+        server.set_function_latency(
+            0x03,
+            Latency {
+                delay: Duration::from_millis(300),
+                ..Default::default()
+            },
+        );
+        let cfg = Config {
+            tcp_port: server.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        thread::spawn(move || server.serve());
+        let client = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+
+        // the write times out while it waits for the slow read
+        let mut watchdog = Watchdog::new(client, Duration::from_millis(100));
+        assert!(matches!(
+            watchdog.read_holding_registers(0, 2),
+            Err(Error::Timeout)
+        ));
+        assert!(matches!(
+            watchdog.write_single_register(1, 7),
+            Err(Error::Timeout)
+        ));
+
+        watchdog.set_timeout(Duration::from_secs(5));
+        assert_eq!(watchdog.read_holding_registers(0, 2).unwrap(), vec![0, 0]);
+    }
+}
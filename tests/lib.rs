@@ -1,5 +1,4 @@
 extern crate modbus;
-extern crate test_server;
 
 mod connection_tests {
     use modbus::tcp::{Config, Transport};
@@ -7,8 +6,10 @@ mod connection_tests {
 
     #[test]
     fn test_connect_timeout() {
-        let mut cfg = Config::default();
-        cfg.tcp_connect_timeout = Some(Duration::from_millis(1050));
+        let cfg = Config::builder()
+            .connect_timeout(Duration::from_millis(1050))
+            .build()
+            .unwrap();
         let now = Instant::now();
         if Transport::new_with_cfg("30.30.30.30", cfg).is_err() {
             let elapsed = now.elapsed().as_secs();
@@ -17,25 +18,33 @@ mod connection_tests {
     }
 }
 
-#[cfg(feature = "modbus-server-tests")]
+#[cfg(feature = "server")]
 mod modbus_server_tests {
-    use modbus::scoped::{CoilDropFunction, RegisterDropFunction, ScopedCoil, ScopedRegister};
+    use modbus::scoped::{
+        CoilDropFunction, RegisterDropFunction, ScopedCoil, ScopedCoils, ScopedRegister,
+        ScopedRegisters,
+    };
+    use modbus::server::{Bank, Server};
     use modbus::tcp::{Config, Transport};
     use modbus::{Client, Coil};
-    use test_server::{start_dummy_server, ChildKiller};
+    use std::thread;
 
-    fn start_dummy_server_with_cfg() -> (ChildKiller, Config) {
-        let (k, port) = start_dummy_server(None);
-        let mut cfg = Config::default();
-        cfg.tcp_port = port;
-        (k, cfg)
+    /// Serve 500 entries per table on a free port in a background thread.
+    fn start_dummy_server_with_cfg() -> Config {
+        let server = Server::bind("127.0.0.1:0", Bank::new(500)).unwrap();
+        let cfg = Config::builder()
+            .port(server.local_addr().unwrap().port())
+            .build()
+            .unwrap();
+        thread::spawn(move || server.serve());
+        cfg
     }
 
     /// /////////////////////
     /// simple READ tests
     #[test]
     fn test_read_coils() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert_eq!(trans.read_coils(0, 5).unwrap().len(), 5);
         assert!(trans
@@ -47,7 +56,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_read_discrete_inputs() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert_eq!(trans.read_discrete_inputs(0, 5).unwrap().len(), 5);
         assert!(trans
@@ -59,7 +68,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_read_holding_registers() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert_eq!(trans.read_holding_registers(0, 5).unwrap().len(), 5);
         assert!(trans
@@ -71,7 +80,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_read_input_registers() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert_eq!(trans.read_input_registers(0, 5).unwrap().len(), 5);
         assert!(trans
@@ -85,21 +94,21 @@ mod modbus_server_tests {
     /// simple WRITE tests
     #[test]
     fn test_write_single_coil() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans.write_single_coil(0, Coil::On).is_ok());
     }
 
     #[test]
     fn test_write_single_register() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans.write_single_register(0, 1).is_ok());
     }
 
     #[test]
     fn test_write_multiple_coils() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans
             .write_multiple_coils(0, &[Coil::On, Coil::Off])
@@ -109,7 +118,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_write_multiple_registers() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans.write_multiple_registers(0, &[0, 1, 2, 3]).is_ok());
         // assert!(write_multiple_registers(&mut trans, 0, &[]).is_err());
@@ -119,7 +128,7 @@ mod modbus_server_tests {
     /// coil WRITE-READ tests
     #[test]
     fn test_write_read_single_coils() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
 
         assert!(trans.write_single_coil(1, Coil::On).is_ok());
@@ -148,7 +157,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_write_read_single_register() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans.write_single_register(0, 23).is_ok());
         assert_eq!(trans.read_holding_registers(0, 1).unwrap(), vec![23]);
@@ -162,7 +171,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_write_read_multiple_coils() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans
             .write_multiple_coils(0, &[Coil::Off, Coil::On])
@@ -177,7 +186,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_write_read_multiple_registers() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans
             .write_read_multiple_registers(0, 3, &[1, 2, 3], 0, 3)
@@ -197,7 +206,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_write_too_big() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
         assert!(trans.write_multiple_registers(0, &[0xdead; 123]).is_ok());
         assert!(trans.write_multiple_registers(0, &[0xdead; 124]).is_err());
@@ -205,7 +214,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_scoped_coil() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
 
         {
@@ -257,7 +266,7 @@ mod modbus_server_tests {
 
     #[test]
     fn test_scoped_register() {
-        let (_s, cfg) = start_dummy_server_with_cfg();
+        let cfg = start_dummy_server_with_cfg();
         let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
 
         {
@@ -320,4 +329,60 @@ mod modbus_server_tests {
         }
         assert_eq!(trans.read_holding_registers(0, 1).unwrap(), vec![0xbeef]);
     }
+
+    #[test]
+    fn test_scoped_coils() {
+        let cfg = start_dummy_server_with_cfg();
+        let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        trans.write_single_coil(1, Coil::On).unwrap();
+
+        {
+            let mut auto = ScopedCoils::new(&mut trans, 0, 3).unwrap();
+            assert_eq!(auto.original(), &[Coil::Off, Coil::On, Coil::Off]);
+            auto.mut_transport()
+                .write_multiple_coils(0, &[Coil::On, Coil::Off, Coil::On])
+                .unwrap();
+        }
+        assert_eq!(
+            trans.read_coils(0, 3).unwrap(),
+            vec![Coil::Off, Coil::On, Coil::Off]
+        );
+
+        {
+            let _auto = ScopedCoils::with_drop_function(&mut trans, 0, 3, CoilDropFunction::Toggle)
+                .unwrap();
+        }
+        assert_eq!(
+            trans.read_coils(0, 3).unwrap(),
+            vec![Coil::On, Coil::Off, Coil::On]
+        );
+    }
+
+    #[test]
+    fn test_scoped_registers() {
+        let cfg = start_dummy_server_with_cfg();
+        let mut trans = Transport::new_with_cfg("127.0.0.1", cfg).unwrap();
+        trans.write_multiple_registers(0, &[1, 2, 3]).unwrap();
+
+        {
+            let mut auto = ScopedRegisters::new(&mut trans, 0, 3).unwrap();
+            assert_eq!(auto.original(), &[1, 2, 3]);
+            auto.mut_transport()
+                .write_multiple_registers(0, &[0xbeef; 3])
+                .unwrap();
+        }
+        assert_eq!(trans.read_holding_registers(0, 3).unwrap(), vec![1, 2, 3]);
+
+        {
+            let fun = |v| v * 2;
+            let _auto = ScopedRegisters::with_drop_function(
+                &mut trans,
+                0,
+                3,
+                RegisterDropFunction::Fun(&fun),
+            )
+            .unwrap();
+        }
+        assert_eq!(trans.read_holding_registers(0, 3).unwrap(), vec![2, 4, 6]);
+    }
 }
@@ -0,0 +1,217 @@
+//! Snapshots of the request PDUs sent by the `Client` methods, to catch accidental changes of
+//! the wire format.
+//!
+//! The requests are recorded with a `MockClient` and compared with the snapshots in
+//! `tests/snapshots`, one request per line. After an intended change, run the tests with
+//! `UPDATE_SNAPSHOTS=1` to rewrite the snapshots and review the diff.
+
+#![cfg(feature = "mock")]
+
+extern crate modbus;
+
+use modbus::binary::Endian;
+use modbus::mock::MockClient;
+use modbus::server::Bank;
+use modbus::{Client, Coil, DiagnosticsSubFunction, FileRecord};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+type Case = (&'static str, fn(&mut MockClient));
+
+// Run the `cases` on a fresh client each and format their requests, cases whose requests are
+// rejected before sending them are listed too.
+fn record(cases: &[Case]) -> String {
+    let mut out = String::new();
+    for (name, case) in cases {
+        let mut client = MockClient::new(Bank::new(0x10000));
+        case(&mut client);
+        if client.requests().is_empty() {
+            writeln!(out, "{} no request", name).unwrap();
+        }
+        for req in client.requests() {
+            write!(out, "{} uid={}", name, req.uid).unwrap();
+            for b in &req.pdu {
+                write!(out, " {:02x}", b).unwrap();
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// Compare `actual` with the snapshot `name`, or rewrite it with `UPDATE_SNAPSHOTS` set.
+fn check_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap();
+    for (i, (e, a)) in expected.lines().zip(actual.lines()).enumerate() {
+        assert_eq!(e, a, "line {} of {} differs", i + 1, name);
+    }
+    assert_eq!(
+        expected.lines().count(),
+        actual.lines().count(),
+        "number of requests in {} differs",
+        name
+    );
+}
+
+#[test]
+fn requests() {
+    let cases: &[Case] = &[
+        ("read_coils_first", |c| drop(c.read_coils(0, 1))),
+        ("read_coils_last", |c| drop(c.read_coils(0xffff, 1))),
+        ("read_coils_max", |c| drop(c.read_coils(0x0100, 2000))),
+        ("read_coils_too_many", |c| drop(c.read_coils(0, 2001))),
+        ("read_coils_none", |c| drop(c.read_coils(0, 0))),
+        ("read_discrete_inputs", |c| {
+            drop(c.read_discrete_inputs(0x1234, 9))
+        }),
+        ("read_coil_range", |c| drop(c.read_coil_range(7..20))),
+        ("read_holding_registers", |c| {
+            drop(c.read_holding_registers(0x0010, 2))
+        }),
+        ("read_holding_registers_max", |c| {
+            drop(c.read_holding_registers(0xff82, 125))
+        }),
+        ("read_holding_registers_too_many", |c| {
+            drop(c.read_holding_registers(0, 126))
+        }),
+        ("read_input_registers", |c| {
+            drop(c.read_input_registers(0x00ff, 7))
+        }),
+        ("read_holding_registers_chunked", |c| {
+            drop(c.read_holding_registers_chunked(10, 300))
+        }),
+        ("read_input_registers_chunked", |c| {
+            drop(c.read_input_registers_chunked(0, 126))
+        }),
+        ("read_holding_registers_batch", |c| {
+            drop(c.read_holding_registers_batch(&[200, 1, 2, 10, 400], 8))
+        }),
+        ("write_single_coil_on", |c| {
+            drop(c.write_single_coil(0, Coil::On))
+        }),
+        ("write_single_coil_off", |c| {
+            drop(c.write_single_coil(0xffff, Coil::Off))
+        }),
+        ("write_single_register", |c| {
+            drop(c.write_single_register(1, 0xbeef))
+        }),
+        ("write_single_register_max", |c| {
+            drop(c.write_single_register(0xffff, 0xffff))
+        }),
+        ("write_multiple_coils_3", |c| {
+            drop(c.write_multiple_coils(3, &[Coil::On, Coil::Off, Coil::On]))
+        }),
+        ("write_multiple_coils_8", |c| {
+            drop(c.write_multiple_coils(8, &[Coil::On; 8]))
+        }),
+        ("write_multiple_coils_9", |c| {
+            drop(c.write_multiple_coils(0, &[Coil::On; 9]))
+        }),
+        ("write_multiple_coils_empty", |c| {
+            drop(c.write_multiple_coils(0, &[]))
+        }),
+        ("write_multiple_coils_chunked", |c| {
+            let coils: Vec<Coil> = (0..2000).map(|i| (i % 3 == 0).into()).collect();
+            drop(c.write_multiple_coils_chunked(0, &coils))
+        }),
+        ("write_multiple_registers_1", |c| {
+            drop(c.write_multiple_registers(0, &[1]))
+        }),
+        ("write_multiple_registers_max", |c| {
+            let values: Vec<u16> = (0..123).map(|i| i * 0x0101).collect();
+            drop(c.write_multiple_registers(0x1000, &values))
+        }),
+        ("write_multiple_registers_too_many", |c| {
+            drop(c.write_multiple_registers(0, &[0; 124]))
+        }),
+        ("write_multiple_registers_chunked", |c| {
+            drop(c.write_multiple_registers_chunked(5, &[0xaaaa; 130]))
+        }),
+        ("read_u32", |c| {
+            drop(c.read_u32(4, Endian::Big, Endian::Little))
+        }),
+        ("read_f64", |c| {
+            drop(c.read_f64(8, Endian::Little, Endian::Big))
+        }),
+        ("write_u32_big_big", |c| {
+            drop(c.write_u32(0, 0x11223344, Endian::Big, Endian::Big))
+        }),
+        ("write_u32_little_little", |c| {
+            drop(c.write_u32(0, 0x11223344, Endian::Little, Endian::Little))
+        }),
+        ("write_i64", |c| {
+            drop(c.write_i64(2, -2, Endian::Big, Endian::Little))
+        }),
+        ("write_f32", |c| {
+            drop(c.write_f32(6, 1.5, Endian::Big, Endian::Big))
+        }),
+        ("write_f64", |c| {
+            drop(c.write_f64(6, -0.25, Endian::Little, Endian::Big))
+        }),
+        ("read_exception_status", |c| drop(c.read_exception_status())),
+        ("diagnostics_query_data", |c| {
+            drop(c.diagnostics(DiagnosticsSubFunction::ReturnQueryData, &[0xa537, 0x0001]))
+        }),
+        ("diagnostics_clear_counters", |c| {
+            drop(c.diagnostics(
+                DiagnosticsSubFunction::ClearCountersAndDiagnosticRegister,
+                &[],
+            ))
+        }),
+        ("report_slave_id", |c| drop(c.report_slave_id())),
+        ("mask_write_register", |c| {
+            drop(c.mask_write_register(4, 0x00f2, 0x0025))
+        }),
+        ("write_read_multiple_registers", |c| {
+            drop(c.write_read_multiple_registers(3, 2, &[0x00ff, 0xff00], 0x10, 4))
+        }),
+        ("read_fifo_queue", |c| drop(c.read_fifo_queue(0x04de))),
+        ("read_file_records", |c| {
+            drop(c.read_file_records(&[(4, 1, 2), (3, 9, 2)]))
+        }),
+        ("write_file_records", |c| {
+            drop(c.write_file_records(&[FileRecord {
+                file_number: 4,
+                record_number: 7,
+                record_data: vec![0x06af, 0x04be, 0x100d],
+            }]))
+        }),
+        ("set_uid", |c| {
+            c.set_uid(0);
+            drop(c.read_coils(0, 1));
+            c.set_uid(247);
+            drop(c.read_coils(0, 1));
+            c.set_uid(255);
+            drop(c.read_coils(0, 1));
+        }),
+    ];
+    check_snapshot("requests.txt", &record(cases));
+}
+
+#[cfg(feature = "read-device-info")]
+#[test]
+fn device_info_requests() {
+    use modbus::mei::DeviceInfoCategory;
+
+    let cases: &[Case] = &[
+        ("read_device_info_basic", |c| {
+            drop(c.read_device_info(DeviceInfoCategory::Basic))
+        }),
+        ("read_device_info_regular", |c| {
+            drop(c.read_device_info(DeviceInfoCategory::Regular))
+        }),
+        ("read_device_info_extended", |c| {
+            drop(c.read_device_info(DeviceInfoCategory::Extended))
+        }),
+    ];
+    check_snapshot("device_info_requests.txt", &record(cases));
+}